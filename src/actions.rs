@@ -0,0 +1,89 @@
+//! Custom actions on the selected song, which are commands from the `[actions]` table of the
+//! configuration file.
+//!
+//! In a command, `{key}`, `{artist}`, `{title}` and `{album}` are replaced with the media key and
+//! the tags of the song. The values are quoted for the shell, because they come from the server,
+//! so they should not be put in quotes in the command.
+
+use std::io::Error as IOError;
+use std::process::{Child, Command, Stdio};
+
+use libclient::Media;
+use template;
+
+/// Replace the placeholders in `command` with the quoted values of `media`
+///
+/// Anything between braces that is not a placeholder is left alone.
+pub fn expand(command: &str, media: &Media) -> String {
+    template::expand(command, |name| {
+        let value = match name {
+            "key" => &media.key[..],
+            "artist" => &media.artist[..],
+            "title" => &media.title[..],
+            "album" => media.album.as_ref().map_or("", |x| &x[..]),
+            _ => return None,
+        };
+        Some(quote(value))
+    })
+}
+
+/// Start `command` with the shell, without giving it the terminal
+pub fn run(command: &str) -> Result<Child, IOError> {
+    debug!("running action {:?}", command);
+    shell(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(not(unix))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+/// Quote `s` as a single word for the shell
+#[cfg(unix)]
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r#"'\''"#))
+}
+
+/// Quote `s` as a single word for the shell
+///
+/// cmd.exe cannot escape double quotes inside double quotes, so they are left out.
+#[cfg(not(unix))]
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', ""))
+}
+
+
+#[cfg(unix)]
+#[test]
+fn test() {
+    use time::Duration;
+
+    let mut media = Media {
+        artist: String::from("Guns N' Roses"),
+        key: String::from("56bafc2c8dc01b4ea67fad9c"),
+        length: Duration::seconds(231),
+        title: String::from("{key} $(rm -rf ~)"),
+        uploaded_by: String::from("dsprenkels"),
+        album: None,
+        url: None,
+    };
+    assert_eq!(expand("echo {key} | xclip", &media), "echo '56bafc2c8dc01b4ea67fad9c' | xclip");
+    assert_eq!(expand("echo {artist}", &media), r#"echo 'Guns N'\'' Roses'"#);
+    assert_eq!(expand("echo {title}", &media), "echo '{key} $(rm -rf ~)'");
+    assert_eq!(expand("echo {album} {foo} {", &media), "echo '' {foo} {");
+    media.album = Some(String::from("Appetite for Destruction"));
+    assert_eq!(expand("{album}{title", &media), "'Appetite for Destruction'{title");
+}
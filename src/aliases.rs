@@ -0,0 +1,57 @@
+//! Aliases for commands, from the `[aliases]` table of the configuration file.
+//!
+//! An alias stands for a command and maybe the start of its arguments, e.g. `q = "quit"` or
+//! `mine = "uploads dsprenkels"`. The `:` commands of the terminal UI and the subcommands of
+//! `maruska-cli` share the table, and each look up the aliases that are not one of their own
+//! commands: an alias never changes what a command does. Aliases are not expanded again, so
+//! they cannot loop.
+
+use std::collections::BTreeMap;
+
+use toml::Value;
+
+/// The aliases of a configuration file, by their name; invalid ones are skipped
+pub fn from_table(table: &BTreeMap<String, Value>) -> BTreeMap<String, String> {
+    let mut aliases = BTreeMap::new();
+    let table = match table.get("aliases").and_then(|x| x.as_table()) {
+        Some(table) => table,
+        None => return aliases,
+    };
+    for (name, command) in table {
+        match command.as_str() {
+            Some(command) if is_valid_name(name) && !command.trim().is_empty() => {
+                aliases.insert(name.clone(), command.trim().to_string());
+            },
+            _ => warn!("ignoring alias {:?}, it needs a single word and a command", name),
+        }
+    }
+    aliases
+}
+
+/// Can `name` be the name of an alias, i.e. is it a single word?
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(char::is_whitespace)
+}
+
+/// What `name` stands for, if it is an alias and not one of `commands`
+pub fn resolve<'a>(aliases: &'a BTreeMap<String, String>, commands: &[&str], name: &str)
+                   -> Option<&'a str> {
+    if commands.contains(&name) {
+        return None;
+    }
+    aliases.get(name).map(|x| x.as_str())
+}
+
+
+#[test]
+fn test() {
+    let table = ::toml::Parser::new("[aliases]\nq = \"quit\"\nmine = \" uploads bob \"\n\
+                                     quit = \"suspend\"\n\"a b\" = \"raw\"\nn = 1")
+        .parse().unwrap();
+    let aliases = from_table(&table);
+    assert_eq!(aliases.len(), 3);
+    assert_eq!(resolve(&aliases, &["quit"], "q"), Some("quit"));
+    assert_eq!(resolve(&aliases, &["quit"], "mine"), Some("uploads bob"));
+    assert_eq!(resolve(&aliases, &["quit"], "quit"), None);
+    assert_eq!(resolve(&aliases, &["quit"], "x"), None);
+}
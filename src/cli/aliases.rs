@@ -0,0 +1 @@
+../aliases.rs
\ No newline at end of file
@@ -0,0 +1,76 @@
+use std::process;
+
+use docopt::Docopt;
+use time::at;
+
+use libclient::audit::{read_entries, Entry};
+use paths;
+
+#[derive(Debug, RustcDecodable)]
+pub struct Args {
+    flag_all: bool,
+    flag_user: Option<String>,
+    flag_count: usize,
+}
+
+pub const USAGE: &'static str = "
+Show what this machine asked the server to change
+
+The terminal UI, the daemon and the other commands keep a log of the requests,
+removals, moves, skip votes, reports, edits and logins that they send, and of
+the answers of the server. Only the last entries for the server are shown,
+oldest first.
+
+Usage:
+  maruska audit [options]
+
+Options:
+  --all             Show the entries for every server
+  -u --user <name>  Only show what was sent as this user
+  -n --count <n>    Show this many entries [default: 20]
+  -h --help         Display this message
+";
+
+pub fn main(argv: Vec<String>, global_args: super::Args) {
+    let args: Args = Docopt::new(USAGE)
+        .map(|d| d.help(true))
+        .map(|d| d.argv(argv))
+        .and_then(|d| d.decode())
+        .unwrap_or_else(|e| e.exit());
+    execute(args, global_args);
+}
+
+pub fn execute(args: Args, global_args: super::Args) {
+    let path = paths::audit_file().unwrap_or_else(|| {
+        println!("Could not find a state directory");
+        process::exit(1);
+    });
+    let entries = read_entries(&path).unwrap_or_else(|err| {
+        println!("Could not read the audit log {}: {}", path.display(), err);
+        process::exit(1);
+    });
+    let entries: Vec<Entry> = entries.into_iter()
+        .filter(|x| args.flag_all || x.url == global_args.flag_host)
+        .filter(|x| args.flag_user.as_ref().map_or(true, |user| &x.username == user))
+        .collect();
+    if entries.is_empty() {
+        println!("Nothing was sent yet");
+        return;
+    }
+
+    let time_format = super::time_format();
+    let shown = &entries[entries.len().saturating_sub(args.flag_count)..];
+    let width = shown.iter().map(|x| x.username.chars().count()).max().unwrap_or(0);
+    for entry in shown {
+        // the format is valid, so this does not fail
+        let date = at(entry.time).strftime("%Y-%m-%d").unwrap().to_string();
+        let server = if args.flag_all { format!("{}  ", entry.url) } else { String::new() };
+        let what = if entry.target.is_empty() {
+            entry.action.clone()
+        } else {
+            format!("{} {}", entry.action, entry.target)
+        };
+        println!("{} {}  {}{:<width$}  {}: {}", date, time_format.time(entry.time), server,
+                 entry.username, what, entry.outcome, width = width);
+    }
+}
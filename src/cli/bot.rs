@@ -0,0 +1,393 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{Error as IOError, Read};
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use docopt::Docopt;
+use hyper;
+use hyper::Url;
+use hyper::error::Error as HyperError;
+use hyper::header::{Authorization, Bearer, ContentType};
+use hyper::method::Method;
+use rustc_serialize::json::{Json, ParserError as JsonError, ToJson};
+use toml::{Parser, Value};
+
+use libclient::{Client, DisplayChain, Message, Playing, RequestStatus};
+use daemon::search;
+use paths;
+
+#[derive(Debug, RustcDecodable)]
+pub struct Args {
+    flag_matrix: String,
+}
+
+pub const USAGE: &'static str = "
+Run a chat bot that announces song changes and takes requests
+
+Usage:
+  maruska bot --matrix=CONFIG [options]
+
+Options:
+  --matrix CONFIG  Join a Matrix room, using the settings in the file CONFIG
+  -h --help        Display this message
+
+Matrix config:
+  CONFIG is a TOML file with the following keys:
+
+  homeserver = \"https://matrix.org\"        The homeserver of the bot's account
+  access_token = \"...\"                     The access token of the bot's account
+  room = \"#marietje:matrix.org\"            The id or alias of the room to join
+
+Chat commands:
+  !request QUERY  Request the first song that matches QUERY
+  !queue          Show the request queue
+
+Use --username and --password to let the bot log in, which is needed for requests.
+";
+
+/// How long the homeserver may hold on to a sync request
+const SYNC_TIMEOUT_MILLIS: u64 = 30000;
+
+/// How long to wait before syncing again after a failed sync
+const RETRY_DELAY_MILLIS: u64 = 5000;
+
+/// The maximum amount of queue entries that `!queue` shows
+const MAX_QUEUE_LINES: usize = 10;
+
+#[derive(Debug)]
+pub enum BotError {
+    Config(String),
+    Hyper(HyperError),
+    IO(IOError),
+    Json(JsonError),
+    Matrix(String),
+}
+
+impl fmt::Display for BotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BotError::Config(ref msg) => write!(f, "bad config: {}", msg),
+            BotError::Hyper(_) => write!(f, "http request to the homeserver failed"),
+            BotError::IO(_) => write!(f, "io error"),
+            BotError::Json(_) => write!(f, "the homeserver sent invalid json"),
+            BotError::Matrix(ref msg) => write!(f, "matrix error: {}", msg),
+        }
+    }
+}
+
+impl Error for BotError {
+    fn description(&self) -> &str {
+        match *self {
+            BotError::Config(_) => "bad config",
+            BotError::Hyper(ref err) => err.description(),
+            BotError::IO(ref err) => err.description(),
+            BotError::Json(ref err) => err.description(),
+            BotError::Matrix(_) => "matrix error",
+        }
+    }
+
+    fn source(&self) -> Option<&(Error + 'static)> {
+        match *self {
+            BotError::Hyper(ref err) => Some(err),
+            BotError::IO(ref err) => Some(err),
+            BotError::Json(ref err) => Some(err),
+            BotError::Config(_) | BotError::Matrix(_) => None,
+        }
+    }
+}
+
+impl From<HyperError> for BotError {
+    fn from(err: HyperError) -> Self {
+        BotError::Hyper(err)
+    }
+}
+
+impl From<IOError> for BotError {
+    fn from(err: IOError) -> Self {
+        BotError::IO(err)
+    }
+}
+
+impl From<JsonError> for BotError {
+    fn from(err: JsonError) -> Self {
+        BotError::Json(err)
+    }
+}
+
+pub fn main(argv: Vec<String>, global_args: super::Args) {
+    let args: Args = Docopt::new(USAGE)
+        .map(|d| d.help(true))
+        .map(|d| d.argv(argv))
+        .and_then(|d| d.decode())
+        .unwrap_or_else(|e| e.exit());
+    execute(args, global_args);
+}
+
+pub fn execute(args: Args, global_args: super::Args) {
+    let matrix = Matrix::from_config(&args.flag_matrix)
+        .and_then(|mut matrix| matrix.join().map(|_| matrix))
+        .unwrap_or_else(|err| panic!("could not connect to matrix: {}", DisplayChain(&err)));
+    let user_id = matrix.whoami()
+        .unwrap_or_else(|err| panic!("could not connect to matrix: {}", DisplayChain(&err)));
+    println!("Joined {} as {}", matrix.room, user_id);
+
+    let (mut client, client_r) = Client::new(&global_args.flag_host).unwrap();
+    client.set_audit_log(paths::audit_file());
+    client.follow_all();
+    client.serve();
+    if !global_args.flag_username.is_empty() && !global_args.flag_password.is_empty() {
+        let hash = client.hash_password(&global_args.flag_password);
+        client.do_login(&global_args.flag_username, &hash);
+    }
+    let client = Arc::new(Mutex::new(client));
+
+    {
+        let matrix = matrix.clone();
+        let client = client.clone();
+        thread::spawn(move || serve_commands(matrix, client, user_id));
+    }
+
+    while let Some(message) = client_r.recv() {
+        let mut client = client.lock().unwrap();
+        match client.handle_message(&message) {
+            Ok(Message::PlayingChanged) => {
+                if let Some(ref playing) = *client.get_playing() {
+                    if let Err(err) = matrix.send_notice(&now_playing(playing)) {
+                        warn!("could not announce song: {}", DisplayChain(&err));
+                    }
+                }
+            },
+            Ok(Message::LoginError(msg)) => error!("could not log in: {}", msg),
+            Ok(_) => {},
+            Err(err) => error!("{}", DisplayChain(&err)),
+        }
+    }
+    error!("the connection to the server was lost");
+    process::exit(1);
+}
+
+/// Sync with the homeserver forever, and answer the commands that are sent to the room
+fn serve_commands(matrix: Matrix, client: Arc<Mutex<Client>>, user_id: String) {
+    // The first sync only tells us where we are, we don't want to answer old messages
+    let mut since = None;
+    loop {
+        let timeout = if since.is_some() { SYNC_TIMEOUT_MILLIS } else { 0 };
+        let response = match matrix.sync(since.as_ref().map(|x: &String| x.as_str()), timeout) {
+            Ok(response) => response,
+            Err(err) => {
+                warn!("matrix sync failed: {}", DisplayChain(&err));
+                thread::sleep(Duration::from_millis(RETRY_DELAY_MILLIS));
+                continue;
+            },
+        };
+        if since.is_some() {
+            let events = response.find_path(&["rooms", "join", &matrix.room_id, "timeline", "events"])
+                                 .and_then(|x| x.as_array());
+            for event in events.into_iter().flat_map(|x| x.iter()) {
+                if event.find("type").and_then(|x| x.as_string()) != Some("m.room.message") ||
+                   event.find("sender").and_then(|x| x.as_string()) == Some(&user_id) {
+                    continue;
+                }
+                let body = match event.find_path(&["content", "body"]).and_then(|x| x.as_string()) {
+                    Some(body) => body,
+                    None => continue,
+                };
+                if let Some(reply) = handle_command(&client, body) {
+                    if let Err(err) = matrix.send_notice(&reply) {
+                        warn!("could not send reply: {}", DisplayChain(&err));
+                    }
+                }
+            }
+        }
+        since = response.find("next_batch").and_then(|x| x.as_string()).map(String::from);
+    }
+}
+
+/// Handle a message from the room, and return the reply if it was a command
+fn handle_command(client: &Mutex<Client>, body: &str) -> Option<String> {
+    let body = body.trim();
+    if body == "!queue" {
+        return Some(queue(client));
+    }
+    if body.starts_with("!request ") {
+        let query = body["!request ".len()..].trim();
+        return Some(request(client, query));
+    }
+    None
+}
+
+fn queue(client: &Mutex<Client>) -> String {
+    let client = client.lock().unwrap();
+    let requests = match *client.get_requests() {
+        Some(ref requests) if requests.is_empty() => return "The queue is empty".to_string(),
+        Some(ref requests) => requests,
+        None => return "I have not received the queue yet".to_string(),
+    };
+    let mut lines: Vec<String> = requests.iter().take(MAX_QUEUE_LINES).enumerate().map(|(i, x)| {
+        let requested_by = x.by.as_ref().map_or("marietje", |x| x.as_str());
+        format!("{}. {} - {} ({})", i + 1, x.media.artist, x.media.title, requested_by)
+    }).collect();
+    if requests.len() > MAX_QUEUE_LINES {
+        lines.push(format!("...and {} more", requests.len() - MAX_QUEUE_LINES));
+    }
+    lines.join("\n")
+}
+
+fn request(client: &Mutex<Client>, query: &str) -> String {
+    if query.is_empty() {
+        return "Usage: !request QUERY".to_string();
+    }
+    let results = search(client, query, 1);
+    let mut client = client.lock().unwrap();
+    // Forget the query, so that the same query is searched again next time
+    client.update_query(None, 0);
+    let media = match results {
+        Some(ref results) if !results.is_empty() => &results[0],
+        Some(_) => return format!("No songs found for '{}'", query),
+        None => return "The server did not answer my search in time".to_string(),
+    };
+    match client.do_request(media) {
+        RequestStatus::Ok => format!("Requested {} - {}", media.artist, media.title),
+        RequestStatus::Deferred => {
+            format!("Will request {} - {} as soon as I am logged in", media.artist, media.title)
+        },
+        RequestStatus::Queued => {
+            format!("Cannot reach the server, will request {} - {} once I can", media.artist,
+                    media.title)
+        },
+    }
+}
+
+fn now_playing(playing: &Playing) -> String {
+    let media = &playing.media;
+    match playing.requested_by {
+        Some(ref by) => format!("Now playing: {} - {} (requested by {})",
+                                media.artist, media.title, by),
+        None => format!("Now playing: {} - {}", media.artist, media.title),
+    }
+}
+
+/// A (very) small client for the Matrix client-server API
+#[derive(Clone)]
+struct Matrix {
+    http: Arc<hyper::Client>,
+    homeserver: Url,
+    access_token: String,
+
+    /// The room as it was given in the config, this may be an alias
+    room: String,
+    room_id: String,
+
+    /// Transaction ids make sure that a message is not sent twice when a request is retried
+    txn_prefix: String,
+    txn_counter: Arc<AtomicUsize>,
+}
+
+impl Matrix {
+    fn from_config(path: &str) -> Result<Matrix, BotError> {
+        let mut s = String::new();
+        try!(File::open(path).and_then(|mut f| f.read_to_string(&mut s)));
+        let mut parser = Parser::new(&s);
+        let table = try!(parser.parse().ok_or_else(|| {
+            let msg = parser.errors.iter().map(|x| x.desc.clone()).collect::<Vec<_>>().join(", ");
+            BotError::Config(msg)
+        }));
+        let get = |key: &str| match table.get(key) {
+            Some(&Value::String(ref x)) => Ok(x.clone()),
+            Some(_) => Err(BotError::Config(format!("'{}' is not a string", key))),
+            None => Err(BotError::Config(format!("missing key '{}'", key))),
+        };
+        let homeserver = try!(get("homeserver"));
+        let homeserver = try!(Url::parse(&homeserver).map_err(|err| {
+            BotError::Config(format!("invalid homeserver url: {}", err))
+        }));
+        let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(Duration::new(0, 0));
+
+        let mut http = hyper::Client::new();
+        http.set_read_timeout(Some(Duration::from_millis(SYNC_TIMEOUT_MILLIS * 2)));
+        let room = try!(get("room"));
+        Ok(Matrix {
+            http: Arc::new(http),
+            homeserver: homeserver,
+            access_token: try!(get("access_token")),
+            room_id: room.clone(),
+            room: room,
+            txn_prefix: format!("maruska{}", since_epoch.as_secs()),
+            txn_counter: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    /// Join the room from the config, and look up its id
+    fn join(&mut self) -> Result<(), BotError> {
+        let url = self.url(&["join", &self.room]);
+        let response = try!(self.call(Method::Post, url, Some("{}".to_string())));
+        self.room_id = try!(response.find("room_id").and_then(|x| x.as_string()).ok_or_else(|| {
+            BotError::Matrix("join response has no room_id".to_string())
+        })).to_string();
+        Ok(())
+    }
+
+    /// Get our own user id
+    fn whoami(&self) -> Result<String, BotError> {
+        let url = self.url(&["account", "whoami"]);
+        let response = try!(self.call(Method::Get, url, None));
+        response.find("user_id").and_then(|x| x.as_string()).map(String::from).ok_or_else(|| {
+            BotError::Matrix("whoami response has no user_id".to_string())
+        })
+    }
+
+    fn sync(&self, since: Option<&str>, timeout_millis: u64) -> Result<Json, BotError> {
+        let filter = format!(r#"{{"room":{{"rooms":[{}],"timeline":{{"types":["m.room.message"]}}}},"presence":{{"types":[]}},"account_data":{{"types":[]}}}}"#,
+                             self.room_id.to_json());
+        let mut url = self.url(&["sync"]);
+        {
+            let mut query = url.query_pairs_mut();
+            query.append_pair("filter", &filter);
+            query.append_pair("timeout", &timeout_millis.to_string());
+            if let Some(since) = since {
+                query.append_pair("since", since);
+            }
+        }
+        self.call(Method::Get, url, None)
+    }
+
+    fn send_notice(&self, text: &str) -> Result<(), BotError> {
+        let txn_id = format!("{}-{}", self.txn_prefix, self.txn_counter.fetch_add(1, Ordering::SeqCst));
+        let url = self.url(&["rooms", &self.room_id, "send", "m.room.message", &txn_id]);
+        let body = format!(r#"{{"msgtype":"m.notice","body":{}}}"#, text.to_json());
+        self.call(Method::Put, url, Some(body)).map(|_| ())
+    }
+
+    /// Make the url for a client-server API endpoint, the segments are percent-encoded
+    fn url(&self, segments: &[&str]) -> Url {
+        let mut url = self.homeserver.clone();
+        url.path_segments_mut()
+           .expect("homeserver url cannot be a base")
+           .pop_if_empty()
+           .extend(&["_matrix", "client", "v3"])
+           .extend(segments);
+        url
+    }
+
+    fn call(&self, method: Method, url: Url, body: Option<String>) -> Result<Json, BotError> {
+        debug!("matrix: {} {}", method, url.path());
+        let mut request = self.http.request(method, url)
+            .header(Authorization(Bearer { token: self.access_token.clone() }))
+            .header(ContentType::json());
+        if let Some(ref body) = body {
+            request = request.body(body.as_str());
+        }
+        let mut response = try!(request.send());
+        let json = try!(Json::from_reader(&mut response));
+        if !response.status.is_success() {
+            let msg = json.find("error").and_then(|x| x.as_string()).unwrap_or("unknown error");
+            return Err(BotError::Matrix(format!("{} ({})", msg, response.status)));
+        }
+        Ok(json)
+    }
+}
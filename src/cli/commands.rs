@@ -0,0 +1 @@
+../commands.rs
\ No newline at end of file
@@ -0,0 +1,495 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{Error as IOError, Read, Write};
+use std::path::Path;
+use std::process;
+
+use docopt::Docopt;
+use strsim::levenshtein;
+use toml::{Parser, Value};
+
+use aliases;
+use paths;
+use super::COMMANDS;
+
+#[derive(Debug, RustcDecodable)]
+pub struct Args {
+    cmd_check: bool,
+    cmd_get: bool,
+    cmd_set: bool,
+    arg_key: String,
+    arg_value: String,
+}
+
+pub const USAGE: &'static str = "
+Check or change the configuration file
+
+`get` prints the value of KEY, and `set` changes it to VALUE, e.g.
+`maruska config set clock 24h`, `maruska config set actions.s \"xdg-open {url}\"`
+or `maruska config set aliases.q quit`.
+The value is checked first, and the rest of the file is kept as it is,
+comments included.
+
+`check` reads the configuration file of the terminal UI and tells which keys it
+does not know, which values have the wrong type or are not one of the choices,
+and which keys are deprecated. It fails if there are errors, i.e. settings that
+the terminal UI would ignore. The terminal UI only logs these problems, so this
+way an edit can be tested without starting it.
+
+Usage:
+  maruska config check
+  maruska config get <key>
+  maruska config set <key> <value>
+  maruska config (-h | --help)
+
+Options:
+  -h --help  Display this message
+";
+
+/// What the value of a key should look like
+enum Kind {
+    Bool,
+    /// A whole number of at least this much
+    Integer(i64),
+    /// A decimal number from the first to the second number
+    Float(f64, f64),
+    Str,
+    /// One of these strings
+    Choice(&'static [&'static str]),
+    /// A table of single characters to commands, the `[actions]`
+    Actions,
+    /// A table of words to commands, the `[aliases]`
+    Aliases,
+}
+
+/// The keys of the configuration file, see `Config::from_table` in the terminal UI
+///
+/// `clock`, `eta` and `match_threshold` are read by maruska-cli as well.
+/// The `version` key of the file is checked separately.
+const KEYS: [(&'static str, Kind); 25] = [
+    ("restore_session", Kind::Bool),
+    ("encrypt_credentials", Kind::Bool),
+    ("media_sync_interval", Kind::Integer(0)),
+    ("idle_minutes", Kind::Integer(0)),
+    ("session_summary", Kind::Bool),
+    ("local_search", Kind::Bool),
+    ("clock", Kind::Choice(&["24h", "12h", "locale"])),
+    ("eta", Kind::Choice(&["duration", "time"])),
+    ("preview_command", Kind::Str),
+    ("preview_seconds", Kind::Integer(1)),
+    ("cover_art", Kind::Choice(&["auto", "kitty", "sixel", "none"])),
+    ("cover_art_lookup", Kind::Bool),
+    ("lyrics_command", Kind::Str),
+    ("notify_next_up", Kind::Bool),
+    ("notify_watchlist", Kind::Bool),
+    ("quiet_hours", Kind::Str),
+    ("toasts", Kind::Bool),
+    ("search_order", Kind::Choice(&["server", "popular", "relevance"])),
+    ("match_threshold", Kind::Float(0.0, 1.0)),
+    ("command_fifo", Kind::Bool),
+    ("split_layout", Kind::Bool),
+    ("header", Kind::Str),
+    ("status", Kind::Str),
+    ("actions", Kind::Actions),
+    ("aliases", Kind::Aliases),
+];
+
+/// Keys that are still accepted, but should be replaced by the second key
+const DEPRECATED_KEYS: [(&'static str, &'static str); 0] = [];
+
+/// A problem in the configuration file
+#[derive(Debug, PartialEq)]
+pub enum Problem {
+    /// The terminal UI ignores the setting
+    Error(String),
+    /// The setting works, but probably not as intended
+    Warning(String),
+}
+
+pub fn main(argv: Vec<String>, global_args: super::Args) {
+    let args: Args = Docopt::new(USAGE)
+        .map(|d| d.help(true))
+        .map(|d| d.argv(argv))
+        .and_then(|d| d.decode())
+        .unwrap_or_else(|e| e.exit());
+    execute(args, global_args);
+}
+
+pub fn execute(args: Args, _: super::Args) {
+    let path = paths::config_file().unwrap_or_else(|| {
+        println!("Could not find a config directory");
+        process::exit(1);
+    });
+    let mut contents = String::new();
+    if !path.exists() && !args.cmd_set {
+        println!("There is no configuration file at {}, the defaults are used", path.display());
+        process::exit(if args.cmd_check { 0 } else { 1 });
+    }
+    if let Err(err) = read_file(&path, &mut contents) {
+        println!("Could not read {}: {}", path.display(), err);
+        process::exit(1);
+    }
+    if args.cmd_get {
+        get(&contents, &args.arg_key);
+    } else if args.cmd_set {
+        set(&path, &contents, &args.arg_key, &args.arg_value);
+    } else {
+        check_file(&path, &contents);
+    }
+}
+
+/// Print the value of `key` in `contents`
+fn get(contents: &str, key: &str) {
+    let table = Parser::new(contents).parse().unwrap_or_else(|| {
+        println!("The configuration file is not valid, see `maruska config check`");
+        process::exit(1);
+    });
+    let value = match key.find('.') {
+        Some(idx) => table.get(&key[..idx]).and_then(|x| x.as_table())
+                          .and_then(|x| x.get(&key[idx + 1..])),
+        None => table.get(key),
+    };
+    match value {
+        // without quotes, so that scripts can use it
+        Some(&Value::String(ref s)) => println!("{}", s),
+        Some(value) => println!("{}", value),
+        None => {
+            println!("{} is not set", key);
+            process::exit(1);
+        },
+    }
+}
+
+/// Set `key` to `value` in the file at `path`, of which `contents` are the contents
+fn set(path: &Path, contents: &str, key: &str, value: &str) {
+    let (section, name, kind) = match key.find('.') {
+        Some(idx) if &key[..idx] == "actions" || &key[..idx] == "aliases" => {
+            (Some(&key[..idx]), &key[idx + 1..], &Kind::Str)
+        },
+        _ => match KEYS.iter().find(|x| x.0 == key) {
+            Some(&(_, Kind::Actions)) => {
+                println!("Set a single action, e.g. actions.s");
+                process::exit(1);
+            },
+            Some(&(_, Kind::Aliases)) => {
+                println!("Set a single alias, e.g. aliases.q");
+                process::exit(1);
+            },
+            Some(&(name, ref kind)) => (None, name, kind),
+            None => {
+                println!("Could not set {}: {}", key, unknown_key(key));
+                process::exit(1);
+            },
+        },
+    };
+    if section == Some("actions") && name.chars().count() != 1 {
+        println!("An action needs a single character as its key");
+        process::exit(1);
+    }
+    if section == Some("aliases") && !aliases::is_valid_name(name) {
+        println!("An alias needs a single word as its name");
+        process::exit(1);
+    }
+    let value = parse_value(kind, value);
+    if let Err(msg) = check_value(kind, &value) {
+        println!("{} {}, not {}", key, msg, value);
+        process::exit(1);
+    }
+
+    let mut table = Parser::new(contents).parse().unwrap_or_else(|| {
+        println!("The configuration file is not valid, see `maruska config check`");
+        process::exit(1);
+    });
+    let new_contents = set_line(contents, section, name, &value);
+    match section {
+        Some(section) => {
+            let mut entries = table.get(section).and_then(|x| x.as_table()).cloned()
+                                   .unwrap_or_default();
+            entries.insert(name.to_string(), value);
+            table.insert(section.to_string(), Value::Table(entries));
+        },
+        None => {
+            table.insert(name.to_string(), value);
+        },
+    }
+    // Lines that we do not understand, like values over several lines, are not edited in place
+    let new_contents = if Parser::new(&new_contents).parse() == Some(table.clone()) {
+        new_contents
+    } else {
+        println!("Could not keep the comments in the file, they are removed");
+        Value::Table(table).to_string()
+    };
+    if let Err(err) = write_file(path, &new_contents) {
+        println!("Could not write {}: {}", path.display(), err);
+        process::exit(1);
+    }
+}
+
+/// What `value` is for a key of `kind`, which is a string unless it looks like the kind
+fn parse_value(kind: &Kind, value: &str) -> Value {
+    let parsed = match *kind {
+        Kind::Bool => value.parse().ok().map(Value::Boolean),
+        Kind::Integer(_) => value.parse().ok().map(Value::Integer),
+        Kind::Float(..) => value.parse().ok().map(Value::Float),
+        _ => None,
+    };
+    parsed.unwrap_or_else(|| Value::String(value.to_string()))
+}
+
+/// `contents` with the line of `key` in `section` (`None` for the top) set to `value`, the line
+/// is added if there is none
+fn set_line(contents: &str, section: Option<&str>, key: &str, value: &Value) -> String {
+    let mut lines: Vec<String> = contents.lines().map(String::from).collect();
+    // The lines of the section, without the header; the top starts out empty
+    let mut range = if section.is_none() { Some((0, 0)) } else { None };
+    let mut current = None;
+    for (i, line) in lines.iter().enumerate() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            current = Some(line.trim_matches(|x| x == '[' || x == ']').trim());
+            if range.is_some() {
+                break;
+            }
+        }
+        if current == section {
+            let start = if section.is_some() { i + 1 } else { i };
+            range = Some((range.map_or(start, |x: (usize, usize)| x.0), i + 1));
+        }
+    }
+    let key_text = if key.chars().all(|x| x.is_alphanumeric() || x == '_' || x == '-') {
+        key.to_string()
+    } else {
+        Value::String(key.to_string()).to_string()
+    };
+    let new_line = format!("{} = {}", key_text, value);
+    let (start, end) = match range {
+        Some(range) => range,
+        None => {
+            // add the section at the end
+            if lines.last().map_or(false, |x| !x.trim().is_empty()) {
+                lines.push(String::new());
+            }
+            lines.push(format!("[{}]", section.unwrap()));
+            lines.push(new_line);
+            return lines.join("\n") + "\n";
+        },
+    };
+    let existing = (start..end).find(|&i| {
+        let line = lines[i].trim();
+        line.find('=').map_or(false, |idx| {
+            let name = line[..idx].trim();
+            name == key || name.trim_matches(|x| x == '"' || x == '\'') == key
+        })
+    });
+    match existing {
+        Some(i) => lines[i] = new_line,
+        None => {
+            // after the last key of the section, so that a comment at its end stays there, or
+            // else after the comments at the top of the file
+            let is_key = |x: &str| x.contains('=') && !x.trim().starts_with('#');
+            let at = (start..end).rev().find(|&i| is_key(&lines[i])).map_or_else(|| {
+                match section {
+                    Some(_) => start,
+                    None => (start..end).rev().find(|&i| !lines[i].trim().is_empty())
+                                        .map_or(start, |i| i + 1),
+                }
+            }, |i| i + 1);
+            lines.insert(at, new_line);
+        },
+    }
+    lines.join("\n") + "\n"
+}
+
+fn read_file(path: &Path, contents: &mut String) -> Result<(), IOError> {
+    if !path.exists() {
+        return Ok(());
+    }
+    File::open(path).and_then(|mut f| f.read_to_string(contents)).map(|_| ())
+}
+
+/// Write `contents` to the file at `path`, by replacing it, so that it is never half written
+fn write_file(path: &Path, contents: &str) -> Result<(), IOError> {
+    if let Some(dir) = path.parent() {
+        try!(fs::create_dir_all(dir));
+    }
+    let tmp = path.with_extension("toml.tmp");
+    try!(File::create(&tmp).and_then(|mut f| f.write_all(contents.as_bytes())));
+    fs::rename(&tmp, path)
+}
+
+/// Print the problems in `contents`, the contents of the file at `path`, and fail if there are
+/// errors
+fn check_file(path: &Path, contents: &str) {
+    let problems = check(contents);
+    if problems.is_empty() {
+        println!("{} is fine", path.display());
+        return;
+    }
+    let mut errors = 0;
+    for problem in &problems {
+        match *problem {
+            Problem::Error(ref msg) => {
+                println!("error: {}", msg);
+                errors += 1;
+            },
+            Problem::Warning(ref msg) => println!("warning: {}", msg),
+        }
+    }
+    if errors > 0 {
+        process::exit(1);
+    }
+}
+
+/// The problems in `contents`, the contents of a configuration file
+pub fn check(contents: &str) -> Vec<Problem> {
+    let mut parser = Parser::new(contents);
+    let table = match parser.parse() {
+        Some(table) => table,
+        None => return parser.errors.iter().map(|err| {
+            let (line, col) = parser.to_linecol(err.lo);
+            Problem::Error(format!("line {}, column {}: {}", line + 1, col + 1, err.desc))
+        }).collect(),
+    };
+    check_table(&table)
+}
+
+fn check_table(table: &BTreeMap<String, Value>) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    for (key, value) in table {
+        if let Some(&(_, new)) = DEPRECATED_KEYS.iter().find(|x| x.0 == key) {
+            problems.push(Problem::Warning(format!("{} is deprecated, use {} instead", key, new)));
+            continue;
+        }
+        // the files of the terminal UI have a version, only version 1 of this one exists
+        if key == "version" {
+            if value.as_integer() != Some(1) {
+                problems.push(Problem::Error(format!("unknown version {}", value)));
+            }
+            continue;
+        }
+        let kind = match KEYS.iter().find(|x| x.0 == key) {
+            Some(&(_, ref kind)) => kind,
+            None => {
+                problems.push(Problem::Warning(unknown_key(key)));
+                continue;
+            },
+        };
+        if let Err(msg) = check_value(kind, value) {
+            problems.push(Problem::Error(format!("{} {}, not {}", key, msg, value)));
+        }
+        if let (&Kind::Actions, Some(actions)) = (kind, value.as_table()) {
+            for (ch, command) in actions {
+                if ch.chars().count() != 1 {
+                    let msg = format!("action {:?} needs a single character as its key", ch);
+                    problems.push(Problem::Error(msg));
+                }
+                if command.as_str().is_none() {
+                    let msg = format!("action {:?} should be a command, not {}", ch, command);
+                    problems.push(Problem::Error(msg));
+                }
+            }
+        }
+        if let (&Kind::Aliases, Some(aliases)) = (kind, value.as_table()) {
+            for (name, command) in aliases {
+                if !aliases::is_valid_name(name) {
+                    let msg = format!("alias {:?} needs a single word as its name", name);
+                    problems.push(Problem::Error(msg));
+                }
+                match command.as_str() {
+                    Some(command) if !command.trim().is_empty() => {},
+                    _ => {
+                        let msg = format!("alias {:?} should be a command, not {}", name, command);
+                        problems.push(Problem::Error(msg));
+                    },
+                }
+                if COMMANDS.iter().any(|x| x.name == name) {
+                    let msg = format!("alias {:?} is ignored by maruska-cli, which has a command \
+                                       with that name", name);
+                    problems.push(Problem::Warning(msg));
+                }
+            }
+        }
+    }
+    problems
+}
+
+/// Does `value` look like `kind`? If not, what it should look like
+fn check_value(kind: &Kind, value: &Value) -> Result<(), String> {
+    match (kind, value) {
+        (&Kind::Bool, &Value::Boolean(_)) => Ok(()),
+        (&Kind::Bool, _) => Err("should be true or false".to_string()),
+        (&Kind::Integer(min), &Value::Integer(x)) if x >= min => Ok(()),
+        (&Kind::Integer(min), _) => Err(format!("should be a whole number of at least {}", min)),
+        (&Kind::Float(min, max), &Value::Float(x)) if x >= min && x <= max => Ok(()),
+        (&Kind::Float(min, max), _) => {
+            Err(format!("should be a decimal number from {:.1} to {:.1}", min, max))
+        },
+        (&Kind::Str, &Value::String(_)) => Ok(()),
+        (&Kind::Str, _) => Err("should be a string".to_string()),
+        (&Kind::Choice(choices), &Value::String(ref x)) if choices.contains(&x.as_str()) => Ok(()),
+        (&Kind::Choice(choices), _) => {
+            let choices: Vec<String> = choices.iter().map(|x| format!("{:?}", x)).collect();
+            Err(format!("should be one of {}", choices.join(", ")))
+        },
+        (&Kind::Actions, &Value::Table(_)) => Ok(()),
+        (&Kind::Actions, _) => Err("should be a table of keys and commands".to_string()),
+        (&Kind::Aliases, &Value::Table(_)) => Ok(()),
+        (&Kind::Aliases, _) => Err("should be a table of names and commands".to_string()),
+    }
+}
+
+/// The warning for a key that we do not know, which suggests a known key that looks like it
+fn unknown_key(key: &str) -> String {
+    let closest = KEYS.iter()
+        .map(|x| x.0)
+        .map(|x| (x, levenshtein(key, x)))
+        .min_by_key(|x| x.1);
+    match closest {
+        Some((closest, dist)) if dist <= 3 => {
+            format!("unknown key {}, did you mean {}?", key, closest)
+        },
+        _ => format!("unknown key {}", key),
+    }
+}
+
+
+#[test]
+fn test() {
+    let input = "# the clock\nclock = \"12h\"\n\n[actions]\ns = \"true\" # search\n\n# the end\n";
+    assert_eq!(set_line(input, None, "clock", &Value::String("24h".to_string())),
+               "# the clock\nclock = \"24h\"\n\n[actions]\ns = \"true\" # search\n\n# the end\n");
+    assert_eq!(set_line(input, None, "toasts", &Value::Boolean(false)),
+               "# the clock\nclock = \"12h\"\ntoasts = false\n\n[actions]\ns = \"true\" # search\n\n\
+                # the end\n");
+    assert_eq!(set_line(input, Some("actions"), ".", &Value::String("ls".to_string())),
+               "# the clock\nclock = \"12h\"\n\n[actions]\ns = \"true\" # search\n\".\" = \"ls\"\n\n\
+                # the end\n");
+    assert_eq!(set_line("clock = \"12h\"", Some("actions"), "s", &Value::String("ls".to_string())),
+               "clock = \"12h\"\n\n[actions]\ns = \"ls\"\n");
+    assert_eq!(set_line("", None, "eta", &Value::String("time".to_string())), "eta = \"time\"\n");
+    assert_eq!(parse_value(&Kind::Integer(0), "5"), Value::Integer(5));
+    assert_eq!(parse_value(&Kind::Bool, "yes"), Value::String("yes".to_string()));
+
+    assert_eq!(check("restore_session = true\nclock = \"24h\"\nmatch_threshold = 0.9\n\
+                      version = 1\n[actions]\ns = \"xdg-open {url}\"\n"),
+               []);
+    assert_eq!(check("restore_session = \"yes\""),
+               [Problem::Error("restore_session should be true or false, not \"yes\"".to_string())]);
+    assert_eq!(check("preview_seconds = 0\ncover_art = \"ascii\""),
+               [Problem::Error("cover_art should be one of \"auto\", \"kitty\", \"sixel\", \
+                                \"none\", not \"ascii\"".to_string()),
+                Problem::Error("preview_seconds should be a whole number of at least 1, \
+                                not 0".to_string())]);
+    assert_eq!(check("toast = false"),
+               [Problem::Warning("unknown key toast, did you mean toasts?".to_string())]);
+    assert_eq!(check("[actions]\nyy = \"true\""),
+               [Problem::Error("action \"yy\" needs a single character as its key".to_string())]);
+    assert_eq!(check("[aliases]\nq = \"quit\"\nqueue = \"queue\"\n\"a b\" = \"skip\""),
+               [Problem::Error("alias \"a b\" needs a single word as its name".to_string()),
+                Problem::Warning("alias \"queue\" is ignored by maruska-cli, which has a \
+                                  command with that name".to_string())]);
+    match &check("clock = \n")[..] {
+        [Problem::Error(ref msg)] => assert!(msg.starts_with("line 1, column"), "{}", msg),
+        other => panic!("{:?}", other),
+    }
+}
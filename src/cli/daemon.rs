@@ -0,0 +1,356 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use docopt::Docopt;
+use hyper::Url;
+use hyper::header::{Authorization, Bearer, ContentType};
+use hyper::method::Method;
+use hyper::server::{Handler, Request as HttpRequest, Response, Server};
+use hyper::status::StatusCode;
+use hyper::uri::RequestUri;
+use rand::{OsRng, Rng};
+use rustc_serialize::hex::ToHex;
+use rustc_serialize::json::{Json, ToJson};
+
+use libclient::{Client, DisplayChain, Media, RequestStatus};
+use hooks::Hooks;
+use instance::{self, DaemonInfo};
+use mpris;
+use paths;
+use serialize::{media_json, playing_json, request_json};
+
+#[derive(Debug, RustcDecodable)]
+pub struct Args {
+    flag_listen: String,
+    flag_token: Option<String>,
+    flag_web: bool,
+    flag_media_keys: bool,
+}
+
+pub const USAGE: &'static str = "
+Stay connected to the server and serve a JSON API on a local port
+
+Usage:
+  maruska daemon [options]
+
+Options:
+  -l --listen ADDR  Address to listen on [default: 127.0.0.1:6780]
+  -t --token TOKEN  Token that clients must send to use the API (default: random)
+  -w --web          Also serve a web interface on /
+  -m --media-keys   Vote to skip with the Next media key (see below)
+  -h --help         Display this message
+
+API:
+  Every request must carry the token, either in an `Authorization: Bearer TOKEN`
+  header or in a `token` query parameter. All responses are JSON; errors look
+  like {\"error\": \"message\"}.
+
+  GET  /playing           The song that is currently playing (or null)
+  GET  /queue             The request queue
+  GET  /search?q=QUERY    Search for songs (optional: count=N, default 100)
+  POST /request           Request a song, body: {\"key\": \"MEDIA KEY\"}
+  POST /skip              Skip the current song (if the server allows us to)
+
+Media keys:
+  With `--media-keys`, the daemon shows up as a media player (over MPRIS), so
+  that the media keys of the keyboard control it. Next votes to skip the song
+  that is playing. The music cannot be paused, so Play/Pause pauses following
+  the server instead, until it is pressed again.
+";
+
+const INDEX_HTML: &'static [u8] = include_bytes!("web/index.html");
+
+/// The maximum amount of search results that may be requested at once
+const MAX_SEARCH_COUNT: usize = 1000;
+
+pub fn main(argv: Vec<String>, global_args: super::Args) {
+    let args: Args = Docopt::new(USAGE)
+        .map(|d| d.help(true))
+        .map(|d| d.argv(argv))
+        .and_then(|d| d.decode())
+        .unwrap_or_else(|e| e.exit());
+    execute(args, global_args);
+}
+
+pub fn execute(args: Args, global_args: super::Args) {
+    let token = args.flag_token.clone().unwrap_or_else(generate_token);
+    let info = DaemonInfo {
+        host: global_args.flag_host.clone(),
+        listen: args.flag_listen.clone(),
+        token: token.clone(),
+    };
+    let daemon_lock = match instance::lock_daemon(&info) {
+        Ok(lock) => lock,
+        Err(_) => {
+            match instance::running_daemon() {
+                Some(daemon) => println!("A daemon is already running on http://{}/", daemon.listen),
+                None => println!("A daemon is already running"),
+            }
+            process::exit(1);
+        },
+    };
+
+    let (mut client, client_r) = Client::new(&global_args.flag_host).unwrap();
+    client.set_audit_log(paths::audit_file());
+    client.follow_all();
+    client.serve();
+    if !global_args.flag_username.is_empty() && !global_args.flag_password.is_empty() {
+        let hash = client.hash_password(&global_args.flag_password);
+        client.do_login(&global_args.flag_username, &hash);
+    }
+
+    let client = Arc::new(Mutex::new(client));
+    let daemon = Daemon {
+        client: client.clone(),
+        search_lock: Mutex::new(()),
+        token: token.clone(),
+        web: args.flag_web,
+    };
+    let _listening = Server::http(&args.flag_listen[..])
+        .and_then(|server| server.handle(daemon))
+        .unwrap_or_else(|err| panic!("could not listen on {}: {}", args.flag_listen, err));
+    println!("Listening on http://{}/", args.flag_listen);
+    if args.flag_token.is_none() {
+        println!("API token: {}", token);
+    }
+    if args.flag_web {
+        println!("Web interface: http://{}/#token={}", args.flag_listen, token);
+    }
+    if args.flag_media_keys {
+        if let Err(err) = mpris::serve(client.clone()) {
+            println!("Could not use the media keys: {}", err);
+            process::exit(1);
+        }
+    }
+
+    let mut hooks = Hooks::new(paths::hooks_dir());
+    while let Some(message) = client_r.recv() {
+        let mut client = client.lock().unwrap();
+        match client.handle_message(&message) {
+            Ok(message) => hooks.handle_message(&client, &message, false),
+            Err(err) => error!("{}", DisplayChain(&err)),
+        }
+    }
+    error!("the connection to the server was lost");
+    // `process::exit` does not run destructors, so let another daemon start now
+    drop(daemon_lock);
+    process::exit(1);
+}
+
+struct Daemon {
+    client: Arc<Mutex<Client>>,
+
+    /// The client only keeps track of one search query, so we can only do one search at a time
+    search_lock: Mutex<()>,
+
+    /// The token that API clients have to provide
+    token: String,
+
+    /// Are we serving the web interface?
+    web: bool,
+}
+
+type ApiResult = Result<Json, (StatusCode, String)>;
+
+impl Handler for Daemon {
+    fn handle(&self, mut req: HttpRequest, res: Response) {
+        let url = match req.uri {
+            RequestUri::AbsolutePath(ref path) => Url::parse(&format!("http://localhost{}", path)),
+            _ => return respond(res, Err((StatusCode::BadRequest, "bad request uri".to_string()))),
+        };
+        let url = match url {
+            Ok(url) => url,
+            Err(_) => return respond(res, Err((StatusCode::BadRequest, "bad request uri".to_string()))),
+        };
+        debug!("{} {}", req.method, url);
+
+        match (&req.method, url.path()) {
+            (&Method::Get, "/") | (&Method::Get, "/index.html") if self.web => {
+                // The web interface itself is public, it gets the token from the url fragment
+                let mut res = res;
+                res.headers_mut().set(ContentType::html());
+                if let Err(err) = res.send(INDEX_HTML) {
+                    warn!("could not send response: {}", err);
+                }
+                return;
+            },
+            _ => {},
+        }
+
+        if !self.is_authorized(&req, &url) {
+            let mut res = res;
+            res.headers_mut().set_raw("WWW-Authenticate", vec![b"Bearer".to_vec()]);
+            return respond(res, Err((StatusCode::Unauthorized, "invalid token".to_string())));
+        }
+
+        let result = match (&req.method, url.path()) {
+            (&Method::Get, "/playing") => self.get_playing(),
+            (&Method::Get, "/queue") => self.get_queue(),
+            (&Method::Get, "/search") => self.get_search(&url),
+            (&Method::Post, "/request") => {
+                let mut body = String::new();
+                match req.read_to_string(&mut body) {
+                    Ok(_) => self.post_request(&body),
+                    Err(_) => Err((StatusCode::BadRequest, "could not read body".to_string())),
+                }
+            },
+            (&Method::Post, "/skip") => self.post_skip(),
+            _ => Err((StatusCode::NotFound, "not found".to_string())),
+        };
+        respond(res, result)
+    }
+}
+
+impl Daemon {
+    fn is_authorized(&self, req: &HttpRequest, url: &Url) -> bool {
+        if let Some(&Authorization(Bearer { ref token })) = req.headers.get() {
+            return constant_time_eq(token.as_bytes(), self.token.as_bytes());
+        }
+        url.query_pairs()
+           .find(|&(ref key, _)| key == "token")
+           .map_or(false, |(_, token)| constant_time_eq(token.as_bytes(), self.token.as_bytes()))
+    }
+
+    fn get_playing(&self) -> ApiResult {
+        let client = self.client.lock().unwrap();
+        Ok(client.get_playing().as_ref().map_or(Json::Null, playing_json))
+    }
+
+    fn get_queue(&self) -> ApiResult {
+        let client = self.client.lock().unwrap();
+        match *client.get_requests() {
+            Some(ref requests) => Ok(Json::Array(requests.iter().map(request_json).collect())),
+            None => Err((StatusCode::ServiceUnavailable, "queue not received yet".to_string())),
+        }
+    }
+
+    fn get_search(&self, url: &Url) -> ApiResult {
+        let mut query = None;
+        let mut count = 100;
+        for (key, value) in url.query_pairs() {
+            match &key[..] {
+                "q" => query = Some(value.into_owned()),
+                "count" => count = try!(value.parse::<usize>().map_err(|_| {
+                    (StatusCode::BadRequest, "count is not a number".to_string())
+                })),
+                _ => {},
+            }
+        }
+        let query = try!(query.ok_or((StatusCode::BadRequest, "missing q parameter".to_string())));
+        let count = ::std::cmp::min(count, MAX_SEARCH_COUNT);
+
+        let _search_guard = self.search_lock.lock().unwrap();
+        match search(&self.client, &query, count) {
+            Some(results) => Ok(Json::Array(results.iter().map(media_json).collect())),
+            None => Err((StatusCode::GatewayTimeout, "server did not send results in time".to_string())),
+        }
+    }
+
+    fn post_request(&self, body: &str) -> ApiResult {
+        let body = try!(Json::from_str(body).map_err(|_| {
+            (StatusCode::BadRequest, "body is not valid json".to_string())
+        }));
+        let key = try!(body.find("key").and_then(|x| x.as_string()).ok_or_else(|| {
+            (StatusCode::BadRequest, "missing key string".to_string())
+        }));
+        Ok(status_json(self.client.lock().unwrap().do_request_from_key(key)))
+    }
+
+    /// Skip the song that is playing, which usually only moderators may do
+    fn post_skip(&self) -> ApiResult {
+        Ok(status_json(self.client.lock().unwrap().do_skip()))
+    }
+}
+
+/// What the API answers once a request or a skip is sent (or waits for a login)
+fn status_json(status: RequestStatus) -> Json {
+    let status = match status {
+        RequestStatus::Ok => "ok",
+        RequestStatus::Deferred => "deferred",
+        RequestStatus::Queued => "queued",
+    };
+    let mut obj = BTreeMap::new();
+    obj.insert("status".to_string(), status.to_json());
+    Json::Object(obj)
+}
+
+fn respond(mut res: Response, result: ApiResult) {
+    let body = match result {
+        Ok(json) => json,
+        Err((status, msg)) => {
+            *res.status_mut() = status;
+            let mut obj = BTreeMap::new();
+            obj.insert("error".to_string(), msg.to_json());
+            Json::Object(obj)
+        },
+    };
+    res.headers_mut().set(ContentType::json());
+    if let Err(err) = res.send(body.to_string().as_bytes()) {
+        warn!("could not send response: {}", err);
+    }
+}
+
+/// How long to wait for search results from the server
+const SEARCH_TIMEOUT_MILLIS: u64 = 5000;
+
+/// Search for `query`, and wait until we have `count` results or the server has no more
+///
+/// Another thread has to pass the incoming messages to the client in the meantime. Returns
+/// `None` if the server did not send the results in time.
+pub fn search(client: &Mutex<Client>, query: &str, count: usize) -> Option<Vec<Media>> {
+    client.lock().unwrap().update_query(Some(query), count);
+    for _ in 0..SEARCH_TIMEOUT_MILLIS / 50 {
+        {
+            let client = client.lock().unwrap();
+            let (results, done) = client.get_qm_results();
+            if *done || results.len() >= count {
+                return Some(results.iter().take(count).cloned().collect());
+            }
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    None
+}
+
+/// The number of random bytes in a generated token, which is twice as long in hex
+const TOKEN_LEN: usize = 16;
+
+/// Generate a random token, to use when the user did not provide one
+fn generate_token() -> String {
+    let mut rng = OsRng::new().unwrap_or_else(|err| panic!("could not generate a token: {}", err));
+    let mut token = [0; TOKEN_LEN];
+    rng.fill_bytes(&mut token);
+    token.to_hex()
+}
+
+/// Compare two byte strings, in time that does not depend on where they differ
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+
+#[test]
+fn test() {
+    let (client, _client_r, _offline) = Client::offline("test");
+    let daemon = Daemon {
+        client: Arc::new(Mutex::new(client)),
+        search_lock: Mutex::new(()),
+        token: "token".to_string(),
+        web: false,
+    };
+    // the skip waits until we are logged in
+    assert_eq!(daemon.post_skip().unwrap().find("status").and_then(|x| x.as_string()),
+               Some("deferred"));
+    assert!(daemon.post_request("{}").is_err());
+    assert!(constant_time_eq(b"token", b"token"));
+    assert!(!constant_time_eq(b"token", b"tokem"));
+    assert_eq!(generate_token().len(), 2 * TOKEN_LEN);
+    assert!(generate_token() != generate_token());
+}
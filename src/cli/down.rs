@@ -0,0 +1,32 @@
+use docopt::Docopt;
+
+#[derive(Debug, RustcDecodable)]
+pub struct Args {
+    arg_request: i64,
+}
+
+pub const USAGE: &'static str = "
+Move a request down in the queue
+
+REQUEST is the key of the request. You can move your own requests, moderators
+can move the requests of everyone.
+
+Usage:
+  maruska down [options] <request>
+
+Options:
+  -h --help     Display this message
+";
+
+pub fn main(argv: Vec<String>, global_args: super::Args) {
+    let args: Args = Docopt::new(USAGE)
+        .map(|d| d.help(true))
+        .map(|d| d.argv(argv))
+        .and_then(|d| d.decode())
+        .unwrap_or_else(|e| e.exit());
+    execute(args, global_args);
+}
+
+pub fn execute(args: Args, global_args: super::Args) {
+    super::up::move_request(args.arg_request, Some(1), global_args);
+}
@@ -0,0 +1 @@
+../format.rs
\ No newline at end of file
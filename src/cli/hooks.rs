@@ -0,0 +1 @@
+../hooks.rs
\ No newline at end of file
@@ -0,0 +1,131 @@
+//! Detection of a running daemon, so that commands can be forwarded to it instead of opening
+//! another session with the server.
+//!
+//! The running daemon holds an exclusive lock on `daemon.lock` in the state directory, and
+//! describes how to reach it in `daemon.json`. The operating system releases the lock when the
+//! daemon exits, so a crashed daemon is not mistaken for a running one.
+
+use std::fs;
+use std::io::{Read, Write};
+
+use fs2::{FileExt, lock_contended_error};
+use hyper;
+use hyper::header::{Authorization, Bearer};
+use rustc_serialize::json::{self, Json};
+
+use paths;
+
+#[derive(Debug, RustcDecodable, RustcEncodable)]
+pub struct DaemonInfo {
+    /// The marietje server that the daemon is connected to
+    pub host: String,
+    /// The address on which the daemon serves its API
+    pub listen: String,
+    pub token: String,
+}
+
+/// Held by the running daemon, the lock is released when this is dropped
+pub struct DaemonLock {
+    _file: fs::File,
+}
+
+/// Another daemon is already running
+#[derive(Debug)]
+pub struct AlreadyRunning;
+
+/// Register this process as the running daemon
+///
+/// If the lock file cannot be used at all, we cannot tell whether another daemon is running, so
+/// we assume that there is none and return `Ok(None)`.
+pub fn lock_daemon(info: &DaemonInfo) -> Result<Option<DaemonLock>, AlreadyRunning> {
+    let state_dir = match paths::state_dir() {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let lock_filename = state_dir.join("daemon.lock");
+    let file = match fs::create_dir_all(&state_dir).and_then(|_| {
+        fs::OpenOptions::new().write(true).create(true).open(&lock_filename)
+    }) {
+        Ok(file) => file,
+        Err(err) => {
+            warn!("could not open {:?}: {}", lock_filename, err);
+            return Ok(None);
+        },
+    };
+    match file.try_lock_exclusive() {
+        Ok(()) => {},
+        Err(ref err) if err.kind() == lock_contended_error().kind() => return Err(AlreadyRunning),
+        Err(err) => {
+            warn!("could not lock {:?}: {}", lock_filename, err);
+            return Ok(None);
+        },
+    }
+
+    // The info file may contain the token, so only we should be able to read it
+    let info_filename = state_dir.join("daemon.json");
+    let mut open_options = fs::OpenOptions::new();
+    open_options.write(true).truncate(true).create(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        open_options.mode(0o600);
+    }
+    let written = open_options.open(&info_filename).and_then(|mut f| {
+        write!(f, "{}", json::encode(info).unwrap())
+    });
+    if let Err(err) = written {
+        warn!("could not write {:?}: {}", info_filename, err);
+    }
+    Ok(Some(DaemonLock { _file: file }))
+}
+
+/// Find the running daemon, if there is one
+pub fn running_daemon() -> Option<DaemonInfo> {
+    let state_dir = match paths::state_dir() {
+        Some(x) => x,
+        None => return None,
+    };
+    let lock_file = match fs::File::open(state_dir.join("daemon.lock")) {
+        Ok(file) => file,
+        Err(_) => return None,
+    };
+    match FileExt::try_lock_shared(&lock_file) {
+        Err(ref err) if err.kind() == lock_contended_error().kind() => {},
+        _ => return None, // nobody holds the lock, so no daemon is running
+    }
+    let mut s = String::new();
+    if fs::File::open(state_dir.join("daemon.json")).and_then(|mut f| f.read_to_string(&mut s))
+                                                    .is_err() {
+        return None;
+    }
+    json::decode(&s).ok()
+}
+
+/// Do a GET request on the API of the daemon that is connected to `host`, if it is running
+///
+/// Returns `None` if there is no such daemon, or if the request failed.
+pub fn forward(host: &str, path: &str) -> Option<Json> {
+    let daemon = match running_daemon() {
+        Some(daemon) => daemon,
+        None => return None,
+    };
+    if daemon.host != host {
+        return None;
+    }
+    let url = format!("http://{}{}", daemon.listen, path);
+    debug!("forwarding to the daemon: GET {}", url);
+    let mut res = match hyper::Client::new()
+        .get(&url)
+        .header(Authorization(Bearer { token: daemon.token.clone() }))
+        .send() {
+        Ok(res) => res,
+        Err(err) => {
+            warn!("could not reach the daemon: {}", err);
+            return None;
+        },
+    };
+    if !res.status.is_success() {
+        return None;
+    }
+    Json::from_reader(&mut res).ok()
+}
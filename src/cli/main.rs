@@ -1,86 +1,227 @@
+#[macro_use] extern crate chan;
+#[cfg(feature = "mpris")] extern crate dbus;
+extern crate dirs;
 extern crate docopt;
 extern crate env_logger;
+extern crate fs2;
+extern crate hyper;
+#[cfg(unix)] extern crate libc;
 extern crate libclient;
 #[macro_use] extern crate log;
+extern crate rand;
+extern crate regex;
 extern crate rustc_serialize;
 extern crate strsim;
+extern crate time;
+extern crate toml;
 
+mod aliases;
+mod audit;
+#[cfg(feature = "matrix")]
+mod bot;
+mod commands;
+mod config;
+mod daemon;
+mod debug_bundle;
+mod down;
+mod format;
+mod hooks;
+mod instance;
+mod man;
+mod mpris;
+#[allow(dead_code)]
+mod paths;
+mod picker;
 mod playing;
+mod plugins;
 mod queue;
+mod remove;
+mod report;
+mod request;
+mod search;
+mod serialize;
+mod skip;
+mod stats;
+mod status;
+mod sync;
+mod template;
+mod uploads;
+mod up;
 mod utils;
+mod watchlist;
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
 
 use docopt::{Docopt, Error as DocoptError};
-use strsim::levenshtein;
-use utils::show_version_and_exit;
+
+use commands::Resolved;
+use format::TimeFormat;
+use libclient::md5;
+use utils::{current_user, init_logger, show_version_and_exit};
 
 const USAGE: &'static str = "
 Usage:
-  maruska-cli --host=HOST <command> [<args>...]
+  maruska-cli [options] --host=HOST <command> [<args>...]
+  maruska-cli gen-man [<args>...]
+  maruska-cli [options] config [<args>...]
+  maruska-cli [options] debug-bundle [<args>...]
   maruska-cli [options]
 
 Options:
   -v --verbose          Use verbose output
   -H --host HOST        Hostname of marietje server
-  -u --username USER    Use a different username (than `whoami`)
+  -u --username USER    Use a different username (than the current user)
   -p --password PASSWD  Provide a password on the command line
   -y --yes              Run non-interactively (assume yes)
+  --log-level LEVEL     Log messages of at least LEVEL (error, warn, info, debug or trace)
+  --log-file FILE       Append log messages to FILE instead of printing them
+  --trace-protocol      Log every packet that is exchanged with the server
   -h --help             Display this message
+  --help-all            Display this message and the help of all commands
   --version             Print version info and exit
-
-Commands:
-  playing      Get the currently playing song
-  queue        List the current queue
-  search       Search the songs list for a particular query
-  request      Request playback one or more songs
-  skip         Skip the currenly playing song (alias for `maruska remove 0`)
-  remove       Cancel a song from the queue
-  up           Move a song up in the queue
-  down         Move a song down in the queue
-  help         Get some help with another command
 ";
 
-const COMMANDS: [&'static str; 9] = [
-    "playing",
-    "queue",
-    "search",
-    "request",
-    "skip",
-    "remove",
-    "up",
-    "down",
-    "help",
+pub struct Command {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// The usage message of this command, if it is implemented
+    pub usage: Option<&'static str>,
+}
+
+#[cfg(feature = "matrix")]
+const BOT_USAGE: Option<&'static str> = Some(bot::USAGE);
+#[cfg(not(feature = "matrix"))]
+const BOT_USAGE: Option<&'static str> = None;
+
+static COMMANDS: [Command; 21] = [
+    Command { name: "playing", description: "Get the currently playing song",
+              usage: Some(playing::USAGE) },
+    Command { name: "queue", description: "List the current queue",
+              usage: Some(queue::USAGE) },
+    Command { name: "status", description: "Print a line about what is playing",
+              usage: Some(status::USAGE) },
+    Command { name: "search", description: "Search the songs list for a particular query",
+              usage: Some(search::USAGE) },
+    Command { name: "request", description: "Request playback one or more songs",
+              usage: Some(request::USAGE) },
+    Command { name: "skip", description: "Skip the currently playing song",
+              usage: Some(skip::USAGE) },
+    Command { name: "remove", description: "Cancel a song from the queue",
+              usage: Some(remove::USAGE) },
+    Command { name: "up", description: "Move a song up in the queue",
+              usage: Some(up::USAGE) },
+    Command { name: "down", description: "Move a song down in the queue",
+              usage: Some(down::USAGE) },
+    Command { name: "help", description: "Get some help with another command",
+              usage: None },
+    Command { name: "report", description: "Report a song with wrong tags or a broken file",
+              usage: Some(report::USAGE) },
+    Command { name: "uploads", description: "List the songs that someone uploaded",
+              usage: Some(uploads::USAGE) },
+    Command { name: "sync", description: "Download the media index into the local cache",
+              usage: Some(sync::USAGE) },
+    Command { name: "stats", description: "Show how often songs were requested and played",
+              usage: Some(stats::USAGE) },
+    Command { name: "watchlist", description: "Manage the searches of which new songs are shown",
+              usage: Some(watchlist::USAGE) },
+    Command { name: "audit", description: "Show what this machine asked the server to change",
+              usage: Some(audit::USAGE) },
+    Command { name: "daemon", description: "Stay connected and serve a local JSON API",
+              usage: Some(daemon::USAGE) },
+    Command { name: "bot", description: "Announce songs and take requests in a chat room",
+              usage: BOT_USAGE },
+    Command { name: "config", description: "Check or change the configuration file",
+              usage: Some(config::USAGE) },
+    Command { name: "debug-bundle", description: "Gather what is needed for a bug report",
+              usage: Some(debug_bundle::USAGE) },
+    Command { name: "gen-man", description: "Print a roff man page for maruska-cli",
+              usage: Some(man::USAGE) },
 ];
 
 #[derive(Debug, RustcDecodable)]
 pub struct Args {
     arg_command: Option<String>,
     arg_args: Vec<String>,
+    cmd_gen_man: bool,
+    cmd_config: bool,
+    cmd_debug_bundle: bool,
     flag_help: bool,
+    flag_help_all: bool,
     flag_version: bool,
     flag_verbose: bool,
     flag_host: String,
     flag_username: String,
     flag_password: String,
     flag_yes: bool,
+    flag_log_level: Option<String>,
+    flag_log_file: Option<String>,
+    flag_trace_protocol: bool,
 }
 
 
 pub fn main() {
-    if let Err(err) = env_logger::init() {
-        panic!("Failed to initialize logger: {}", err);
-    }
-
-    let args: Args = Docopt::new(USAGE)
+    let mut args: Args = Docopt::new(usage())
         .map(|d| d.options_first(true))
         .map(|d| d.help(true))
         .and_then(|d| d.decode())
         .unwrap_or_else(|e| e.exit());
 
+    if let Err(err) = init_logger(args.flag_log_level.as_ref().map(|x| x.as_str()),
+                                  args.flag_log_file.as_ref().map(|x| x.as_str()),
+                                  args.flag_trace_protocol) {
+        panic!("Failed to initialize logger: {}", err);
+    }
+
     if args.flag_version {
         show_version_and_exit();
     }
+    if args.flag_help_all {
+        print!("{}", man::long_help());
+        return;
+    }
 
-    match &args.arg_command.clone().unwrap()[..] {
+    let command = if args.cmd_gen_man {
+        String::from("gen-man")
+    } else if args.cmd_config {
+        String::from("config")
+    } else if args.cmd_debug_bundle {
+        String::from("debug-bundle")
+    } else {
+        args.arg_command.clone().unwrap()
+    };
+    let command = match resolve_alias(&command) {
+        Some(mut words) => {
+            // the words of the alias go before the arguments that were given
+            let command = words.remove(0);
+            words.extend(args.arg_args.drain(..));
+            args.arg_args = words;
+            command
+        },
+        None => command,
+    };
+    let names: Vec<&str> = COMMANDS.iter().map(|x| x.name).collect();
+    // a plugin goes before a built-in command that its name is only the start of
+    if !names.contains(&command.as_str()) {
+        if let Some(path) = plugins::find(&command) {
+            plugins::run(&path, &args);
+        }
+    }
+    let command = match commands::resolve(&names, &command) {
+        Resolved::Command(name) => name,
+        Resolved::Ambiguous(matching) => {
+            let matching: Vec<String> = matching.iter().map(|x| format!("'{}'", x)).collect();
+            command_error(format!("Ambiguous subcommand: '{}'. Did you mean {}?", command,
+                                  commands::or_list(&matching)))
+        },
+        Resolved::Unknown(Some(other_command)) => {
+            command_error(format!("No such subcommand: '{}'. Did you mean '{}'?", command,
+                                  other_command))
+        },
+        Resolved::Unknown(None) => command_error(format!("No such subcommand: '{}'", command)),
+    };
+    match command {
         "playing" => {
             let argv = ["maruska", "playing"].into_iter()
                 .map(|x| String::from(*x))
@@ -95,36 +236,218 @@ pub fn main() {
                 .collect();
             queue::main(argv, args)
         }
-        "search" => unimplemented!(),
-        "request" => unimplemented!(),
-        "skip" => unimplemented!(),
-        "remove" => unimplemented!(),
-        "up" => unimplemented!(),
-        "down" => unimplemented!(),
+        "status" => {
+            let argv = ["maruska", "status"].into_iter()
+                .map(|x| String::from(*x))
+                .chain(args.arg_args.clone())
+                .collect();
+            status::main(argv, args)
+        },
+        "search" => {
+            let argv = ["maruska", "search"].into_iter()
+                .map(|x| String::from(*x))
+                .chain(args.arg_args.clone())
+                .collect();
+            search::main(argv, args)
+        },
+        "request" => {
+            let argv = ["maruska", "request"].into_iter()
+                .map(|x| String::from(*x))
+                .chain(args.arg_args.clone())
+                .collect();
+            request::main(argv, args)
+        },
+        "skip" => {
+            let argv = ["maruska", "skip"].into_iter()
+                .map(|x| String::from(*x))
+                .chain(args.arg_args.clone())
+                .collect();
+            skip::main(argv, args)
+        },
+        "remove" => {
+            let argv = ["maruska", "remove"].into_iter()
+                .map(|x| String::from(*x))
+                .chain(args.arg_args.clone())
+                .collect();
+            remove::main(argv, args)
+        },
+        "up" => {
+            let argv = ["maruska", "up"].into_iter()
+                .map(|x| String::from(*x))
+                .chain(args.arg_args.clone())
+                .collect();
+            up::main(argv, args)
+        },
+        "down" => {
+            let argv = ["maruska", "down"].into_iter()
+                .map(|x| String::from(*x))
+                .chain(args.arg_args.clone())
+                .collect();
+            down::main(argv, args)
+        },
         "help" => unimplemented!(),
-        command => command_not_found(command)
+        "sync" => {
+            let argv = ["maruska", "sync"].into_iter()
+                .map(|x| String::from(*x))
+                .chain(args.arg_args.clone())
+                .collect();
+            sync::main(argv, args)
+        },
+        "report" => {
+            let argv = ["maruska", "report"].into_iter()
+                .map(|x| String::from(*x))
+                .chain(args.arg_args.clone())
+                .collect();
+            report::main(argv, args)
+        },
+        "uploads" => {
+            let argv = ["maruska", "uploads"].into_iter()
+                .map(|x| String::from(*x))
+                .chain(args.arg_args.clone())
+                .collect();
+            uploads::main(argv, args)
+        },
+        "stats" => {
+            let argv = ["maruska", "stats"].into_iter()
+                .map(|x| String::from(*x))
+                .chain(args.arg_args.clone())
+                .collect();
+            stats::main(argv, args)
+        },
+        "watchlist" => {
+            let argv = ["maruska", "watchlist"].into_iter()
+                .map(|x| String::from(*x))
+                .chain(args.arg_args.clone())
+                .collect();
+            watchlist::main(argv, args)
+        },
+        "audit" => {
+            let argv = ["maruska", "audit"].into_iter()
+                .map(|x| String::from(*x))
+                .chain(args.arg_args.clone())
+                .collect();
+            audit::main(argv, args)
+        },
+        "daemon" => {
+            let argv = ["maruska", "daemon"].into_iter()
+                .map(|x| String::from(*x))
+                .chain(args.arg_args.clone())
+                .collect();
+            daemon::main(argv, args)
+        },
+        #[cfg(feature = "matrix")]
+        "bot" => {
+            let argv = ["maruska", "bot"].into_iter()
+                .map(|x| String::from(*x))
+                .chain(args.arg_args.clone())
+                .collect();
+            bot::main(argv, args)
+        },
+        #[cfg(not(feature = "matrix"))]
+        "bot" => {
+            use std::io::Write;
+            let _ = writeln!(std::io::stderr(), "maruska-cli was built without chat bot support, \
+                                                 rebuild it with `--features matrix`");
+            std::process::exit(1);
+        },
+        "config" => {
+            let argv = ["maruska", "config"].into_iter()
+                .map(|x| String::from(*x))
+                .chain(args.arg_args.clone())
+                .collect();
+            config::main(argv, args)
+        },
+        "debug-bundle" => {
+            let argv = ["maruska", "debug-bundle"].into_iter()
+                .map(|x| String::from(*x))
+                .chain(args.arg_args.clone())
+                .collect();
+            debug_bundle::main(argv, args)
+        },
+        "gen-man" => {
+            let argv = ["maruska", "gen-man"].into_iter()
+                .map(|x| String::from(*x))
+                .chain(args.arg_args.clone())
+                .collect();
+            man::main(argv, args)
+        },
+        // `resolve` only gives the names in COMMANDS
+        _ => unreachable!(),
     }
 }
 
-fn command_not_found(command: &str) -> ! {
-    let mut other_command_dist: (Option<(&str, usize)>) = None;
-    for x in COMMANDS.iter() {
-        let dist = levenshtein(&command, x);
-        match other_command_dist {
-            None if dist <= 3 => {
-                other_command_dist = Some((&x, dist));
-            },
-            Some((_, other_dist)) if dist < other_dist => {
-                other_command_dist = Some((&x, dist));
-            },
-            _ => {}
+/// Get the main usage message, including the list of available commands
+pub fn usage() -> String {
+    let mut s = String::from(USAGE);
+    s.push_str("\nCommands:\n");
+    for cmd in COMMANDS.iter() {
+        s.push_str(&format!("  {:<12} {}\n", cmd.name, cmd.description));
+    }
+    let plugins = plugins::list();
+    if !plugins.is_empty() {
+        s.push_str("\nPlugins (maruska-NAME on the PATH):\n");
+        for name in plugins {
+            s.push_str(&format!("  {}\n", name));
         }
     }
-    let msg = match other_command_dist {
-        Some((other_command, _)) => format!("No such subcommand: '{}'. Did you mean '{}'?",
-                                           command, other_command),
-        None => format!("No such subcommand: '{}'", command)
+    s
+}
+
+/// The words that `name` stands for if it is an alias from the configuration file, the first
+/// being the command
+fn resolve_alias(name: &str) -> Option<Vec<String>> {
+    let aliases = config_table().map_or_else(BTreeMap::new, |table| aliases::from_table(&table));
+    let commands: Vec<&str> = COMMANDS.iter().map(|x| x.name).collect();
+    aliases::resolve(&aliases, &commands, name).map(|command| {
+        command.split_whitespace().map(String::from).collect()
+    })
+}
+
+/// The user given with `--username`, or else the current user
+pub fn username_arg(args: &Args) -> Option<String> {
+    if args.flag_username.is_empty() {
+        current_user()
+    } else {
+        Some(args.flag_username.clone())
+    }
+}
+
+/// The username and the password hash to log in with, if a password was given
+///
+/// Without `--username`, we log in as the current user.
+pub fn login_args(args: &Args) -> Option<(String, String)> {
+    match username_arg(args) {
+        Some(ref username) if !args.flag_password.is_empty() => {
+            Some((username.clone(), md5(&args.flag_password)))
+        },
+        _ => None,
+    }
+}
+
+/// The configuration file of the TUI, if there is a valid one
+pub fn config_table() -> Option<BTreeMap<String, toml::Value>> {
+    let mut contents = String::new();
+    let path = match paths::config_file() {
+        Some(path) => path,
+        None => return None,
     };
+    if File::open(&path).and_then(|mut f| f.read_to_string(&mut contents)).is_err() {
+        return None;
+    }
+    let table = toml::Parser::new(&contents).parse();
+    if table.is_none() {
+        warn!("ignoring invalid config file {:?}", path);
+    }
+    table
+}
+
+/// How to show times, as set in the configuration file of the TUI
+pub fn time_format() -> TimeFormat {
+    config_table().map_or_else(TimeFormat::default, |table| TimeFormat::from_table(&table))
+}
+
+/// Exit because the subcommand is not one, with `msg` and the start of the usage message
+fn command_error(msg: String) -> ! {
     let err = DocoptError::Argv(msg);
     let usage_str = USAGE
         .trim()
@@ -0,0 +1,156 @@
+use docopt::Docopt;
+
+use super::COMMANDS;
+
+#[derive(Debug, RustcDecodable)]
+pub struct Args;
+
+pub const USAGE: &'static str = "
+Print a roff man page for maruska-cli to stdout
+
+Usage:
+  maruska gen-man [options]
+
+Options:
+  -h --help     Display this message
+";
+
+pub fn main(argv: Vec<String>, _: super::Args) {
+    let _: Args = Docopt::new(USAGE)
+        .map(|d| d.help(true))
+        .map(|d| d.argv(argv))
+        .and_then(|d| d.decode())
+        .unwrap_or_else(|e| e.exit());
+    print!("{}", man_page());
+}
+
+/// Render the main usage message, followed by the usage message of every subcommand
+pub fn long_help() -> String {
+    let mut s = String::from(super::usage().trim());
+    s.push('\n');
+    for cmd in COMMANDS.iter() {
+        if let Some(usage) = cmd.usage {
+            s.push_str(&format!("\n{}\n\n", repeat_char('-', 72)));
+            s.push_str(&format!("maruska {}\n\n", cmd.name));
+            s.push_str(usage.trim());
+            s.push('\n');
+        }
+    }
+    s
+}
+
+/// Render a roff man page from the main usage message and the subcommand metadata
+pub fn man_page() -> String {
+    const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+    let mut s = String::new();
+    s.push_str(&format!(".TH MARUSKA-CLI 1 \"\" \"maruska-cli {}\" \"User Commands\"\n",
+                        VERSION));
+    s.push_str(".SH NAME\n");
+    s.push_str("maruska-cli \\- command line client for the marietje music daemon\n");
+
+    s.push_str(".SH SYNOPSIS\n");
+    for line in section(super::USAGE, "Usage:") {
+        s.push_str(".nf\n");
+        s.push_str(&escape(line.trim()));
+        s.push_str("\n.fi\n");
+    }
+
+    s.push_str(".SH OPTIONS\n");
+    for line in section(super::USAGE, "Options:") {
+        let (flags, description) = split_option(line);
+        s.push_str(".TP\n");
+        s.push_str(&format!(".B {}\n", escape(flags)));
+        s.push_str(&escape(description));
+        s.push('\n');
+    }
+
+    s.push_str(".SH COMMANDS\n");
+    for cmd in COMMANDS.iter() {
+        s.push_str(".TP\n");
+        s.push_str(&format!(".B {}\n", escape(cmd.name)));
+        s.push_str(&escape(cmd.description));
+        s.push('\n');
+        if let Some(usage) = cmd.usage {
+            let mut lines = section(usage, "Usage:");
+            lines.push("");
+            lines.extend(section(usage, "Options:"));
+            s.push_str(".RS\n.nf\n");
+            for line in lines {
+                s.push_str(&escape(line));
+                s.push('\n');
+            }
+            s.push_str(".fi\n.RE\n");
+        }
+    }
+
+    s.push_str(".SH PLUGINS\n");
+    s.push_str(&escape("A command that is not built in runs the executable maruska-NAME on the \
+                        PATH, with the global options in MARUSKA_HOST, MARUSKA_USERNAME, \
+                        MARUSKA_PASSWORD_HASH and the other MARUSKA_ environment variables."));
+    s.push('\n');
+
+    s.push_str(".SH SEE ALSO\n");
+    s.push_str(".BR maruska (1)\n");
+    s
+}
+
+/// Get the (non-empty) lines of the section with title `title` in a docopt usage message
+fn section<'a>(usage: &'a str, title: &str) -> Vec<&'a str> {
+    usage.lines()
+         .skip_while(|line| line.trim() != title)
+         .skip(1)
+         .take_while(|line| !line.trim().is_empty())
+         .collect()
+}
+
+/// Split an option description line in its flags and its description
+fn split_option(line: &str) -> (&str, &str) {
+    let line = line.trim();
+    match line.find("  ") {
+        Some(idx) => (&line[..idx], line[idx..].trim()),
+        None => (line, ""),
+    }
+}
+
+/// Escape a string so that it is printed literally by roff
+fn escape(s: &str) -> String {
+    let s = s.replace('\\', "\\\\").replace('-', "\\-");
+    if s.starts_with('.') || s.starts_with('\'') {
+        format!("\\&{}", s)
+    } else {
+        s
+    }
+}
+
+fn repeat_char(ch: char, n: usize) -> String {
+    ::std::iter::repeat(ch).take(n).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape, section, split_option};
+
+    #[test]
+    fn escape_roff() {
+        assert_eq!(escape("--host"), "\\-\\-host");
+        assert_eq!(escape(".hidden"), "\\&.hidden");
+        assert_eq!(escape("a\\b"), "a\\\\b");
+    }
+
+    #[test]
+    fn parse_usage() {
+        let usage = "
+Usage:
+  foo [options]
+
+Options:
+  -h --help     Display this message
+  --version     Print version info and exit
+";
+        assert_eq!(section(usage, "Usage:"), vec!["  foo [options]"]);
+        let options = section(usage, "Options:");
+        assert_eq!(options.len(), 2);
+        assert_eq!(split_option(options[0]), ("-h --help", "Display this message"));
+        assert_eq!(split_option(options[1]), ("--version", "Print version info and exit"));
+    }
+}
@@ -0,0 +1,190 @@
+//! Media keys for `maruska daemon`, through MPRIS: the D-Bus interface that desktops use to find
+//! and control media players.
+//!
+//! The daemon shows up as the media player "maruska" on the session bus. Next votes to skip the
+//! song that is playing. The music plays on the server, so it cannot be paused here: Play/Pause
+//! stops following the server instead (like suspending the terminal UI does), so that hooks do
+//! not run, and starts following it again.
+//!
+//! Without the `mpris` feature, the media keys cannot be used.
+
+use std::sync::{Arc, Mutex};
+
+use libclient::Client;
+
+/// Control `client` with the media keys, from another thread
+#[cfg(feature = "mpris")]
+pub fn serve(client: Arc<Mutex<Client>>) -> Result<(), String> {
+    imp::serve(client).map_err(|err| err.to_string())
+}
+
+#[cfg(not(feature = "mpris"))]
+pub fn serve(_: Arc<Mutex<Client>>) -> Result<(), String> {
+    Err("maruska was built without media keys, build it with `--features mpris`".to_string())
+}
+
+#[cfg(feature = "mpris")]
+mod imp {
+    use std::collections::HashMap;
+    use std::ffi::CString;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration as StdDuration;
+
+    use dbus::{Error as DBusError, Message, Path};
+    use dbus::arg::{RefArg, Variant};
+    use dbus::blocking::Connection;
+    use dbus::channel::{MatchingReceiver, Sender};
+    use dbus::message::MatchRule;
+    use dbus::strings::ErrorName;
+    use time::get_time;
+
+    use libclient::{Client, md5, Playing, RequestStatus};
+
+    const BUS_NAME: &'static str = "org.mpris.MediaPlayer2.maruska";
+    const OBJECT_PATH: &'static str = "/org/mpris/MediaPlayer2";
+    const ROOT_IFACE: &'static str = "org.mpris.MediaPlayer2";
+    const PLAYER_IFACE: &'static str = "org.mpris.MediaPlayer2.Player";
+    const PROPERTIES_IFACE: &'static str = "org.freedesktop.DBus.Properties";
+
+    /// How long to wait for D-Bus messages at a time
+    const PROCESS_TIMEOUT_MILLIS: u64 = 1000;
+
+    type Properties = HashMap<String, Variant<Box<RefArg>>>;
+
+    pub fn serve(client: Arc<Mutex<Client>>) -> Result<(), DBusError> {
+        let conn = try!(Connection::new_session());
+        try!(conn.request_name(BUS_NAME, false, true, false));
+        conn.start_receive(MatchRule::new_method_call(), Box::new(move |msg, conn| {
+            let reply = handle(&client, &msg);
+            if conn.send(reply).is_err() {
+                warn!("could not answer a D-Bus call");
+            }
+            true
+        }));
+        thread::spawn(move || loop {
+            if let Err(err) = conn.process(StdDuration::from_millis(PROCESS_TIMEOUT_MILLIS)) {
+                warn!("the media keys stopped working: {}", err);
+                return;
+            }
+        });
+        Ok(())
+    }
+
+    /// The reply to the method call `msg`
+    fn handle(client: &Mutex<Client>, msg: &Message) -> Message {
+        if msg.path().map_or(true, |x| &*x != OBJECT_PATH) {
+            return error(msg, "org.freedesktop.DBus.Error.UnknownObject", "no such object");
+        }
+        let iface = msg.interface().map(|x| x.to_string()).unwrap_or_default();
+        let member = msg.member().map(|x| x.to_string()).unwrap_or_default();
+        let mut client = client.lock().unwrap();
+        match (&iface[..], &member[..]) {
+            (PLAYER_IFACE, "Next") => {
+                debug!("media key: next");
+                if let RequestStatus::Deferred = client.vote_skip() {
+                    warn!("voting to skip a song needs a login");
+                }
+            },
+            (PLAYER_IFACE, "PlayPause") if client.is_suspended() => client.resume(),
+            (PLAYER_IFACE, "PlayPause") | (PLAYER_IFACE, "Pause") | (PLAYER_IFACE, "Stop") => {
+                client.suspend();
+            },
+            (PLAYER_IFACE, "Play") => client.resume(),
+            // Previous, Seek, Raise etc. cannot be done, but they are not errors either
+            (PLAYER_IFACE, _) | (ROOT_IFACE, _) => {},
+            (PROPERTIES_IFACE, "Get") => {
+                let (iface, name): (&str, &str) = match msg.read2() {
+                    Ok(args) => args,
+                    Err(_) => return error(msg, "org.freedesktop.DBus.Error.InvalidArgs",
+                                           "expected an interface and a property"),
+                };
+                return match properties(&client, iface).remove(name) {
+                    Some(value) => msg.method_return().append1(value),
+                    None => error(msg, "org.freedesktop.DBus.Error.UnknownProperty",
+                                  "no such property"),
+                };
+            },
+            (PROPERTIES_IFACE, "GetAll") => {
+                let iface: &str = match msg.read1() {
+                    Ok(iface) => iface,
+                    Err(_) => return error(msg, "org.freedesktop.DBus.Error.InvalidArgs",
+                                           "expected an interface"),
+                };
+                return msg.method_return().append1(properties(&client, iface));
+            },
+            // Setting properties is not supported, they are all read-only to us
+            _ => return error(msg, "org.freedesktop.DBus.Error.UnknownMethod", "no such method"),
+        }
+        msg.method_return()
+    }
+
+    /// The properties of the interface `iface`
+    fn properties(client: &Client, iface: &str) -> Properties {
+        let mut props = Properties::new();
+        {
+            let mut set = |name: &str, value: Box<RefArg>| {
+                props.insert(name.to_string(), Variant(value));
+            };
+            match iface {
+                ROOT_IFACE => {
+                    set("Identity", Box::new("maruska".to_string()));
+                    set("CanQuit", Box::new(false));
+                    set("CanRaise", Box::new(false));
+                    set("HasTrackList", Box::new(false));
+                    set("SupportedUriSchemes", Box::new(Vec::<String>::new()));
+                    set("SupportedMimeTypes", Box::new(Vec::<String>::new()));
+                },
+                PLAYER_IFACE => {
+                    let playing = client.get_playing().as_ref();
+                    let status = match playing {
+                        _ if client.is_suspended() => "Paused",
+                        Some(_) => "Playing",
+                        None => "Stopped",
+                    };
+                    set("PlaybackStatus", Box::new(status.to_string()));
+                    set("Metadata", Box::new(playing.map_or_else(Properties::new, metadata)));
+                    set("Position", Box::new(playing.map_or(0, position)));
+                    set("Rate", Box::new(1.0));
+                    set("MinimumRate", Box::new(1.0));
+                    set("MaximumRate", Box::new(1.0));
+                    set("Volume", Box::new(1.0));
+                    set("CanGoNext", Box::new(true));
+                    set("CanGoPrevious", Box::new(false));
+                    set("CanPlay", Box::new(true));
+                    set("CanPause", Box::new(true));
+                    set("CanSeek", Box::new(false));
+                    set("CanControl", Box::new(true));
+                },
+                _ => {},
+            }
+        }
+        props
+    }
+
+    /// The MPRIS metadata of the song that is playing
+    fn metadata(playing: &Playing) -> Properties {
+        let media = &playing.media;
+        let mut metadata = Properties::new();
+        let track_id = format!("/org/mpris/MediaPlayer2/maruska/track/{}", md5(&media.key));
+        metadata.insert("mpris:trackid".to_string(), Variant(Box::new(Path::from(track_id))));
+        metadata.insert("mpris:length".to_string(),
+                        Variant(Box::new(media.length.num_microseconds().unwrap_or(0))));
+        metadata.insert("xesam:title".to_string(), Variant(Box::new(media.title.clone())));
+        metadata.insert("xesam:artist".to_string(), Variant(Box::new(vec![media.artist.clone()])));
+        if let Some(ref album) = media.album {
+            metadata.insert("xesam:album".to_string(), Variant(Box::new(album.clone())));
+        }
+        metadata
+    }
+
+    /// How far the song that is playing is, in microseconds
+    fn position(playing: &Playing) -> i64 {
+        let left = playing.end_time - get_time();
+        (playing.media.length - left).num_microseconds().unwrap_or(0).max(0)
+    }
+
+    fn error(msg: &Message, name: &'static str, text: &str) -> Message {
+        msg.error(&ErrorName::from(name), &CString::new(text).unwrap())
+    }
+}
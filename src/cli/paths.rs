@@ -0,0 +1 @@
+../paths.rs
\ No newline at end of file
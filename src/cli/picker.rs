@@ -0,0 +1,196 @@
+//! A small picker in the terminal, like fzf: type to narrow down a list, Enter picks an item.
+//!
+//! The picker is drawn on the terminal (`/dev/tty`) below the cursor, and erased again when it
+//! is done, so that the standard output of `maruska-cli` can still be piped somewhere else.
+
+use std::io::Error as IOError;
+
+use libclient::text::fold;
+
+/// The number of items that are shown at once
+const PICKER_LINES: usize = 10;
+
+/// How well `pattern` matches `item`, or `None` if it does not
+///
+/// All characters of the pattern (except spaces) have to appear in the item in the same order,
+/// after folding case and accents. Characters that follow each other, or that start a word, make
+/// a better match.
+pub fn score(item: &str, pattern: &str) -> Option<i64> {
+    let item: Vec<char> = fold(item).chars().collect();
+    let mut score = 0;
+    let mut pos = 0;
+    let mut last_match = None;
+    for ch in fold(pattern).chars().filter(|x| !x.is_whitespace()) {
+        let found = match item[pos..].iter().position(|x| *x == ch) {
+            Some(offset) => pos + offset,
+            None => return None,
+        };
+        score += 1;
+        if found > 0 && last_match == Some(found - 1) {
+            score += 2;
+        }
+        if found == 0 || !item[found - 1].is_alphanumeric() {
+            score += 3;
+        }
+        last_match = Some(found);
+        pos = found + 1;
+    }
+    Some(score)
+}
+
+/// The indices of the items that match `pattern`, the best matches first
+fn filter(items: &[String], pattern: &str) -> Vec<usize> {
+    let mut matches: Vec<(usize, i64)> = items.iter().enumerate()
+        .filter_map(|(i, item)| score(item, pattern).map(|score| (i, score)))
+        .collect();
+    // the sort is stable, so items that match as well keep their order
+    matches.sort_by(|a, b| b.1.cmp(&a.1));
+    matches.into_iter().map(|x| x.0).collect()
+}
+
+/// Let the user pick one of `items`, returns its index or `None` if the user cancelled
+#[cfg(unix)]
+pub fn pick(items: &[String]) -> Result<Option<usize>, IOError> {
+    use std::fs::OpenOptions;
+    use std::io::{Read, Write};
+
+    let mut tty = try!(OpenOptions::new().read(true).write(true).open("/dev/tty"));
+    let _raw = try!(raw::RawMode::enable(&tty));
+    let width = raw::width(&tty);
+
+    let mut pattern = String::new();
+    let mut selected = 0;
+    loop {
+        let matches = filter(items, &pattern);
+        selected = ::std::cmp::min(selected, matches.len().saturating_sub(1));
+        try!(tty.write_all(draw(items, &matches, &pattern, selected, width).as_bytes()));
+        try!(tty.flush());
+
+        let mut buf = [0; 64];
+        let n = try!(tty.read(&mut buf));
+        let input = &buf[..n];
+        match input {
+            b"\r" | b"\n" => {
+                try!(tty.write_all(b"\r\x1b[J"));
+                return Ok(matches.get(selected).cloned());
+            },
+            // Escape, Ctrl-C or Ctrl-G
+            b"\x1b" | b"\x03" | b"\x07" => {
+                try!(tty.write_all(b"\r\x1b[J"));
+                return Ok(None);
+            },
+            // arrow up or Ctrl-P
+            b"\x1b[A" | b"\x1bOA" | b"\x10" => selected = selected.saturating_sub(1),
+            // arrow down or Ctrl-N
+            b"\x1b[B" | b"\x1bOB" | b"\x0e" => selected += 1,
+            b"\x7f" | b"\x08" => {
+                pattern.pop();
+                selected = 0;
+            },
+            // Ctrl-U
+            b"\x15" => {
+                pattern.clear();
+                selected = 0;
+            },
+            _ if input.first() == Some(&0x1b) => {}, // another escape sequence
+            _ => {
+                if let Ok(s) = ::std::str::from_utf8(input) {
+                    pattern.extend(s.chars().filter(|x| !x.is_control()));
+                    selected = 0;
+                }
+            },
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn pick(_: &[String]) -> Result<Option<usize>, IOError> {
+    use std::io::ErrorKind;
+    Err(IOError::new(ErrorKind::Other, "the picker only works on Unix-like systems"))
+}
+
+/// The escape sequences that draw the picker below the cursor, and put the cursor after the
+/// pattern
+fn draw(items: &[String], matches: &[usize], pattern: &str, selected: usize,
+        width: usize) -> String {
+    let truncate = |s: &str| s.chars().take(width.saturating_sub(3)).collect::<String>();
+    let mut s = format!("\r\x1b[J> {}", truncate(pattern));
+    let first = (selected + 1).saturating_sub(PICKER_LINES);
+    let shown = matches.iter().skip(first).take(PICKER_LINES);
+    let lines = shown.len() + 1;
+    for (i, &idx) in shown.enumerate() {
+        if first + i == selected {
+            s.push_str(&format!("\r\n\x1b[7m> {}\x1b[0m", truncate(&items[idx])));
+        } else {
+            s.push_str(&format!("\r\n  {}", truncate(&items[idx])));
+        }
+    }
+    s.push_str(&format!("\r\n  {}/{}", matches.len(), items.len()));
+    s.push_str(&format!("\x1b[{}A\r\x1b[{}C", lines, 2 + pattern.chars().count()));
+    s
+}
+
+#[cfg(unix)]
+mod raw {
+    use std::fs::File;
+    use std::io::Error as IOError;
+    use std::mem;
+    use std::os::unix::io::{AsRawFd, RawFd};
+
+    use libc;
+
+    /// The terminal in raw mode, it is restored when this is dropped
+    pub struct RawMode {
+        fd: RawFd,
+        original: libc::termios,
+    }
+
+    impl RawMode {
+        pub fn enable(tty: &File) -> Result<RawMode, IOError> {
+            let fd = tty.as_raw_fd();
+            let mut original: libc::termios = unsafe { mem::zeroed() };
+            if unsafe { libc::tcgetattr(fd, &mut original) } != 0 {
+                return Err(IOError::last_os_error());
+            }
+            let mut raw = original;
+            // read every key when it is pressed, and do not echo it or turn it into a signal
+            raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+            raw.c_iflag &= !(libc::IXON | libc::ICRNL);
+            raw.c_cc[libc::VMIN] = 1;
+            raw.c_cc[libc::VTIME] = 0;
+            if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) } != 0 {
+                return Err(IOError::last_os_error());
+            }
+            Ok(RawMode { fd: fd, original: original })
+        }
+    }
+
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+            unsafe { libc::tcsetattr(self.fd, libc::TCSANOW, &self.original); }
+        }
+    }
+
+    /// The width of the terminal
+    pub fn width(tty: &File) -> usize {
+        let mut size = libc::winsize { ws_row: 0, ws_col: 0, ws_xpixel: 0, ws_ypixel: 0 };
+        let ok = unsafe { libc::ioctl(tty.as_raw_fd(), libc::TIOCGWINSZ, &mut size) } == 0;
+        if ok && size.ws_col > 0 { size.ws_col as usize } else { 80 }
+    }
+}
+
+
+#[test]
+fn test() {
+    assert_eq!(score("Motörhead - Ace of Spades", "motace"), Some(15));
+    assert_eq!(score("Motörhead - Ace of Spades", "aces"),
+               score("Motörhead - Ace of Spades", "ace s"));
+    assert_eq!(score("Motörhead - Ace of Spades", "spadesace"), None);
+
+    let items = vec!["Radiohead - Creep".to_string(), "Tool - Schism".to_string(),
+                     "Queens Of The Stone Age - No One Knows".to_string()];
+    // "Of" starts with an o, "Radiohead" and "Tool" match as well and keep their order
+    assert_eq!(filter(&items, "cre"), [0]);
+    assert_eq!(filter(&items, "o"), [2, 0, 1]);
+    assert_eq!(filter(&items, "").len(), 3);
+}
@@ -1,11 +1,17 @@
+use std::process;
+
 use docopt::Docopt;
+use rustc_serialize::json::Json;
+use time::{Duration, get_time, Timespec};
 
-use libclient::Client;
+use format::Eta;
+use libclient::{Client, DisplayChain};
+use instance;
 
 #[derive(Debug, RustcDecodable)]
 pub struct Args;
 
-const USAGE: &'static str = "
+pub const USAGE: &'static str = "
 Retrieve the song that is currently played
 
 Usage:
@@ -25,20 +31,59 @@ pub fn main(argv: Vec<String>, global_args: super::Args) {
 }
 
 pub fn execute(_: Args, global_args: super::Args) {
+    let time_format = super::time_format();
+    let ends = |end_time: Timespec| {
+        let remaining = end_time - get_time();
+        if remaining < Duration::zero() {
+            String::new()
+        } else {
+            let preposition = match time_format.eta {
+                Eta::Duration => "in",
+                Eta::Time => "at",
+            };
+            format!(", ends {} {}", preposition, time_format.eta(remaining))
+        }
+    };
+
+    // Ask the running daemon, if there is one, instead of opening another session
+    if let Some(playing) = instance::forward(&global_args.flag_host, "/playing") {
+        if let Some(media) = playing.find("media") {
+            let get = |key| media.find(key).and_then(|x: &Json| x.as_string()).unwrap_or("");
+            let (artist, title) = (get("artist"), get("title"));
+            let end_time = playing.find("end_time").and_then(|x| x.as_f64())
+                                  .map_or(String::new(), |x| {
+                ends(Timespec::new(x as i64, (x.fract() * 1e9) as i32))
+            });
+            match playing.find("requested_by").and_then(|x| x.as_string()) {
+                Some(requested_by) => {
+                    println!("{} - {} (requested by {}{})", artist, title, requested_by, end_time)
+                },
+                None => println!("{} - {} (requested at random by the server{})", artist, title,
+                                 end_time),
+            }
+            return;
+        }
+    }
+
     let (mut client, client_r) = Client::new(&global_args.flag_host).unwrap();
     client.follow(vec!(String::from("playing")));
     client.serve();
 
     while client.get_playing() == &None {
         let message = client_r.recv().unwrap();
-        client.handle_message(&message).unwrap();
+        if let Err(err) = client.handle_message(&message) {
+            println!("Could not read the playing song: {}", DisplayChain(&err));
+            process::exit(1);
+        }
     }
 
     let playing = client.get_playing().clone().unwrap();
     let media = playing.media;
+    let end_time = ends(playing.end_time);
     if let Some(requested_by) = playing.requested_by {
-        println!("{} - {} (requested by {})", media.artist, media.title, requested_by);
+        println!("{} - {} (requested by {}{})", media.artist, media.title, requested_by, end_time);
     } else {
-        println!("{} - {} (requested at random by the server)", media.artist, media.title);
+        println!("{} - {} (requested at random by the server{})", media.artist, media.title,
+                 end_time);
         };
 }
@@ -0,0 +1,149 @@
+//! Commands that are not built in, but are executables named `maruska-NAME` on the `PATH`.
+//!
+//! Like the subcommands of git and cargo, `maruska-cli NAME ARGS...` runs `maruska-NAME ARGS...`
+//! if NAME is not a command (or a start of one) or an alias. The plugin gets the global options
+//! in environment variables, so that it can talk to the same server as the user:
+//!
+//! - `MARUSKA_HOST`: the URL of the server (`--host`)
+//! - `MARUSKA_USERNAME`: the user to log in as (`--username`, or the current user)
+//! - `MARUSKA_PASSWORD_HASH`: the MD5 hash of `--password`, which the server logs in with; it is
+//!   only set if a password was given
+//! - `MARUSKA_VERBOSE` and `MARUSKA_YES`: `1` if `--verbose` and `--yes` were given
+//! - `MARUSKA_LOG_LEVEL` and `MARUSKA_LOG_FILE`: `--log-level` and `--log-file`, if given
+//! - `MARUSKA_CLI`: the path of `maruska-cli` itself, to run the built-in commands with
+
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+/// The start of the name of a plugin executable
+const PREFIX: &'static str = "maruska-";
+
+/// The plugin executable for the command `name`, if there is one on the `PATH`
+pub fn find(name: &str) -> Option<PathBuf> {
+    // `maruska-cli` itself is on the path as well
+    if name.is_empty() || name == "cli" || name.contains(|x: char| x == '/' || x == '\\') {
+        return None;
+    }
+    let file_name = format!("{}{}{}", PREFIX, name, env::consts::EXE_SUFFIX);
+    env::var_os("PATH").and_then(|paths| {
+        env::split_paths(&paths).map(|dir| dir.join(&file_name)).find(|x| is_executable(x))
+    })
+}
+
+/// The names of the plugins on the `PATH`, sorted and without duplicates
+pub fn list() -> Vec<String> {
+    let mut names = Vec::new();
+    let paths = env::var_os("PATH").unwrap_or_default();
+    for dir in env::split_paths(&paths) {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.filter_map(|x| x.ok()) {
+            let file_name = entry.file_name();
+            let name = match plugin_name(&file_name.to_string_lossy()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            if name != "cli" && is_executable(&entry.path()) {
+                names.push(name);
+            }
+        }
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// The command that the executable `file_name` is a plugin for, if it is one
+fn plugin_name(file_name: &str) -> Option<&str> {
+    if !file_name.starts_with(PREFIX) || !file_name.ends_with(env::consts::EXE_SUFFIX) {
+        return None;
+    }
+    let name = &file_name[PREFIX.len()..file_name.len() - env::consts::EXE_SUFFIX.len()];
+    if name.is_empty() { None } else { Some(name) }
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).map(|x| x.is_file() && x.permissions().mode() & 0o111 != 0)
+                      .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// The environment variables that pass the global options on to a plugin
+pub fn environment(args: &super::Args) -> Vec<(&'static str, OsString)> {
+    let mut vars = vec!(("MARUSKA_HOST", OsString::from(args.flag_host.clone())));
+    if let Some(username) = super::username_arg(args) {
+        vars.push(("MARUSKA_USERNAME", OsString::from(username)));
+    }
+    if let Some((_, password_hash)) = super::login_args(args) {
+        vars.push(("MARUSKA_PASSWORD_HASH", OsString::from(password_hash)));
+    }
+    if args.flag_verbose {
+        vars.push(("MARUSKA_VERBOSE", OsString::from("1")));
+    }
+    if args.flag_yes {
+        vars.push(("MARUSKA_YES", OsString::from("1")));
+    }
+    if let Some(ref level) = args.flag_log_level {
+        vars.push(("MARUSKA_LOG_LEVEL", OsString::from(level.clone())));
+    }
+    if let Some(ref file) = args.flag_log_file {
+        vars.push(("MARUSKA_LOG_FILE", OsString::from(file.clone())));
+    }
+    if let Ok(exe) = env::current_exe() {
+        vars.push(("MARUSKA_CLI", exe.into_os_string()));
+    }
+    vars
+}
+
+/// Run the plugin at `path` with the arguments that were given after the command, and exit with
+/// its exit status
+pub fn run(path: &Path, args: &super::Args) -> ! {
+    let mut command = Command::new(path);
+    command.args(&args.arg_args);
+    for (key, value) in environment(args) {
+        command.env(key, value);
+    }
+    debug!("running plugin {:?}", path);
+    let err = exec(&mut command);
+    let _ = writeln!(io::stderr(), "Could not run {}: {}", path.display(), err);
+    process::exit(1);
+}
+
+/// Replace this process with `command`, which only returns if that failed
+#[cfg(unix)]
+fn exec(command: &mut Command) -> io::Error {
+    use std::os::unix::process::CommandExt;
+    command.exec()
+}
+
+/// Run `command` and exit like it did, Windows cannot replace a process
+#[cfg(not(unix))]
+fn exec(command: &mut Command) -> io::Error {
+    match command.status() {
+        Ok(status) => process::exit(status.code().unwrap_or(1)),
+        Err(err) => err,
+    }
+}
+
+
+#[test]
+fn test() {
+    let exe = |name: &str| format!("{}{}", name, env::consts::EXE_SUFFIX);
+    assert_eq!(plugin_name(&exe("maruska-lyrics")), Some("lyrics"));
+    assert_eq!(plugin_name(&exe("maruska-")), None);
+    assert_eq!(plugin_name(&exe("cargo-lyrics")), None);
+    assert_eq!(find("../bin/sh"), None);
+    assert_eq!(find(""), None);
+}
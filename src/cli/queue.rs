@@ -1,11 +1,15 @@
+use std::process;
+
 use docopt::Docopt;
+use rustc_serialize::json::Json;
 
-use libclient::Client;
+use libclient::{Client, DisplayChain};
+use instance;
 
 #[derive(Debug, RustcDecodable)]
 pub struct Args;
 
-const USAGE: &'static str = "
+pub const USAGE: &'static str = "
 List the current request queue
 
 Usage:
@@ -25,13 +29,27 @@ pub fn main(argv: Vec<String>, global_args: super::Args) {
 }
 
 pub fn execute(_: Args, global_args: super::Args) {
+    // Ask the running daemon, if there is one, instead of opening another session
+    if let Some(Json::Array(requests)) = instance::forward(&global_args.flag_host, "/queue") {
+        for request in requests {
+            let get = |key: &[&str]| request.find_path(key).and_then(|x| x.as_string())
+                                            .unwrap_or("").to_string();
+            let requested_by = request.find("by").and_then(|x| x.as_string()).unwrap_or("marietje");
+            println!("{}: {} - {}", requested_by, get(&["media", "artist"]), get(&["media", "title"]));
+        }
+        return;
+    }
+
     let (mut client, client_r) = Client::new(&global_args.flag_host).unwrap();
     client.follow(vec!(String::from("requests")));
     client.serve();
 
     while client.get_requests() == &None {
         let message = client_r.recv().unwrap();
-        client.handle_message(&message).unwrap();
+        if let Err(err) = client.handle_message(&message) {
+            println!("Could not read the queue: {}", DisplayChain(&err));
+            process::exit(1);
+        }
     }
 
     for request in client.get_requests().clone().unwrap() {
@@ -0,0 +1,129 @@
+use std::process;
+use std::time::Duration;
+
+use chan;
+use docopt::Docopt;
+
+use libclient::{Client, DisplayChain, Message};
+use paths;
+
+/// How long to wait for the server to remove the requests
+const REMOVE_TIMEOUT_SECS: u64 = 10;
+
+#[derive(Debug, RustcDecodable)]
+pub struct Args {
+    arg_request: Vec<i64>,
+    flag_user: Option<String>,
+}
+
+pub const USAGE: &'static str = "
+Cancel requests from the queue
+
+REQUEST is the key of a request. You can remove your own requests, moderators
+can remove the requests of everyone.
+
+Usage:
+  maruska remove [options] <request>...
+  maruska remove [options] --user <name>
+
+Options:
+  --user <name>  Remove all requests of this user
+  -h --help      Display this message
+";
+
+pub fn main(argv: Vec<String>, global_args: super::Args) {
+    let args: Args = Docopt::new(USAGE)
+        .map(|d| d.help(true))
+        .map(|d| d.argv(argv))
+        .and_then(|d| d.decode())
+        .unwrap_or_else(|e| e.exit());
+    execute(args, global_args);
+}
+
+pub fn execute(args: Args, global_args: super::Args) {
+    let (username, password_hash) = super::login_args(&global_args).unwrap_or_else(|| {
+        println!("Removing requests needs a login, use --username and --password");
+        process::exit(1);
+    });
+
+    let (mut client, client_r) = Client::new(&global_args.flag_host).unwrap();
+    client.set_audit_log(paths::audit_file());
+    client.follow(vec!(String::from("requests")));
+    client.serve();
+    client.do_login(&username, &password_hash);
+
+    let timeout = chan::after(Duration::from_secs(REMOVE_TIMEOUT_SECS));
+    let mut logged_in = false;
+    // The keys of the requests that we asked the server to remove
+    let mut removing: Option<Vec<i64>> = None;
+    loop {
+        let message;
+        chan_select! {
+            timeout.recv() => {
+                println!("The server did not remove the requests");
+                process::exit(1);
+            },
+            client_r.recv() -> msg => message = msg.unwrap(),
+        }
+        match client.handle_message(&message) {
+            Ok(Message::Login) => logged_in = true,
+            Ok(Message::LoginError(msg)) => {
+                println!("Could not log in: {}", msg);
+                process::exit(1);
+            },
+            Ok(_) => {},
+            Err(err) => {
+                println!("Removing the requests failed: {}", DisplayChain(&err));
+                process::exit(1);
+            },
+        }
+        let requests = match *client.get_requests() {
+            Some(ref requests) if logged_in => requests.clone(),
+            _ => continue,
+        };
+
+        if let Some(ref keys) = removing {
+            if !requests.iter().any(|x| keys.contains(&x.key)) {
+                println!("Removed {} request(s)", keys.len());
+                return;
+            }
+            continue;
+        }
+
+        let targets = match args.flag_user {
+            Some(ref user) => {
+                let targets: Vec<_> = requests.iter()
+                    .filter(|x| x.by.as_ref() == Some(user))
+                    .collect();
+                if targets.is_empty() {
+                    println!("{} has no requests in the queue", user);
+                    return;
+                }
+                targets
+            },
+            None => {
+                let mut targets = Vec::new();
+                for key in &args.arg_request {
+                    match requests.iter().find(|x| x.key == *key) {
+                        Some(request) => targets.push(request),
+                        None => {
+                            println!("There is no request {} in the queue", key);
+                            process::exit(1);
+                        },
+                    }
+                }
+                targets
+            },
+        };
+        // Check all of them first, so that we remove all requests or none
+        if let Some(request) = targets.iter().find(|x| !client.can_manage(x)) {
+            println!("You are not allowed to remove request {}", request.key);
+            process::exit(1);
+        }
+        let keys: Vec<i64> = targets.iter().map(|x| x.key).collect();
+        for key in &keys {
+            client.do_remove_request(*key);
+        }
+        removing = Some(keys);
+    }
+}
@@ -0,0 +1,123 @@
+use std::process;
+use std::time::Duration;
+
+use chan;
+use docopt::Docopt;
+use time::get_time;
+
+use libclient::{Client, DisplayChain, Message};
+use libclient::cache::MediaCache;
+use libclient::report::append_report;
+use paths;
+
+/// How long to wait for the server to log us in and to receive the report
+const REPORT_TIMEOUT_SECS: u64 = 10;
+
+#[derive(Debug, RustcDecodable)]
+pub struct Args {
+    arg_key: String,
+    arg_reason: Vec<String>,
+}
+
+pub const USAGE: &'static str = "
+Report a song that is wrong, e.g. because of wrong tags or a corrupt file
+
+KEY is the media key of the song. If you log in and the server takes reports,
+the report is sent to the server. Otherwise it is saved in a local file, so
+that you can pass it on to the people who run the server.
+
+Usage:
+  maruska report [options] <key> [<reason>...]
+
+Options:
+  -h --help     Display this message
+";
+
+pub fn main(argv: Vec<String>, global_args: super::Args) {
+    let args: Args = Docopt::new(USAGE)
+        .map(|d| d.help(true))
+        .map(|d| d.argv(argv))
+        .and_then(|d| d.decode())
+        .unwrap_or_else(|e| e.exit());
+    execute(args, global_args);
+}
+
+pub fn execute(args: Args, global_args: super::Args) {
+    let reason = args.arg_reason.join(" ");
+    if let Some((username, password_hash)) = super::login_args(&global_args) {
+        if report_to_server(&global_args.flag_host, &username, &password_hash, &args.arg_key,
+                            &reason) {
+            println!("Reported {} to the server", args.arg_key);
+            return;
+        }
+    }
+
+    let path = paths::report_file().unwrap_or_else(|| {
+        println!("Could not find a directory for the report file");
+        process::exit(1);
+    });
+    let description = describe(&global_args.flag_host, &args.arg_key);
+    match append_report(&path, get_time(), &global_args.flag_host, &args.arg_key, &description,
+                        &reason) {
+        Ok(()) => println!("The server does not take reports, saved the report in {}",
+                           path.display()),
+        Err(err) => {
+            println!("Could not save the report in {}: {}", path.display(), err);
+            process::exit(1);
+        },
+    }
+}
+
+/// Send the report to the server, returns false if the server does not take reports
+fn report_to_server(host: &str, username: &str, password_hash: &str, key: &str,
+                    reason: &str) -> bool {
+    let (mut client, client_r) = Client::new(host).unwrap();
+    client.set_audit_log(paths::audit_file());
+    client.serve();
+    client.do_login(username, password_hash);
+
+    let timeout = chan::after(Duration::from_secs(REPORT_TIMEOUT_SECS));
+    loop {
+        let message;
+        chan_select! {
+            timeout.recv() => {
+                println!("The server did not answer in time");
+                process::exit(1);
+            },
+            client_r.recv() -> msg => message = msg.unwrap(),
+        }
+        match client.handle_message(&message) {
+            Ok(Message::Login) if client.has_capability("report") => {
+                client.do_report(key, reason);
+            },
+            Ok(Message::Login) => return false,
+            Ok(Message::MediaReported(ref reported)) if reported == key => return true,
+            Ok(Message::LoginError(msg)) => {
+                println!("Could not log in: {}", msg);
+                process::exit(1);
+            },
+            Ok(_) => {},
+            Err(err) => {
+                println!("Reporting failed: {}", DisplayChain(&err));
+                process::exit(1);
+            },
+        }
+    }
+}
+
+/// The artist and title of the song, if it is in the local media index
+fn describe(host: &str, key: &str) -> String {
+    let path = match paths::media_cache_file(host) {
+        Some(ref path) if path.exists() => path.clone(),
+        _ => return String::new(),
+    };
+    match MediaCache::open(&path) {
+        Ok(cache) => cache.get(key).map_or(String::new(), |x| {
+            format!("{} - {}", x.artist, x.title)
+        }),
+        Err(err) => {
+            warn!("could not read the media index: {}", DisplayChain(&err));
+            String::new()
+        },
+    }
+}
@@ -0,0 +1,301 @@
+use std::io::{self, BufRead};
+use std::process;
+
+use chan;
+use docopt::Docopt;
+use rustc_serialize::json::Json;
+use toml::Value;
+
+use libclient::{Client, DisplayChain, Media, Message};
+use libclient::rank::similarity;
+use paths;
+use search;
+
+/// How many songs are searched for to resolve a query
+const RESOLVE_COUNT: usize = 20;
+
+/// How similar a song has to be to a query to be requested for it, from 0 to 1, unless
+/// `match_threshold` in the configuration file says otherwise
+const DEFAULT_MATCH_THRESHOLD: f64 = 0.85;
+
+/// How many songs are shown when a query is not clear
+const CANDIDATES_SHOWN: usize = 5;
+
+#[derive(Debug, RustcDecodable)]
+pub struct Args {
+    arg_query: Vec<String>,
+    flag_threshold: Option<f64>,
+    flag_yes: bool,
+    flag_wait: bool,
+}
+
+pub const USAGE: &'static str = "
+Request songs
+
+Searches the server for QUERY, and requests the song that it names, e.g.
+\"queens of the stone age - no one knows\" or just \"no one knows\". How close
+the artist and title of a song are to the query is scored from 0% to 100%. If
+no song scores at least the threshold, or several do, nothing is requested
+unless you pass `--yes`; the closest songs are shown with their scores instead.
+The threshold is 85%, or `match_threshold` in the configuration file (0.85).
+
+With \"-\" as the query, the queries are read from the standard input, one per
+line. The songs that can be found are requested and the other lines are
+reported, so that a playlist can be requested from a script.
+
+With `--wait`, this waits until the songs start playing, and tells when they
+move up in the queue in the meantime. That way, a script can do something when
+a song starts, e.g. turn on the disco lights. It fails if a song is removed from
+the queue before it plays.
+
+Usage:
+  maruska request [options] <query>...
+
+Options:
+  --threshold <t>  Request songs that score at least this much, from 0 to 1
+  -y --yes         Request the closest song, also if it is not clear
+  -w --wait        Wait until the songs start playing
+  -h --help        Display this message
+";
+
+pub fn main(argv: Vec<String>, global_args: super::Args) {
+    let args: Args = Docopt::new(USAGE)
+        .map(|d| d.help(true))
+        .map(|d| d.argv(argv))
+        .and_then(|d| d.decode())
+        .unwrap_or_else(|e| e.exit());
+    execute(args, global_args);
+}
+
+pub fn execute(args: Args, global_args: super::Args) {
+    let queries: Vec<String> = if args.arg_query == ["-"] {
+        let stdin = io::stdin();
+        let lines: Result<Vec<String>, _> = stdin.lock().lines().collect();
+        lines.unwrap_or_else(|err| {
+            println!("Could not read the queries: {}", err);
+            process::exit(1);
+        }).into_iter().map(|x| x.trim().to_string()).filter(|x| !x.is_empty()).collect()
+    } else {
+        vec!(args.arg_query.join(" "))
+    };
+    let threshold = args.flag_threshold.unwrap_or_else(match_threshold);
+    let (username, password_hash) = super::login_args(&global_args).unwrap_or_else(|| {
+        println!("Requesting needs a login, use --username and --password");
+        process::exit(1);
+    });
+
+    let (mut client, client_r) = Client::new(&global_args.flag_host).unwrap();
+    client.set_audit_log(paths::audit_file());
+    client.serve();
+    client.follow(vec!(String::from("playing"), String::from("requests")));
+    client.do_login(&username, &password_hash);
+
+    let mut unresolved = 0;
+    let mut requested = Vec::new();
+    for query in &queries {
+        let results = search::search(&mut client, &client_r, &search_query(query), RESOLVE_COUNT);
+        match resolve(query, &results, threshold, args.flag_yes) {
+            Resolved::Song(media) => {
+                let key = search::request(&mut client, &client_r, media);
+                requested.push((key, media.clone()));
+            },
+            Resolved::NotFound => {
+                println!("Found no song for \"{}\"", query);
+                unresolved += 1;
+            },
+            Resolved::Ambiguous(candidates) => {
+                println!("Not sure which song \"{}\" is, the closest are:", query);
+                for (media, score) in candidates {
+                    println!("  {:3.0}%  {} - {}", score * 100.0, media.artist, media.title);
+                }
+                unresolved += 1;
+            },
+        }
+    }
+    if args.flag_wait && wait(&mut client, &client_r, requested) > 0 {
+        process::exit(1);
+    }
+    if unresolved > 0 {
+        process::exit(1);
+    }
+}
+
+/// A request that we wait for to start playing
+struct Waiting {
+    key: i64,
+    media: Media,
+    /// Where it was in the queue when we last told
+    position: Option<usize>,
+    /// Has it left the queue without starting to play (yet)?
+    left_queue: bool,
+}
+
+/// Wait until the requests with these keys start playing, and tell when they move up in the
+/// queue in the meantime, returns the number of requests that were removed from the queue
+///
+/// The server may tell that a request left the queue before it tells that the song is playing,
+/// so a request is only taken to be removed if another song starts after that.
+fn wait(client: &mut Client, client_r: &chan::Receiver<Json>,
+        requested: Vec<(i64, Media)>) -> usize {
+    let position = |client: &Client, key| {
+        client.get_requests().as_ref().and_then(|x| x.iter().position(|x| x.key == key))
+    };
+    let mut waiting: Vec<Waiting> = requested.into_iter().map(|(key, media)| Waiting {
+        key: key,
+        media: media,
+        position: position(client, key),
+        left_queue: false,
+    }).collect();
+    let mut removed = 0;
+    while !waiting.is_empty() {
+        let message = client_r.recv().unwrap();
+        let playing_changed = match client.handle_message(&message) {
+            Ok(Message::PlayingChanged) => true,
+            Ok(Message::Requests) => false,
+            Ok(_) => continue,
+            Err(err) => {
+                println!("Waiting for the songs failed: {}", DisplayChain(&err));
+                process::exit(1);
+            },
+        };
+        let playing = client.get_playing().as_ref().map(|x| x.media.key.clone());
+        let mut still_waiting = Vec::with_capacity(waiting.len());
+        for mut request in waiting {
+            let media = &request.media;
+            match position(client, request.key) {
+                Some(i) => {
+                    if request.position != Some(i) {
+                        println!("{} - {} is {}", media.artist, media.title,
+                                 search::queue_position(client, request.key));
+                        request.position = Some(i);
+                    }
+                },
+                None if playing.as_ref() == Some(&media.key) => {
+                    println!("Now playing {} - {}", media.artist, media.title);
+                    continue;
+                },
+                None if request.left_queue && playing_changed => {
+                    println!("{} - {} was removed from the queue", media.artist, media.title);
+                    removed += 1;
+                    continue;
+                },
+                None => request.left_queue = true,
+            }
+            still_waiting.push(request);
+        }
+        waiting = still_waiting;
+    }
+    removed
+}
+
+/// The song that a query names, if it names one
+#[derive(Debug)]
+enum Resolved<'a> {
+    Song(&'a Media),
+    NotFound,
+    /// Several songs are close to the query, or none is close enough; the closest ones and
+    /// their scores
+    Ambiguous(Vec<(&'a Media, f64)>),
+}
+
+/// The `match_threshold` in the configuration file, or the default one
+fn match_threshold() -> f64 {
+    let table = super::config_table();
+    match table.as_ref().and_then(|x| x.get("match_threshold")) {
+        Some(&Value::Float(threshold)) if threshold >= 0.0 && threshold <= 1.0 => threshold,
+        None => DEFAULT_MATCH_THRESHOLD,
+        Some(other) => {
+            warn!("ignoring match_threshold {}, it should be between 0 and 1", other);
+            DEFAULT_MATCH_THRESHOLD
+        },
+    }
+}
+
+/// Find the song in `results` that `query` names
+///
+/// Only the songs that are at least `threshold` similar to the query qualify, and when several
+/// do, only one that is exactly the same as the query wins. With `yes`, the closest song is
+/// taken anyway.
+fn resolve<'a>(query: &str, results: &'a [Media], threshold: f64, yes: bool) -> Resolved<'a> {
+    if results.is_empty() {
+        return Resolved::NotFound;
+    }
+    let mut scored: Vec<(&Media, f64)> = results.iter()
+        .map(|media| (media, similarity(query, media)))
+        .collect();
+    scored.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+    let close = scored.iter().take_while(|x| x.1 >= threshold).count();
+    if yes || close == 1 || (close > 1 && scored[0].1 == 1.0 && scored[1].1 < 1.0) {
+        return Resolved::Song(scored[0].0);
+    }
+    scored.truncate(CANDIDATES_SHOWN);
+    Resolved::Ambiguous(scored)
+}
+
+/// What to ask the server for to find the song that `query` names
+///
+/// The server looks for the words of a query, so separators like the dash between the artist
+/// and the title are left out.
+fn search_query(query: &str) -> String {
+    query.split_whitespace().filter(|x| x.chars().any(|x| x.is_alphanumeric()))
+         .collect::<Vec<_>>().join(" ")
+}
+
+
+#[test]
+fn test() {
+    let media = |artist: &str, title: &str| Media {
+        key: format!("{} - {}", artist, title),
+        artist: artist.to_string(),
+        title: title.to_string(),
+        length: ::time::Duration::seconds(200),
+        uploaded_by: "dsprenkels".to_string(),
+        album: None,
+        url: None,
+    };
+    let results = vec![media("Queens Of The Stone Age", "No One Knows"),
+                       media("Queens Of The Stone Age", "Go with the Flow"),
+                       media("Radiohead", "Creep"),
+                       media("Stone Temple Pilots", "Creep")];
+    let threshold = DEFAULT_MATCH_THRESHOLD;
+
+    assert_eq!(search_query("queens of the stone age - no one knows"),
+               "queens of the stone age no one knows");
+    assert_eq!(similarity("Queens of the Stone Age - No One Knows", &results[0]), 1.0);
+    match resolve("queens of the stone age - no one knows", &results, threshold, false) {
+        Resolved::Song(media) => assert_eq!(media.title, "No One Knows"),
+        other => panic!("{:?}", other),
+    }
+    // a typo is fine
+    match resolve("queens of the stoneage - no one know", &results, threshold, false) {
+        Resolved::Song(media) => assert_eq!(media.title, "No One Knows"),
+        other => panic!("{:?}", other),
+    }
+    match resolve("radiohead creep", &results, threshold, false) {
+        Resolved::Song(media) => assert_eq!(media.artist, "Radiohead"),
+        other => panic!("{:?}", other),
+    }
+    match resolve("creep", &results, threshold, false) {
+        Resolved::Ambiguous(songs) => assert!(songs[..2].iter().all(|x| x.0.title == "Creep")),
+        other => panic!("{:?}", other),
+    }
+    match resolve("queens of the stone age", &results, threshold, false) {
+        Resolved::Ambiguous(songs) => {
+            assert_eq!(songs.len(), 4);
+            assert!(songs[0].1 < threshold);
+        },
+        other => panic!("{:?}", other),
+    }
+    match resolve("queens of the stone age", &results, threshold, true) {
+        Resolved::Song(media) => assert_eq!(media.title, "No One Knows"),
+        other => panic!("{:?}", other),
+    }
+    match resolve("radiohead creep", &results, 1.1, false) {
+        Resolved::Ambiguous(_) => {},
+        other => panic!("{:?}", other),
+    }
+    match resolve("creep", &[], threshold, true) {
+        Resolved::NotFound => {},
+        other => panic!("{:?}", other),
+    }
+}
@@ -0,0 +1,249 @@
+use std::cmp::{max, min};
+use std::process;
+use std::time::Duration;
+
+use chan;
+use docopt::Docopt;
+use rustc_serialize::json::Json;
+use time::{Duration as TimeDuration, get_time};
+
+use format::Eta;
+use libclient::{Client, DisplayChain, MAX_LEFT_OUT_RESULTS, Media, Message};
+use paths;
+use picker;
+
+/// How long to wait for the server to answer the search, or to take the request
+const SEARCH_TIMEOUT_SECS: u64 = 10;
+
+/// How many songs are asked for at once when they are printed
+const PAGE_SIZE: usize = 100;
+
+#[derive(Debug, RustcDecodable)]
+pub struct Args {
+    arg_query: Vec<String>,
+    flag_interactive: bool,
+    flag_request: bool,
+    flag_count: usize,
+}
+
+pub const USAGE: &'static str = "
+Search the songs on the server
+
+Prints the media key, artist and title of the songs that match QUERY. With
+`--interactive`, you pick one of them instead: type to narrow the songs down
+(the letters that you type have to appear in that order), select one with the
+arrow keys and pick it with Enter, or cancel with Escape. The song that you
+picked is printed, or requested with `--request`.
+
+Usage:
+  maruska search [options] [<query>...]
+
+Options:
+  -i --interactive  Pick one of the songs that were found
+  --request         Request the song that was picked, which needs a login
+  -n --count <n>    Get at most this many songs [default: 100]
+  -h --help         Display this message
+";
+
+pub fn main(argv: Vec<String>, global_args: super::Args) {
+    let args: Args = Docopt::new(USAGE)
+        .map(|d| d.help(true))
+        .map(|d| d.argv(argv))
+        .and_then(|d| d.decode())
+        .unwrap_or_else(|e| e.exit());
+    execute(args, global_args);
+}
+
+pub fn execute(args: Args, global_args: super::Args) {
+    let query = args.arg_query.join(" ");
+    if query.is_empty() && !args.flag_interactive {
+        println!("What should I search for?");
+        process::exit(1);
+    }
+    if args.flag_request && !args.flag_interactive {
+        println!("Only a song that was picked can be requested, use --interactive");
+        process::exit(1);
+    }
+    let login = if args.flag_request {
+        Some(super::login_args(&global_args).unwrap_or_else(|| {
+            println!("Requesting needs a login, use --username and --password");
+            process::exit(1);
+        }))
+    } else {
+        None
+    };
+
+    let (mut client, client_r) = Client::new(&global_args.flag_host).unwrap();
+    client.set_audit_log(paths::audit_file());
+    client.serve();
+    if let Some((ref username, ref password_hash)) = login {
+        client.follow(vec!(String::from("playing"), String::from("requests")));
+        client.do_login(username, password_hash);
+    }
+    if !args.flag_interactive {
+        print_results(&mut client, &client_r, &query, args.flag_count);
+        return;
+    }
+    let results = search(&mut client, &client_r, &query, args.flag_count);
+    if results.is_empty() {
+        println!("Found no songs");
+        process::exit(1);
+    }
+    let items: Vec<String> = results.iter()
+                                    .map(|x| format!("{} - {}", x.artist, x.title))
+                                    .collect();
+    let media = match picker::pick(&items) {
+        Ok(Some(i)) => &results[i],
+        Ok(None) => process::exit(1),
+        Err(err) => {
+            println!("Could not show the picker: {}", err);
+            process::exit(1);
+        },
+    };
+    if args.flag_request {
+        request(&mut client, &client_r, media);
+    } else {
+        println!("{}", format_media(media));
+    }
+}
+
+/// Search for `query`, and wait until we have `count` results or the server has no more
+pub fn search(client: &mut Client, client_r: &chan::Receiver<Json>, query: &str,
+          count: usize) -> Vec<Media> {
+    client.update_query(Some(query), count);
+    let timeout = chan::after(Duration::from_secs(SEARCH_TIMEOUT_SECS));
+    loop {
+        let message;
+        chan_select! {
+            timeout.recv() => {
+                println!("The server did not answer the search");
+                process::exit(1);
+            },
+            client_r.recv() -> msg => message = msg.unwrap(),
+        }
+        match client.handle_message(&message) {
+            Ok(Message::QueryMediaResults) => {
+                if client.is_query_truncated() {
+                    println!("Stopped searching after leaving out {} songs, search for words of \
+                              the artist or title too", MAX_LEFT_OUT_RESULTS);
+                }
+                let (results, done) = client.get_qm_results();
+                if *done || results.len() >= count {
+                    return results.iter().take(count).cloned().collect();
+                }
+            },
+            Ok(Message::LoginError(msg)) => {
+                println!("Could not log in: {}", msg);
+                process::exit(1);
+            },
+            Ok(_) => {},
+            Err(err) => {
+                println!("Searching failed: {}", DisplayChain(&err));
+                process::exit(1);
+            },
+        }
+    }
+}
+
+/// Print at most `count` songs that match `query`, a page at a time as they arrive
+fn print_results(client: &mut Client, client_r: &chan::Receiver<Json>, query: &str,
+                 count: usize) {
+    let stream = client.query_media_stream(query, min(count, PAGE_SIZE));
+    let mut printed = 0;
+    while printed < count && !client.is_media_stream_done(stream) {
+        let timeout = chan::after(Duration::from_secs(SEARCH_TIMEOUT_SECS));
+        let message;
+        chan_select! {
+            timeout.recv() => {
+                println!("The server did not answer the search");
+                process::exit(1);
+            },
+            client_r.recv() -> msg => message = msg.unwrap(),
+        }
+        match client.handle_message(&message) {
+            Ok(Message::MediaPage(id)) if id == stream => {
+                let page = client.next_media_page(stream).unwrap_or_default();
+                for media in page.iter().take(count - printed) {
+                    println!("{}", format_media(media));
+                }
+                printed += page.len();
+            },
+            Ok(_) => {},
+            Err(err) => {
+                println!("Searching failed: {}", DisplayChain(&err));
+                process::exit(1);
+            },
+        }
+    }
+}
+
+/// Request `media`, and wait until it is in the queue, returns the key of the request
+///
+/// The client has to follow the requests, and should follow what is playing to tell when the
+/// song will play.
+pub fn request(client: &mut Client, client_r: &chan::Receiver<Json>, media: &Media) -> i64 {
+    let mut requested = false;
+    let timeout = chan::after(Duration::from_secs(SEARCH_TIMEOUT_SECS));
+    loop {
+        // wait for the queue first, so that our request is not mistaken for an earlier one
+        if !requested && client.get_requests().is_some() {
+            client.do_request(media);
+            requested = true;
+        }
+        let message;
+        chan_select! {
+            timeout.recv() => {
+                println!("The server did not take the request");
+                process::exit(1);
+            },
+            client_r.recv() -> msg => message = msg.unwrap(),
+        }
+        match client.handle_message(&message) {
+            Ok(Message::LoginError(msg)) => {
+                println!("Could not log in: {}", msg);
+                process::exit(1);
+            },
+            Ok(Message::Requests) => {
+                let queued = client.get_queued_requests().iter().find(|x| x.media.key == media.key);
+                if let Some(request) = queued {
+                    println!("Requested {} - {}, {}", media.artist, media.title,
+                             queue_position(client, request.key));
+                    return request.key;
+                }
+            },
+            Ok(_) => {},
+            Err(err) => {
+                println!("Requesting failed: {}", DisplayChain(&err));
+                process::exit(1);
+            },
+        }
+    }
+}
+
+/// Where the request with `key` is in the queue and when it plays, e.g. "queued at position 5,
+/// plays in ~17 min"
+pub fn queue_position(client: &Client, key: i64) -> String {
+    let requests = client.get_requests().as_ref().map_or(&[][..], |x| &x[..]);
+    let i = match requests.iter().position(|x| x.key == key) {
+        Some(i) => i,
+        None => return "but it is not in the queue anymore".to_string(),
+    };
+    let position = format!("queued at position {}", i + 1);
+    let playing = match *client.get_playing() {
+        Some(ref playing) => playing,
+        None => return position,
+    };
+    let wait = requests[..i].iter().fold(max(playing.end_time - get_time(), TimeDuration::zero()),
+                                         |acc, x| acc + x.media.length);
+    let time_format = super::time_format();
+    match time_format.eta {
+        Eta::Duration => {
+            format!("{}, plays in ~{} min", position, max((wait.num_seconds() + 30) / 60, 1))
+        },
+        Eta::Time => format!("{}, plays at {}", position, time_format.time(get_time() + wait)),
+    }
+}
+
+fn format_media(media: &Media) -> String {
+    format!("{}  {} - {}", media.key, media.artist, media.title)
+}
@@ -0,0 +1 @@
+../serialize.rs
\ No newline at end of file
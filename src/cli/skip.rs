@@ -0,0 +1,103 @@
+use std::process;
+use std::time::Duration;
+
+use chan;
+use docopt::Docopt;
+
+use libclient::{Client, DisplayChain, Message};
+use paths;
+
+/// How long to wait for the server to skip the song or to count our vote
+const SKIP_TIMEOUT_SECS: u64 = 10;
+
+#[derive(Debug, RustcDecodable)]
+pub struct Args {
+    flag_vote: bool,
+}
+
+pub const USAGE: &'static str = "
+Skip the song that is currently playing
+
+Skipping right away is usually only allowed for moderators, the others can vote
+to skip the song. Voting needs a server that supports skip votes. The song is
+skipped once enough people voted to skip it.
+
+Usage:
+  maruska skip [options]
+
+Options:
+  --vote        Vote to skip the song, instead of skipping it right away
+  -h --help     Display this message
+";
+
+pub fn main(argv: Vec<String>, global_args: super::Args) {
+    let args: Args = Docopt::new(USAGE)
+        .map(|d| d.help(true))
+        .map(|d| d.argv(argv))
+        .and_then(|d| d.decode())
+        .unwrap_or_else(|e| e.exit());
+    execute(args, global_args);
+}
+
+pub fn execute(args: Args, global_args: super::Args) {
+    let (username, password_hash) = super::login_args(&global_args).unwrap_or_else(|| {
+        println!("Skipping needs a login, use --username and --password");
+        process::exit(1);
+    });
+
+    let (mut client, client_r) = Client::new(&global_args.flag_host).unwrap();
+    client.set_audit_log(paths::audit_file());
+    if !args.flag_vote {
+        client.follow(vec!(String::from("playing")));
+    }
+    client.serve();
+    client.do_login(&username, &password_hash);
+    if args.flag_vote {
+        client.vote_skip();
+    }
+
+    let timeout = chan::after(Duration::from_secs(SKIP_TIMEOUT_SECS));
+    let mut skipping = false;
+    loop {
+        let message;
+        chan_select! {
+            timeout.recv() => {
+                if args.flag_vote {
+                    println!("The server did not count the vote, maybe it does not support skip \
+                              votes");
+                } else {
+                    println!("The server did not skip the song, maybe you are not allowed to");
+                }
+                process::exit(1);
+            },
+            client_r.recv() -> msg => message = msg.unwrap(),
+        }
+        match client.handle_message(&message) {
+            Ok(Message::SkipVotes(votes, needed)) if args.flag_vote => {
+                println!("{} of the {} needed votes to skip the playing song", votes, needed);
+                return;
+            },
+            Ok(Message::PlayingChanged) if skipping => {
+                if let Some(ref playing) = *client.get_playing() {
+                    println!("Skipped, now playing {} - {}", playing.media.artist,
+                             playing.media.title);
+                }
+                return;
+            },
+            Ok(Message::LoginError(msg)) => {
+                println!("Could not log in: {}", msg);
+                process::exit(1);
+            },
+            Ok(_) => {},
+            Err(err) => {
+                println!("Skipping failed: {}", DisplayChain(&err));
+                process::exit(1);
+            },
+        }
+        // skip once we know what is playing, so that it is that song that is skipped
+        if !args.flag_vote && !skipping && client.is_logged_in() && client.get_playing().is_some() {
+            client.do_skip();
+            skipping = true;
+        }
+    }
+}
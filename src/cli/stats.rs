@@ -0,0 +1,78 @@
+use std::process;
+
+use docopt::Docopt;
+
+use libclient::stats::{Counts, Stats};
+use paths;
+
+#[derive(Debug, RustcDecodable)]
+pub struct Args {
+    flag_top: bool,
+    flag_count: usize,
+}
+
+pub const USAGE: &'static str = "
+Show how often songs were requested and played
+
+The server does not keep count, so the terminal UI counts the requests and the
+songs that are played while it is running. Without options, the totals and the
+songs that you requested most often are shown.
+
+Usage:
+  maruska stats [options]
+
+Options:
+  --top            List the songs that were requested and played most often
+  -n --count <n>   List this many songs [default: 10]
+  -h --help        Display this message
+";
+
+pub fn main(argv: Vec<String>, global_args: super::Args) {
+    let args: Args = Docopt::new(USAGE)
+        .map(|d| d.help(true))
+        .map(|d| d.argv(argv))
+        .and_then(|d| d.decode())
+        .unwrap_or_else(|e| e.exit());
+    execute(args, global_args);
+}
+
+pub fn execute(args: Args, global_args: super::Args) {
+    let path = paths::stats_file(&global_args.flag_host).unwrap_or_else(|| {
+        println!("Could not find a state directory");
+        process::exit(1);
+    });
+    let stats = Stats::load(&path).unwrap_or_else(|err| {
+        println!("Could not read the stats from {}: {}", path.display(), err);
+        process::exit(1);
+    });
+
+    if args.flag_top {
+        print_songs(stats.top(args.flag_count).into_iter().map(|x| x.1));
+        return;
+    }
+    let (mut requested, mut requested_by_you, mut played) = (0, 0, 0);
+    for (_, counts) in stats.songs() {
+        requested += counts.requested;
+        requested_by_you += counts.requested_by_you;
+        played += counts.played;
+    }
+    println!("Counted {} requests ({} by you) and {} plays of {} songs", requested,
+             requested_by_you, played, stats.songs().count());
+
+    let mut yours: Vec<&Counts> = stats.songs().map(|x| x.1)
+                                        .filter(|x| x.requested_by_you > 0)
+                                        .collect();
+    yours.sort_by(|a, b| b.requested_by_you.cmp(&a.requested_by_you));
+    if !yours.is_empty() {
+        println!("\nYou requested most often:");
+        print_songs(yours.into_iter().take(args.flag_count));
+    }
+}
+
+fn print_songs<'a, I: Iterator<Item=&'a Counts>>(songs: I) {
+    println!("{:>9} {:>4} {:>6}  {}", "requested", "you", "played", "song");
+    for counts in songs {
+        println!("{:>9} {:>4} {:>6}  {} - {}", counts.requested, counts.requested_by_you,
+                 counts.played, counts.artist, counts.title);
+    }
+}
@@ -0,0 +1,79 @@
+use std::process;
+
+use docopt::Docopt;
+
+use libclient::{Client, DisplayChain};
+use template::{self, StatusFields};
+
+#[derive(Debug, RustcDecodable)]
+pub struct Args {
+    flag_format: Option<String>,
+}
+
+pub const USAGE: &'static str = "
+Print a line about what is playing, e.g. for the status bar of a window manager
+
+The line is a template, in which the placeholders between braces are filled in
+the same way as in the `header` and `status` templates of the terminal UI. The
+`status` template of config.toml is used, unless another one is given:
+
+  maruska status --format '{playing_artist} - {playing_title} ({remaining})'
+
+Placeholders:
+  {user}            The user that would log in
+  {server}          The host of the server
+  {playing_artist}  The artist of the song that is playing
+  {playing_title}   The title of the song that is playing
+  {requested_by}    Who requested the song that is playing
+  {remaining}       When the song that is playing ends
+  {queue_length}    The number of requests in the queue
+  {queue_end}       The time at which the queue runs dry
+  {time}            The time of day
+
+Usage:
+  maruska status [options]
+
+Options:
+  -f --format <template>  The template to fill in
+  -h --help               Display this message
+";
+
+/// The template if none is given and the configuration file has none
+const DEFAULT_FORMAT: &'static str = "{playing_artist} - {playing_title} ({remaining})";
+
+pub fn main(argv: Vec<String>, global_args: super::Args) {
+    let args: Args = Docopt::new(USAGE)
+        .map(|d| d.help(true))
+        .map(|d| d.argv(argv))
+        .and_then(|d| d.decode())
+        .unwrap_or_else(|e| e.exit());
+    execute(args, global_args);
+}
+
+pub fn execute(args: Args, global_args: super::Args) {
+    let format = args.flag_format.clone().unwrap_or_else(|| {
+        super::config_table().and_then(|table| {
+            table.get("status").and_then(|x| x.as_str()).map(String::from)
+        }).unwrap_or_else(|| String::from(DEFAULT_FORMAT))
+    });
+
+    let (mut client, client_r) = Client::new(&global_args.flag_host).unwrap();
+    client.follow_all();
+    client.serve();
+
+    while client.get_playing() == &None || client.get_requests() == &None {
+        let message = client_r.recv().unwrap();
+        if let Err(err) = client.handle_message(&message) {
+            println!("Could not read the status: {}", DisplayChain(&err));
+            process::exit(1);
+        }
+    }
+
+    // We do not log in, so the user is the one that we would log in as
+    let user = super::username_arg(&global_args);
+    let fields = StatusFields::new(&client, super::time_format());
+    println!("{}", template::expand(&format, |name| match name {
+        "user" => Some(user.clone().unwrap_or_default()),
+        _ => fields.get(name),
+    }));
+}
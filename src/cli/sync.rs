@@ -0,0 +1,75 @@
+use std::process;
+
+use docopt::Docopt;
+
+use libclient::{DisplayChain, Media};
+use libclient::cache::MediaCache;
+use libclient::sync::sync_media;
+use libclient::watchlist::Watchlist;
+use paths;
+
+#[derive(Debug, RustcDecodable)]
+pub struct Args {
+    flag_full: bool,
+}
+
+pub const USAGE: &'static str = "
+Download the media index of the server into the local cache
+
+The first time, the whole index is downloaded. After that, only the songs that
+were added or changed since the last sync are downloaded, if the server
+supports it.
+
+Usage:
+  maruska sync [options]
+
+Options:
+  -f --full     Download the whole index, also to find songs that were removed
+  -h --help     Display this message
+";
+
+pub fn main(argv: Vec<String>, global_args: super::Args) {
+    let args: Args = Docopt::new(USAGE)
+        .map(|d| d.help(true))
+        .map(|d| d.argv(argv))
+        .and_then(|d| d.decode())
+        .unwrap_or_else(|e| e.exit());
+    execute(args, global_args);
+}
+
+pub fn execute(args: Args, global_args: super::Args) {
+    let path = paths::media_cache_file(&global_args.flag_host).unwrap_or_else(|| {
+        println!("Could not find a cache directory");
+        process::exit(1);
+    });
+    let result = MediaCache::open_or_reset(&path).map_err(From::from).and_then(|mut cache| {
+        sync_media(&global_args.flag_host, &mut cache, args.flag_full).map(|x| (x, cache.len()))
+    });
+    match result {
+        Ok((stats, total)) => {
+            let kind = if stats.full { "full" } else { "incremental" };
+            println!("Received {} songs ({} sync), removed {}, {} songs in {}",
+                     stats.received, kind, stats.removed, total, path.display());
+            print_watched(&stats.added);
+        },
+        Err(err) => {
+            println!("Sync failed: {}", DisplayChain(&err));
+            process::exit(1);
+        },
+    }
+}
+
+/// Print the new songs that match a saved search, see `maruska watchlist`
+fn print_watched(added: &[Media]) {
+    let watchlist = match paths::watchlist_file().map(|x| Watchlist::load(&x)) {
+        Some(Ok(watchlist)) => watchlist,
+        Some(Err(err)) => {
+            println!("Could not read the watchlist: {}", err);
+            return;
+        },
+        None => return,
+    };
+    for (search, media) in watchlist.find(added) {
+        println!("New song for \"{}\": {} - {}", search, media.artist, media.title);
+    }
+}
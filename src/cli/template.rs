@@ -0,0 +1 @@
+../template.rs
\ No newline at end of file
@@ -0,0 +1,132 @@
+use std::cmp::{max, min};
+use std::process;
+use std::time::Duration;
+
+use chan;
+use docopt::Docopt;
+
+use libclient::{Client, DisplayChain, Message, Request};
+use paths;
+
+/// How long to wait for the server to move the request
+const MOVE_TIMEOUT_SECS: u64 = 10;
+
+#[derive(Debug, RustcDecodable)]
+pub struct Args {
+    arg_request: i64,
+    flag_top: bool,
+}
+
+pub const USAGE: &'static str = "
+Move a request up in the queue
+
+REQUEST is the key of the request. You can usually only move your own requests,
+and moving a request to the front needs a server that allows it.
+
+Usage:
+  maruska up [options] <request>
+
+Options:
+  --top         Move the request to the front of the queue
+  -h --help     Display this message
+";
+
+pub fn main(argv: Vec<String>, global_args: super::Args) {
+    let args: Args = Docopt::new(USAGE)
+        .map(|d| d.help(true))
+        .map(|d| d.argv(argv))
+        .and_then(|d| d.decode())
+        .unwrap_or_else(|e| e.exit());
+    execute(args, global_args);
+}
+
+pub fn execute(args: Args, global_args: super::Args) {
+    if args.flag_top {
+        move_request(args.arg_request, None, global_args);
+    } else {
+        move_request(args.arg_request, Some(-1), global_args);
+    }
+}
+
+/// Move the request with key `key` by `amount` places, or to the front of the queue if it is
+/// `None`, and wait until the server did
+pub fn move_request(key: i64, amount: Option<i64>, global_args: super::Args) {
+    let (username, password_hash) = super::login_args(&global_args).unwrap_or_else(|| {
+        println!("Moving requests needs a login, use --username and --password");
+        process::exit(1);
+    });
+
+    let (mut client, client_r) = Client::new(&global_args.flag_host).unwrap();
+    client.set_audit_log(paths::audit_file());
+    client.follow(vec!(String::from("requests")));
+    client.serve();
+    client.do_login(&username, &password_hash);
+
+    let timeout = chan::after(Duration::from_secs(MOVE_TIMEOUT_SECS));
+    let mut logged_in = false;
+    // moving to the front is a boost, which is allowed for other requests than moving them
+    let allowed = |client: &Client, request: &Request| match amount {
+        Some(_) => client.can_manage(request),
+        None => client.can_boost(request),
+    };
+    // Where the request was when we asked the server to move it
+    let mut moving: Option<usize> = None;
+    loop {
+        let message;
+        chan_select! {
+            timeout.recv() => {
+                println!("The server did not move the request");
+                process::exit(1);
+            },
+            client_r.recv() -> msg => message = msg.unwrap(),
+        }
+        match client.handle_message(&message) {
+            Ok(Message::Login) => logged_in = true,
+            Ok(Message::LoginError(msg)) => {
+                println!("Could not log in: {}", msg);
+                process::exit(1);
+            },
+            Ok(_) => {},
+            Err(err) => {
+                println!("Moving the request failed: {}", DisplayChain(&err));
+                process::exit(1);
+            },
+        }
+        let (position, len) = match *client.get_requests() {
+            Some(ref requests) if logged_in => {
+                match requests.iter().position(|x| x.key == key) {
+                    Some(i) if moving.is_none() && !allowed(&client, &requests[i]) => {
+                        println!("You are not allowed to move request {}", key);
+                        process::exit(1);
+                    },
+                    Some(i) => (i, requests.len()),
+                    None => {
+                        println!("There is no request {} in the queue", key);
+                        process::exit(1);
+                    },
+                }
+            },
+            _ => continue,
+        };
+        match moving {
+            Some(from) if position == from => {},
+            Some(_) => {
+                println!("Request {} is at place {} in the queue", key, position + 1);
+                return;
+            },
+            None => {
+                // the server does not move a request past either end
+                let to = amount.map_or(0, |x| max(0, min(len as i64 - 1, position as i64 + x)));
+                if to as usize == position {
+                    println!("Request {} is at place {} in the queue already", key, position + 1);
+                    return;
+                }
+                match amount {
+                    Some(amount) => client.do_move_request(key, amount),
+                    None => client.do_boost(key),
+                };
+                moving = Some(position);
+            },
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use std::process;
+
+use docopt::Docopt;
+
+use libclient::DisplayChain;
+use libclient::sync::open_synced;
+use paths;
+
+#[derive(Debug, RustcDecodable)]
+pub struct Args {
+    arg_word: Vec<String>,
+    flag_user: Option<String>,
+}
+
+pub const USAGE: &'static str = "
+List the songs that someone uploaded
+
+The songs are looked up in the local copy of the media index. If there is none
+yet, the index is downloaded first, see `maruska sync`. With words, only the
+songs whose artist, title or album contain all of them are listed; case and
+accents do not matter, so \"motorhead\" finds Motörhead.
+
+Usage:
+  maruska uploads [options] [<word>...]
+
+Options:
+  --user <name>  List the uploads of this user, instead of your own
+  -h --help      Display this message
+";
+
+pub fn main(argv: Vec<String>, global_args: super::Args) {
+    let args: Args = Docopt::new(USAGE)
+        .map(|d| d.help(true))
+        .map(|d| d.argv(argv))
+        .and_then(|d| d.decode())
+        .unwrap_or_else(|e| e.exit());
+    execute(args, global_args);
+}
+
+pub fn execute(args: Args, global_args: super::Args) {
+    let user = args.flag_user.or_else(|| super::username_arg(&global_args)).unwrap_or_else(|| {
+        println!("Whose uploads? Use --user");
+        process::exit(1);
+    });
+    let path = paths::media_cache_file(&global_args.flag_host).unwrap_or_else(|| {
+        println!("Could not find a cache directory");
+        process::exit(1);
+    });
+    let cache = open_synced(&global_args.flag_host, &path).unwrap_or_else(|err| {
+        println!("Could not read the media index: {}", DisplayChain(&err));
+        process::exit(1);
+    });
+
+    let query = args.arg_word.join(" ");
+    let uploads: Vec<_> = cache.uploaded_by(&user).into_iter()
+                               .filter(|x| x.matches(&query))
+                               .collect();
+    for media in &uploads {
+        println!("{} - {}", media.artist, media.title);
+    }
+    println!("{} uploaded {} songs", user, uploads.len());
+}
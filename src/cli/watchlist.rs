@@ -0,0 +1,112 @@
+use std::path::PathBuf;
+use std::process;
+
+use docopt::Docopt;
+
+use libclient::DisplayChain;
+use libclient::sync::open_synced;
+use libclient::watchlist::Watchlist;
+use paths;
+
+#[derive(Debug, RustcDecodable)]
+pub struct Args {
+    cmd_add: bool,
+    cmd_remove: bool,
+    cmd_check: bool,
+    arg_search: Vec<String>,
+}
+
+pub const USAGE: &'static str = "
+Manage the saved searches of which new songs are announced
+
+Whenever the media index is synced (by `maruska sync`, or in the background by
+the terminal UI), the new songs that match a saved search are shown. A search
+is made of words that the artist, title or album of a song must contain. A word
+can be kept to one tag by putting the tag in front of it: `artist:tool` only
+matches songs of which the artist contains \"tool\"; the tags are artist, title,
+album and uploader. Case and accents do not matter.
+
+Without a subcommand, the saved searches are listed. `check` lists the songs
+in the local media index that match them, also the ones that are not new.
+
+Usage:
+  maruska watchlist [list]
+  maruska watchlist add <search>...
+  maruska watchlist remove <search>...
+  maruska watchlist check
+
+Options:
+  -h --help     Display this message
+";
+
+pub fn main(argv: Vec<String>, global_args: super::Args) {
+    let args: Args = Docopt::new(USAGE)
+        .map(|d| d.help(true))
+        .map(|d| d.argv(argv))
+        .and_then(|d| d.decode())
+        .unwrap_or_else(|e| e.exit());
+    execute(args, global_args);
+}
+
+pub fn execute(args: Args, global_args: super::Args) {
+    let path = paths::watchlist_file().unwrap_or_else(|| {
+        println!("Could not find a state directory");
+        process::exit(1);
+    });
+    let mut watchlist = load(&path);
+    let search = args.arg_search.join(" ");
+
+    if args.cmd_add || args.cmd_remove {
+        let changed = if args.cmd_add {
+            watchlist.add(&search)
+        } else {
+            watchlist.remove(&search)
+        };
+        if !changed {
+            let msg = if args.cmd_add { "is saved already" } else { "is not saved" };
+            println!("\"{}\" {}", search, msg);
+            process::exit(1);
+        }
+        if let Err(err) = watchlist.save(&path) {
+            println!("Could not save the watchlist to {}: {}", path.display(), err);
+            process::exit(1);
+        }
+        let msg = if args.cmd_add { "Saved" } else { "Removed" };
+        println!("{} \"{}\"", msg, search);
+    } else if args.cmd_check {
+        let cache_path = paths::media_cache_file(&global_args.flag_host).unwrap_or_else(|| {
+            println!("Could not find a cache directory");
+            process::exit(1);
+        });
+        let cache = open_synced(&global_args.flag_host, &cache_path).unwrap_or_else(|err| {
+            println!("Could not read the media index: {}", DisplayChain(&err));
+            process::exit(1);
+        });
+        let media: Vec<_> = cache.media().cloned().collect();
+        print_matches(&watchlist, &media);
+    } else {
+        for search in watchlist.searches() {
+            println!("{}", search);
+        }
+        if watchlist.searches().is_empty() {
+            println!("No saved searches, add one with `maruska watchlist add SEARCH`");
+        }
+    }
+}
+
+/// Print the songs of `media` that match a saved search of `watchlist`
+pub fn print_matches(watchlist: &Watchlist, media: &[::libclient::Media]) {
+    let matches = watchlist.find(media);
+    for &(search, media) in &matches {
+        println!("{} - {}  ({})", media.artist, media.title, search);
+    }
+    println!("{} songs match your watchlist", matches.len());
+}
+
+/// The watchlist in the file at `path`, exits if it cannot be read
+pub fn load(path: &PathBuf) -> Watchlist {
+    Watchlist::load(path).unwrap_or_else(|err| {
+        println!("Could not read the watchlist from {}: {}", path.display(), err);
+        process::exit(1);
+    })
+}
@@ -0,0 +1,83 @@
+//! Copying text to the system clipboard.
+//!
+//! The text is piped to the clipboard tool of the platform. Over SSH, those would copy to the
+//! clipboard of the wrong machine, so then (or if there is no tool) we ask the terminal to copy it
+//! with OSC 52. Most terminals support that, although some only after it was enabled.
+
+use std::env;
+use std::io::{stdout, Error as IOError, ErrorKind, Write};
+use std::process::{Command, Stdio};
+
+use rustc_serialize::base64::{self, STANDARD, ToBase64};
+
+/// How the text was copied
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Copied {
+    /// By a clipboard tool, so it is on the clipboard
+    Tool,
+    /// By the terminal, which may have ignored it
+    Terminal,
+}
+
+/// Copy `text` to the clipboard
+pub fn copy(text: &str) -> Result<Copied, IOError> {
+    let over_ssh = env::var_os("SSH_TTY").is_some() || env::var_os("SSH_CONNECTION").is_some();
+    if !over_ssh {
+        for tool in tools() {
+            match pipe_to(tool, text) {
+                Ok(()) => return Ok(Copied::Tool),
+                Err(err) => debug!("could not copy with {}: {}", tool[0], err),
+            }
+        }
+    }
+    let mut out = stdout();
+    try!(out.write_all(osc52(text).as_bytes()).and_then(|_| out.flush()));
+    Ok(Copied::Terminal)
+}
+
+/// The clipboard tools that may work here, with their arguments
+fn tools() -> Vec<&'static [&'static str]> {
+    if cfg!(target_os = "macos") {
+        return vec!(&["pbcopy"]);
+    }
+    if cfg!(windows) {
+        return vec!(&["clip"]);
+    }
+    let mut tools: Vec<&'static [&'static str]> = Vec::new();
+    if env::var_os("WAYLAND_DISPLAY").is_some() {
+        tools.push(&["wl-copy"]);
+    }
+    if env::var_os("DISPLAY").is_some() {
+        tools.push(&["xclip", "-selection", "clipboard"]);
+        tools.push(&["xsel", "--clipboard", "--input"]);
+    }
+    tools
+}
+
+fn pipe_to(tool: &[&str], text: &str) -> Result<(), IOError> {
+    let mut child = try!(Command::new(tool[0])
+        .args(&tool[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn());
+    // the tool stops reading when its stdin is closed, which happens when it is dropped
+    try!(child.stdin.take().unwrap().write_all(text.as_bytes()));
+    let status = try!(child.wait());
+    if !status.success() {
+        return Err(IOError::new(ErrorKind::Other, format!("{} exited with {}", tool[0], status)));
+    }
+    Ok(())
+}
+
+/// The escape sequence that asks the terminal to copy `text` to the clipboard
+fn osc52(text: &str) -> String {
+    let data = text.as_bytes().to_base64(base64::Config { line_length: None, ..STANDARD });
+    format!("\x1b]52;c;{}\x07", data)
+}
+
+
+#[test]
+fn test() {
+    assert_eq!(osc52("Tool – Schism"), "\x1b]52;c;VG9vbCDigJMgU2NoaXNt\x07");
+}
@@ -0,0 +1,85 @@
+//! Finding the command that the user meant, from what they typed.
+//!
+//! The `:` commands of the terminal UI and the subcommands of `maruska-cli` may be shortened to any
+//! prefix that only one command starts with, e.g. `:q` for `:quit`. An alias with that name goes
+//! first, see the `aliases` module. When nothing matches, the command that is closest to what was
+//! typed is suggested.
+
+use std::cmp::min;
+
+use strsim::levenshtein;
+
+/// What a typed command name stands for, see `resolve`
+#[derive(Debug, PartialEq)]
+pub enum Resolved<'a> {
+    /// The command itself, or a prefix that only this command starts with
+    Command(&'a str),
+    /// A prefix of all of these commands
+    Ambiguous(Vec<&'a str>),
+    /// Not a command, but maybe a typo of this one
+    Unknown(Option<&'a str>),
+}
+
+/// The command of `commands` that `name` is, or that it is the start of
+pub fn resolve<'a>(commands: &[&'a str], name: &str) -> Resolved<'a> {
+    if let Some(command) = commands.iter().find(|x| **x == name) {
+        return Resolved::Command(command);
+    }
+    let mut matching: Vec<&str> = commands.iter().cloned()
+                                          .filter(|x| !name.is_empty() && x.starts_with(name))
+                                          .collect();
+    match matching.len() {
+        0 => Resolved::Unknown(suggest(commands, name)),
+        1 => Resolved::Command(matching.remove(0)),
+        _ => Resolved::Ambiguous(matching),
+    }
+}
+
+/// The command of `commands` that `name` is most likely a typo of, if it is close enough to one
+///
+/// A name that is a typo of the start of a command (e.g. `uplp` for `uploads`) counts as well.
+pub fn suggest<'a>(commands: &[&'a str], name: &str) -> Option<&'a str> {
+    let len = name.chars().count();
+    // two typos in a short name make it a different word
+    let max_distance = if len <= 2 { 1 } else if len <= 5 { 2 } else { 3 };
+    commands.iter().cloned().map(|command| {
+        let mut distance = levenshtein(name, command);
+        let start: String = command.chars().take(len).collect();
+        // a single typo in the start of a longer command
+        if len >= 3 && levenshtein(name, &start) <= 1 {
+            distance = min(distance, 1);
+        }
+        (command, distance)
+    }).filter(|&(_, distance)| distance <= max_distance)
+      .min_by_key(|&(_, distance)| distance)
+      .map(|(command, _)| command)
+}
+
+/// `items` as a list in a sentence, e.g. `a, b or c`
+pub fn or_list<S: AsRef<str>>(items: &[S]) -> String {
+    match items.split_last() {
+        Some((last, rest)) if !rest.is_empty() => {
+            let rest: Vec<&str> = rest.iter().map(|x| x.as_ref()).collect();
+            format!("{} or {}", rest.join(", "), last.as_ref())
+        },
+        Some((last, _)) => last.as_ref().to_string(),
+        None => String::new(),
+    }
+}
+
+
+#[test]
+fn test() {
+    let commands = ["username", "password", "passphrase", "uploads", "playing", "quit"];
+    assert_eq!(resolve(&commands, "quit"), Resolved::Command("quit"));
+    assert_eq!(resolve(&commands, "q"), Resolved::Command("quit"));
+    assert_eq!(resolve(&commands, "user"), Resolved::Command("username"));
+    assert_eq!(resolve(&commands, "pass"), Resolved::Ambiguous(vec!["password", "passphrase"]));
+    assert_eq!(resolve(&commands, "quiet"), Resolved::Unknown(Some("quit")));
+    assert_eq!(resolve(&commands, "uplp"), Resolved::Unknown(Some("uploads")));
+    assert_eq!(resolve(&commands, "x"), Resolved::Unknown(None));
+    assert_eq!(resolve(&commands, "xyz"), Resolved::Unknown(None));
+    assert_eq!(resolve(&commands, ""), Resolved::Unknown(None));
+    assert_eq!(or_list(&["a", "b", "c"]), "a, b or c");
+    assert_eq!(or_list(&["a"]), "a");
+}
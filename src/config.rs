@@ -0,0 +1,285 @@
+//! The user's configuration, which is read from `config.toml` in the config directory.
+
+use std::cmp::max;
+use std::collections::BTreeMap;
+
+use toml::Value;
+
+use aliases;
+use format::TimeFormat;
+use libclient::DisplayChain;
+use notify::QuietHours;
+use paths;
+use store;
+
+/// The format of the configuration file
+///
+/// The file is written by the user, so never add migrations here: they would rewrite the file and
+/// throw away the user's comments. Keep accepting the old keys instead.
+const SCHEMA: store::Schema = store::Schema { migrations: &[] };
+
+#[derive(Debug)]
+pub struct Config {
+    /// Restore the query and the scroll position of the last session on startup
+    pub restore_session: bool,
+
+    /// Encrypt the saved credentials with a passphrase, which is asked once per session
+    pub encrypt_credentials: bool,
+
+    /// Sync the local media index every this many minutes (if there is one), 0 to never sync
+    pub media_sync_interval: u64,
+
+    /// Poll the server less often and stop redrawing after this many minutes without input or a
+    /// new song, 0 to never do so
+    pub idle_minutes: u64,
+
+    /// Print how long we were connected, the songs that were played and the traffic on quit
+    pub session_summary: bool,
+
+    /// Answer searches from the local media index (if it is synced) instead of asking the server
+    pub local_search: bool,
+
+    /// How to show times
+    pub time_format: TimeFormat,
+
+    /// The command that previews songs, which gets the url of the song as its last argument
+    pub preview_command: Option<String>,
+
+    /// Stop previews after this many seconds
+    pub preview_seconds: u64,
+
+    /// How to draw cover art: "kitty", "sixel", "none", or "auto" to guess it from the terminal
+    pub cover_art: String,
+
+    /// Look up cover art on MusicBrainz if the server has none, which tells them what is playing
+    pub cover_art_lookup: bool,
+
+    /// The command that prints the lyrics of a song, which gets the artist and the title of the
+    /// song as its last two arguments
+    pub lyrics_command: Option<String>,
+
+    /// Show the songs that were requested and played most often first in the search results,
+    /// instead of in the order of the server
+    pub popular_first: bool,
+
+    /// Show the search results that match the query best first, see `libclient::rank`
+    pub relevance_first: bool,
+
+    /// Also send a desktop notification when one of our requests is up next
+    pub notify_next_up: bool,
+
+    /// Also send a desktop notification when a new song matches a saved search, see `watchlist`
+    pub notify_watchlist: bool,
+
+    /// Send no desktop notifications and run no hooks during these hours of every day
+    pub quiet_hours: Option<QuietHours>,
+
+    /// Show events from the server, like an accepted request, in toasts at the top right instead
+    /// of on the status line
+    pub toasts: bool,
+
+    /// Read commands from other programs from a named pipe, see `fifo`
+    pub command_fifo: bool,
+
+    /// Show the queue and the search results side by side, if the terminal is wide enough
+    pub split_layout: bool,
+
+    /// The line at the top of the screen, a template with placeholders like `{playing_title}`
+    /// (see `template::StatusFields`), or no such line
+    pub header: Option<String>,
+
+    /// What the status line shows when there is no message, a template like `header`, instead
+    /// of the time and when the queue ends
+    pub status: Option<String>,
+
+    /// Commands that can be run on the selected song, by the key that runs them
+    pub actions: BTreeMap<char, String>,
+
+    /// What the names of the `[aliases]` table stand for, see `aliases`
+    pub aliases: BTreeMap<String, String>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            restore_session: false,
+            encrypt_credentials: false,
+            media_sync_interval: 60,
+            idle_minutes: 0,
+            session_summary: false,
+            local_search: false,
+            time_format: TimeFormat::default(),
+            preview_command: None,
+            preview_seconds: 15,
+            cover_art: String::from("auto"),
+            cover_art_lookup: false,
+            lyrics_command: None,
+            popular_first: false,
+            relevance_first: false,
+            notify_next_up: false,
+            notify_watchlist: false,
+            quiet_hours: None,
+            toasts: true,
+            command_fifo: false,
+            split_layout: false,
+            header: None,
+            status: None,
+            actions: BTreeMap::new(),
+            aliases: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Load the configuration file, or use the defaults if there is none
+    pub fn load() -> Config {
+        let config_filename = match paths::config_file() {
+            Some(x) => x,
+            None => return Config::default(),
+        };
+        if !config_filename.exists() {
+            return Config::default();
+        }
+        match store::load_file(&config_filename, &SCHEMA) {
+            Ok(table) => Config::from_table(&table),
+            Err(err) => {
+                warn!("ignoring invalid config file {:?}: {}", config_filename, DisplayChain(&err));
+                Config::default()
+            },
+        }
+    }
+
+    fn from_table(table: &BTreeMap<String, Value>) -> Config {
+        let mut config = Config::default();
+        if let Some(val) = table.get("restore_session").and_then(|x| x.as_bool()) {
+            config.restore_session = val;
+        }
+        if let Some(val) = table.get("encrypt_credentials").and_then(|x| x.as_bool()) {
+            config.encrypt_credentials = val;
+        }
+        if let Some(val) = table.get("media_sync_interval").and_then(|x| x.as_integer()) {
+            config.media_sync_interval = max(val, 0) as u64;
+        }
+        if let Some(val) = table.get("idle_minutes").and_then(|x| x.as_integer()) {
+            config.idle_minutes = max(val, 0) as u64;
+        }
+        if let Some(val) = table.get("session_summary").and_then(|x| x.as_bool()) {
+            config.session_summary = val;
+        }
+        if let Some(val) = table.get("local_search").and_then(|x| x.as_bool()) {
+            config.local_search = val;
+        }
+        config.time_format = TimeFormat::from_table(table);
+        if let Some(val) = table.get("preview_command").and_then(|x| x.as_str()) {
+            if !val.trim().is_empty() {
+                config.preview_command = Some(val.to_string());
+            }
+        }
+        if let Some(val) = table.get("preview_seconds").and_then(|x| x.as_integer()) {
+            config.preview_seconds = max(val, 1) as u64;
+        }
+        if let Some(val) = table.get("cover_art").and_then(|x| x.as_str()) {
+            config.cover_art = val.to_string();
+        }
+        if let Some(val) = table.get("cover_art_lookup").and_then(|x| x.as_bool()) {
+            config.cover_art_lookup = val;
+        }
+        if let Some(val) = table.get("lyrics_command").and_then(|x| x.as_str()) {
+            if !val.trim().is_empty() {
+                config.lyrics_command = Some(val.to_string());
+            }
+        }
+        match table.get("search_order").and_then(|x| x.as_str()) {
+            Some("server") | None => {},
+            Some("popular") => config.popular_first = true,
+            Some("relevance") => config.relevance_first = true,
+            Some(val) => warn!("ignoring search_order {:?}, it is \"server\", \"popular\" or \
+                                \"relevance\"", val),
+        }
+        if let Some(val) = table.get("notify_next_up").and_then(|x| x.as_bool()) {
+            config.notify_next_up = val;
+        }
+        if let Some(val) = table.get("notify_watchlist").and_then(|x| x.as_bool()) {
+            config.notify_watchlist = val;
+        }
+        if let Some(val) = table.get("quiet_hours").and_then(|x| x.as_str()) {
+            match QuietHours::parse(val) {
+                Some(quiet_hours) => config.quiet_hours = Some(quiet_hours),
+                None => warn!("ignoring quiet_hours {:?}, it is like \"22:00-7:00\"", val),
+            }
+        }
+        if let Some(val) = table.get("toasts").and_then(|x| x.as_bool()) {
+            config.toasts = val;
+        }
+        if let Some(val) = table.get("command_fifo").and_then(|x| x.as_bool()) {
+            config.command_fifo = val;
+        }
+        if let Some(val) = table.get("split_layout").and_then(|x| x.as_bool()) {
+            config.split_layout = val;
+        }
+        if let Some(val) = table.get("header").and_then(|x| x.as_str()) {
+            if !val.trim().is_empty() {
+                config.header = Some(val.to_string());
+            }
+        }
+        if let Some(val) = table.get("status").and_then(|x| x.as_str()) {
+            if !val.trim().is_empty() {
+                config.status = Some(val.to_string());
+            }
+        }
+        if let Some(actions) = table.get("actions").and_then(|x| x.as_table()) {
+            for (key, command) in actions {
+                let mut chars = key.chars();
+                match (chars.next(), chars.next(), command.as_str()) {
+                    (Some(ch), None, Some(command)) => {
+                        config.actions.insert(ch, command.to_string());
+                    },
+                    _ => warn!("ignoring action {:?}, it needs a single character and a command",
+                               key),
+                }
+            }
+        }
+        config.aliases = aliases::from_table(table);
+        config
+    }
+}
+
+
+#[test]
+fn test() {
+    let mut input = "restore_session = true\nencrypt_credentials = true\nmedia_sync_interval = 5\n\
+                     idle_minutes = 10\nsession_summary = true\n\
+                     preview_command = \"mpv --no-video\"\ncover_art = \"sixel\"\n\
+                     search_order = \"popular\"\ncommand_fifo = true\ntoasts = false\n\
+                     quiet_hours = \"23:00-8:00\"\n\
+                     header = \"{user}@{server}\"\nstatus = \" \"\n\
+                     [actions]\ny = \"echo {key} | xclip\"\nyy = \"true\"\n\
+                     [aliases]\nq = \"quit\""
+        .as_bytes();
+    let config = Config::from_table(&store::load(&mut input).unwrap());
+    assert!(config.restore_session);
+    assert!(config.encrypt_credentials);
+    assert_eq!(config.media_sync_interval, 5);
+    assert_eq!(config.idle_minutes, 10);
+    assert!(config.session_summary);
+    assert_eq!(config.preview_command, Some(String::from("mpv --no-video")));
+    assert_eq!(config.preview_seconds, 15);
+    assert_eq!(config.cover_art, "sixel");
+    assert!(!config.cover_art_lookup);
+    assert_eq!(config.lyrics_command, None);
+    assert!(config.popular_first);
+    assert!(!config.relevance_first);
+    let mut input = "search_order = \"relevance\"".as_bytes();
+    assert!(Config::from_table(&store::load(&mut input).unwrap()).relevance_first);
+    assert!(config.command_fifo);
+    assert!(!config.toasts);
+    assert_eq!(config.quiet_hours, QuietHours::parse("23:00-8:00"));
+    assert_eq!(config.header, Some(String::from("{user}@{server}")));
+    assert_eq!(config.status, None);
+    assert_eq!(config.actions.len(), 1);
+    assert_eq!(config.actions[&'y'], "echo {key} | xclip");
+    assert_eq!(config.aliases["q"], "quit");
+    assert!(!Config::from_table(&BTreeMap::new()).restore_session);
+    assert!(!Config::from_table(&BTreeMap::new()).encrypt_credentials);
+    assert!(!Config::from_table(&BTreeMap::new()).session_summary);
+}
@@ -0,0 +1,440 @@
+//! Cover art for the `:playing` view, drawn with the graphics protocol of kitty or as sixels.
+//!
+//! The cover comes from the server if it tells where it is (the `coverUrl` of the playing song),
+//! or else from the Cover Art Archive after looking up the song on MusicBrainz. That lookup sends
+//! the artist and the title to a third party, so it only happens if `cover_art_lookup` is set in
+//! the config file. Covers are scaled down and kept as PNG files in the cache directory.
+//!
+//! Without the `cover-art` feature, no terminal supports graphics and nothing is fetched.
+
+use std::env;
+use std::path::{Path, PathBuf};
+
+use libclient::Media;
+
+/// The id of our image in kitty, every cover replaces the previous one
+#[cfg(feature = "cover-art")]
+const KITTY_IMAGE_ID: u32 = 1;
+
+/// Covers are scaled down to fit a square of this many pixels
+#[cfg(feature = "cover-art")]
+const COVER_PIXELS: u32 = 300;
+
+/// The size of a character cell in pixels, if the terminal does not tell
+#[cfg(feature = "cover-art")]
+const DEFAULT_CELL_PIXELS: (u32, u32) = (8, 16);
+
+/// MusicBrainz asks every client to identify itself
+#[cfg(feature = "cover-art")]
+const USER_AGENT: &'static str = concat!("maruska/", env!("CARGO_PKG_VERSION"),
+                                         " ( https://github.com/dsprenkels/maruska )");
+
+/// How images can be drawn in the terminal
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Graphics {
+    /// The graphics protocol of kitty, which is also supported by e.g. WezTerm and Ghostty
+    Kitty,
+    /// Sixels, which are supported by e.g. foot, mlterm and xterm (with `-ti vt340`)
+    Sixel,
+    /// The terminal cannot draw images
+    None,
+}
+
+impl Graphics {
+    /// The protocol that is set with `cover_art` in the config file
+    ///
+    /// With "auto", we guess it from the environment. Terminals do not always tell what they
+    /// support, so we rather draw nothing than garbage.
+    pub fn from_config(name: &str) -> Graphics {
+        if !cfg!(feature = "cover-art") {
+            return Graphics::None;
+        }
+        match name {
+            "auto" => Graphics::detect(),
+            "kitty" => Graphics::Kitty,
+            "sixel" => Graphics::Sixel,
+            "none" => Graphics::None,
+            other => {
+                warn!("ignoring unknown cover_art {:?}", other);
+                Graphics::detect()
+            },
+        }
+    }
+
+    fn detect() -> Graphics {
+        let term = env::var("TERM").unwrap_or_default();
+        let program = env::var("TERM_PROGRAM").unwrap_or_default();
+        if env::var_os("KITTY_WINDOW_ID").is_some() || term.contains("kitty") ||
+           term.contains("ghostty") || program == "WezTerm" || program == "ghostty" {
+            Graphics::Kitty
+        } else if term.contains("sixel") || term.starts_with("foot") ||
+                  term.starts_with("mlterm") || term.starts_with("contour") {
+            Graphics::Sixel
+        } else {
+            Graphics::None
+        }
+    }
+}
+
+/// Fetch the cover of the playing song `media` from the server at `server`
+///
+/// The cover is fetched from `cover_url` if the server gave one, or else from the Cover Art
+/// Archive if `lookup` is set. Covers that were fetched before are taken from `dir`. Returns the
+/// file with the cover, or None if the song has no cover.
+#[cfg(feature = "cover-art")]
+pub fn fetch(dir: &Path, server: &str, media: &Media, cover_url: Option<&str>,
+             lookup: bool) -> Result<Option<PathBuf>, String> {
+    fetch_cover(dir, server, media, cover_url, lookup).map_err(|err| {
+        ::libclient::DisplayChain(&err).to_string()
+    })
+}
+
+#[cfg(not(feature = "cover-art"))]
+pub fn fetch(_: &Path, _: &str, _: &Media, _: Option<&str>,
+             _: bool) -> Result<Option<PathBuf>, String> {
+    Ok(None)
+}
+
+/// Draw the cover in the file `path` over `cols` by `rows` cells, from column `x` and row `y`
+///
+/// This writes to the terminal directly, so call it after termbox drew the rest of the screen.
+/// The cover stays until the cells below it are drawn over, or with kitty until `clear`.
+#[cfg(feature = "cover-art")]
+pub fn draw(graphics: Graphics, path: &Path, x: i32, y: i32, cols: i32,
+            rows: i32) -> Result<(), String> {
+    draw_cover(graphics, path, x, y, cols, rows).map_err(|err| {
+        ::libclient::DisplayChain(&err).to_string()
+    })
+}
+
+#[cfg(not(feature = "cover-art"))]
+pub fn draw(_: Graphics, _: &Path, _: i32, _: i32, _: i32, _: i32) -> Result<(), String> {
+    Ok(())
+}
+
+/// Remove the cover that was drawn with kitty, sixels can only be drawn over
+#[cfg(feature = "cover-art")]
+pub fn clear(graphics: Graphics) {
+    use std::io::{stdout, Write};
+
+    if graphics == Graphics::Kitty {
+        let mut out = stdout();
+        let seq = format!("\x1b_Ga=d,d=I,i={},q=2\x1b\\", KITTY_IMAGE_ID);
+        if let Err(err) = out.write_all(seq.as_bytes()).and_then(|_| out.flush()) {
+            warn!("could not remove the cover: {}", err);
+        }
+    }
+}
+
+#[cfg(not(feature = "cover-art"))]
+pub fn clear(_: Graphics) {}
+
+#[cfg(feature = "cover-art")]
+use self::imp::{draw_cover, fetch_cover};
+
+#[cfg(feature = "cover-art")]
+mod imp {
+    use std::error::Error;
+    use std::fmt;
+    use std::fs::{self, File};
+    use std::io::{stdout, Error as IOError, Read, Write};
+    use std::path::{Path, PathBuf};
+
+    use hyper;
+    use hyper::Url;
+    use hyper::error::Error as HyperError;
+    use hyper::header::UserAgent;
+    use hyper::status::StatusCode;
+    use image::{self, ImageError, ImageFormat};
+    use rustc_serialize::base64::{self, STANDARD, ToBase64};
+    use rustc_serialize::json::{Json, ParserError as JsonError};
+
+    use libclient::{md5, Media};
+    use preview::resolve_url;
+    use super::*;
+
+    #[derive(Debug)]
+    pub enum CoverError {
+        Hyper(HyperError),
+        IO(IOError),
+        Json(JsonError),
+        Image(ImageError),
+        Status(String, StatusCode),
+    }
+
+    impl fmt::Display for CoverError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match *self {
+                CoverError::Hyper(_) => write!(f, "http request failed"),
+                CoverError::IO(_) => write!(f, "io error"),
+                CoverError::Json(_) => write!(f, "MusicBrainz sent invalid json"),
+                CoverError::Image(_) => write!(f, "could not read the image"),
+                CoverError::Status(ref url, status) => write!(f, "{} answered {}", url, status),
+            }
+        }
+    }
+
+    impl Error for CoverError {
+        fn description(&self) -> &str {
+            match *self {
+                CoverError::Hyper(ref err) => err.description(),
+                CoverError::IO(ref err) => err.description(),
+                CoverError::Json(ref err) => err.description(),
+                CoverError::Image(ref err) => err.description(),
+                CoverError::Status(..) => "bad http status",
+            }
+        }
+
+        fn source(&self) -> Option<&(Error + 'static)> {
+            match *self {
+                CoverError::Hyper(ref err) => Some(err),
+                CoverError::IO(ref err) => Some(err),
+                CoverError::Json(ref err) => Some(err),
+                CoverError::Image(ref err) => Some(err),
+                CoverError::Status(..) => None,
+            }
+        }
+    }
+
+    impl From<HyperError> for CoverError {
+        fn from(err: HyperError) -> Self {
+            CoverError::Hyper(err)
+        }
+    }
+
+    impl From<IOError> for CoverError {
+        fn from(err: IOError) -> Self {
+            CoverError::IO(err)
+        }
+    }
+
+    impl From<JsonError> for CoverError {
+        fn from(err: JsonError) -> Self {
+            CoverError::Json(err)
+        }
+    }
+
+    impl From<ImageError> for CoverError {
+        fn from(err: ImageError) -> Self {
+            CoverError::Image(err)
+        }
+    }
+
+    pub fn fetch_cover(dir: &Path, server: &str, media: &Media, cover_url: Option<&str>,
+                       lookup: bool) -> Result<Option<PathBuf>, CoverError> {
+        // media keys are only unique on one server
+        let path = dir.join(format!("{}.png", md5(&format!("{} {}", server, media.key))));
+        if path.exists() {
+            return Ok(Some(path));
+        }
+        let url = match cover_url {
+            Some(url) => Some(resolve_url(server, url)),
+            None if lookup => try!(musicbrainz_release(media)).map(|id| {
+                format!("https://coverartarchive.org/release/{}/front-250", id)
+            }),
+            None => None,
+        };
+        let data = match url {
+            Some(url) => try!(get(&url)),
+            None => None,
+        };
+        let data = match data {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+        let cover = try!(image::load_from_memory(&data)).thumbnail(COVER_PIXELS, COVER_PIXELS);
+        try!(fs::create_dir_all(dir));
+        try!(cover.save_with_format(&path, ImageFormat::Png));
+        Ok(Some(path))
+    }
+
+    /// Get the body of `url`, or None if it is not found
+    fn get(url: &str) -> Result<Option<Vec<u8>>, CoverError> {
+        debug!("fetching {}", url);
+        let client = hyper::Client::new();
+        let mut response = try!(client.get(url).header(UserAgent(USER_AGENT.to_string())).send());
+        if response.status == StatusCode::NotFound {
+            return Ok(None);
+        }
+        if !response.status.is_success() {
+            return Err(CoverError::Status(url.to_string(), response.status));
+        }
+        let mut data = Vec::new();
+        try!(response.read_to_end(&mut data));
+        Ok(Some(data))
+    }
+
+    /// Look up the MusicBrainz id of a release that has `media` on it
+    ///
+    /// If we know the album, we look for that release. Otherwise we take the first release of
+    /// the first recording with the same artist and title.
+    fn musicbrainz_release(media: &Media) -> Result<Option<String>, CoverError> {
+        // quotes would end the phrases in the Lucene query
+        let phrase = |x: &str| x.replace('\\', "").replace('"', "");
+        let (entity, query) = match media.album {
+            Some(ref album) => ("release", format!("release:\"{}\" AND artist:\"{}\"",
+                                                   phrase(album), phrase(&media.artist))),
+            None => ("recording", format!("recording:\"{}\" AND artist:\"{}\"",
+                                          phrase(&media.title), phrase(&media.artist))),
+        };
+        let mut url = Url::parse(&format!("https://musicbrainz.org/ws/2/{}/", entity)).unwrap();
+        url.query_pairs_mut()
+           .append_pair("query", &query)
+           .append_pair("fmt", "json")
+           .append_pair("limit", "1");
+        let data = match try!(get(url.as_str())) {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+        let json = try!(Json::from_str(&String::from_utf8_lossy(&data)));
+        let release = match entity {
+            "release" => json.find("releases").and_then(|x| x.as_array())
+                             .and_then(|x| x.first()),
+            _ => json.find("recordings").and_then(|x| x.as_array())
+                     .and_then(|x| x.first())
+                     .and_then(|x| x.find("releases")).and_then(|x| x.as_array())
+                     .and_then(|x| x.first()),
+        };
+        Ok(release.and_then(|x| x.find("id")).and_then(|x| x.as_string()).map(String::from))
+    }
+
+    pub fn draw_cover(graphics: Graphics, path: &Path, x: i32, y: i32, cols: i32,
+                      rows: i32) -> Result<(), CoverError> {
+        let image = match graphics {
+            Graphics::Kitty => {
+                let mut png = Vec::new();
+                try!(File::open(path).and_then(|mut f| f.read_to_end(&mut png)));
+                encode_kitty(&png, cols, rows)
+            },
+            Graphics::Sixel => {
+                let (cell_w, cell_h) = cell_pixels();
+                let cover = try!(image::open(path))
+                    .thumbnail(cols as u32 * cell_w, rows as u32 * cell_h)
+                    .to_rgb8();
+                encode_sixel(&cover, cover.width() as usize, cover.height() as usize)
+            },
+            Graphics::None => return Ok(()),
+        };
+        // termbox keeps track of where the cursor is, so we put it back where we found it
+        let seq = format!("\x1b7\x1b[{};{}H{}\x1b8", y + 1, x + 1, image);
+        let mut out = stdout();
+        try!(out.write_all(seq.as_bytes()).and_then(|_| out.flush()));
+        Ok(())
+    }
+
+    /// The size of a character cell in pixels
+    #[cfg(unix)]
+    fn cell_pixels() -> (u32, u32) {
+        use libc::{ioctl, winsize, STDOUT_FILENO, TIOCGWINSZ};
+
+        let mut size = winsize { ws_row: 0, ws_col: 0, ws_xpixel: 0, ws_ypixel: 0 };
+        let ok = unsafe { ioctl(STDOUT_FILENO, TIOCGWINSZ, &mut size) } == 0;
+        if ok && size.ws_xpixel > 0 && size.ws_ypixel > 0 && size.ws_col > 0 && size.ws_row > 0 {
+            (size.ws_xpixel as u32 / size.ws_col as u32, size.ws_ypixel as u32 / size.ws_row as u32)
+        } else {
+            DEFAULT_CELL_PIXELS
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn cell_pixels() -> (u32, u32) {
+        DEFAULT_CELL_PIXELS
+    }
+
+    /// Encode a PNG image for kitty, which scales it to `cols` by `rows` cells
+    ///
+    /// The image is sent in chunks, because kitty does not take more than 4096 bytes at once.
+    pub fn encode_kitty(png: &[u8], cols: i32, rows: i32) -> String {
+        let data = png.to_base64(base64::Config { line_length: None, ..STANDARD });
+        let chunks: Vec<&[u8]> = data.as_bytes().chunks(4096).collect();
+        let mut out = String::new();
+        for (i, chunk) in chunks.iter().enumerate() {
+            let more = if i + 1 < chunks.len() { 1 } else { 0 };
+            if i == 0 {
+                // C=1 leaves the cursor where it is, q=2 keeps kitty from answering
+                out.push_str(&format!("\x1b_Ga=T,f=100,i={},c={},r={},C=1,q=2,m={};",
+                                      KITTY_IMAGE_ID, cols, rows, more));
+            } else {
+                out.push_str(&format!("\x1b_Gm={};", more));
+            }
+            out.push_str(&String::from_utf8_lossy(chunk));
+            out.push_str("\x1b\\");
+        }
+        out
+    }
+
+    /// Encode an RGB image as sixels, with a palette of 6 levels of red, green and blue
+    pub fn encode_sixel(rgb: &[u8], width: usize, height: usize) -> String {
+        let level = |x: u8| (x as usize * 5 + 127) / 255;
+        let colors: Vec<usize> = rgb.chunks(3)
+            .map(|p| level(p[0]) * 36 + level(p[1]) * 6 + level(p[2]))
+            .collect();
+        let mut out = format!("\x1bPq\"1;1;{};{}", width, height);
+        let mut used = [false; 216];
+        for &color in &colors {
+            used[color] = true;
+        }
+        for color in (0..216).filter(|&x| used[x]) {
+            // the palette is given in percentages
+            let percent = |x: usize| x * 100 / 5;
+            out.push_str(&format!("#{};2;{};{};{}", color, percent(color / 36),
+                                  percent(color / 6 % 6), percent(color % 6)));
+        }
+        // every sixel is a column of 6 pixels, so we draw in bands of 6 rows
+        for top in (0..height).step_by(6) {
+            let rows = top..::std::cmp::min(top + 6, height);
+            let mut band: Vec<usize> = colors[top * width..rows.end * width].to_vec();
+            band.sort();
+            band.dedup();
+            for color in band {
+                out.push_str(&format!("#{}", color));
+                let mut run = ('\0', 0);
+                for x in 0..width {
+                    let bits = rows.clone()
+                        .filter(|&y| colors[y * width + x] == color)
+                        .fold(0, |acc, y| acc | 1 << (y - top));
+                    let ch = (63 + bits) as u8 as char;
+                    if ch == run.0 {
+                        run.1 += 1;
+                    } else {
+                        push_run(&mut out, run);
+                        run = (ch, 1);
+                    }
+                }
+                push_run(&mut out, run);
+                // go back to the start of the band for the next color
+                out.push('$');
+            }
+            out.push('-');
+        }
+        out.push_str("\x1b\\");
+        out
+    }
+
+    /// Append `n` times the sixel `ch`, with a repeat introducer if that is shorter
+    fn push_run(out: &mut String, (ch, n): (char, usize)) {
+        if n > 3 {
+            out.push_str(&format!("!{}{}", n, ch));
+        } else {
+            out.extend(::std::iter::repeat(ch).take(n));
+        }
+    }
+
+
+    #[test]
+    fn test() {
+        let red_blue = [255, 0, 0, 0, 0, 255];
+        assert_eq!(encode_sixel(&red_blue, 2, 1),
+                   "\x1bPq\"1;1;2;1#5;2;0;0;100#180;2;100;0;0#5?@$#180@?$-\x1b\\");
+        let red_column = [255, 0, 0].iter().cloned().cycle().take(3 * 7).collect::<Vec<u8>>();
+        assert_eq!(encode_sixel(&red_column, 1, 7),
+                   "\x1bPq\"1;1;1;7#180;2;100;0;0#180~$-#180@$-\x1b\\");
+        let red_row = [255, 0, 0].iter().cloned().cycle().take(3 * 5).collect::<Vec<u8>>();
+        assert!(encode_sixel(&red_row, 5, 1).contains("#180!5@$"));
+
+        let kitty = encode_kitty(&[0; 4000], 20, 10);
+        assert!(kitty.starts_with("\x1b_Ga=T,f=100,i=1,c=20,r=10,C=1,q=2,m=1;"));
+        assert_eq!(kitty.matches("\x1b\\\x1b_Gm=0;").count(), 1);
+        assert!(kitty.ends_with("AA==\x1b\\") && !kitty.contains('\n'));
+    }
+}
@@ -0,0 +1,69 @@
+//! Detection of crashes of the previous session.
+//!
+//! While the TUI is running, a sentinel file exists in the state directory, which is removed
+//! when it exits normally. When it panics, the panic message is written to a crash log. So if the
+//! sentinel file still exists on startup, the previous session crashed and we start in safe mode.
+//! The crash log is removed once it is read, so that a later session that is killed without a
+//! panic is not blamed on the same one.
+
+use std::fs;
+use std::io::{Read, Write};
+use std::panic;
+
+use paths;
+
+/// Check whether the previous session crashed, and if so, return what is known about the crash
+///
+/// The crash log is removed, so the message is only returned once.
+pub fn check_previous_session() -> Option<String> {
+    let sentinel = match paths::sentinel_file() {
+        Some(x) => x,
+        None => return None,
+    };
+    if !sentinel.exists() {
+        return None;
+    }
+    let mut msg = String::new();
+    if let Some(crash_log) = paths::crash_log_file() {
+        if let Ok(mut f) = fs::File::open(&crash_log) {
+            let _ = f.read_to_string(&mut msg);
+        }
+        let _ = fs::remove_file(crash_log);
+    }
+    let msg = msg.trim();
+    if msg.is_empty() {
+        Some("maruska did not exit cleanly".to_string())
+    } else {
+        Some(msg.to_string())
+    }
+}
+
+/// Write the message of every panic to the crash log, before the default panic handling
+pub fn install_panic_hook() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if let (Some(state_dir), Some(crash_log)) = (paths::state_dir(), paths::crash_log_file()) {
+            let _ = fs::create_dir_all(state_dir);
+            if let Ok(mut f) = fs::File::create(&crash_log) {
+                let _ = writeln!(f, "{}", info);
+            }
+        }
+        default_hook(info);
+    }));
+}
+
+/// Create the sentinel file, which marks that a session is running
+pub fn mark_running() {
+    if let (Some(state_dir), Some(sentinel)) = (paths::state_dir(), paths::sentinel_file()) {
+        if fs::create_dir_all(state_dir).and_then(|_| fs::File::create(&sentinel)).is_err() {
+            warn!("could not create {:?}, crashes will not be detected", sentinel);
+        }
+    }
+}
+
+/// Remove the sentinel file and the crash log, because the session exited normally
+pub fn mark_stopped() {
+    for path in paths::sentinel_file().into_iter().chain(paths::crash_log_file()) {
+        let _ = fs::remove_file(path);
+    }
+}
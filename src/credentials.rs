@@ -0,0 +1,344 @@
+//! Places where the login credentials can be kept between sessions.
+//!
+//! The credentials of every server are kept in a table keyed by the server's url. The plain
+//! store writes that table to the credentials file as is. The encrypted store encrypts it with a
+//! key that is derived from a passphrase, for users that do not want their password hash to be
+//! readable by anyone who can read their files.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{Error as IOError, ErrorKind};
+use std::path::PathBuf;
+
+use crypto::aead::{AeadDecryptor, AeadEncryptor};
+use crypto::chacha20poly1305::ChaCha20Poly1305;
+use crypto::scrypt::{scrypt, ScryptParams};
+use rand::{OsRng, Rng};
+use rustc_serialize::hex::{FromHex, ToHex};
+use toml::Value;
+
+use store::{self, Schema, StoreError};
+
+pub use libclient::app::Secret;
+
+/// The format of the plain credentials file
+const PLAIN_SCHEMA: Schema = Schema { migrations: &[] };
+
+/// The format of the encrypted credentials file
+const ENCRYPTED_SCHEMA: Schema = Schema { migrations: &[] };
+
+/// The cost parameters of scrypt for newly encrypted files (N = 2^15, r = 8, p = 1)
+#[cfg(not(test))]
+const SCRYPT_LOG_N: u8 = 15;
+#[cfg(test)]
+const SCRYPT_LOG_N: u8 = 4; // the tests only have to check that it works, not be secure
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+
+const SALT_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 8;
+const TAG_LEN: usize = 16;
+
+#[derive(Debug)]
+pub struct Credentials {
+    pub username: String,
+    pub secret: Secret,
+}
+
+#[derive(Debug)]
+pub enum CredentialError {
+    Store(StoreError),
+
+    /// The passphrase is wrong, or the file was changed by someone else
+    Decrypt,
+
+    /// The encrypted file is missing a field, or a field is not valid
+    Format(&'static str),
+
+    /// We could not get random bytes for a new salt and nonce
+    Random(IOError),
+}
+
+impl fmt::Display for CredentialError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CredentialError::Store(_) => write!(f, "could not access the credentials file"),
+            CredentialError::Decrypt => write!(f, "wrong passphrase, or the credentials file is damaged"),
+            CredentialError::Format(what) => write!(f, "invalid encrypted credentials file: {}", what),
+            CredentialError::Random(_) => write!(f, "could not generate random bytes"),
+        }
+    }
+}
+
+impl Error for CredentialError {
+    fn description(&self) -> &str {
+        match *self {
+            CredentialError::Store(_) => "could not access the credentials file",
+            CredentialError::Decrypt => "could not decrypt the credentials",
+            CredentialError::Format(_) => "invalid encrypted credentials file",
+            CredentialError::Random(_) => "could not generate random bytes",
+        }
+    }
+
+    fn source(&self) -> Option<&(Error + 'static)> {
+        match *self {
+            CredentialError::Store(ref err) => Some(err),
+            CredentialError::Random(ref err) => Some(err),
+            CredentialError::Decrypt | CredentialError::Format(_) => None,
+        }
+    }
+}
+
+impl From<StoreError> for CredentialError {
+    fn from(err: StoreError) -> Self {
+        CredentialError::Store(err)
+    }
+}
+
+pub type CredentialResult<T> = Result<T, CredentialError>;
+
+/// Somewhere to keep the credentials of every server we log in to
+pub trait CredentialStore {
+    /// Get the credentials for the server at `url`, if we have any
+    fn load(&self, url: &str) -> CredentialResult<Option<Credentials>>;
+
+    /// Save the credentials for the server at `url`, replacing the old ones
+    fn save(&self, url: &str, credentials: &Credentials) -> CredentialResult<()>;
+
+    /// Forget the credentials for the server at `url`
+    fn remove(&self, url: &str) -> CredentialResult<()>;
+}
+
+/// Keeps the credentials in a plain toml file
+pub struct PlainStore {
+    path: PathBuf,
+}
+
+impl PlainStore {
+    pub fn new(path: PathBuf) -> PlainStore {
+        PlainStore { path: path }
+    }
+}
+
+impl CredentialStore for PlainStore {
+    fn load(&self, url: &str) -> CredentialResult<Option<Credentials>> {
+        let store_obj = match store::load_file(&self.path, &PLAIN_SCHEMA) {
+            Ok(x) => x,
+            Err(StoreError::IO(ref err)) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(CredentialError::from(err)),
+        };
+        Ok(store_obj.get(url).and_then(from_toml))
+    }
+
+    fn save(&self, url: &str, credentials: &Credentials) -> CredentialResult<()> {
+        let result = store::update_file(&self.path, &PLAIN_SCHEMA, |store_obj| {
+            store_obj.insert(url.to_string(), to_toml(credentials));
+        });
+        result.map_err(CredentialError::from)
+    }
+
+    fn remove(&self, url: &str) -> CredentialResult<()> {
+        if !self.path.exists() {
+            return Ok(());
+        }
+        try!(store::update_file(&self.path, &PLAIN_SCHEMA, |store_obj| { store_obj.remove(url); }));
+        // The backup still has the old version, which may hold the credentials we just removed
+        match fs::remove_file(store::backup_path(&self.path)) {
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(CredentialError::Store(StoreError::IO(err))),
+            Ok(()) => Ok(()),
+        }
+    }
+}
+
+/// Keeps the credentials in a toml file that is encrypted with a passphrase
+///
+/// The key is derived from the passphrase with scrypt, and the credentials table is encrypted
+/// with ChaCha20-Poly1305, so a wrong passphrase or a changed file is noticed. Every save uses a
+/// new salt and nonce.
+pub struct EncryptedStore {
+    path: PathBuf,
+    passphrase: String,
+}
+
+impl EncryptedStore {
+    pub fn new(path: PathBuf, passphrase: &str) -> EncryptedStore {
+        EncryptedStore { path: path, passphrase: passphrase.to_string() }
+    }
+
+    /// Decrypt the credentials table, or return an empty table if there is no file yet
+    fn load_table(&self) -> CredentialResult<BTreeMap<String, Value>> {
+        match store::load_file(&self.path, &ENCRYPTED_SCHEMA) {
+            Ok(store_obj) => decrypt(&store_obj, &self.passphrase),
+            Err(StoreError::IO(ref err)) if err.kind() == ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(err) => Err(CredentialError::from(err)),
+        }
+    }
+
+    /// Decrypt the credentials table, change it using `f`, and encrypt it again
+    fn update_table<F>(&self, f: F) -> CredentialResult<()>
+        where F : FnOnce(&mut BTreeMap<String, Value>) {
+        let mut result = Ok(());
+        try!(store::update_file(&self.path, &ENCRYPTED_SCHEMA, |store_obj| {
+            // An empty file is new, so there is nothing to decrypt yet
+            let table = if store_obj.is_empty() {
+                Ok(BTreeMap::new())
+            } else {
+                decrypt(store_obj, &self.passphrase)
+            };
+            result = table.and_then(|mut table| {
+                f(&mut table);
+                encrypt(&table, &self.passphrase).map(|x| *store_obj = x)
+            });
+        }));
+        result
+    }
+}
+
+impl CredentialStore for EncryptedStore {
+    fn load(&self, url: &str) -> CredentialResult<Option<Credentials>> {
+        Ok(try!(self.load_table()).get(url).and_then(from_toml))
+    }
+
+    fn save(&self, url: &str, credentials: &Credentials) -> CredentialResult<()> {
+        self.update_table(|table| { table.insert(url.to_string(), to_toml(credentials)); })
+    }
+
+    fn remove(&self, url: &str) -> CredentialResult<()> {
+        self.update_table(|table| { table.remove(url); })
+    }
+}
+
+fn from_toml(host: &Value) -> Option<Credentials> {
+    let username = match host.lookup("username").and_then(|x| x.as_str()) {
+        Some(x) => x.to_string(),
+        None => return None,
+    };
+    let secret = if let Some(val) = host.lookup("password_hash").and_then(|x| x.as_str()) {
+        Secret::PasswordHash(val.to_string())
+    } else if let Some(val) = host.lookup("access_key").and_then(|x| x.as_str()) {
+        Secret::AccessKey(val.to_string())
+    } else {
+        return None;
+    };
+    Some(Credentials { username: username, secret: secret })
+}
+
+fn to_toml(credentials: &Credentials) -> Value {
+    let mut toml_creds = BTreeMap::new();
+    toml_creds.insert("username".to_string(), Value::String(credentials.username.clone()));
+    match credentials.secret {
+        Secret::AccessKey(ref x) => {
+            toml_creds.insert("access_key".to_string(), Value::String(x.clone()));
+        },
+        Secret::PasswordHash(ref x) => {
+            toml_creds.insert("password_hash".to_string(), Value::String(x.clone()));
+        },
+    }
+    Value::Table(toml_creds)
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], params: &ScryptParams) -> [u8; KEY_LEN] {
+    let mut key = [0; KEY_LEN];
+    scrypt(passphrase.as_bytes(), salt, params, &mut key);
+    key
+}
+
+fn encrypt(table: &BTreeMap<String, Value>, passphrase: &str)
+           -> CredentialResult<BTreeMap<String, Value>> {
+    let mut rng = try!(OsRng::new().map_err(CredentialError::Random));
+    let mut salt = [0; SALT_LEN];
+    let mut nonce = [0; NONCE_LEN];
+    rng.fill_bytes(&mut salt);
+    rng.fill_bytes(&mut nonce);
+
+    let params = ScryptParams::new(SCRYPT_LOG_N, SCRYPT_R, SCRYPT_P);
+    let key = derive_key(passphrase, &salt, &params);
+    let plaintext = Value::Table(table.clone()).to_string().into_bytes();
+    let mut ciphertext = vec![0; plaintext.len()];
+    let mut tag = [0; TAG_LEN];
+    ChaCha20Poly1305::new(&key, &nonce, &[]).encrypt(&plaintext, &mut ciphertext, &mut tag);
+
+    let mut store_obj = BTreeMap::new();
+    store_obj.insert("log_n".to_string(), Value::Integer(SCRYPT_LOG_N as i64));
+    store_obj.insert("r".to_string(), Value::Integer(SCRYPT_R as i64));
+    store_obj.insert("p".to_string(), Value::Integer(SCRYPT_P as i64));
+    store_obj.insert("salt".to_string(), Value::String(salt.to_hex()));
+    store_obj.insert("nonce".to_string(), Value::String(nonce.to_hex()));
+    store_obj.insert("tag".to_string(), Value::String(tag.to_hex()));
+    store_obj.insert("ciphertext".to_string(), Value::String(ciphertext.to_hex()));
+    Ok(store_obj)
+}
+
+fn decrypt(store_obj: &BTreeMap<String, Value>, passphrase: &str)
+           -> CredentialResult<BTreeMap<String, Value>> {
+    let get_int = |key: &'static str| {
+        store_obj.get(key).and_then(|x| x.as_integer()).ok_or(CredentialError::Format(key))
+    };
+    let get_hex = |key: &'static str, len: Option<usize>| {
+        store_obj.get(key)
+                 .and_then(|x| x.as_str())
+                 .and_then(|x| x.from_hex().ok())
+                 .and_then(|x| if len.map_or(true, |len| x.len() == len) { Some(x) } else { None })
+                 .ok_or(CredentialError::Format(key))
+    };
+    let log_n = try!(get_int("log_n"));
+    let r = try!(get_int("r"));
+    let p = try!(get_int("p"));
+    if log_n < 1 || log_n > 30 || r < 1 || r > 64 || p < 1 || p > 64 {
+        return Err(CredentialError::Format("scrypt parameters out of range"));
+    }
+    let salt = try!(get_hex("salt", None));
+    let nonce = try!(get_hex("nonce", Some(NONCE_LEN)));
+    let tag = try!(get_hex("tag", Some(TAG_LEN)));
+    let ciphertext = try!(get_hex("ciphertext", None));
+
+    let params = ScryptParams::new(log_n as u8, r as u32, p as u32);
+    let key = derive_key(passphrase, &salt, &params);
+    let mut plaintext = vec![0; ciphertext.len()];
+    if !ChaCha20Poly1305::new(&key, &nonce, &[]).decrypt(&ciphertext, &mut plaintext, &tag) {
+        return Err(CredentialError::Decrypt);
+    }
+    let plaintext = try!(String::from_utf8(plaintext)
+                                .map_err(|_| CredentialError::Format("ciphertext")));
+    store::load(&mut plaintext.as_bytes()).map_err(|_| CredentialError::Format("ciphertext"))
+}
+
+
+#[test]
+fn test_encrypted_store() {
+    use std::env;
+    use std::io::Read;
+    use std::process;
+
+    let dir = env::temp_dir().join(format!("maruska-credentials-test-{}", process::id()));
+    let path = dir.join("credentials.toml");
+    let url = "http://localhost/api";
+    let credentials = Credentials {
+        username: "user".to_string(),
+        secret: Secret::PasswordHash("5f4dcc3b5aa765d61d8327deb882cf99".to_string()),
+    };
+
+    let store = EncryptedStore::new(path.clone(), "correct horse");
+    assert!(store.load(url).unwrap().is_none());
+    store.save(url, &credentials).unwrap();
+    let mut contents = String::new();
+    fs::File::open(&path).unwrap().read_to_string(&mut contents).unwrap();
+    assert!(!contents.contains("5f4dcc3b"));
+
+    let loaded = store.load(url).unwrap().unwrap();
+    assert_eq!(loaded.username, "user");
+    match loaded.secret {
+        Secret::PasswordHash(ref x) => assert_eq!(x, "5f4dcc3b5aa765d61d8327deb882cf99"),
+        ref x => panic!("unexpected secret {:?}", x),
+    }
+
+    match EncryptedStore::new(path.clone(), "wrong horse").load(url) {
+        Err(CredentialError::Decrypt) => {},
+        res => panic!("unexpected result {:?}", res),
+    }
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,241 @@
+//! A pretend server with a small library, for `maruska --demo`.
+//!
+//! It answers searches like a server does, and plays the requests in its queue, followed by the
+//! rest of the library in order, so that the terminal UI can be tried out without a server or a
+//! network. Everything starts the same way every time, which makes for repeatable screenshots.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration as StdDuration;
+
+use rustc_serialize::json::{Json, ToJson};
+use time::{Duration, get_time, Timespec};
+
+use libclient::Offline;
+use libclient::text;
+
+/// What the client shows as the url of the server
+pub const DEMO_URL: &'static str = "demo";
+
+/// The first song ends this many seconds after the demo starts, so that the next one starts soon
+const FIRST_SONG_SECS: i64 = 30;
+
+/// The songs of the library: artist, title, album, length in seconds and uploader
+const LIBRARY: [(&'static str, &'static str, &'static str, i64, &'static str); 24] = [
+    ("Queens Of The Stone Age", "No One Knows", "Songs for the Deaf", 279, "dsprenkels"),
+    ("Queens Of The Stone Age", "In the Fade", "Rated R", 231, "dsprenkels"),
+    ("Queens Of The Stone Age", "Go with the Flow", "Songs for the Deaf", 187, "dsprenkels"),
+    ("Radiohead", "Creep", "Pablo Honey", 238, "bkoks"),
+    ("Radiohead", "Karma Police", "OK Computer", 264, "bkoks"),
+    ("Radiohead", "Paranoid Android", "OK Computer", 387, "bkoks"),
+    ("Beyoncé", "Halo", "I Am... Sasha Fierce", 261, "alice"),
+    ("Motörhead", "Ace of Spades", "Ace of Spades", 169, "dsprenkels"),
+    ("Tool", "Schism", "Lateralus", 407, "alice"),
+    ("Björk", "Army of Me", "Post", 234, "carol"),
+    ("Sigur Rós", "Hoppípolla", "Takk...", 268, "carol"),
+    ("Daft Punk", "One More Time", "Discovery", 320, "bob"),
+    ("Daft Punk", "Digital Love", "Discovery", 301, "bob"),
+    ("The White Stripes", "Seven Nation Army", "Elephant", 232, "bob"),
+    ("Nirvana", "Smells Like Teen Spirit", "Nevermind", 301, "bkoks"),
+    ("Pixies", "Where Is My Mind?", "Surfer Rosa", 233, "carol"),
+    ("Arctic Monkeys", "Do I Wanna Know?", "AM", 272, "alice"),
+    ("Kraftwerk", "The Model", "Die Mensch-Maschine", 218, "bob"),
+    ("Massive Attack", "Teardrop", "Mezzanine", 330, "carol"),
+    ("Portishead", "Glory Box", "Dummy", 306, "carol"),
+    ("The Strokes", "Last Nite", "Is This It", 193, "alice"),
+    ("Muse", "Plug In Baby", "Origin of Symmetry", 219, "bkoks"),
+    ("Gorillaz", "Feel Good Inc.", "Demon Days", 221, "bob"),
+    ("Rage Against the Machine", "Killing in the Name", "Rage Against the Machine", 314,
+     "dsprenkels"),
+];
+
+/// The requests in the queue when the demo starts: the song (an index in `LIBRARY`) and who
+/// requested it
+const QUEUE: [(usize, &'static str); 5] = [
+    (9, "carol"), (11, "bob"), (4, "bkoks"), (16, "alice"), (18, "carol"),
+];
+
+/// Play the server for the client of `offline`
+pub fn serve(offline: Offline) {
+    let now = get_time();
+    let demo = Arc::new(Mutex::new(Demo {
+        queue: QUEUE.iter().enumerate().map(|(i, &(song, by))| Request {
+            key: i as i64 + 1,
+            song: song,
+            by: by.to_string(),
+        }).collect(),
+        next_key: QUEUE.len() as i64 + 1,
+        playing: (0, "dsprenkels".to_string()),
+        end_time: now + Duration::seconds(FIRST_SONG_SECS),
+        next_song: 1,
+        following: Vec::new(),
+    }));
+
+    let Offline { packet_s, message_r } = offline;
+    {
+        let demo = demo.clone();
+        let packet_s = packet_s.clone();
+        thread::spawn(move || {
+            for message in message_r {
+                let answers = demo.lock().unwrap().answer(&message);
+                if !answers.is_empty() {
+                    packet_s.send(packet(answers));
+                }
+            }
+        });
+    }
+    thread::spawn(move || {
+        loop {
+            thread::sleep(StdDuration::from_secs(1));
+            let updates = demo.lock().unwrap().play();
+            if !updates.is_empty() {
+                packet_s.send(packet(updates));
+            }
+        }
+    });
+}
+
+struct Request {
+    key: i64,
+    /// An index in `LIBRARY`
+    song: usize,
+    by: String,
+}
+
+struct Demo {
+    queue: VecDeque<Request>,
+    next_key: i64,
+    /// The song that is playing (an index in `LIBRARY`) and who requested it
+    playing: (usize, String),
+    end_time: Timespec,
+    /// The song that is played when the queue is empty
+    next_song: usize,
+    /// The topics that the client follows
+    following: Vec<String>,
+}
+
+impl Demo {
+    /// The messages that answer `message` from the client
+    fn answer(&mut self, message: &Json) -> Vec<Json> {
+        let get_str = |key| message.find(key).and_then(|x| x.as_string()).unwrap_or("");
+        let get_u64 = |key| message.find(key).and_then(|x| x.as_u64()).unwrap_or(0);
+        let topics = || -> Vec<String> {
+            message.find("which").and_then(|x| x.as_array()).map_or(Vec::new(), |x| {
+                x.iter().filter_map(|x| x.as_string()).map(String::from).collect()
+            })
+        };
+        match get_str("type") {
+            "follow" => {
+                let topics = topics();
+                let mut answers = Vec::new();
+                if topics.iter().any(|x| x == "playing") {
+                    answers.push(self.playing_message());
+                }
+                if topics.iter().any(|x| x == "requests") {
+                    answers.push(self.requests_message());
+                }
+                self.following.extend(topics);
+                answers
+            },
+            "unfollow" => {
+                let topics = topics();
+                self.following.retain(|x| !topics.contains(x));
+                Vec::new()
+            },
+            "query_media" => {
+                let query = get_str("query");
+                let results: Vec<Json> = (0..LIBRARY.len())
+                    .filter(|&i| {
+                        let (artist, title, album, _, _) = LIBRARY[i];
+                        text::matches(&format!("{} {} {}", artist, title, album), query)
+                    })
+                    .skip(get_u64("skip") as usize)
+                    .take(get_u64("count") as usize)
+                    .map(media_json)
+                    .collect();
+                let mut obj = BTreeMap::new();
+                obj.insert("type".to_string(), "query_media_results".to_json());
+                obj.insert("token".to_string(), get_u64("token").to_json());
+                obj.insert("results".to_string(), Json::Array(results));
+                vec![Json::Object(obj)]
+            },
+            other => {
+                debug!("the demo does not answer {} messages", other);
+                Vec::new()
+            },
+        }
+    }
+
+    /// Start the next song if the one that is playing ended, and return the messages about it
+    fn play(&mut self) -> Vec<Json> {
+        if get_time() < self.end_time {
+            return Vec::new();
+        }
+        self.playing = match self.queue.pop_front() {
+            Some(request) => (request.song, request.by),
+            None => {
+                let song = self.next_song;
+                self.next_song = (song + 1) % LIBRARY.len();
+                (song, "dsprenkels".to_string())
+            },
+        };
+        self.end_time = get_time() + Duration::seconds(LIBRARY[self.playing.0].3);
+        let mut updates = Vec::new();
+        if self.following.iter().any(|x| x == "playing") {
+            updates.push(self.playing_message());
+        }
+        if self.following.iter().any(|x| x == "requests") {
+            updates.push(self.requests_message());
+        }
+        updates
+    }
+
+    fn playing_message(&self) -> Json {
+        let mut playing = BTreeMap::new();
+        playing.insert("byKey".to_string(), self.playing.1.to_json());
+        playing.insert("endTime".to_string(), seconds(self.end_time).to_json());
+        playing.insert("serverTime".to_string(), seconds(get_time()).to_json());
+        playing.insert("media".to_string(), media_json(self.playing.0));
+        let mut obj = BTreeMap::new();
+        obj.insert("type".to_string(), "playing".to_json());
+        obj.insert("playing".to_string(), Json::Object(playing));
+        Json::Object(obj)
+    }
+
+    fn requests_message(&self) -> Json {
+        let requests = self.queue.iter().map(|request| {
+            let mut obj = BTreeMap::new();
+            obj.insert("key".to_string(), request.key.to_json());
+            obj.insert("byKey".to_string(), request.by.to_json());
+            obj.insert("media".to_string(), media_json(request.song));
+            Json::Object(obj)
+        }).collect();
+        let mut obj = BTreeMap::new();
+        obj.insert("type".to_string(), "requests".to_json());
+        obj.insert("requests".to_string(), Json::Array(requests));
+        Json::Object(obj)
+    }
+}
+
+/// The song `LIBRARY[i]` as the server sends it
+fn media_json(i: usize) -> Json {
+    let (artist, title, album, length, uploaded_by) = LIBRARY[i];
+    let mut obj = BTreeMap::new();
+    obj.insert("key".to_string(), format!("demo{}", i).to_json());
+    obj.insert("artist".to_string(), artist.to_json());
+    obj.insert("title".to_string(), title.to_json());
+    obj.insert("album".to_string(), album.to_json());
+    obj.insert("length".to_string(), length.to_json());
+    obj.insert("uploadedByKey".to_string(), uploaded_by.to_json());
+    Json::Object(obj)
+}
+
+fn seconds(time: Timespec) -> f64 {
+    time.sec as f64 + time.nsec as f64 / 1e9
+}
+
+/// A packet from the server with `messages`
+fn packet(messages: Vec<Json>) -> Json {
+    Json::Array(vec!(DEMO_URL.to_json(), Json::Array(messages)))
+}
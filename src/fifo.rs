@@ -0,0 +1,169 @@
+//! A named pipe through which other programs can control the running TUI, e.g. the keybindings
+//! of a window manager.
+//!
+//! Every line that is written to the pipe is a command: `request KEY` requests the song with that
+//! media key, `search QUERY` searches for QUERY as if it was typed, and `command LINE` runs LINE
+//! as a `:` command, e.g. `command playing`. The pipe is
+//! `maruska.cmd` in the runtime directory (`$XDG_RUNTIME_DIR`), and it only exists while the TUI
+//! is running. Only one TUI runs at a time, so it is the only one that reads the pipe.
+
+use std::fs;
+use std::io::Error as IOError;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use chan;
+
+use paths;
+
+/// A command that was written to the pipe
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RemoteCommand {
+    /// Request the song with this media key
+    Request(String),
+    /// Search for this query
+    Search(String),
+    /// Run this `:` command, which is given without the `:`
+    Command(String),
+}
+
+impl RemoteCommand {
+    /// The command on `line`, if it is one
+    pub fn parse(line: &str) -> Option<RemoteCommand> {
+        let line = line.trim();
+        let (command, arg) = match line.find(char::is_whitespace) {
+            Some(idx) => (&line[..idx], line[idx..].trim()),
+            None => (line, ""),
+        };
+        match (command, arg) {
+            ("request", key) if !key.is_empty() => Some(RemoteCommand::Request(key.to_string())),
+            ("search", query) => Some(RemoteCommand::Search(query.to_string())),
+            ("command", line) if !line.is_empty() => {
+                Some(RemoteCommand::Command(line.trim_left_matches(':').to_string()))
+            },
+            _ => None,
+        }
+    }
+}
+
+/// The pipe, which is removed when this is dropped
+pub struct Fifo {
+    path: PathBuf,
+}
+
+impl Drop for Fifo {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Create the pipe if `enabled`, and read the commands that are written to it
+///
+/// If the pipe cannot be created, that is logged, and no commands are ever received.
+pub fn listen(enabled: bool) -> (Option<Fifo>, chan::Receiver<RemoteCommand>) {
+    let (command_s, command_r) = chan::async();
+    let fifo = if enabled { start(command_s.clone()) } else { None };
+    // A closed channel would be ready all the time, so the sender is never dropped
+    mem::forget(command_s);
+    (fifo, command_r)
+}
+
+fn start(command_s: chan::Sender<RemoteCommand>) -> Option<Fifo> {
+    let path = match paths::command_fifo() {
+        Some(path) => path,
+        None => {
+            warn!("there is no runtime directory for the command pipe");
+            return None;
+        },
+    };
+    match create(&path) {
+        Ok(()) => {
+            info!("reading commands from {:?}", path);
+            read_commands(path.clone(), command_s);
+            Some(Fifo { path: path })
+        },
+        Err(err) => {
+            warn!("could not create the command pipe {:?}: {}", path, err);
+            None
+        },
+    }
+}
+
+/// Create the pipe at `path`, replacing one that was left behind by a crash
+#[cfg(unix)]
+fn create(path: &Path) -> Result<(), IOError> {
+    use std::ffi::CString;
+    use std::io::ErrorKind;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::FileTypeExt;
+    use libc;
+
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_fifo() {
+            return Err(IOError::new(ErrorKind::AlreadyExists, "another file is in the way"));
+        }
+        try!(fs::remove_file(path));
+    }
+    let c_path = try!(CString::new(path.as_os_str().as_bytes()).map_err(|_| {
+        IOError::new(ErrorKind::InvalidInput, "the path contains a null byte")
+    }));
+    // only we may write commands
+    if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+        return Err(IOError::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn create(_: &Path) -> Result<(), IOError> {
+    use std::io::ErrorKind;
+    Err(IOError::new(ErrorKind::Other, "named pipes only exist on Unix-like systems"))
+}
+
+/// Send the commands that are written to the pipe at `path` on `command_s`, on another thread
+fn read_commands(path: PathBuf, command_s: chan::Sender<RemoteCommand>) {
+    use std::fs::File;
+    use std::io::{BufRead, BufReader};
+    use std::thread;
+
+    thread::spawn(move || loop {
+        // Opening blocks until a program opens the pipe to write to it, and reading ends when
+        // every program that did closed it again, so then we start over
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(err) => {
+                warn!("could not open the command pipe {:?}: {}", path, err);
+                return;
+            },
+        };
+        for line in BufReader::new(file).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(err) => {
+                    warn!("could not read the command pipe: {}", err);
+                    break;
+                },
+            };
+            match RemoteCommand::parse(&line) {
+                Some(command) => command_s.send(command),
+                None if line.trim().is_empty() => {},
+                None => warn!("ignoring unknown command {:?}", line),
+            }
+        }
+    });
+}
+
+
+#[test]
+fn test() {
+    assert_eq!(RemoteCommand::parse("request 5f3a\n"),
+               Some(RemoteCommand::Request("5f3a".to_string())));
+    assert_eq!(RemoteCommand::parse("search  queens of the  stone age"),
+               Some(RemoteCommand::Search("queens of the  stone age".to_string())));
+    assert_eq!(RemoteCommand::parse("search"), Some(RemoteCommand::Search(String::new())));
+    assert_eq!(RemoteCommand::parse("command :uploads bob"),
+               Some(RemoteCommand::Command("uploads bob".to_string())));
+    assert_eq!(RemoteCommand::parse("command"), None);
+    assert_eq!(RemoteCommand::parse("request"), None);
+    assert_eq!(RemoteCommand::parse("skip"), None);
+}
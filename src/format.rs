@@ -0,0 +1,135 @@
+//! Formatting times and durations, the same way in the TUI and in `maruska-cli`.
+//!
+//! How times are shown is set with two keys in `config.toml`: `clock` is `"24h"`, `"12h"` or
+//! `"locale"` (12 hours if the locale in `LC_ALL`, `LC_TIME` or `LANG` usually uses it), and
+//! `eta` is `"duration"` (in 3:45) or `"time"` (at 14:05).
+
+use std::collections::BTreeMap;
+use std::env;
+
+use time::{at, Duration, get_time, Timespec};
+use toml::Value;
+
+/// Locales that usually write the time of day with a 12 hour clock
+const TWELVE_HOUR_LOCALES: [&'static str; 6] = [
+    "en_US", "en_AU", "en_CA", "en_IN", "en_NZ", "en_PH",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Clock {
+    /// 14:05
+    TwentyFourHour,
+    /// 2:05 PM
+    TwelveHour,
+}
+
+/// How to show when a song is done
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Eta {
+    /// The time that is left
+    Duration,
+    /// The time of day
+    Time,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeFormat {
+    pub clock: Clock,
+    pub eta: Eta,
+}
+
+impl Default for TimeFormat {
+    fn default() -> TimeFormat {
+        TimeFormat {
+            clock: locale_clock(),
+            eta: Eta::Duration,
+        }
+    }
+}
+
+impl TimeFormat {
+    /// Read the `clock` and `eta` keys of a configuration file, unknown values are ignored
+    pub fn from_table(table: &BTreeMap<String, Value>) -> TimeFormat {
+        let mut format = TimeFormat::default();
+        match table.get("clock").and_then(|x| x.as_str()) {
+            Some("24h") => format.clock = Clock::TwentyFourHour,
+            Some("12h") => format.clock = Clock::TwelveHour,
+            Some("locale") | None => {},
+            Some(other) => warn!("ignoring unknown clock {:?}", other),
+        }
+        match table.get("eta").and_then(|x| x.as_str()) {
+            Some("duration") | None => {},
+            Some("time") => format.eta = Eta::Time,
+            Some(other) => warn!("ignoring unknown eta format {:?}", other),
+        }
+        format
+    }
+
+    /// Format the time of day of `t`, in the local time zone
+    pub fn time(&self, t: Timespec) -> String {
+        // the formats are valid, so this does not fail
+        match self.clock {
+            Clock::TwentyFourHour => at(t).strftime("%H:%M").unwrap().to_string(),
+            Clock::TwelveHour => at(t).strftime("%l:%M %p").unwrap().to_string().trim().to_string(),
+        }
+    }
+
+    /// Format when something that takes `remaining` from now is done
+    pub fn eta(&self, remaining: Duration) -> String {
+        match self.eta {
+            Eta::Duration => format_duration(remaining),
+            Eta::Time => self.time(get_time() + remaining),
+        }
+    }
+}
+
+/// Format a duration like a stopwatch, e.g. "3:45" or "1:02:03"
+pub fn format_duration(d: Duration) -> String {
+    match () {
+        _ if d.num_days() != 0 => format!("{}d{:02}:{:02}:{:02}",
+            d.num_days(), d.num_hours() % 24, d.num_minutes() % 60, d.num_seconds() % 60),
+        _ if d.num_hours() != 0 => format!("{}:{:02}:{:02}",
+            d.num_hours(), d.num_minutes() % 60, d.num_seconds() % 60),
+        _ =>  format!("{}:{:02}", d.num_minutes(), d.num_seconds() % 60)
+    }
+}
+
+/// Format a number of bytes for people, e.g. "512 B" or "1.3 MB"
+pub fn format_bytes(bytes: u64) -> String {
+    if bytes < 1000 {
+        format!("{} B", bytes)
+    } else if bytes < 1_000_000 {
+        format!("{:.1} kB", bytes as f64 / 1e3)
+    } else {
+        format!("{:.1} MB", bytes as f64 / 1e6)
+    }
+}
+
+/// The clock that the user's locale usually uses
+fn locale_clock() -> Clock {
+    let locale = ["LC_ALL", "LC_TIME", "LANG"].iter()
+                                              .filter_map(|x| env::var(x).ok())
+                                              .find(|x| !x.is_empty())
+                                              .unwrap_or_default();
+    // e.g. "en_US.UTF-8"
+    let name = locale.split(|c| c == '.' || c == '@').next().unwrap_or("");
+    if TWELVE_HOUR_LOCALES.contains(&name) {
+        Clock::TwelveHour
+    } else {
+        Clock::TwentyFourHour
+    }
+}
+
+
+#[test]
+fn test() {
+    let table = ::toml::Parser::new("clock = \"12h\"\neta = \"time\"").parse().unwrap();
+    let format = TimeFormat::from_table(&table);
+    assert_eq!(format, TimeFormat { clock: Clock::TwelveHour, eta: Eta::Time });
+    assert_eq!(format_duration(Duration::seconds(3725)), "1:02:05");
+    assert_eq!(format_bytes(999), "999 B");
+    assert_eq!(format_bytes(1260), "1.3 kB");
+    assert_eq!(format_bytes(3_400_000), "3.4 MB");
+    assert_eq!(TimeFormat { clock: Clock::TwentyFourHour, eta: Eta::Duration }
+                   .eta(Duration::seconds(225)), "3:45");
+}
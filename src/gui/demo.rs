@@ -0,0 +1 @@
+../demo.rs
\ No newline at end of file
@@ -0,0 +1,317 @@
+//! A prototype of a desktop front-end, built with `--features gui`.
+//!
+//! It shows what is playing, the queue and a search box of which the results can be requested,
+//! and asks for a username and a password when a request waits for a login. Everything apart
+//! from drawing is done by `libclient::app::AppState`, like in the terminal UI.
+
+extern crate chan;
+extern crate docopt;
+extern crate eframe;
+extern crate env_logger;
+extern crate libclient;
+#[macro_use] extern crate log;
+extern crate rustc_serialize;
+extern crate time;
+extern crate toml;
+
+mod demo;
+#[allow(dead_code)]
+mod format;
+mod utils;
+
+use std::io::{self, Write};
+use std::process;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use docopt::Docopt;
+use eframe::egui;
+use rustc_serialize::json::Json;
+use time::get_time;
+
+use format::format_duration;
+use libclient::{Client, ConnectionState, DisplayChain, Message};
+use libclient::app::{AppState, LoginPrompt};
+use utils::{current_user, init_logger, show_version_and_exit};
+
+const DEFAULT_HOST: &'static str = "http://marietje-noord.marie-curie.nl/api";
+
+const USAGE: &'static str = "
+Usage:
+  maruska-gui [options]
+  maruska-gui ( --help | --version )
+
+Options:
+  -H --host HOST        Hostname of marietje server
+  -u --username USER    Use a different username (than the current user)
+  --log-level LEVEL     Log messages of at least LEVEL (error, warn, info, debug or trace)
+  --log-file FILE       Append log messages to FILE instead of printing them
+  --demo                Do not connect, but show a made up server with a few songs
+  -h --help             Display this message
+  --version             Print version info and exit
+";
+
+#[derive(Debug, RustcDecodable)]
+pub struct Args {
+    flag_host: Option<String>,
+    flag_username: Option<String>,
+    flag_log_level: Option<String>,
+    flag_log_file: Option<String>,
+    flag_demo: bool,
+    flag_help: bool,
+    flag_version: bool,
+}
+
+/// The window, and what was typed in it
+struct Gui {
+    app: AppState,
+    /// The messages from the server, see `forward_messages`
+    messages_r: mpsc::Receiver<Json>,
+    query: String,
+    /// The number of search results that were scrolled to
+    shown: usize,
+    /// What we ask the user for before a request can be sent
+    prompt: Option<LoginPrompt>,
+    username: String,
+    password: String,
+    /// The last thing that happened, at the bottom of the window
+    status: String,
+}
+
+impl Gui {
+    fn new(client: Client, messages_r: mpsc::Receiver<Json>, username: Option<String>) -> Gui {
+        Gui {
+            app: AppState::new(client),
+            messages_r: messages_r,
+            query: String::new(),
+            shown: 0,
+            prompt: None,
+            username: username.unwrap_or_default(),
+            password: String::new(),
+            status: String::new(),
+        }
+    }
+
+    fn handle_messages(&mut self) {
+        while let Ok(msg) = self.messages_r.try_recv() {
+            match self.app.handle_message(&msg) {
+                Ok(Message::Login) => {
+                    self.status = format!("Logged in as {}", self.app.username().unwrap_or(""));
+                },
+                Ok(Message::LoginError(reason)) => {
+                    self.status = format!("Could not log in: {}", reason);
+                    self.prompt = self.app.login_prompt();
+                },
+                Ok(_) => {},
+                Err(err) => {
+                    warn!("could not handle message {}: {}", msg, DisplayChain(&err));
+                    self.status = "The server sent a message that maruska cannot read, \
+                                   see the log".to_string();
+                },
+            }
+        }
+    }
+
+    fn request(&mut self, key: &str, what: String) {
+        match self.app.request(&[key]) {
+            Ok(()) if !self.app.client().get_offline_requests().is_empty() => {
+                self.status = format!("Cannot reach the server, requesting {} once it can", what);
+            },
+            Ok(()) => self.status = format!("Requested {}", what),
+            Err(prompt) => {
+                self.status = format!("Requesting {} after logging in", what);
+                self.prompt = prompt;
+            },
+        }
+    }
+
+    fn draw_playing(&mut self, ui: &mut egui::Ui) {
+        let client = self.app.client();
+        match *client.get_playing() {
+            Some(ref playing) => {
+                ui.heading(format!("{} - {}", playing.media.artist, playing.media.title));
+                let remaining = playing.end_time - get_time();
+                ui.label(format!("{} left, requested by {}", format_duration(remaining),
+                                 playing.requested_by.as_ref().map_or("marietje", |x| x)));
+            },
+            None => {
+                ui.heading("Nothing is playing");
+            },
+        }
+        match client.get_connection_state() {
+            ConnectionState::Connected => {},
+            _ => {
+                ui.label("Cannot reach the server, trying again");
+            },
+        }
+    }
+
+    fn draw_queue(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Queue");
+        let requests = match *self.app.client().get_requests() {
+            Some(ref requests) => requests,
+            None => return,
+        };
+        egui::ScrollArea::vertical().id_source("queue").show(ui, |ui| {
+            for request in requests {
+                ui.label(format!("{} - {} ({}), by {}", request.media.artist,
+                                 request.media.title, format_duration(request.media.length),
+                                 request.by.as_ref().map_or("marietje", |x| x)));
+            }
+        });
+    }
+
+    fn draw_search(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Search");
+        if ui.text_edit_singleline(&mut self.query).changed() {
+            self.shown = 0;
+            let query = self.query.trim().to_string();
+            self.app.search(if query.is_empty() { None } else { Some(query.as_str()) }, 0);
+        }
+        let results = self.app.client().get_qm_results().0.clone();
+        let mut requested = None;
+        let mut shown = self.shown;
+        let row_height = ui.spacing().interact_size.y;
+        egui::ScrollArea::vertical().id_source("results")
+                                    .show_rows(ui, row_height, results.len(), |ui, rows| {
+            shown = rows.end;
+            for media in &results[rows] {
+                ui.horizontal(|ui| {
+                    if ui.button("Request").clicked() {
+                        requested = Some(media.clone());
+                    }
+                    ui.label(format!("{} - {} ({})", media.artist, media.title,
+                                     format_duration(media.length)));
+                });
+            }
+        });
+        let query = self.query.trim().to_string();
+        if shown > self.shown && !query.is_empty() {
+            // ask for more results before they are scrolled to
+            self.shown = shown;
+            self.app.search(Some(&query), shown);
+        }
+        if let Some(media) = requested {
+            self.request(&media.key, format!("{} - {}", media.artist, media.title));
+        }
+    }
+
+    fn draw_login(&mut self, ui: &mut egui::Ui, prompt: LoginPrompt) {
+        ui.horizontal(|ui| {
+            let submitted = match prompt {
+                LoginPrompt::Username => {
+                    ui.label("Username:");
+                    let field = ui.text_edit_singleline(&mut self.username);
+                    entered(ui, &field) || ui.button("Next").clicked()
+                },
+                LoginPrompt::Password => {
+                    ui.label(format!("Password for {}:", self.app.username().unwrap_or("")));
+                    let field = ui.add(egui::TextEdit::singleline(&mut self.password)
+                                                        .password(true));
+                    entered(ui, &field) || ui.button("Log in").clicked()
+                },
+            };
+            if submitted {
+                self.prompt = match prompt {
+                    LoginPrompt::Username => self.app.log_in_as(self.username.trim()),
+                    LoginPrompt::Password => self.app.log_in_with_password(&self.password),
+                };
+                self.password.clear();
+            }
+        });
+    }
+}
+
+impl eframe::App for Gui {
+    fn update(&mut self, ctx: &egui::Context, _: &mut eframe::Frame) {
+        self.handle_messages();
+        egui::TopBottomPanel::top("playing").show(ctx, |ui| self.draw_playing(ui));
+        egui::TopBottomPanel::bottom("status").show(ctx, |ui| {
+            if let Some(prompt) = self.prompt {
+                self.draw_login(ui, prompt);
+            }
+            ui.label(self.status.as_str());
+        });
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.columns(2, |columns| {
+                self.draw_queue(&mut columns[0]);
+                self.draw_search(&mut columns[1]);
+            });
+        });
+        // the time that is left of the playing song
+        ctx.request_repaint_after(Duration::from_secs(1));
+    }
+}
+
+/// Was Enter pressed in the text field of `response`?
+fn entered(ui: &egui::Ui, response: &egui::Response) -> bool {
+    response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter))
+}
+
+/// Pass the messages from `client_r` on, and redraw `ctx` for each
+///
+/// The window only wakes up for input or when it is asked to redraw, so the messages cannot be
+/// waited for in `update`.
+fn forward_messages(client_r: chan::Receiver<Json>, ctx: egui::Context) -> mpsc::Receiver<Json> {
+    let (messages_s, messages_r) = mpsc::channel();
+    thread::spawn(move || {
+        for msg in client_r {
+            if messages_s.send(msg).is_err() {
+                break; // the window is closed
+            }
+            ctx.request_repaint();
+        }
+    });
+    messages_r
+}
+
+fn main() {
+    let args: Args = Docopt::new(USAGE)
+        .map(|d| d.help(true))
+        .and_then(|d| d.decode())
+        .unwrap_or_else(|e| e.exit());
+
+    if let Err(err) = init_logger(args.flag_log_level.as_ref().map(|x| x.as_str()),
+                                  args.flag_log_file.as_ref().map(|x| x.as_str()),
+                                  false) {
+        panic!("Failed to initialize logger: {}", err);
+    }
+
+    if args.flag_version {
+        show_version_and_exit();
+    }
+
+    let (client, client_r) = if args.flag_demo {
+        let (client, client_r, offline) = Client::offline(demo::DEMO_URL);
+        demo::serve(offline);
+        (client, client_r)
+    } else {
+        let host = args.flag_host.clone().unwrap_or_else(|| String::from(DEFAULT_HOST));
+        match Client::new(&host) {
+            Ok(x) => x,
+            Err(err) => {
+                let _ = writeln!(io::stderr(), "Could not connect to {}: {}", host,
+                                 DisplayChain(&err));
+                process::exit(1);
+            },
+        }
+    };
+    let username = args.flag_username.clone().or_else(current_user);
+
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default().with_inner_size([960.0, 640.0]),
+        ..Default::default()
+    };
+    let result = eframe::run_native("maruska", options, Box::new(move |cc| {
+        let mut client = client;
+        client.follow_all();
+        client.serve();
+        let messages_r = forward_messages(client_r, cc.egui_ctx.clone());
+        Box::new(Gui::new(client, messages_r, username))
+    }));
+    if let Err(err) = result {
+        let _ = writeln!(io::stderr(), "Could not open a window: {}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1 @@
+../utils.rs
\ No newline at end of file
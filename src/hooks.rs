@@ -0,0 +1,134 @@
+//! Hook scripts, which are run when something happens on the server.
+//!
+//! A hook is an executable in the `hooks` directory next to the configuration file
+//! (`~/.config/maruska/hooks` on Linux) that is named after an event: `song-change` when another
+//! song starts playing, `request-accepted` when one of our requests shows up in the queue and
+//! `queue-empty` when the last request in the queue starts playing. It gets a JSON object that
+//! describes the event on its standard input. Hooks run in the background, and a failure is only
+//! logged. A front-end can hold them back for a while, e.g. during the quiet hours of the terminal
+//! UI.
+
+use std::collections::BTreeMap;
+use std::io::{Error as IOError, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+
+use rustc_serialize::json::{Json, ToJson};
+
+use libclient::{Client, Message};
+use serialize::{playing_json, request_json};
+
+/// Runs the hook scripts for the messages of a client
+pub struct Hooks {
+    dir: Option<PathBuf>,
+    /// Was the queue empty when we last saw it? `None` until we have seen it.
+    queue_was_empty: Option<bool>,
+}
+
+impl Hooks {
+    /// The hooks in `dir`, usually `paths::hooks_dir()`; with `None`, no hooks run (e.g. in the
+    /// safe mode of the terminal UI)
+    pub fn new(dir: Option<PathBuf>) -> Hooks {
+        Hooks {
+            dir: dir,
+            queue_was_empty: None,
+        }
+    }
+
+    /// Run the hooks for what `message` changed on `client`
+    ///
+    /// Clients that are not connected to a server run no hooks, so that replaying a trace does
+    /// not set anything off. If `quiet`, the hooks are held back, but they still follow the
+    /// queue, so that they do not run later for what happened meanwhile.
+    pub fn handle_message(&mut self, client: &Client, message: &Message, quiet: bool) {
+        if client.is_offline() {
+            return;
+        }
+        match *message {
+            Message::PlayingChanged => {
+                if let Some(ref playing) = *client.get_playing() {
+                    self.run("song-change", "playing", playing_json(playing), quiet);
+                }
+            },
+            Message::Requests => {
+                let requests = client.get_requests().as_ref().map_or(&[][..], |x| &x[..]);
+                for request in client.get_queued_requests() {
+                    self.run("request-accepted", "request", request_json(request), quiet);
+                }
+                if requests.is_empty() && self.queue_was_empty == Some(false) {
+                    let playing = client.get_playing().as_ref().map_or(Json::Null, playing_json);
+                    self.run("queue-empty", "playing", playing, quiet);
+                }
+                self.queue_was_empty = Some(requests.is_empty());
+            },
+            _ => {},
+        }
+    }
+
+    /// Run the hook for `event` if there is one, with `value` as `key` in the JSON object
+    fn run(&self, event: &str, key: &str, value: Json, quiet: bool) {
+        let path = match self.dir {
+            Some(ref dir) if dir.join(event).is_file() => dir.join(event),
+            _ => return,
+        };
+        if quiet {
+            debug!("holding back the {} hook", event);
+            return;
+        }
+        let mut obj = BTreeMap::new();
+        obj.insert("event".to_string(), event.to_json());
+        obj.insert(key.to_string(), value);
+        let input = Json::Object(obj).to_string();
+        debug!("running the {} hook", event);
+        thread::spawn(move || {
+            match spawn(&path, &input).and_then(|mut child| child.wait()) {
+                Ok(status) if !status.success() => warn!("hook {:?} failed: {}", path, status),
+                Ok(_) => {},
+                Err(err) => warn!("could not run hook {:?}: {}", path, err),
+            }
+        });
+    }
+}
+
+/// Start the executable at `path`, with `input` on its standard input
+fn spawn(path: &Path, input: &str) -> Result<Child, IOError> {
+    let mut child = try!(Command::new(path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn());
+    // closing its standard input afterwards tells it that the input ended
+    if let Some(mut stdin) = child.stdin.take() {
+        // the hook does not have to read its input
+        match stdin.write_all(input.as_bytes()) {
+            Err(ref err) if err.kind() == ErrorKind::BrokenPipe => {},
+            other => try!(other),
+        }
+    }
+    Ok(child)
+}
+
+
+#[cfg(unix)]
+#[test]
+fn test() {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Read;
+    use std::os::unix::fs::PermissionsExt;
+    use std::process;
+
+    let dir = env::temp_dir().join(format!("maruska-hooks-test-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let hook = dir.join("song-change");
+    File::create(&hook).unwrap().write_all(b"#!/bin/sh\ncat > \"$0.json\"\n").unwrap();
+    fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let status = spawn(&hook, "{\"event\": \"song-change\"}").unwrap().wait().unwrap();
+    assert!(status.success());
+    let mut input = String::new();
+    File::open(dir.join("song-change.json")).unwrap().read_to_string(&mut input).unwrap();
+    assert_eq!(input, "{\"event\": \"song-change\"}");
+    fs::remove_dir_all(dir).unwrap();
+}
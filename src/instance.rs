@@ -0,0 +1,47 @@
+//! Makes sure that only one TUI is running at a time.
+//!
+//! The running TUI holds an exclusive lock on a lock file in the state directory. The operating
+//! system releases the lock when the process exits, so a crash does not leave a stale lock behind.
+
+use std::fs;
+
+use fs2::{FileExt, lock_contended_error};
+
+use paths;
+
+/// Held by the running TUI, the lock is released when this is dropped
+pub struct InstanceLock {
+    _file: fs::File,
+}
+
+/// Another TUI is already running
+#[derive(Debug)]
+pub struct AlreadyRunning;
+
+/// Try to become the only running TUI
+///
+/// If the lock file cannot be used at all, we cannot tell whether another TUI is running, so we
+/// assume that there is none and return `Ok(None)`.
+pub fn acquire() -> Result<Option<InstanceLock>, AlreadyRunning> {
+    let (state_dir, lock_filename) = match (paths::state_dir(), paths::lock_file()) {
+        (Some(state_dir), Some(lock_filename)) => (state_dir, lock_filename),
+        _ => return Ok(None),
+    };
+    let file = match fs::create_dir_all(state_dir).and_then(|_| {
+        fs::OpenOptions::new().write(true).create(true).open(&lock_filename)
+    }) {
+        Ok(file) => file,
+        Err(err) => {
+            warn!("could not open {:?}: {}", lock_filename, err);
+            return Ok(None);
+        },
+    };
+    match file.try_lock_exclusive() {
+        Ok(()) => Ok(Some(InstanceLock { _file: file })),
+        Err(ref err) if err.kind() == lock_contended_error().kind() => Err(AlreadyRunning),
+        Err(err) => {
+            warn!("could not lock {:?}: {}", lock_filename, err);
+            Ok(None)
+        },
+    }
+}
@@ -0,0 +1,224 @@
+//! The state of a front-end, apart from how it is shown.
+//!
+//! Every front-end needs the same things next to the `Client`: it remembers who the user logs in
+//! as, asks for a username or a password when something waits for a login, and asks the server for
+//! as many search results as can be scrolled to without waiting. `AppState` does that, so that a
+//! front-end only has to show the state and pass on what the user does:
+//!
+//! ```no_run
+//! extern crate libclient;
+//!
+//! use libclient::Client;
+//! use libclient::app::{AppState, LoginPrompt};
+//!
+//! # fn main() {
+//! let (client, client_r) = Client::new("http://marietje-noord.marie-curie.nl/api").unwrap();
+//! let mut app = AppState::new(client);
+//! app.client_mut().follow_all();
+//! app.client_mut().serve();
+//!
+//! app.search(Some("queens of the stone age"), 20);
+//! if let Err(Some(LoginPrompt::Username)) = app.request(&["5f3a"]) {
+//!     // ask the user for a username, and pass it to `app.log_in_as`
+//! }
+//! while let Some(msg) = client_r.recv() {
+//!     app.handle_message(&msg).unwrap();
+//! }
+//! # }
+//! ```
+
+use std::fmt;
+
+use rustc_serialize::json::Json;
+
+use {Client, ClientError, Message, RequestStatus};
+
+/// How many search results are asked for after the last one that is shown, so that scrolling
+/// through them does not wait for the server
+pub const SEARCH_BUFFER_SIZE: usize = 5000;
+
+/// What we log in with, besides the username
+#[derive(Clone)]
+pub enum Secret {
+    /// The access key that the server gave us when we logged in before
+    AccessKey(String),
+    /// The hash of the password, see `Client::hash_password`
+    PasswordHash(String),
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Secret::AccessKey(_) => write!(f, "Secret::AccessKey(*****)"),
+            Secret::PasswordHash(_) => write!(f, "Secret::PasswordHash(*****)"),
+        }
+    }
+}
+
+/// What the user has to give before we can log in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoginPrompt {
+    Username,
+    Password,
+}
+
+/// A client, and who it logs in as
+pub struct AppState {
+    client: Client,
+    username: Option<String>,
+    secret: Option<Secret>,
+}
+
+impl AppState {
+    pub fn new(client: Client) -> AppState {
+        AppState { client: client, username: None, secret: None }
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub fn client_mut(&mut self) -> &mut Client {
+        &mut self.client
+    }
+
+    /// The user that we log in as, also while we are not logged in yet
+    pub fn username(&self) -> Option<&str> {
+        self.username.as_ref().map(|x| x.as_str())
+    }
+
+    /// The username and the secret that we log in with, e.g. to save them
+    pub fn credentials(&self) -> Option<(&str, &Secret)> {
+        match (&self.username, &self.secret) {
+            (&Some(ref username), &Some(ref secret)) => Some((username, secret)),
+            _ => None,
+        }
+    }
+
+    /// Log in with saved credentials from now on, call `try_login` to log in with them
+    pub fn set_credentials(&mut self, username: String, secret: Secret) {
+        self.username = Some(username);
+        self.secret = Some(secret);
+    }
+
+    /// Log in as `username`, or ask for the password if we do not have it
+    pub fn log_in_as(&mut self, username: &str) -> Option<LoginPrompt> {
+        self.username = Some(username.to_string());
+        if self.try_login() { None } else { Some(LoginPrompt::Password) }
+    }
+
+    /// Log in with `password`, or ask for the username if we do not have it
+    pub fn log_in_with_password(&mut self, password: &str) -> Option<LoginPrompt> {
+        self.secret = Some(Secret::PasswordHash(self.client.hash_password(password)));
+        if self.try_login() { None } else { Some(LoginPrompt::Username) }
+    }
+
+    /// Log in with the username and the secret, if we have both
+    pub fn try_login(&mut self) -> bool {
+        match (&self.username, &self.secret) {
+            (&Some(ref username), &Some(Secret::PasswordHash(ref secret))) => {
+                self.client.do_login(username, secret)
+            },
+            (&Some(ref username), &Some(Secret::AccessKey(ref secret))) => {
+                self.client.do_login_accesskey(username, secret)
+            },
+            _ => return false,
+        }
+        true
+    }
+
+    /// What to ask the user for, if we are not logged in and cannot log in by ourselves
+    pub fn login_prompt(&self) -> Option<LoginPrompt> {
+        match (&self.username, &self.secret) {
+            _ if self.client.is_logged_in() => None,
+            (&None, _) => Some(LoginPrompt::Username),
+            (_, &None) => Some(LoginPrompt::Password),
+            _ => None, // logging in
+        }
+    }
+
+    /// Request the songs with `media_keys`, in this order
+    ///
+    /// If they are only requested after logging in, this tells what to ask the user for; nothing
+    /// if we are logging in already.
+    pub fn request(&mut self, media_keys: &[&str]) -> Result<(), Option<LoginPrompt>> {
+        let mut deferred = false;
+        for key in media_keys {
+            if let RequestStatus::Deferred = self.client.do_request_from_key(key) {
+                deferred = true;
+            }
+        }
+        if deferred { Err(self.login_prompt()) } else { Ok(()) }
+    }
+
+    /// Move the requests by the amounts in `moves`, of a request key and an amount each, like
+    /// `request` does with songs
+    pub fn move_requests(&mut self, moves: &[(i64, i64)]) -> Result<(), Option<LoginPrompt>> {
+        let mut deferred = false;
+        for &(key, amount) in moves {
+            if let RequestStatus::Deferred = self.client.do_move_request(key, amount) {
+                deferred = true;
+            }
+        }
+        if deferred { Err(self.login_prompt()) } else { Ok(()) }
+    }
+
+    /// What to ask the user for after an action that ended with `status`, see `request`
+    pub fn prompt_after(&self, status: RequestStatus) -> Result<(), Option<LoginPrompt>> {
+        match status {
+            RequestStatus::Ok | RequestStatus::Queued => Ok(()),
+            RequestStatus::Deferred => Err(self.login_prompt()),
+        }
+    }
+
+    /// Search for `query`, or stop searching if it is `None`, with room to scroll to the result
+    /// at index `shown` and beyond
+    pub fn search(&mut self, query: Option<&str>, shown: usize) {
+        match query {
+            Some(query) => self.client.update_query(Some(query), shown + SEARCH_BUFFER_SIZE),
+            None => self.client.update_query(None, 0),
+        }
+    }
+
+    /// Are results still arriving that can be scrolled to from the result at index `shown`?
+    pub fn is_searching(&self, shown: usize) -> bool {
+        let (results, done) = self.client.get_qm_results();
+        !*done && results.len() < shown + SEARCH_BUFFER_SIZE
+    }
+
+    /// Update the state with a message from the server, see `Client::handle_message`
+    ///
+    /// A secret that the server refused is forgotten, so that `login_prompt` asks for another.
+    pub fn handle_message(&mut self, msg: &Json) -> Result<Message, ClientError> {
+        let message = try!(self.client.handle_message(msg));
+        if let Message::LoginError(_) = message {
+            self.secret = None;
+        }
+        Ok(message)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn login_prompt() {
+        use rustc_serialize::json::Json;
+        use Client;
+        use super::{AppState, LoginPrompt};
+
+        let (client, _, _) = Client::offline("test");
+        let mut app = AppState::new(client);
+        assert_eq!(app.request(&["m1"]), Err(Some(LoginPrompt::Username)));
+        assert_eq!(app.log_in_as("bob"), Some(LoginPrompt::Password));
+        assert_eq!(app.login_prompt(), Some(LoginPrompt::Password));
+        assert_eq!(app.log_in_with_password("secret"), None);
+        assert_eq!(app.login_prompt(), None);
+        assert_eq!(app.request(&["m2"]), Err(None));
+
+        let error = r#"{"type": "error_login", "message": "Wrong password"}"#;
+        app.handle_message(&Json::from_str(error).unwrap()).unwrap();
+        assert_eq!(app.login_prompt(), Some(LoginPrompt::Password));
+        assert_eq!(app.username(), Some("bob"));
+    }
+}
@@ -0,0 +1,298 @@
+//! A client for applications that run on futures, e.g. on tokio (needs the `async` feature).
+//!
+//! `AsyncClient` handles the messages from the server as they arrive and passes them on as a
+//! `Stream` of `Message`s, so there is no receiver to poll and no `handle_message` to call.
+//! What gets an answer from the server, like logging in or searching, returns a future that is
+//! done when the answer is there. In an `async` function they are awaited, here they are run
+//! with the executor of `futures`:
+//!
+//! ```no_run
+//! extern crate futures;
+//! extern crate libclient;
+//!
+//! use futures::executor::{block_on, block_on_stream};
+//! use libclient::{ClientBuilder, Message};
+//! use libclient::async_client::AsyncClient;
+//!
+//! # fn main() {
+//! let builder = ClientBuilder::new("http://marietje-noord.marie-curie.nl/api").follow_all();
+//! let (client, messages) = block_on(AsyncClient::connect(builder)).unwrap();
+//!
+//! let results = block_on(client.search("queens of the stone age", 20)).unwrap();
+//! println!("found {} songs", results.len());
+//!
+//! for message in block_on_stream(messages) {
+//!     if let Ok(Message::PlayingChanged) = message {
+//!         println!("now playing: {:?}", client.lock().get_playing());
+//!     }
+//! }
+//! # }
+//! ```
+//!
+//! Underneath is a `Client`, which `lock` gives access to. The comet channel still talks to the
+//! server on its own threads (hyper does blocking requests only), and the messages are handled
+//! on one more thread; the futures do not block, so they can run on any executor. The `Client`
+//! itself stays the blocking API, which the terminal UI and the CLI use.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll};
+use std::thread;
+
+use chan;
+use futures::Stream;
+use futures::channel::{mpsc, oneshot};
+use rustc_serialize::json::Json;
+
+use {Client, ClientBuilder, ClientError, CometError, Media, Message};
+
+/// What a future is waiting for: a message for which `matches` is true, given the client state
+/// after handling it
+struct Waiter {
+    matches: Box<Fn(&Client, &Message) -> bool + Send>,
+    done_s: oneshot::Sender<Message>,
+}
+
+/// A `Client` of which the messages are handled in the background
+#[derive(Clone)]
+pub struct AsyncClient {
+    client: Arc<Mutex<Client>>,
+    waiters: Arc<Mutex<Vec<Waiter>>>,
+}
+
+/// The messages that the `AsyncClient` handled, or the errors that handling them gave
+///
+/// The stream ends when the connection to the server is gone for good. Messages are handled
+/// whether or not this is read, so it may be dropped if only the futures are of interest.
+pub struct Messages {
+    messages_r: mpsc::UnboundedReceiver<Result<Message, ClientError>>,
+}
+
+/// The future of a message that the server will send, see `AsyncClient::wait_for`
+pub struct Answer {
+    done_r: oneshot::Receiver<Message>,
+}
+
+/// The future of a connected `AsyncClient`, see `AsyncClient::connect`
+pub struct Connecting {
+    result_r: oneshot::Receiver<Result<(AsyncClient, Messages), ClientError>>,
+}
+
+/// The future of a search, see `AsyncClient::search`
+pub struct Search {
+    client: AsyncClient,
+    answer: Answer,
+}
+
+/// The future of a login, see `AsyncClient::login`
+pub struct Login {
+    answer: Answer,
+}
+
+impl AsyncClient {
+    /// Connect with the options of `builder`, without blocking
+    ///
+    /// Connecting is done on another thread. Once it is done, the client serves.
+    pub fn connect(builder: ClientBuilder) -> Connecting {
+        let (result_s, result_r) = oneshot::channel();
+        thread::spawn(move || {
+            let result = builder.build().map(|(client, client_r)| {
+                AsyncClient::start(client, client_r)
+            });
+            let _ = result_s.send(result);
+        });
+        Connecting { result_r: result_r }
+    }
+
+    /// Serve `client`, and handle the messages from `client_r`, its receiver, in the background
+    pub fn start(client: Client, client_r: chan::Receiver<Json>) -> (AsyncClient, Messages) {
+        client.serve();
+        let async_client = AsyncClient {
+            client: Arc::new(Mutex::new(client)),
+            waiters: Arc::new(Mutex::new(Vec::new())),
+        };
+        let (messages_s, messages_r) = mpsc::unbounded();
+        let dispatcher = async_client.clone();
+        thread::spawn(move || {
+            while let Some(msg) = client_r.recv() {
+                let result = dispatcher.handle_message(&msg);
+                // nobody reads the stream, but the futures still need the messages handled
+                let _ = messages_s.unbounded_send(result);
+            }
+            debug!("the receiver of the client is closed, stopped handling messages");
+            // nothing will answer the futures anymore
+            dispatcher.waiters.lock().unwrap().clear();
+        });
+        (async_client, Messages { messages_r: messages_r })
+    }
+
+    /// The client, to read its state or to send what has no answer
+    ///
+    /// Messages are not handled while the client is locked, so do not hold on to it.
+    pub fn lock(&self) -> MutexGuard<Client> {
+        self.client.lock().unwrap()
+    }
+
+    /// Wait for the first message after this one for which `matches` is true, given the state of
+    /// the client after handling it
+    ///
+    /// Call this before sending what the message answers, so that the answer cannot be missed.
+    pub fn wait_for<F>(&self, matches: F) -> Answer
+        where F: Fn(&Client, &Message) -> bool + Send + 'static {
+        let (done_s, done_r) = oneshot::channel();
+        self.waiters.lock().unwrap().push(Waiter { matches: Box::new(matches), done_s: done_s });
+        Answer { done_r: done_r }
+    }
+
+    /// Log in, see `Client::do_login`
+    ///
+    /// The future is done when the server has answered, with the reason if it refused. If we
+    /// were not connected, the login waits for a login token first.
+    pub fn login(&self, username: &str, password_hash: &str) -> Login {
+        let answer = self.wait_for(|_, message| match *message {
+            Message::Login | Message::LoginError(_) => true,
+            _ => false,
+        });
+        self.lock().do_login(username, password_hash);
+        Login { answer: answer }
+    }
+
+    /// Search for `query`, see `Client::update_query`
+    ///
+    /// The future is done when there are `count` results, or all of them if there are fewer.
+    pub fn search(&self, query: &str, count: usize) -> Search {
+        let (done_s, done_r) = oneshot::channel();
+        // the messages cannot be handled before the waiter is there, as the client is locked
+        let mut client = self.lock();
+        client.update_query(Some(query), count);
+        let (results, done) = client.get_qm_results();
+        if *done || results.len() >= count {
+            // we have the results of this search already, nothing more will arrive
+            let _ = done_s.send(Message::QueryMediaResults);
+        } else {
+            let matches = move |client: &Client, message: &Message| match *message {
+                Message::QueryMediaResults => {
+                    let (results, done) = client.get_qm_results();
+                    *done || results.len() >= count
+                },
+                _ => false,
+            };
+            let waiter = Waiter { matches: Box::new(matches), done_s: done_s };
+            self.waiters.lock().unwrap().push(waiter);
+        }
+        Search { client: self.clone(), answer: Answer { done_r: done_r } }
+    }
+
+    /// Handle `msg`, and finish the futures that it answers
+    fn handle_message(&self, msg: &Json) -> Result<Message, ClientError> {
+        let mut client = self.lock();
+        let message = try!(client.handle_message(msg));
+        let mut waiters = self.waiters.lock().unwrap();
+        let mut i = 0;
+        while i < waiters.len() {
+            if (waiters[i].matches)(&client, &message) {
+                let _ = waiters.remove(i).done_s.send(message.clone());
+            } else {
+                i += 1;
+            }
+        }
+        Ok(message)
+    }
+}
+
+/// The error of a future of which the client is gone
+fn gone() -> ClientError {
+    ClientError::Comet(CometError::Recv)
+}
+
+impl Stream for Messages {
+    type Item = Result<Message, ClientError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.messages_r).poll_next(cx)
+    }
+}
+
+impl Future for Answer {
+    type Output = Result<Message, ClientError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        match Pin::new(&mut self.done_r).poll(cx) {
+            Poll::Ready(Ok(message)) => Poll::Ready(Ok(message)),
+            Poll::Ready(Err(_)) => Poll::Ready(Err(gone())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl Future for Connecting {
+    type Output = Result<(AsyncClient, Messages), ClientError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        match Pin::new(&mut self.result_r).poll(cx) {
+            Poll::Ready(Ok(result)) => Poll::Ready(result),
+            Poll::Ready(Err(_)) => Poll::Ready(Err(gone())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl Future for Search {
+    /// The results, which are also in `Client::get_qm_results`
+    type Output = Result<Vec<Media>, ClientError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        match Pin::new(&mut self.answer).poll(cx) {
+            Poll::Ready(Ok(_)) => Poll::Ready(Ok(self.client.lock().get_qm_results().0.clone())),
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl Future for Login {
+    /// `Err` with the reason if the server refused the login
+    type Output = Result<Result<(), String>, ClientError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        match Pin::new(&mut self.answer).poll(cx) {
+            Poll::Ready(Ok(Message::LoginError(reason))) => Poll::Ready(Ok(Err(reason))),
+            Poll::Ready(Ok(_)) => Poll::Ready(Ok(Ok(()))),
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn search() {
+        use futures::StreamExt;
+        use futures::executor::block_on;
+        use rustc_serialize::json::Json;
+        use {Client, Message};
+        use super::AsyncClient;
+
+        let (client, client_r, offline) = Client::offline("test");
+        let (client, mut messages) = AsyncClient::start(client, client_r);
+        let search = client.search("queens", 10);
+        let query = offline.message_r.recv().unwrap();
+        assert_eq!(query.find("query").and_then(|x| x.as_string()), Some("queens"));
+        let results = r#"["s1", [{"type": "query_media_results", "token": 1, "results": [
+            {"key": "m1", "artist": "Queens of the Stone Age", "title": "No One Knows",
+             "uploadedByKey": "bob", "length": 258}
+        ]}]]"#;
+        offline.packet_s.send(Json::from_str(results).unwrap());
+
+        let results = block_on(search).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].key, "m1");
+        match block_on(messages.next()) {
+            Some(Ok(Message::QueryMediaResults)) => {},
+            x => panic!("unexpected {:?}", x),
+        }
+    }
+}
@@ -0,0 +1,170 @@
+//! A local log of what this client asked the server to change, e.g. to settle who skipped a song.
+//!
+//! With `Client::set_audit_log`, every message that changes something on the server (a request,
+//! removing or moving a request, skipping a song or voting to, a login, etc.) is appended to a
+//! file, one entry per line, and so is the answer of the server if it gives one. Nothing checks
+//! that the server did what it was asked, so an entry that was sent is not necessarily one that
+//! happened.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Error as IOError, ErrorKind, Write};
+use std::path::Path;
+
+use fs2::FileExt;
+use rustc_serialize::json::Json;
+use time::{at_utc, strptime, Timespec};
+
+const TIME_FORMAT: &'static str = "%Y-%m-%dT%H:%M:%SZ";
+
+/// Something that was sent to the server, or its outcome
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub time: Timespec,
+    /// The server
+    pub url: String,
+    /// Who we were logged in as, or are logging in as; empty if we were not
+    pub username: String,
+    /// What was asked, e.g. `"request"` or `"skip"`
+    pub action: String,
+    /// What it was asked for, e.g. the media key and the song
+    pub target: String,
+    /// What became of it, e.g. `"sent"` or `"accepted"`
+    pub outcome: String,
+}
+
+impl Entry {
+    /// The entry on `line` of an audit log, if it is one
+    pub fn parse(line: &str) -> Option<Entry> {
+        let fields: Vec<&str> = line.trim_end_matches('\r').split('\t').collect();
+        if fields.len() != 6 {
+            return None;
+        }
+        let time = match strptime(fields[0], TIME_FORMAT) {
+            Ok(tm) => tm.to_timespec(),
+            Err(_) => return None,
+        };
+        Some(Entry {
+            time: time,
+            url: fields[1].to_string(),
+            username: fields[2].to_string(),
+            action: fields[3].to_string(),
+            target: fields[4].to_string(),
+            outcome: fields[5].to_string(),
+        })
+    }
+
+    fn to_line(&self) -> String {
+        // the fields of an entry may not span more than one line or field
+        let clean = |x: &str| x.replace(|c| c == '\t' || c == '\n' || c == '\r', " ");
+        // the format is valid, so this does not fail
+        format!("{}\t{}\t{}\t{}\t{}\t{}\n", at_utc(self.time).strftime(TIME_FORMAT).unwrap(),
+                clean(&self.url), clean(&self.username), clean(&self.action),
+                clean(&self.target), clean(&self.outcome))
+    }
+}
+
+/// The action and the target of `msg`, if it asks the server to change something
+///
+/// Searching, following and the like do not change anything, and are not audited.
+pub fn describe(msg: &Json) -> Option<(&'static str, String)> {
+    let string = |key| msg.find(key).and_then(|x| x.as_string()).unwrap_or("").to_string();
+    let number = |key| msg.find(key).and_then(|x| x.as_i64()).map_or(String::new(), |x| {
+        x.to_string()
+    });
+    match msg.find("type").and_then(|x| x.as_string()) {
+        Some("request") => Some(("request", string("mediaKey"))),
+        Some("remove_request") => Some(("remove", number("requestKey"))),
+        Some("move_request") => Some(("move", format!("{} by {}", number("requestKey"),
+                                                      number("amount")))),
+        Some("boost") => Some(("boost", number("requestKey"))),
+        // a skip has the song that was playing, a skip vote has no target
+        Some("skip_playing") => Some(("skip", string("mediaKey"))),
+        Some("vote_skip") => Some(("skip", String::new())),
+        Some("report_media") => Some(("report", string("mediaKey"))),
+        Some("update_media") => Some(("edit", string("mediaKey"))),
+        Some("login") | Some("login_accessKey") => Some(("login", String::new())),
+        _ => None,
+    }
+}
+
+/// Append `entry` to the audit log at `path`
+pub fn append_entry(path: &Path, entry: &Entry) -> Result<(), IOError> {
+    if let Some(dir) = path.parent() {
+        try!(fs::create_dir_all(dir));
+    }
+    let mut file = try!(OpenOptions::new().append(true).create(true).open(path));
+    // another maruska could be writing at the same time
+    try!(FileExt::lock_exclusive(&file));
+    let result = file.write_all(entry.to_line().as_bytes());
+    try!(FileExt::unlock(&file));
+    result
+}
+
+/// Read the entries of the audit log at `path`, oldest first; there are none if it does not exist
+///
+/// Lines that are not entries are skipped.
+pub fn read_entries(path: &Path) -> Result<Vec<Entry>, IOError> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    let mut entries = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = try!(line);
+        match Entry::parse(&line) {
+            Some(entry) => entries.push(entry),
+            None => warn!("skipping invalid audit log line {:?}", line),
+        }
+    }
+    Ok(entries)
+}
+
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn append_entry() {
+        use std::env;
+        use std::fs;
+        use std::process;
+        use time::Timespec;
+        use super::{append_entry, read_entries, Entry};
+
+        let dir = env::temp_dir().join(format!("maruska-audit-test-{}", process::id()));
+        let path = dir.join("audit.tsv");
+        assert_eq!(read_entries(&path).unwrap(), []);
+        let entry = Entry {
+            time: Timespec::new(1459420207, 0),
+            url: "http://localhost/api".to_string(),
+            username: "bob".to_string(),
+            action: "request".to_string(),
+            target: "m1 (Tool\t- Schism)".to_string(),
+            outcome: "sent".to_string(),
+        };
+        append_entry(&path, &entry).unwrap();
+        append_entry(&path, &Entry { outcome: "accepted".to_string(), ..entry.clone() }).unwrap();
+
+        let entries = read_entries(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0], Entry { target: "m1 (Tool - Schism)".to_string(), ..entry.clone() });
+        assert_eq!(entries[1].outcome, "accepted");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn describe() {
+        use rustc_serialize::json::Json;
+        use super::describe;
+
+        let describe_str = |msg: &str| describe(&Json::from_str(msg).unwrap());
+        assert_eq!(describe_str(r#"{"type": "request", "mediaKey": "m1"}"#),
+                   Some(("request", "m1".to_string())));
+        assert_eq!(describe_str(r#"{"type": "move_request", "requestKey": 5, "amount": -1}"#),
+                   Some(("move", "5 by -1".to_string())));
+        assert_eq!(describe_str(r#"{"type": "vote_skip"}"#), Some(("skip", String::new())));
+        assert_eq!(describe_str(r#"{"type": "skip_playing", "mediaKey": "m2"}"#),
+                   Some(("skip", "m2".to_string())));
+        assert_eq!(describe_str(r#"{"type": "query_media", "query": "a"}"#), None);
+    }
+}
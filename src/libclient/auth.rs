@@ -0,0 +1,143 @@
+//! Hashing the secrets that we log in with.
+//!
+//! The server never sees a password: the client sends a hash of a secret and a login token that
+//! the server handed out for this login only. For a password the secret is itself a hash of the
+//! password, which front-ends may keep instead of the password. How both are hashed depends on
+//! the protocol of the server; a `LoginHasher` does it for one protocol, and `Client` uses
+//! `Md5Hasher` unless it is given another one with `Client::set_login_hasher`.
+//!
+//! MD5 is implemented here, so that logging in does not need a crypto library.
+
+use std::fmt::{self, Write};
+
+/// How the secrets that we log in with are hashed for a protocol of the server
+pub trait LoginHasher: fmt::Debug + Send + Sync {
+    /// The secret that logs in with `password`
+    fn password_secret(&self, password: &str) -> String;
+
+    /// What is sent to the server to log in with `secret` and `login_token`
+    fn login_hash(&self, secret: &str, login_token: &str) -> String;
+}
+
+/// The hashes of the current protocol: the hex encoded MD5 digests of the password and of the
+/// secret followed by the login token
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Md5Hasher;
+
+impl LoginHasher for Md5Hasher {
+    fn password_secret(&self, password: &str) -> String {
+        md5(password)
+    }
+
+    fn login_hash(&self, secret: &str, login_token: &str) -> String {
+        md5(&format!("{}{}", secret, login_token))
+    }
+}
+
+/// Get the hex encoded md5 digest of `p`, as used in the login protocol
+pub fn md5(p: &str) -> String {
+    let mut c = String::with_capacity(32);
+    for byte in md5_digest(p.as_bytes()).iter() {
+        write!(c, "{:02x}", byte).unwrap();
+    }
+    c
+}
+
+/// The amounts that every round of MD5 rotates by, see RFC 1321
+const SHIFTS: [u32; 64] = [
+    7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22,
+    5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20,
+    4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23,
+    6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+];
+
+/// The constants that every round of MD5 adds, the integer parts of `abs(sin(i + 1)) * 2^32`
+const SINES: [u32; 64] = [
+    0xd76aa478, 0xe8c7b756, 0x242070db, 0xc1bdceee, 0xf57c0faf, 0x4787c62a, 0xa8304613, 0xfd469501,
+    0x698098d8, 0x8b44f7af, 0xffff5bb1, 0x895cd7be, 0x6b901122, 0xfd987193, 0xa679438e, 0x49b40821,
+    0xf61e2562, 0xc040b340, 0x265e5a51, 0xe9b6c7aa, 0xd62f105d, 0x02441453, 0xd8a1e681, 0xe7d3fbc8,
+    0x21e1cde6, 0xc33707d6, 0xf4d50d87, 0x455a14ed, 0xa9e3e905, 0xfcefa3f8, 0x676f02d9, 0x8d2a4c8a,
+    0xfffa3942, 0x8771f681, 0x6d9d6122, 0xfde5380c, 0xa4beea44, 0x4bdecfa9, 0xf6bb4b60, 0xbebfbc70,
+    0x289b7ec6, 0xeaa127fa, 0xd4ef3085, 0x04881d05, 0xd9d4d039, 0xe6db99e5, 0x1fa27cf8, 0xc4ac5665,
+    0xf4292244, 0x432aff97, 0xab9423a7, 0xfc93a039, 0x655b59c3, 0x8f0ccc92, 0xffeff47d, 0x85845dd1,
+    0x6fa87e4f, 0xfe2ce6e0, 0xa3014314, 0x4e0811a1, 0xf7537e82, 0xbd3af235, 0x2ad7d2bb, 0xeb86d391,
+];
+
+/// The MD5 digest of `data`
+fn md5_digest(data: &[u8]) -> [u8; 16] {
+    // the data is padded with a one bit, zeros and its length in bits, to a multiple of 64 bytes
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    let bits = (data.len() as u64).wrapping_mul(8);
+    for i in 0..8 {
+        message.push((bits >> (8 * i)) as u8);
+    }
+
+    let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+    for chunk in message.chunks(64) {
+        let mut words = [0u32; 16];
+        for (i, word) in words.iter_mut().enumerate() {
+            *word = chunk[4 * i] as u32 | (chunk[4 * i + 1] as u32) << 8 |
+                    (chunk[4 * i + 2] as u32) << 16 | (chunk[4 * i + 3] as u32) << 24;
+        }
+        let (mut a, mut b, mut c, mut d) = (state[0], state[1], state[2], state[3]);
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let f = f.wrapping_add(a).wrapping_add(SINES[i]).wrapping_add(words[g]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(SHIFTS[i]));
+        }
+        state[0] = state[0].wrapping_add(a);
+        state[1] = state[1].wrapping_add(b);
+        state[2] = state[2].wrapping_add(c);
+        state[3] = state[3].wrapping_add(d);
+    }
+
+    let mut digest = [0; 16];
+    for (i, word) in state.iter().enumerate() {
+        for j in 0..4 {
+            digest[4 * i + j] = (word >> (8 * j)) as u8;
+        }
+    }
+    digest
+}
+
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn md5() {
+        use super::md5;
+        assert_eq!(md5(""), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(md5("a"), "0cc175b9c0f1b6a831c399e269772661");
+        assert_eq!(md5("abc"), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(md5("message digest"), "f96b697d7cb7938d525a2f31aaf161d0");
+        assert_eq!(md5("abcdefghijklmnopqrstuvwxyz"), "c3fcd3d76192e4007dfb496cca67e13b");
+        assert_eq!(md5("ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789"),
+                   "d174ab98d277d9f5a5611c2c9f419d9f");
+        assert_eq!(md5("12345678901234567890123456789012345678901234567890123456789012345678901234567890"),
+                   "57edf4a22be3c955ac49da2e2107b67a");
+        // the padding does not fit in the last 64 bytes of the data, and multi-byte characters
+        assert_eq!(md5(&"x".repeat(56)), "668a72d5ba17f08e62dabcafad6db14b");
+        assert_eq!(md5("Motörhead"), "0d8f8b8d8c3dec7c40f0119cdb650038");
+    }
+
+    #[test]
+    fn login_hash() {
+        use super::{LoginHasher, Md5Hasher};
+        let secret = Md5Hasher.password_secret("hunter2");
+        assert_eq!(secret, "2ab96390c7dbe3439de74d0c9b0b1767");
+        assert_eq!(Md5Hasher.login_hash(&secret, "token"),
+                   "9ba698d5797f46d22d8138434efe55c1");
+    }
+}
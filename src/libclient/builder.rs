@@ -0,0 +1,223 @@
+//! Connecting a `Client` with other options than the defaults of `Client::new`.
+//!
+//! ```no_run
+//! extern crate libclient;
+//!
+//! use std::time::Duration;
+//!
+//! use libclient::{ChunkPolicy, ClientBuilder};
+//!
+//! # fn main() {
+//! let (mut client, client_r) = ClientBuilder::new("https://marietje-noord.marie-curie.nl/api")
+//!     .timeout(Duration::from_secs(30))
+//!     .user_agent("jukebox-display/0.3")
+//!     .qm_chunk_policy(ChunkPolicy::Fixed(200))
+//!     .follow(vec!["playing".to_string()])
+//!     .build()
+//!     .unwrap();
+//! client.serve();
+//! # }
+//! ```
+
+use std::cmp::max;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use chan;
+use rustc_serialize::json::Json;
+
+use {Client, ClientError};
+use comet::CometChannel;
+use transport::Settings;
+
+/// How long the server may take to answer by default, in seconds; it holds a long poll for less
+const DEFAULT_TIMEOUT_SECS: u64 = 120;
+
+/// How long connecting to the server may take by default, in seconds
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 15;
+
+/// How many search results are asked for at once
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChunkPolicy {
+    /// A few at first, so that the first results show up quickly, and up to 1000 once there are
+    /// more than 500
+    Growing,
+    /// Always this many, or one if this is zero
+    Fixed(usize),
+}
+
+impl Default for ChunkPolicy {
+    fn default() -> ChunkPolicy {
+        ChunkPolicy::Growing
+    }
+}
+
+impl ChunkPolicy {
+    /// How many results to ask for after the first `received` ones
+    pub fn chunk_size(&self, received: usize) -> usize {
+        match *self {
+            ChunkPolicy::Growing => match received {
+                x if x <= 50 => 25, // not too much lag
+                x if x <= 100 => 50,
+                x if x <= 200 => 100,
+                x if x <= 500 => 1000 - x,
+                _ => 1000
+            },
+            ChunkPolicy::Fixed(size) => max(size, 1),
+        }
+    }
+}
+
+/// The options to connect a `Client` with
+///
+/// `build` connects, like `Client::new`, and returns the client and its receiver.
+#[derive(Clone, Debug)]
+pub struct ClientBuilder {
+    url: String,
+    settings: Settings,
+    qm_chunk_policy: ChunkPolicy,
+    follow: Vec<String>,
+    audit_log: Option<PathBuf>,
+}
+
+impl ClientBuilder {
+    /// The options to connect to the server at `url`, which are the defaults of `Client::new`
+    pub fn new(url: &str) -> ClientBuilder {
+        ClientBuilder {
+            url: url.to_string(),
+            settings: Settings {
+                timeout: Some(Duration::from_secs(DEFAULT_TIMEOUT_SECS)),
+                connect_timeout: Some(Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS)),
+                ..Settings::default()
+            },
+            qm_chunk_policy: ChunkPolicy::default(),
+            follow: Vec::new(),
+            audit_log: None,
+        }
+    }
+
+    /// Give up on the server if reading from or writing to it takes longer than `timeout`, two
+    /// minutes by default
+    ///
+    /// A long poll that the server does not answer in time is sent again once, before the
+    /// connection counts as lost. `None` waits forever, which can leave a long poll hanging.
+    pub fn timeout<T: Into<Option<Duration>>>(mut self, timeout: T) -> ClientBuilder {
+        self.settings.timeout = timeout.into();
+        self
+    }
+
+    /// Give up on connecting to the server after `timeout`, 15 seconds by default
+    ///
+    /// `None` waits for as long as the operating system does. The connection to a proxy (see
+    /// `proxy`) waits for that long anyway.
+    pub fn connect_timeout<T: Into<Option<Duration>>>(mut self, timeout: T) -> ClientBuilder {
+        self.settings.connect_timeout = timeout.into();
+        self
+    }
+
+    /// Tell the server that we are `user_agent`, e.g. `"jukebox-display/0.3"`
+    pub fn user_agent(mut self, user_agent: &str) -> ClientBuilder {
+        self.settings.user_agent = Some(user_agent.to_string());
+        self
+    }
+
+    /// Only trust the certificates in the PEM file `path` for `https://` and `wss://` servers,
+    /// instead of the ones of the system
+    ///
+    /// This needs the `tls` feature, `build` fails without it.
+    pub fn ca_file(mut self, path: PathBuf) -> ClientBuilder {
+        self.settings.ca_file = Some(path);
+        self
+    }
+
+    /// Send the requests to `http://` and `https://` servers through the HTTP proxy at `url`,
+    /// e.g. `"http://proxy.example.com:3128"`, instead of the one in `HTTP_PROXY` or
+    /// `HTTPS_PROXY`
+    ///
+    /// `build` fails for `ws://` and `wss://` servers, WebSocket connections do not go through
+    /// proxies.
+    pub fn proxy(mut self, url: &str) -> ClientBuilder {
+        self.settings.proxy = Some(url.to_string());
+        self
+    }
+
+    /// Ask for search results in chunks of the sizes that `policy` gives
+    pub fn qm_chunk_policy(mut self, policy: ChunkPolicy) -> ClientBuilder {
+        self.qm_chunk_policy = policy;
+        self
+    }
+
+    /// Follow the topics in `which` from the start, see `Client::follow`
+    ///
+    /// Panics if a topic is not `"playing"` or `"requests"`.
+    pub fn follow(mut self, which: Vec<String>) -> ClientBuilder {
+        for x in &which {
+            assert!(x == "playing" || x == "requests");
+            if !self.follow.contains(x) {
+                self.follow.push(x.clone());
+            }
+        }
+        self
+    }
+
+    /// Follow all topics from the start, see `Client::follow_all`
+    pub fn follow_all(self) -> ClientBuilder {
+        self.follow(vec!["playing".to_string(), "requests".to_string()])
+    }
+
+    /// Append what we ask the server to change to `path`, see `Client::set_audit_log`
+    pub fn audit_log(mut self, path: PathBuf) -> ClientBuilder {
+        self.audit_log = Some(path);
+        self
+    }
+
+    /// Connect to the server, see `Client::new`
+    pub fn build(self) -> Result<(Client, chan::Receiver<Json>), ClientError> {
+        let ClientBuilder { url, settings, qm_chunk_policy, follow, audit_log } = self;
+        let (mut client, client_r) = try!(Client::with_channel(|send_message_r, recv_message_s,
+                                                                bulk_message_s| {
+            CometChannel::new(&url, &settings, send_message_r, recv_message_s, bulk_message_s)
+        }));
+        client.qm_chunk_policy = qm_chunk_policy;
+        client.set_audit_log(audit_log);
+        if !follow.is_empty() {
+            client.follow(follow);
+        }
+        Ok((client, client_r))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn chunk_policy() {
+        use super::ChunkPolicy;
+
+        assert_eq!(ChunkPolicy::default().chunk_size(0), 25);
+        assert_eq!(ChunkPolicy::Growing.chunk_size(300), 700);
+        assert_eq!(ChunkPolicy::Growing.chunk_size(5000), 1000);
+        assert_eq!(ChunkPolicy::Fixed(200).chunk_size(0), 200);
+        assert_eq!(ChunkPolicy::Fixed(0).chunk_size(0), 1);
+    }
+
+    #[test]
+    fn builder() {
+        use std::time::Duration;
+        use super::{ChunkPolicy, ClientBuilder};
+
+        let builder = ClientBuilder::new("http://localhost/api")
+            .timeout(Duration::from_secs(5))
+            .user_agent("test/1.0")
+            .qm_chunk_policy(ChunkPolicy::Fixed(10))
+            .follow(vec!["playing".to_string()])
+            .follow_all();
+        assert_eq!(builder.settings.timeout, Some(Duration::from_secs(5)));
+        assert_eq!(builder.settings.connect_timeout, Some(Duration::from_secs(15)));
+        let builder = builder.connect_timeout(None);
+        assert_eq!(builder.settings.connect_timeout, None);
+        assert_eq!(builder.settings.user_agent, Some("test/1.0".to_string()));
+        assert_eq!(builder.qm_chunk_policy, ChunkPolicy::Fixed(10));
+        assert_eq!(builder.follow, vec!["playing".to_string(), "requests".to_string()]);
+    }
+}
@@ -0,0 +1,630 @@
+//! A local copy of the media index, kept in a file so that it survives a restart.
+//!
+//! The file starts with a header (a magic string and the format version), followed by a log of
+//! records. Every record is its length, a crc32 checksum of the length, a crc32 checksum of the
+//! payload and the payload, which either adds (or replaces) a song, removes one, or tells when the
+//! cache was last synced. New media are appended
+//! to the end of the log, so that saving a few changes does not rewrite the whole index. Once the
+//! log holds much more records than there are songs, it is compacted: rewritten to hold one
+//! record per song.
+//!
+//! If maruska is killed while appending, the last record is incomplete. Such a record is dropped
+//! when the cache is opened again. Because the length has its own checksum, a damaged length is
+//! not mistaken for a record that runs past the end of the file. A checksum mismatch anywhere
+//! else means that the file is damaged, and `MediaCache::open` fails; use
+//! `MediaCache::open_or_reset` to start over instead.
+//!
+//! Only one `MediaCache` can have a file open at a time, also across processes. `MediaCache::open`
+//! waits until the file is free.
+
+use std::collections::BTreeMap;
+use std::collections::btree_map::Values;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error as IOError, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use fs2::FileExt;
+use time::{Duration, Timespec};
+
+use media::Media;
+use text;
+
+
+/// Identifies a maruska media cache file
+const MAGIC: &'static [u8; 8] = b"MRSKIDX\0";
+
+/// The version of the file format, increase it when the format changes
+///
+/// Caches with another version are not read, they have to be downloaded again.
+pub const CACHE_VERSION: u32 = 4;
+
+const HEADER_LEN: u64 = 12;
+
+/// The length and the two checksums in front of every payload
+const RECORD_HEADER_LEN: usize = 12;
+
+/// Compact once there are this many records more than there are songs, and at least twice as
+/// many records as there are songs
+const COMPACT_MIN_GARBAGE: usize = 1000;
+
+const TAG_PUT: u8 = 1;
+const TAG_REMOVE: u8 = 2;
+const TAG_SYNCED: u8 = 3;
+
+#[derive(Debug)]
+pub enum CacheError {
+    IO(IOError),
+    /// The file is not a media cache
+    Magic,
+    /// The file has another version of the format
+    Version(u32),
+    /// The record at this offset is damaged
+    Checksum(u64),
+    /// The record at this offset has a valid checksum, but could not be decoded
+    Malformed(u64, &'static str),
+}
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CacheError::IO(_) => write!(f, "could not read or write the media cache"),
+            CacheError::Magic => write!(f, "the file is not a media cache"),
+            CacheError::Version(version) => {
+                write!(f, "the media cache has version {}, expected {}", version, CACHE_VERSION)
+            },
+            CacheError::Checksum(offset) => {
+                write!(f, "the media cache is damaged at byte {}", offset)
+            },
+            CacheError::Malformed(offset, what) => {
+                write!(f, "invalid record in the media cache at byte {}: {}", offset, what)
+            },
+        }
+    }
+}
+
+impl Error for CacheError {
+    fn description(&self) -> &str {
+        match *self {
+            CacheError::IO(ref err) => err.description(),
+            CacheError::Magic => "not a media cache",
+            CacheError::Version(_) => "unsupported media cache version",
+            CacheError::Checksum(_) => "damaged media cache",
+            CacheError::Malformed(_, _) => "invalid record in the media cache",
+        }
+    }
+
+    fn source(&self) -> Option<&(Error + 'static)> {
+        match *self {
+            CacheError::IO(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<IOError> for CacheError {
+    fn from(err: IOError) -> Self {
+        CacheError::IO(err)
+    }
+}
+
+/// The media index, backed by a file
+#[derive(Debug)]
+pub struct MediaCache {
+    path: PathBuf,
+    file: File,
+
+    /// All songs, by media key
+    media: BTreeMap<String, Media>,
+
+    /// When the cache was last synced with the server
+    synced_at: Option<Timespec>,
+
+    /// The number of records in the file
+    records: usize,
+
+    /// Held for as long as the cache is open, on a separate file because `compact` replaces
+    /// the cache file
+    _lock: File,
+}
+
+impl MediaCache {
+    /// Open the cache at `path`, or create an empty one if there is none
+    pub fn open(path: &Path) -> Result<MediaCache, CacheError> {
+        if let Some(dir) = path.parent() {
+            try!(fs::create_dir_all(dir));
+        }
+        let lock = try!(OpenOptions::new().write(true).create(true)
+                                          .open(with_suffix(path, ".lock")));
+        try!(FileExt::lock_exclusive(&lock));
+        let mut file = try!(OpenOptions::new().read(true).write(true).create(true).open(path));
+        let mut cache = MediaCache {
+            path: path.to_path_buf(),
+            file: try!(file.try_clone()),
+            media: BTreeMap::new(),
+            synced_at: None,
+            records: 0,
+            _lock: lock,
+        };
+
+        let mut buf = Vec::new();
+        try!(file.read_to_end(&mut buf));
+        if buf.is_empty() {
+            try!(cache.file.write_all(&header()));
+            try!(cache.file.sync_all());
+            return Ok(cache);
+        }
+        if buf.len() < HEADER_LEN as usize || &buf[0..8] != MAGIC {
+            return Err(CacheError::Magic);
+        }
+        let version = read_u32(&buf[8..12]);
+        if version != CACHE_VERSION {
+            return Err(CacheError::Version(version));
+        }
+
+        let mut offset = HEADER_LEN as usize;
+        while offset + RECORD_HEADER_LEN <= buf.len() {
+            let len = &buf[offset..offset + 4];
+            if crc32(len) != read_u32(&buf[offset + 4..offset + 8]) {
+                return Err(CacheError::Checksum(offset as u64));
+            }
+            let record_end = offset + RECORD_HEADER_LEN + read_u32(len) as usize;
+            if record_end > buf.len() {
+                break; // the length is right, so this record is the last thing in the file
+            }
+            let checksum = read_u32(&buf[offset + 8..offset + 12]);
+            let payload = &buf[offset + RECORD_HEADER_LEN..record_end];
+            if crc32(payload) != checksum {
+                if record_end == buf.len() {
+                    break; // the last record was not written completely
+                }
+                return Err(CacheError::Checksum(offset as u64));
+            }
+            try!(cache.apply(payload).map_err(|what| CacheError::Malformed(offset as u64, what)));
+            cache.records += 1;
+            offset = record_end;
+        }
+        if offset != buf.len() {
+            warn!("dropping incomplete record at the end of the media cache");
+            try!(cache.file.set_len(offset as u64));
+        }
+        try!(cache.file.seek(SeekFrom::Start(offset as u64)));
+        Ok(cache)
+    }
+
+    /// The name of the cache file for the server at `url`, so that every server has its own
+    pub fn file_name(url: &str) -> String {
+        let name: String = url.split("://").last().unwrap_or(url).chars().map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '.' { c } else { '_' }
+        }).collect();
+        format!("media-{}.idx", name.trim_matches('_'))
+    }
+
+    /// Open the cache at `path`, or start with an empty cache if it cannot be read
+    pub fn open_or_reset(path: &Path) -> Result<MediaCache, CacheError> {
+        match MediaCache::open(path) {
+            Err(CacheError::IO(err)) => Err(CacheError::IO(err)),
+            Err(err) => {
+                warn!("starting with an empty media cache, because {}", err);
+                try!(fs::remove_file(path));
+                MediaCache::open(path)
+            },
+            Ok(cache) => Ok(cache),
+        }
+    }
+
+    /// The number of songs in the cache
+    pub fn len(&self) -> usize {
+        self.media.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.media.is_empty()
+    }
+
+    /// Get the song with media key `key`
+    pub fn get(&self, key: &str) -> Option<&Media> {
+        self.media.get(key)
+    }
+
+    /// When the cache was last synced with the server, if it ever was
+    pub fn synced_at(&self) -> Option<Timespec> {
+        self.synced_at
+    }
+
+    /// Remember that the cache was synced at `time`
+    pub fn set_synced_at(&mut self, time: Timespec) -> Result<(), CacheError> {
+        let mut buf = Vec::new();
+        push_record(&mut buf, &encode_synced(time));
+        self.synced_at = Some(time);
+        self.write_records(&buf, 1)
+    }
+
+    /// All songs in the cache, ordered by media key
+    pub fn media<'a>(&'a self) -> Values<'a, String, Media> {
+        self.media.values()
+    }
+
+    /// The songs that `user` uploaded, ordered by artist and title
+    pub fn uploaded_by<'a>(&'a self, user: &str) -> Vec<&'a Media> {
+        let mut media: Vec<&Media> = self.media.values()
+                                           .filter(|x| x.uploaded_by == user)
+                                           .collect();
+        media.sort_by(|a, b| a.collate(b));
+        media
+    }
+
+    /// The songs of `artist`, ignoring case and accents, ordered by title
+    pub fn by_artist<'a>(&'a self, artist: &str) -> Vec<&'a Media> {
+        let artist = text::fold(artist);
+        let mut media: Vec<&Media> = self.media.values()
+                                           .filter(|x| text::fold(&x.artist) == artist)
+                                           .collect();
+        media.sort_by(|a, b| a.collate(b));
+        media
+    }
+
+    /// Add the songs in `media` to the cache, or replace them if we already have them
+    ///
+    /// Songs that did not change are skipped. The file is compacted when needed.
+    pub fn append(&mut self, media: &[Media]) -> Result<(), CacheError> {
+        let mut buf = Vec::new();
+        let mut count = 0;
+        for m in media {
+            if self.media.get(&m.key) == Some(m) {
+                continue;
+            }
+            push_record(&mut buf, &encode_put(m));
+            self.media.insert(m.key.clone(), m.clone());
+            count += 1;
+        }
+        self.write_records(&buf, count)
+    }
+
+    /// Remove the songs with the media keys in `keys` from the cache
+    pub fn remove(&mut self, keys: &[&str]) -> Result<(), CacheError> {
+        let mut buf = Vec::new();
+        let mut count = 0;
+        for key in keys {
+            if self.media.remove(*key).is_none() {
+                continue;
+            }
+            let mut payload = vec![TAG_REMOVE];
+            push_str(&mut payload, key);
+            push_record(&mut buf, &payload);
+            count += 1;
+        }
+        self.write_records(&buf, count)
+    }
+
+    /// Rewrite the file, so that it holds exactly one record per song
+    ///
+    /// The new file is written next to the old one and then replaces it at once.
+    pub fn compact(&mut self) -> Result<(), CacheError> {
+        let tmp_path = with_suffix(&self.path, ".tmp");
+        let mut buf = header().to_vec();
+        for m in self.media.values() {
+            push_record(&mut buf, &encode_put(m));
+        }
+        if let Some(time) = self.synced_at {
+            push_record(&mut buf, &encode_synced(time));
+        }
+        {
+            let mut tmp_file = try!(File::create(&tmp_path));
+            try!(tmp_file.write_all(&buf));
+            try!(tmp_file.sync_all());
+        }
+        try!(fs::rename(&tmp_path, &self.path));
+
+        let mut file = try!(OpenOptions::new().read(true).write(true).open(&self.path));
+        try!(file.seek(SeekFrom::End(0)));
+        self.file = file;
+        self.records = self.live_records();
+        debug!("compacted the media cache to {} records", self.records);
+        Ok(())
+    }
+
+    fn write_records(&mut self, buf: &[u8], count: usize) -> Result<(), CacheError> {
+        if count == 0 {
+            return Ok(());
+        }
+        try!(self.file.write_all(buf));
+        try!(self.file.sync_data());
+        self.records += count;
+
+        let garbage = self.records - self.live_records();
+        if garbage >= COMPACT_MIN_GARBAGE && garbage >= self.media.len() {
+            try!(self.compact());
+        }
+        Ok(())
+    }
+
+    /// The number of records that a compacted file has
+    fn live_records(&self) -> usize {
+        self.media.len() + if self.synced_at.is_some() { 1 } else { 0 }
+    }
+
+    /// Apply the record with `payload` to the songs in memory
+    fn apply(&mut self, payload: &[u8]) -> Result<(), &'static str> {
+        let mut reader = payload;
+        match try!(read_u8(&mut reader)) {
+            TAG_PUT => {
+                let media = Media {
+                    key: try!(read_str(&mut reader)),
+                    artist: try!(read_str(&mut reader)),
+                    title: try!(read_str(&mut reader)),
+                    uploaded_by: try!(read_str(&mut reader)),
+                    length: Duration::nanoseconds(try!(read_i64(&mut reader))),
+                    // an empty album is no album
+                    album: Some(try!(read_str(&mut reader))).filter(|x| !x.is_empty()),
+                    url: None,
+                };
+                self.media.insert(media.key.clone(), media);
+            },
+            TAG_REMOVE => {
+                let key = try!(read_str(&mut reader));
+                self.media.remove(&key);
+            },
+            TAG_SYNCED => {
+                self.synced_at = Some(Timespec::new(try!(read_i64(&mut reader)), 0));
+            },
+            _ => return Err("unknown record type"),
+        }
+        if !reader.is_empty() {
+            return Err("trailing bytes");
+        }
+        Ok(())
+    }
+}
+
+fn header() -> [u8; HEADER_LEN as usize] {
+    let mut header = [0; HEADER_LEN as usize];
+    header[0..8].copy_from_slice(MAGIC);
+    header[8..12].copy_from_slice(&u32_bytes(CACHE_VERSION));
+    header
+}
+
+fn push_record(buf: &mut Vec<u8>, payload: &[u8]) {
+    let len = u32_bytes(payload.len() as u32);
+    buf.extend_from_slice(&len);
+    buf.extend_from_slice(&u32_bytes(crc32(&len)));
+    buf.extend_from_slice(&u32_bytes(crc32(payload)));
+    buf.extend_from_slice(payload);
+}
+
+fn encode_put(media: &Media) -> Vec<u8> {
+    let mut payload = vec![TAG_PUT];
+    push_str(&mut payload, &media.key);
+    push_str(&mut payload, &media.artist);
+    push_str(&mut payload, &media.title);
+    push_str(&mut payload, &media.uploaded_by);
+    push_i64(&mut payload, media.length.num_nanoseconds().unwrap_or(0));
+    push_str(&mut payload, media.album.as_ref().map_or("", |x| &x[..]));
+    payload
+}
+
+fn encode_synced(time: Timespec) -> Vec<u8> {
+    let mut payload = vec![TAG_SYNCED];
+    push_i64(&mut payload, time.sec);
+    payload
+}
+
+fn push_i64(buf: &mut Vec<u8>, x: i64) {
+    buf.extend_from_slice(&u32_bytes(x as u64 as u32));
+    buf.extend_from_slice(&u32_bytes((x as u64 >> 32) as u32));
+}
+
+fn push_str(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&u32_bytes(s.len() as u32));
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn read_u8(reader: &mut &[u8]) -> Result<u8, &'static str> {
+    let mut b = [0];
+    try!(reader.read_exact(&mut b).map_err(|_| "record too short"));
+    Ok(b[0])
+}
+
+fn read_i64(reader: &mut &[u8]) -> Result<i64, &'static str> {
+    let mut b = [0; 8];
+    try!(reader.read_exact(&mut b).map_err(|_| "record too short"));
+    Ok((read_u32(&b[0..4]) as u64 | (read_u32(&b[4..8]) as u64) << 32) as i64)
+}
+
+fn read_str(reader: &mut &[u8]) -> Result<String, &'static str> {
+    let mut b = [0; 4];
+    try!(reader.read_exact(&mut b).map_err(|_| "record too short"));
+    let len = read_u32(&b) as usize;
+    if reader.len() < len {
+        return Err("record too short");
+    }
+    let (s, rest) = reader.split_at(len);
+    *reader = rest;
+    String::from_utf8(s.to_vec()).map_err(|_| "string is not utf-8")
+}
+
+/// `path` with `suffix` appended to its file name, for the lock and temporary files next to it
+pub fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut s = path.as_os_str().to_os_string();
+    s.push(suffix);
+    PathBuf::from(s)
+}
+
+/// Read a little endian u32 from the first four bytes of `b`
+fn read_u32(b: &[u8]) -> u32 {
+    b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24
+}
+
+fn u32_bytes(x: u32) -> [u8; 4] {
+    [x as u8, (x >> 8) as u8, (x >> 16) as u8, (x >> 24) as u8]
+}
+
+/// The crc32 checksum of `data` (the one that is used by zlib)
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xedb88320 & mask);
+        }
+    }
+    !crc
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::process;
+    use time::{Duration, Timespec};
+
+    use media::Media;
+    use super::*;
+
+    fn media(key: &str, title: &str) -> Media {
+        Media {
+            key: key.to_string(),
+            artist: "Queens Of The Stone Age".to_string(),
+            title: title.to_string(),
+            length: Duration::seconds(231),
+            uploaded_by: "dsprenkels".to_string(),
+            album: None,
+            url: None,
+        }
+    }
+
+    #[test]
+    fn crc32() {
+        assert_eq!(super::crc32(b""), 0);
+        assert_eq!(super::crc32(b"123456789"), 0xcbf43926);
+    }
+
+    #[test]
+    fn file_name() {
+        assert_eq!(MediaCache::file_name("http://marietje-noord.marie-curie.nl/api"),
+                   "media-marietje-noord.marie-curie.nl_api.idx");
+    }
+
+    #[test]
+    fn append_and_reopen() {
+        let dir = env::temp_dir().join(format!("maruska-cache-test-{}", process::id()));
+        let path = dir.join("media.idx");
+
+        {
+            let mut cache = MediaCache::open(&path).unwrap();
+            assert!(cache.is_empty());
+            cache.append(&[media("a", "In the Fade"), media("b", "No One Knows")]).unwrap();
+            cache.append(&[media("a", "In the Fade (live)")]).unwrap();
+            cache.remove(&["b"]).unwrap();
+            cache.set_synced_at(Timespec::new(1459420207, 0)).unwrap();
+        }
+        let mut cache = MediaCache::open(&path).unwrap();
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.get("a"), Some(&media("a", "In the Fade (live)")));
+        assert_eq!(cache.synced_at(), Some(Timespec::new(1459420207, 0)));
+
+        // A half written record at the end is dropped
+        cache.append(&[media("c", "Go with the Flow")]).unwrap();
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new().write(true).open(&path).unwrap().set_len(len - 3).unwrap();
+        drop(cache);
+        let cache = MediaCache::open(&path).unwrap();
+        assert_eq!(cache.len(), 1);
+        assert!(cache.get("c").is_none());
+        drop(cache);
+
+        // Other damage is an error
+        fs::OpenOptions::new().write(true).open(&path).unwrap()
+                              .write_all(b"MRSKIDX\0\x09").unwrap();
+        match MediaCache::open(&path) {
+            Err(CacheError::Version(_)) => {},
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert!(MediaCache::open_or_reset(&path).unwrap().is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn compact() {
+        let dir = env::temp_dir().join(format!("maruska-cache-compact-test-{}", process::id()));
+        let path = dir.join("media.idx");
+
+        let mut cache = MediaCache::open(&path).unwrap();
+        for i in 0..3 {
+            cache.append(&[media("a", &format!("take {}", i))]).unwrap();
+        }
+        cache.set_synced_at(Timespec::new(1, 0)).unwrap();
+        let len_before = fs::metadata(&path).unwrap().len();
+        cache.compact().unwrap();
+        assert!(fs::metadata(&path).unwrap().len() < len_before);
+        cache.append(&[media("b", "No One Knows")]).unwrap();
+        drop(cache);
+
+        let cache = MediaCache::open(&path).unwrap();
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get("a").unwrap().title, "take 2");
+        assert_eq!(cache.synced_at(), Some(Timespec::new(1, 0)));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn uploaded_by() {
+        let dir = env::temp_dir().join(format!("maruska-cache-uploads-test-{}", process::id()));
+        let path = dir.join("media.idx");
+
+        let mut cache = MediaCache::open(&path).unwrap();
+        let mut other = media("c", "Creep");
+        other.uploaded_by = "bkoks".to_string();
+        other.artist = "Radiohead".to_string();
+        cache.append(&[media("a", "No One Knows"), media("b", "In the Fade"), other]).unwrap();
+        let titles: Vec<&str> = cache.uploaded_by("dsprenkels").iter()
+                                     .map(|x| &x.title[..])
+                                     .collect();
+        assert_eq!(titles, ["In the Fade", "No One Knows"]);
+        assert!(cache.uploaded_by("nobody").is_empty());
+        assert_eq!(cache.by_artist("queens of the stone age").len(), 2);
+        assert_eq!(cache.by_artist("Radiohead")[0].title, "Creep");
+        assert!(cache.by_artist("Muse").is_empty());
+        drop(cache);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn damaged_record() {
+        let dir = env::temp_dir().join(format!("maruska-cache-damage-test-{}", process::id()));
+        let path = dir.join("media.idx");
+
+        {
+            let mut cache = MediaCache::open(&path).unwrap();
+            cache.append(&[media("a", "In the Fade")]).unwrap();
+            cache.append(&[media("b", "No One Knows")]).unwrap();
+        }
+        let original = fs::read(&path).unwrap();
+        let first = HEADER_LEN as usize;
+
+        // A length that runs past the end of the file, in the middle of it
+        let mut buf = original.clone();
+        buf[first + 3] = 0xff;
+        fs::write(&path, &buf).unwrap();
+        match MediaCache::open(&path) {
+            Err(CacheError::Checksum(offset)) => assert_eq!(offset, HEADER_LEN),
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        // A checksum of the payload, in the middle of the file
+        let mut buf = original.clone();
+        buf[first + 8] ^= 1;
+        fs::write(&path, &buf).unwrap();
+        match MediaCache::open(&path) {
+            Err(CacheError::Checksum(offset)) => assert_eq!(offset, HEADER_LEN),
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert_eq!(fs::read(&path).unwrap(), buf);
+        assert!(MediaCache::open_or_reset(&path).unwrap().is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
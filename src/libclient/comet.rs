@@ -1,7 +1,11 @@
+use std::cmp::min;
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::fmt;
-use std::io::Error as IOError;
-use std::sync::{Arc, Mutex, RwLock};
+use std::io::{Error as IOError, ErrorKind};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
 use chan;
 use hyper;
@@ -9,6 +13,67 @@ use hyper::error::Error as HyperError;
 use rustc_serialize::json::{Json, ParserError as JsonError, ToJson};
 use std::thread;
 
+use metrics::{Metrics, Recorder};
+use packets::{Direction, Packet, PacketLog};
+use transport::{self, Http, Settings, Transport};
+
+
+/// Types of messages that can arrive in large numbers, which are delivered after the others
+const BULK_MESSAGE_TYPES: &'static [&'static str] = &["query_media_results"];
+
+/// The longest time to wait before trying to reach the server again, in seconds
+const MAX_RETRY_SECS: u64 = 30;
+
+/// How long to wait for packets from a server that pushes them, before sending what the
+/// front-end has to send, in milliseconds
+const RECEIVE_INTERVAL_MS: u64 = 50;
+
+/// How long a server that pushes packets may take to answer the start of a session, in seconds
+const SESSION_TIMEOUT_SECS: u64 = 10;
+
+/// Whether the server can be reached
+///
+/// Whenever it changes, a `connection_state` message is sent to the front-end, as if the server
+/// sent it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connected,
+    /// The server could not be reached, and we are waiting to try again
+    Disconnected,
+    /// Trying to reach the server again, with this attempt
+    Reconnecting(u32),
+}
+
+impl ConnectionState {
+    /// The `connection_state` message that tells the front-end about this state
+    pub fn to_message(&self) -> Json {
+        let mut obj = BTreeMap::new();
+        obj.insert("type".to_string(), "connection_state".to_json());
+        let state = match *self {
+            ConnectionState::Connected => "connected",
+            ConnectionState::Disconnected => "disconnected",
+            ConnectionState::Reconnecting(attempt) => {
+                obj.insert("attempt".to_string(), attempt.to_json());
+                "reconnecting"
+            },
+        };
+        obj.insert("state".to_string(), state.to_json());
+        Json::Object(obj)
+    }
+
+    /// The state that a `connection_state` message tells about, if it is a valid one
+    pub fn from_message(msg: &Json) -> Option<ConnectionState> {
+        match msg.find("state").and_then(|x| x.as_string()) {
+            Some("connected") => Some(ConnectionState::Connected),
+            Some("disconnected") => Some(ConnectionState::Disconnected),
+            Some("reconnecting") => {
+                let attempt = msg.find("attempt").and_then(|x| x.as_u64()).unwrap_or(1);
+                Some(ConnectionState::Reconnecting(attempt as u32))
+            },
+            _ => None,
+        }
+    }
+}
 
 #[derive(Debug)]
 pub enum CometError {
@@ -16,12 +81,40 @@ pub enum CometError {
     Hyper(HyperError),
     IO(IOError),
     Json(JsonError),
-    MalformedResponse((&'static str, Json))
+    MalformedResponse((&'static str, Json)),
+    /// The WebSocket connection failed, for this reason
+    WebSocket(String),
+    /// TLS could not be set up as asked, for this reason
+    Tls(String),
+    /// The proxy could not be used, for this reason
+    Proxy(String),
 }
 
 impl fmt::Display for CometError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "comet error: ({})", self)
+        match *self {
+            CometError::Recv => write!(f, "cannot read on channel"),
+            CometError::Hyper(_) => write!(f, "http request to the server failed"),
+            CometError::IO(_) => write!(f, "io error while talking to the server"),
+            CometError::Json(_) => write!(f, "the server sent invalid json"),
+            CometError::MalformedResponse((what, _)) => {
+                write!(f, "malformed response from the server: {}", what)
+            },
+            CometError::WebSocket(ref reason) => write!(f, "websocket error: {}", reason),
+            CometError::Tls(ref reason) => write!(f, "tls error: {}", reason),
+            CometError::Proxy(ref reason) => write!(f, "proxy error: {}", reason),
+        }
+    }
+}
+
+impl CometError {
+    /// Did the server take longer to answer than the timeout, see `ClientBuilder::timeout`?
+    pub fn is_timeout(&self) -> bool {
+        let kind = match *self {
+            CometError::IO(ref err) | CometError::Hyper(HyperError::Io(ref err)) => err.kind(),
+            _ => return false,
+        };
+        kind == ErrorKind::WouldBlock || kind == ErrorKind::TimedOut
     }
 }
 
@@ -51,6 +144,19 @@ impl Error for CometError {
             CometError::IO(ref err) => err.description(),
             CometError::Json(ref err) => err.description(),
             CometError::MalformedResponse(_) => "malformed response",
+            CometError::WebSocket(_) => "websocket error",
+            CometError::Tls(_) => "tls error",
+            CometError::Proxy(_) => "proxy error",
+        }
+    }
+
+    fn source(&self) -> Option<&(Error + 'static)> {
+        match *self {
+            CometError::Hyper(ref err) => Some(err),
+            CometError::IO(ref err) => Some(err),
+            CometError::Json(ref err) => Some(err),
+            CometError::Recv | CometError::MalformedResponse(_) |
+                CometError::WebSocket(_) | CometError::Tls(_) | CometError::Proxy(_) => None,
         }
     }
 }
@@ -58,48 +164,117 @@ impl Error for CometError {
 
 #[derive(Clone, Debug)]
 pub struct CometChannel {
-    /// hyper client instance
-    client: Arc<hyper::Client>,
+    /// how the packets get to the server and back, see the `transport` module
+    transport: Arc<Transport>,
 
     /// amount of current outstanding requests
     current_requests: Arc<Mutex<u8>>,
 
+    /// do not long poll, only send the messages from the front-end
+    paused: Arc<AtomicBool>,
+
+    /// how long to wait before every long poll, see `set_poll_delay`
+    poll_delay: Arc<(Mutex<Option<Duration>>, Condvar)>,
+
     /// receive messages to send from the front-end
     send_message_r: chan::Receiver<Json>,
 
     /// where to send messages recieved from the other endpoint
     recv_message_s: chan::Sender<Json>,
 
+    /// where to send the received messages of `BULK_MESSAGE_TYPES`
+    bulk_message_s: chan::Sender<Json>,
+
     /// comet session id
     session_id: Arc<RwLock<Option<String>>>,
 
     /// reference to the url string slice
     url: Arc<String>,
+
+    /// the packets that were exchanged most recently
+    packet_log: Arc<Mutex<PacketLog>>,
+
+    /// whether the server could be reached the last time we tried
+    state: Arc<Mutex<ConnectionState>>,
+
+    /// what went over the connection so far, see `get_metrics`
+    recorder: Arc<Mutex<Recorder>>,
+
+    /// when offline, the packets to handle as if the server sent them, and where to send the
+    /// messages from the front-end instead
+    offline: Option<(chan::Receiver<Json>, chan::Sender<Json>)>,
 }
 
 impl CometChannel {
     pub fn new<T: ToString>(url: T,
+                            settings: &Settings,
                             send_message_r: chan::Receiver<Json>,
-                            recv_message_s: chan::Sender<Json>) -> Result<CometChannel, CometError> {
+                            recv_message_s: chan::Sender<Json>,
+                            bulk_message_s: chan::Sender<Json>) -> Result<CometChannel, CometError> {
+        let url = url.to_string();
         let mut comet = CometChannel {
-            client: Arc::new(hyper::Client::new()),
+            transport: try!(transport::for_url(&url, settings)),
             current_requests: Arc::new(Mutex::new(0)),
+            paused: Arc::new(AtomicBool::new(false)),
+            poll_delay: Arc::new((Mutex::new(None), Condvar::new())),
             send_message_r: send_message_r,
             recv_message_s: recv_message_s,
+            bulk_message_s: bulk_message_s,
             session_id: Arc::new(RwLock::new(None)),
-            url: Arc::new(url.to_string()),
+            url: Arc::new(url),
+            packet_log: Arc::new(Mutex::new(PacketLog::default())),
+            state: Arc::new(Mutex::new(ConnectionState::Connected)),
+            recorder: Arc::new(Mutex::new(Recorder::default())),
+            offline: None,
         };
         try!(CometChannel::connect(&mut comet));
+        comet.recorder.lock().unwrap().set_connected(true);
         Ok(comet)
     }
 
+    /// A channel that does not connect to a server, but handles the packets from `packet_r` as
+    /// if the server sent them
+    ///
+    /// The messages from the front-end are passed on to `message_s`.
+    pub fn offline<T: ToString>(url: T,
+                                packet_r: chan::Receiver<Json>,
+                                message_s: chan::Sender<Json>,
+                                send_message_r: chan::Receiver<Json>,
+                                recv_message_s: chan::Sender<Json>,
+                                bulk_message_s: chan::Sender<Json>) -> CometChannel {
+        let url = url.to_string();
+        CometChannel {
+            // never used, the packets are handled by `serve_offline`
+            transport: Arc::new(Http::new(&url, hyper::Client::new(), &Settings::default())),
+            current_requests: Arc::new(Mutex::new(0)),
+            paused: Arc::new(AtomicBool::new(false)),
+            poll_delay: Arc::new((Mutex::new(None), Condvar::new())),
+            send_message_r: send_message_r,
+            recv_message_s: recv_message_s,
+            bulk_message_s: bulk_message_s,
+            session_id: Arc::new(RwLock::new(None)),
+            url: Arc::new(url),
+            packet_log: Arc::new(Mutex::new(PacketLog::default())),
+            state: Arc::new(Mutex::new(ConnectionState::Connected)),
+            recorder: Arc::new(Mutex::new(Recorder::default())),
+            offline: Some((packet_r, message_s)),
+        }
+    }
+
     fn send(&mut self, msg: Json) -> Result<(), CometError> {
-        let mut res = try!(self.client.post(&*self.url)
-                                      .body(&msg.to_string())
-                                      .send());
-        let decoded = try!(Json::from_reader(&mut res));
-        trace!("received packet: {}", decoded);
-        self.handle_receive_packet(decoded)
+        self.packet_log.lock().unwrap().push(Direction::Sent, &msg);
+        self.recorder.lock().unwrap().packet_sent();
+        match try!(self.transport.send(&msg)) {
+            Some(answer) => self.handle_received(answer),
+            None => Ok(()), // the answer arrives later, see `serve_pushed`
+        }
+    }
+
+    fn handle_received(&mut self, packet: Json) -> Result<(), CometError> {
+        trace!("received packet: {}", packet);
+        self.packet_log.lock().unwrap().push(Direction::Received, &packet);
+        self.recorder.lock().unwrap().packet_received();
+        self.handle_receive_packet(packet)
     }
 
     fn handle_receive_packet(&mut self, packet: Json) -> Result<(), CometError> {
@@ -111,7 +286,11 @@ impl CometChannel {
                                                           packet.clone())))
         );
         for message in packet_contents {
-            self.recv_message_s.send(message.clone());
+            if is_bulk(message) {
+                self.bulk_message_s.send(message.clone());
+            } else {
+                self.recv_message_s.send(message.clone());
+            }
         }
         Ok(())
     }
@@ -151,7 +330,22 @@ impl CometChannel {
             assert_eq!(*self.session_id.read().unwrap(), None); // already connected
         }
         info!("Connecting to {}", self.url);
-        self.send([(); 0].to_json())
+        self.start_session()
+    }
+
+    /// Send an empty packet without a session id, which the server answers with a new session
+    fn start_session(&mut self) -> Result<(), CometError> {
+        try!(self.send([(); 0].to_json()));
+        if !self.transport.pushes() {
+            return Ok(()); // the answer was handled already
+        }
+        match try!(self.transport.receive(Duration::from_secs(SESSION_TIMEOUT_SECS))) {
+            Some(packet) => self.handle_received(packet),
+            None => {
+                let err = IOError::new(ErrorKind::TimedOut, "the server did not start a session");
+                Err(CometError::IO(err))
+            },
+        }
     }
 
     pub fn poll(&mut self) -> Result<(), CometError> {
@@ -189,41 +383,348 @@ impl CometChannel {
     pub fn get_url(&self) -> String {
         self.url.to_string()
     }
+
+    pub fn is_offline(&self) -> bool {
+        self.offline.is_some()
+    }
+
+    pub fn get_packets(&self) -> Vec<Packet> {
+        self.packet_log.lock().unwrap().packets()
+    }
+
+    pub fn get_state(&self) -> ConnectionState {
+        *self.state.lock().unwrap()
+    }
+
+    /// What went over the connection since the channel was created
+    ///
+    /// The offline channel has no connection, so it only counts the packets.
+    pub fn get_metrics(&self) -> Metrics {
+        let (sent, received) = match self.offline {
+            Some(_) => (0, 0),
+            None => self.transport.traffic().map_or((0, 0), |x| x.totals()),
+        };
+        self.recorder.lock().unwrap().metrics(sent, received)
+    }
+
+    /// Tell the front-end that the connection is in `state`, unless it knows that already
+    fn set_state(&self, state: ConnectionState) {
+        let mut current = self.state.lock().unwrap();
+        if *current != state {
+            info!("connection state: {:?}", state);
+            *current = state;
+            self.recorder.lock().unwrap().set_connected(state == ConnectionState::Connected);
+            self.recv_message_s.send(state.to_message());
+        }
+    }
+
+    /// Start a new session with the server, which may have forgotten the old one
+    fn reconnect(&mut self) -> Result<(), CometError> {
+        if self.get_state() == ConnectionState::Connected {
+            return Ok(()); // the other thread reconnected already
+        }
+        *self.session_id.write().unwrap() = None;
+        info!("Reconnecting to {}", self.url);
+        try!(self.transport.reconnect());
+        try!(self.start_session());
+        self.recorder.lock().unwrap().reconnected();
+        Ok(())
+    }
+
+    /// Send the messages from the front-end, or poll the server if nobody is polling yet
+    fn serve_once(&mut self) -> Result<(), CometError> {
+        if try!(self.try_handle_send_message()) {
+            return Ok(());
+        }
+        // do we need to send a long poll request?
+        let poll = !self.paused.load(Ordering::SeqCst) && {
+            let mut x = self.current_requests.lock().unwrap();
+            match *x {
+                0 => { *x += 1; true },
+                1 => false,
+                _ => unreachable!()
+            }
+        };
+        if poll {
+            self.wait_poll_delay();
+            let result = self.poll();
+            // the poll is over, whether it failed or not
+            *self.current_requests.lock().unwrap() -= 1;
+            result
+        } else {
+            self.handle_send_message()
+        }
+    }
+
+    /// Send the messages from the front-end, or else wait a while for packets from a server that
+    /// pushes them
+    fn serve_pushed(&mut self) -> Result<(), CometError> {
+        if try!(self.try_handle_send_message()) {
+            return Ok(());
+        }
+        match try!(self.transport.receive(Duration::from_millis(RECEIVE_INTERVAL_MS))) {
+            Some(packet) => self.handle_received(packet),
+            None => Ok(()),
+        }
+    }
+
+    /// Stop (or start again) polling the server for new messages
+    ///
+    /// Messages from the front-end are still sent while paused, and the responses to them are
+    /// still received. A poll that is already outstanding is not cancelled. Servers that push
+    /// packets are not polled, they stop sending when we unfollow everything.
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::SeqCst);
+    }
+
+    /// Wait `delay` before every long poll, or not at all if it is `None`
+    ///
+    /// The server keeps the messages for us in the meantime, so they arrive together and later,
+    /// but we wake up less often. Messages from the front-end are sent right away, and a poll
+    /// that is waiting is sent as soon as the delay is taken away. Servers that push packets are
+    /// not polled, so it makes no difference for them.
+    pub fn set_poll_delay(&self, delay: Option<Duration>) {
+        let &(ref current, ref changed) = &*self.poll_delay;
+        *current.lock().unwrap() = delay;
+        changed.notify_all();
+    }
+
+    /// Wait until the poll delay is over since we started waiting, see `set_poll_delay`
+    fn wait_poll_delay(&self) {
+        let &(ref current, ref changed) = &*self.poll_delay;
+        let start = Instant::now();
+        let mut delay = current.lock().unwrap();
+        while let Some(timeout) = *delay {
+            let waited = start.elapsed();
+            if waited >= timeout {
+                break;
+            }
+            delay = changed.wait_timeout(delay, timeout - waited).unwrap().0;
+        }
+    }
+}
+
+fn is_bulk(message: &Json) -> bool {
+    message.find("type")
+           .and_then(|x| x.as_string())
+           .map_or(false, |x| BULK_MESSAGE_TYPES.contains(&x))
+}
+
+/// Deliver the messages from `urgent_r` and `bulk_r` on one channel, the urgent ones first
+///
+/// The returned channel is unbuffered, so the next message is only picked when the front-end
+/// asks for it. That way a `playing` update does not wait behind a long queue of search results.
+pub fn merge_by_priority(urgent_r: chan::Receiver<Json>,
+                         bulk_r: chan::Receiver<Json>) -> chan::Receiver<Json> {
+    let (merged_s, merged_r) = chan::sync(0);
+    thread::spawn(move || {
+        loop {
+            let mut message = None;
+            let mut urgent = true;
+            chan_select! {
+                default => {},
+                urgent_r.recv() -> x => message = Some(x),
+            }
+            if message.is_none() {
+                chan_select! {
+                    urgent_r.recv() -> x => message = Some(x),
+                    bulk_r.recv() -> x => {
+                        message = Some(x);
+                        urgent = false;
+                    },
+                }
+            }
+            // Once one side is closed, pass on what is left of the other
+            match message {
+                Some(Some(x)) => merged_s.send(x),
+                Some(None) if urgent => break,
+                _ => {
+                    for x in urgent_r {
+                        merged_s.send(x);
+                    }
+                    return;
+                },
+            }
+        }
+        for x in bulk_r {
+            merged_s.send(x);
+        }
+    });
+    merged_r
 }
 
 pub fn serve(shared_comet: &CometChannel) -> Vec<thread::JoinHandle<Result<(), CometError>>> {
+    if let Some((ref packet_r, ref message_s)) = shared_comet.offline {
+        return serve_offline(shared_comet, packet_r.clone(), message_s.clone());
+    }
     if *shared_comet.session_id.read().unwrap() == None {
         panic!("I cannot serve when I'm not connected!")
     }
 
+    // One thread can keep a long poll outstanding while the other sends, but a transport that
+    // pushes needs no polls, and its connection is used by one thread at a time
+    let pushes = shared_comet.transport.pushes();
     let mut join_handles = Vec::new();
-    for _ in 0..2 {
+    for _ in 0..(if pushes { 1 } else { 2 }) {
         let mut local_comet = shared_comet.clone();
         join_handles.push(thread::spawn(move || -> Result<(), CometError> {
+            let mut attempts = 0;
+            let mut timeouts = 0;
             loop {
-                if try!(local_comet.try_handle_send_message()) {
-                    continue
+                let result = if attempts > 0 {
+                    local_comet.reconnect()
+                } else if pushes {
+                    local_comet.serve_pushed()
                 } else {
-                    // do we need to send a long poll request?
-                    if {
-                        let current_requests = local_comet.current_requests.clone();
-                        let mut x = current_requests.lock().unwrap();
-                        match *x {
-                            0 => { *x += 1; true },
-                            1 => false,
-                            _ => unreachable!()
-                        }
-                    } {
-                        try!(local_comet.poll());
-                        let current_requests = local_comet.current_requests.clone();
-                        let mut x = current_requests.lock().unwrap();
-                        *x -= 1;
-                    } else {
-                        try!(local_comet.handle_send_message());
-                    }
+                    local_comet.serve_once()
+                };
+                match result {
+                    Ok(()) => {
+                        attempts = 0;
+                        timeouts = 0;
+                        local_comet.set_state(ConnectionState::Connected);
+                    },
+                    // the front-end is gone, so there is nothing left to serve
+                    Err(CometError::Recv) => return Err(CometError::Recv),
+                    // a long poll that hangs is given up on and sent again, the connection is
+                    // only lost if the server does not answer that one either
+                    Err(ref err) if err.is_timeout() && attempts == 0 && timeouts == 0 => {
+                        info!("the server did not answer in time, trying again");
+                        timeouts += 1;
+                    },
+                    Err(err) => {
+                        warn!("lost the connection to the server: {}", err);
+                        local_comet.set_state(ConnectionState::Disconnected);
+                        thread::sleep(retry_delay(attempts));
+                        attempts += 1;
+                        local_comet.set_state(ConnectionState::Reconnecting(attempts));
+                    },
                 }
             }
         }));
     }
     join_handles
 }
+
+/// How long to wait before trying to reach the server again, after `attempts` failed attempts
+fn retry_delay(attempts: u32) -> Duration {
+    Duration::from_secs(min(1 << min(attempts, 5), MAX_RETRY_SECS))
+}
+
+/// Handle the packets from `packet_r`, and pass the messages from the front-end on to
+/// `message_s`
+fn serve_offline(shared_comet: &CometChannel, packet_r: chan::Receiver<Json>,
+                 message_s: chan::Sender<Json>)
+                 -> Vec<thread::JoinHandle<Result<(), CometError>>> {
+    let mut local_comet = shared_comet.clone();
+    let receiver = thread::spawn(move || -> Result<(), CometError> {
+        for packet in packet_r {
+            trace!("received packet: {}", packet);
+            local_comet.packet_log.lock().unwrap().push(Direction::Received, &packet);
+            local_comet.recorder.lock().unwrap().packet_received();
+            try!(local_comet.handle_receive_packet(packet));
+        }
+        Ok(())
+    });
+    let local_comet = shared_comet.clone();
+    let sender = thread::spawn(move || -> Result<(), CometError> {
+        for message in local_comet.send_message_r.iter() {
+            let packet = vec![message.clone()].to_json();
+            trace!("sending packet: {}", packet);
+            local_comet.packet_log.lock().unwrap().push(Direction::Sent, &packet);
+            local_comet.recorder.lock().unwrap().packet_sent();
+            message_s.send(message);
+        }
+        Ok(())
+    });
+    vec![receiver, sender]
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use chan;
+    use rustc_serialize::json::{Json, ToJson};
+    use super::{merge_by_priority, ConnectionState};
+
+    fn message(msg_type: &str) -> Json {
+        let mut obj = BTreeMap::new();
+        obj.insert("type".to_string(), msg_type.to_json());
+        Json::Object(obj)
+    }
+
+    #[test]
+    fn urgent_first() {
+        let (urgent_s, urgent_r) = chan::async();
+        let (bulk_s, bulk_r) = chan::async();
+        for _ in 0..3 {
+            bulk_s.send(message("query_media_results"));
+        }
+        let merged_r = merge_by_priority(urgent_r, bulk_r);
+        // The merging thread may already be waiting to hand over a bulk message
+        let first = merged_r.recv().unwrap();
+        urgent_s.send(message("playing"));
+        drop(urgent_s);
+        drop(bulk_s);
+
+        let mut types: Vec<Json> = vec![first];
+        types.extend(merged_r.iter());
+        let types: Vec<&str> = types.iter().map(|x| x.find("type").unwrap().as_string().unwrap()).collect();
+        assert_eq!(types.len(), 4);
+        assert_eq!(types.iter().filter(|x| **x == "playing").count(), 1);
+        assert!(types[3] == "query_media_results");
+    }
+    #[test]
+    fn connection_state() {
+        for state in &[ConnectionState::Connected, ConnectionState::Disconnected,
+                       ConnectionState::Reconnecting(3)] {
+            let msg = state.to_message();
+            assert_eq!(msg.find("type").unwrap().as_string(), Some("connection_state"));
+            assert_eq!(ConnectionState::from_message(&msg), Some(*state));
+        }
+        assert_eq!(ConnectionState::from_message(&message("connection_state")), None);
+    }
+
+    #[test]
+    fn timeout() {
+        use std::io::{Error as IOError, ErrorKind};
+        use hyper::error::Error as HyperError;
+        use super::CometError;
+
+        let io_error = |kind| IOError::new(kind, "test");
+        assert!(CometError::IO(io_error(ErrorKind::WouldBlock)).is_timeout());
+        assert!(CometError::Hyper(HyperError::Io(io_error(ErrorKind::TimedOut))).is_timeout());
+        assert!(!CometError::IO(io_error(ErrorKind::ConnectionRefused)).is_timeout());
+        assert!(!CometError::Recv.is_timeout());
+    }
+
+    #[test]
+    fn poll_delay() {
+        use std::thread;
+        use std::time::{Duration, Instant};
+        use super::CometChannel;
+
+        let (_, packet_r) = chan::async();
+        let (message_s, _) = chan::async();
+        let (_, send_message_r) = chan::async();
+        let (recv_message_s, _) = chan::async();
+        let (bulk_message_s, _) = chan::async();
+        let comet = CometChannel::offline("test", packet_r, message_s, send_message_r,
+                                          recv_message_s, bulk_message_s);
+        let start = Instant::now();
+        comet.set_poll_delay(Some(Duration::from_millis(50)));
+        comet.wait_poll_delay();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        // taking the delay away ends the wait
+        comet.set_poll_delay(Some(Duration::from_secs(60)));
+        let clone = comet.clone();
+        let waiting = thread::spawn(move || clone.wait_poll_delay());
+        thread::sleep(Duration::from_millis(10));
+        comet.set_poll_delay(None);
+        waiting.join().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(60));
+    }
+}
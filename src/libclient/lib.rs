@@ -1,25 +1,117 @@
+//! A client library for the [marietje](https://github.com/marietje/marietje) music daemon.
+//!
+//! The central type is [`Client`](struct.Client.html). It wraps a "comet" channel to the server,
+//! which is served by background threads. Messages from the server are delivered on a
+//! `chan::Receiver<Json>`; pass every message to
+//! [`Client::handle_message`](struct.Client.html#method.handle_message) to update the client
+//! state and to find out what happened:
+//!
+//! ```no_run
+//! extern crate libclient;
+//!
+//! use libclient::{Client, Message};
+//!
+//! # fn main() {
+//! let (mut client, client_r) = Client::new("http://marietje-noord.marie-curie.nl/api").unwrap();
+//! client.follow_all();
+//! client.serve();
+//!
+//! while let Some(msg) = client_r.recv() {
+//!     if let Ok(Message::PlayingChanged) = client.handle_message(&msg) {
+//!         println!("now playing: {:?}", client.get_playing());
+//!     }
+//! }
+//! # }
+//! ```
+//!
+//! `handle_message` decodes the message into a
+//! [`ServerMessage`](message/enum.ServerMessage.html) first; to look at what the server said
+//! before the client state is updated, decode it yourself and pass it to
+//! [`Client::handle_server_message`](struct.Client.html#method.handle_server_message).
+//!
+//! The API is blocking: messages are sent from and received on background threads, so none of
+//! the methods on `Client` block on network I/O. Enable the `tls` feature to connect to servers
+//! over HTTPS.
+
 #[macro_use] extern crate chan;
+extern crate fs2;
+#[cfg(feature = "async")]
+extern crate futures;
 extern crate hyper;
 #[macro_use] extern crate log;
+#[cfg(feature = "tls")]
 extern crate openssl;
+extern crate rand;
 extern crate rustc_serialize;
+extern crate strsim;
 extern crate time;
-
+extern crate unicode_normalization;
+
+pub mod app;
+#[cfg(feature = "async")]
+pub mod async_client;
+pub mod audit;
+pub mod auth;
+mod builder;
+pub mod cache;
 mod comet;
+pub mod library;
 pub mod media;
-
-use std::collections::HashMap;
+pub mod message;
+pub mod metrics;
+pub mod packets;
+pub mod query;
+pub mod queue;
+pub mod rank;
+pub mod replay;
+pub mod report;
+pub mod stats;
+pub mod stream;
+pub mod suggest;
+pub mod sync;
+pub mod tasks;
+pub mod text;
+pub mod watchlist;
+mod transport;
+mod websocket;
+
+use std::cmp::max;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::error::Error;
 use std::fmt;
+use std::mem;
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
+
+use rustc_serialize::json::{DecoderError, Json, ToJson};
+use time::{get_time, Timespec};
+
+use audit::Entry as AuditEntry;
+use auth::{LoginHasher, Md5Hasher};
+use comet::{CometChannel, merge_by_priority, serve as comet_serve};
+use library::Library;
+use message::ServerMessage;
+use metrics::Metrics;
+use packets::Packet;
+use query::Query;
+use stream::{MediaStream, StreamId};
+use tasks::{Executor, TaskCompletion, TaskId, TaskResult};
 
-use rustc_serialize::json::{decode, Json, ToJson};
+pub use auth::md5;
+pub use builder::{ChunkPolicy, ClientBuilder};
+pub use comet::{CometError, ConnectionState};
+pub use media::{Media, Playing, Request};
 
-use comet::{CometChannel, CometError, serve as comet_serve};
-use media::{Media, Playing, Request};
 
+/// After this many updates of the queue that do not show a change that we asked for, the change
+/// is no longer expected
+const PENDING_CHANGE_UPDATES: usize = 2;
 
-const MD5_HASH_LENGTH: usize = 32;
+/// How many results of the server a search may leave out (see the `query` module) before we stop
+/// asking for more, so that e.g. `uploader:bob` does not go through the whole media index
+pub const MAX_LEFT_OUT_RESULTS: usize = 2000;
 
 macro_rules! make_json_hashmap {
     ( $( $key:expr => $val:expr ),* ) => {{
@@ -31,30 +123,90 @@ macro_rules! make_json_hashmap {
     }}
 }
 
-#[derive(Debug)]
+/// What changed in the client state after handling a message from the server
+#[derive(Clone, Debug)]
 pub enum Message {
     Welcome,
+    /// The server sent the song that is playing, which is the one it sent before
     Playing,
+    /// Another song is playing, or the same song was played again
+    PlayingChanged,
     Requests,
     LoginToken,
     Login,
     LoginError(String),
     QueryMediaResults,
+    /// A page of the stream with this id arrived, see `Client::next_media_page`
+    MediaPage(StreamId),
+    /// The number of votes to skip the playing song, and the number that is needed to skip it
+    SkipVotes(usize, usize),
+    /// The server received our report about the song with this media key
+    MediaReported(String),
+    /// The server accepted the changes to the song with this media key
+    MediaUpdated(String),
+    /// The server did not accept the changes to the song with this media key, for this reason,
+    /// so they were undone
+    MediaUpdateError(String, String),
+    /// A task that was started with `Client::spawn_task` is finished, with this result
+    TaskDone(TaskId, Json),
+    /// A task that was started with `Client::spawn_task` failed, for this reason
+    TaskFailed(TaskId, String),
+    /// The server could no longer be reached, is being tried again, or can be reached again
+    ConnectionState(ConnectionState),
+    /// A message of a type that this client does not know, as it was received
+    Raw(Json),
 }
 
+/// Errors that can occur while talking to the server
+///
+/// Errors form a chain: use `Error::source` to find out what caused an error, or format it with
+/// [`DisplayChain`](struct.DisplayChain.html) to show the whole chain to the user.
 #[derive(Debug)]
 pub enum ClientError {
+    /// Communicating with the server failed, or it sent something that we did not expect
     Comet(CometError),
+    /// A part of a message from the server could not be decoded
+    Decode(DecoderError),
+    /// The server sent a message that we cannot place, as described by the string: it has no
+    /// type, or it answers something that we did not ask
+    UnknownMessage(String),
+    /// Another error occurred while we were doing what is described by the string
+    Context(String, Box<ClientError>),
 }
 
+/// The server side of a client that is not connected to a server, see `Client::offline`
+pub struct Offline {
+    /// Packets that are sent on this are handled as if the server sent them; a packet is an
+    /// array of a session id and an array of messages
+    pub packet_s: chan::Sender<Json>,
+    /// The messages that the client sends to the server arrive here
+    pub message_r: chan::Receiver<Json>,
+}
+
+/// Whether a message was sent right away, or if it will be sent after logging in
 #[derive(Debug)]
 pub enum RequestStatus {
-    Ok, Deferred
+    Ok, Deferred,
+    /// The server cannot be reached, so it is sent once it can be again; only requests are
+    /// queued like this, see `Client::get_offline_requests`
+    Queued,
+}
+
+impl ClientError {
+    /// Wrap this error, describing what we were doing when it occurred
+    pub fn context<S: Into<String>>(self, context: S) -> ClientError {
+        ClientError::Context(context.into(), Box::new(self))
+    }
 }
 
 impl fmt::Display for ClientError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "client error: ({})", self)
+        match *self {
+            ClientError::Comet(ref err) => write!(f, "{}", err),
+            ClientError::Decode(_) => write!(f, "could not decode message"),
+            ClientError::UnknownMessage(ref what) => write!(f, "unknown message: {}", what),
+            ClientError::Context(ref context, _) => write!(f, "{}", context),
+        }
     }
 }
 
@@ -64,13 +216,63 @@ impl From<CometError> for ClientError {
     }
 }
 
+impl From<DecoderError> for ClientError {
+    fn from(err: DecoderError) -> Self {
+        ClientError::Decode(err)
+    }
+}
+
 impl Error for ClientError {
     fn description(&self) -> &str {
-        let ClientError::Comet(ref err) = *self;
-        err.description()
+        match *self {
+            ClientError::Comet(ref err) => err.description(),
+            ClientError::Decode(_) => "could not decode message",
+            ClientError::UnknownMessage(_) => "unknown message",
+            ClientError::Context(_, ref err) => err.description(),
+        }
+    }
+
+    fn source(&self) -> Option<&(Error + 'static)> {
+        match *self {
+            // The comet error describes itself, so we only pass on its cause
+            ClientError::Comet(ref err) => err.source(),
+            ClientError::Decode(ref err) => Some(err),
+            ClientError::UnknownMessage(_) => None,
+            ClientError::Context(_, ref err) => Some(&**err),
+        }
     }
 }
 
+/// Displays an error, followed by the errors that caused it
+///
+/// For example: `while handling requests message: while decoding request 2: could not decode
+/// message: MissingFieldError("byKey")`.
+pub struct DisplayChain<'a>(pub &'a (Error + 'static));
+
+impl<'a> fmt::Display for DisplayChain<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{}", self.0));
+        let mut source = self.0.source();
+        while let Some(err) = source {
+            try!(write!(f, ": {}", err));
+            source = err.source();
+        }
+        Ok(())
+    }
+}
+
+/// A change to the queue that we asked the server for, and that it did not confirm yet
+#[derive(Clone, Debug)]
+enum PendingChange {
+    /// A request of ours, which has a made up (negative) key until it shows up in the queue
+    Request(Request),
+    /// The request with this key is removed
+    Remove(i64),
+    /// The request with this key is moved by this many places, in the queue with these keys
+    Move(i64, i64, Vec<i64>),
+}
+
+/// A connection to a marietje server, and the state that we know about it
 #[derive(Clone, Debug)]
 pub struct Client {
     // The wrapped comet channel
@@ -85,6 +287,10 @@ pub struct Client {
     /// What the current requests are
     requests: Option<Vec<Request>>,
 
+    /// The votes to skip the playing song and the number of votes that is needed, if the server
+    /// has told us
+    skip_votes: Option<(usize, usize)>,
+
     /// Store the access key for the users login session, if we have retrieved it from
     /// the server.
     access_key: Option<String>,
@@ -95,6 +301,12 @@ pub struct Client {
     /// Are we currently logged in?
     logged_in: bool,
 
+    /// The user we are logging in or logged in as
+    username: Option<String>,
+
+    /// What the server allows the logged in user to do, e.g. `"boost"`
+    capabilities: Vec<String>,
+
     /// Are we waiting for a login token?
     waiting_for_login_token: bool,
 
@@ -105,12 +317,22 @@ pub struct Client {
     /// but does not have a login_token at this moment
     deferred_login: Option<(String, String, bool)>,
 
+    /// Hashes the secrets that we log in with, see `set_login_hasher`
+    login_hasher: Arc<LoginHasher>,
+
     /// The current search query results
     qm_results: Vec<Media>,
 
     /// The current query_media query, if present
     qm_query: Option<String>,
 
+    /// `qm_query` split into words and terms for one field, see the `query` module
+    qm_scope: Query,
+
+    /// How many results the server sent for the current query, also the ones that `qm_scope`
+    /// left out, which the next results skip
+    qm_received: usize,
+
     /// And the amount of results we requested for this token, so that we will know if we have
     /// reached the end of the results list.
     qm_requested_count: Option<usize>,
@@ -127,62 +349,414 @@ pub struct Client {
     /// Are we currently waiting for query results?
     qm_waiting_for_token: Option<usize>,
 
+    /// Only ask for media that were added or changed after this time
+    qm_modified_since: Option<Timespec>,
+
+    /// How many results we ask for at once, see `ClientBuilder::qm_chunk_policy`
+    qm_chunk_policy: ChunkPolicy,
+
+    /// The queries whose results are handed out page by page, see `query_media_stream`
+    qm_streams: BTreeMap<StreamId, MediaStream>,
+
+    /// Does `get_ranked_results` put the results that match the query best first?
+    qm_ranked: bool,
+
+    /// Did we stop asking for results because too many were left out? See `is_query_truncated`.
+    qm_truncated: bool,
+
+    /// Answers the searches instead of the server, see `set_library`
+    library: Option<Library>,
+
+    /// Delivers the search results from the library, like the search results of the server
+    library_results_s: chan::Sender<Json>,
+
     /// This is a list of all messages that should be sent after we are logged in
     deferred_after_login: Vec<Json>,
+
+    /// Runs the tasks from `spawn_task`
+    tasks: Executor,
+
+    /// Where the tasks from `spawn_task` arrive when they are finished, see `task_receiver`
+    task_r: chan::Receiver<TaskCompletion>,
+
+    /// The topics that we follow
+    following: Vec<String>,
+
+    /// Have we stopped following and polling, because the front-end is not visible?
+    suspended: bool,
+
+    /// The songs that we changed with `update_media` as they were before, by media key, until
+    /// the server accepts or rejects the changes
+    pending_updates: HashMap<String, Media>,
+
+    /// The songs that we requested, by media key, with the keys of the requests that were in the
+    /// queue then (if we knew the queue), until the requests show up in the queue
+    pending_requests: Vec<(String, Option<HashSet<i64>>)>,
+
+    /// Our requests that showed up in the queue with the last update of it
+    queued_requests: Vec<Request>,
+
+    /// The changes to the queue that the server did not confirm yet, with the number of updates
+    /// of the queue that we had received when we asked for them, see `get_expected_requests`
+    pending_changes: Vec<(PendingChange, usize)>,
+
+    /// The number of updates of the queue that we received
+    queue_updates: usize,
+
+    /// The key of the last made up request, see `PendingChange::Request`
+    last_pending_key: i64,
+
+    /// Was the server reached, as far as the connection states that we handled tell?
+    connected: bool,
+
+    /// The media keys of the songs that were requested while the server could not be reached
+    offline_requests: Vec<String>,
+
+    /// The file to which we append what we ask the server to change, see `set_audit_log`
+    audit_log: Option<PathBuf>,
 }
 
 impl Client {
+    /// Connect to the server at `url`
+    ///
+    /// Returns the client and the receiver on which the messages from the server will arrive.
+    /// Nothing is received until `serve` is called. Search results are only delivered when no
+    /// other messages are waiting, so that a large search does not hold up the rest.
+    ///
+    /// See `ClientBuilder` to connect with other options.
     pub fn new(url: &str) -> Result<(Client, chan::Receiver<Json>), ClientError> {
+        ClientBuilder::new(url).build()
+    }
+
+    /// A client that is not connected to a server, e.g. to replay what a server sent before
+    ///
+    /// The returned `Offline` plays the server at `url`, once `serve` is called.
+    pub fn offline(url: &str) -> (Client, chan::Receiver<Json>, Offline) {
+        let (packet_s, packet_r) = chan::async();
+        let (message_s, message_r) = chan::async();
+        let (client, client_r) = Client::with_channel(|send_message_r, recv_message_s,
+                                                       bulk_message_s| {
+            Ok(CometChannel::offline(&url, packet_r, message_s, send_message_r, recv_message_s,
+                                     bulk_message_s))
+        }).unwrap();
+        (client, client_r, Offline { packet_s: packet_s, message_r: message_r })
+    }
+
+    fn with_channel<F>(make_channel: F) -> Result<(Client, chan::Receiver<Json>), CometError>
+        where F: FnOnce(chan::Receiver<Json>, chan::Sender<Json>, chan::Sender<Json>)
+                        -> Result<CometChannel, CometError> {
         let (send_message_s, send_message_r) = chan::async();
         let (recv_message_s, recv_message_r) = chan::async();
-        let comet_channel = match CometChannel::new(&url, send_message_r, recv_message_s) {
-            Ok(comet_channel) => comet_channel,
-            Err(err) => return Err(ClientError::from(err)),
-        };
+        let (bulk_message_s, bulk_message_r) = chan::async();
+        let (task_s, task_r) = chan::async();
+        let tasks = Executor::new(task_s);
+        let library_results_s = bulk_message_s.clone();
+        let comet_channel = try!(make_channel(send_message_r, recv_message_s, bulk_message_s));
         Ok((Client {
             channel: comet_channel,
             send_message_s: send_message_s,
             playing: None,
             requests: None,
+            skip_votes: None,
             access_key: None,
             login_token: None,
             logged_in: false,
+            username: None,
+            capabilities: Vec::new(),
             waiting_for_login_token: false,
             waiting_for_login: false,
             deferred_login: None,
+            login_hasher: Arc::new(Md5Hasher),
             qm_results: Vec::new(),
             qm_query: None,
+            qm_scope: Query::default(),
+            qm_received: 0,
             qm_token: 0,
             qm_results_count: 0,
             qm_requested_count: None,
             qm_done: true,
             qm_waiting_for_token: None,
-            deferred_after_login: Vec::new()
-        }, recv_message_r))
+            qm_modified_since: None,
+            qm_chunk_policy: ChunkPolicy::default(),
+            qm_streams: BTreeMap::new(),
+            qm_ranked: false,
+            qm_truncated: false,
+            library: None,
+            library_results_s: library_results_s,
+            deferred_after_login: Vec::new(),
+            tasks: tasks,
+            task_r: task_r,
+            following: Vec::new(),
+            suspended: false,
+            pending_updates: HashMap::new(),
+            pending_requests: Vec::new(),
+            queued_requests: Vec::new(),
+            pending_changes: Vec::new(),
+            queue_updates: 0,
+            last_pending_key: 0,
+            connected: true,
+            offline_requests: Vec::new(),
+            audit_log: None,
+        }, merge_by_priority(recv_message_r, bulk_message_r)))
     }
 
+    /// The url of the server we are connected to
     pub fn get_url(&self) -> String {
         self.channel.get_url()
     }
 
+    /// Is this client not connected to a server? See `Client::offline`.
+    pub fn is_offline(&self) -> bool {
+        self.channel.is_offline()
+    }
+
+    /// The packets that were exchanged with the server most recently, the oldest first
+    pub fn get_packets(&self) -> Vec<Packet> {
+        self.channel.get_packets()
+    }
+
+    /// Whether the server could be reached the last time it was tried
+    pub fn get_connection_state(&self) -> ConnectionState {
+        self.channel.get_state()
+    }
+
+    /// What went over the connection to the server since the client was created
+    pub fn get_metrics(&self) -> Metrics {
+        self.channel.get_metrics()
+    }
+
+    /// The song that is currently playing, if known
     pub fn get_playing(&self) -> &Option<Playing> {
         &self.playing
     }
 
+    /// The current request queue, if known
     pub fn get_requests(&self) -> &Option<Vec<Request>> {
         &self.requests
     }
 
+    /// The queue as we expect it to be once the server did what we asked
+    ///
+    /// These are the requests from `get_requests` with the requests, removals and moves that the
+    /// server did not confirm yet, so that a front-end can show them right away. Requests that
+    /// are not in the queue yet have a negative key, see `is_pending`. A change that the server
+    /// did not make after a few updates of the queue is no longer expected, so it is undone.
+    pub fn get_expected_requests(&self) -> Vec<Request> {
+        let mut requests = self.requests.clone().unwrap_or_default();
+        for &(ref change, _) in &self.pending_changes {
+            match *change {
+                PendingChange::Request(ref request) => requests.push(request.clone()),
+                PendingChange::Remove(key) => requests.retain(|x| x.key != key),
+                PendingChange::Move(key, amount, _) => {
+                    requests = queue::apply_moves(&requests, &[(key, amount)]);
+                },
+            }
+        }
+        requests
+    }
+
+    /// Is the request with key `key` changed in `get_expected_requests`, but not in the queue yet?
+    pub fn is_pending(&self, key: i64) -> bool {
+        self.pending_changes.iter().any(|&(ref change, _)| match *change {
+            PendingChange::Request(ref request) => request.key == key,
+            PendingChange::Remove(_) => false,
+            PendingChange::Move(moved, _, _) => moved == key,
+        })
+    }
+
+    /// Expect `change` to show up in the queue
+    fn expect_change(&mut self, change: PendingChange) {
+        self.pending_changes.push((change, self.queue_updates));
+    }
+
+    /// Forget the changes that the new queue shows, and the ones that we waited for long enough
+    fn reconcile_changes(&mut self) {
+        self.queue_updates += 1;
+        let requests = self.requests.as_ref().map_or(&[][..], |x| &x[..]);
+        let queued = &self.queued_requests;
+        let updates = self.queue_updates;
+        self.pending_changes.retain(|&(ref change, sent_at)| {
+            let done = match *change {
+                PendingChange::Request(ref request) => {
+                    queued.iter().any(|x| x.media.key == request.media.key)
+                },
+                PendingChange::Remove(key) => !requests.iter().any(|x| x.key == key),
+                PendingChange::Move(key, _, ref before) => {
+                    // it moved if it is somewhere else among the requests that are still there
+                    let old: Vec<i64> = before.iter().cloned()
+                                              .filter(|&x| requests.iter().any(|y| y.key == x))
+                                              .collect();
+                    let new: Vec<i64> = requests.iter().map(|x| x.key)
+                                                .filter(|x| before.contains(x))
+                                                .collect();
+                    !new.contains(&key) || old.iter().position(|&x| x == key)
+                                           != new.iter().position(|&x| x == key)
+                },
+            };
+            if !done && updates - sent_at >= PENDING_CHANGE_UPDATES {
+                warn!("the server did not make the change {:?}, undoing it", change);
+                return false;
+            }
+            !done
+        });
+    }
+
+    /// The requests that we made and that showed up in the queue with the last `Requests`
+    /// message, e.g. to find out where in the queue they ended up
+    pub fn get_queued_requests(&self) -> &[Request] {
+        &self.queued_requests
+    }
+
+    /// The media keys of the songs that were requested while the server could not be reached,
+    /// in the order in which they were requested
+    ///
+    /// They are requested as soon as we are connected again, see `do_request_from_key`.
+    pub fn get_offline_requests(&self) -> &[String] {
+        &self.offline_requests
+    }
+
+    /// Forget the requests that wait for the server to be reached, and return them
+    pub fn clear_offline_requests(&mut self) -> Vec<String> {
+        for key in &self.offline_requests {
+            self.audit_entry("request", key, self.find_media(key), "cancelled while offline");
+        }
+        mem::replace(&mut self.offline_requests, Vec::new())
+    }
+
+    /// Has the server accepted our login?
+    pub fn is_logged_in(&self) -> bool {
+        self.logged_in
+    }
+
+    /// Are we waiting for the server to accept (or refuse) our login?
+    pub fn is_logging_in(&self) -> bool {
+        self.waiting_for_login || self.deferred_login.is_some()
+    }
+
+    /// The user we are logged in as, or are logging in as
+    pub fn get_username(&self) -> Option<&str> {
+        self.username.as_ref().map(|x| x.as_str())
+    }
+
+    /// Does the server allow the logged in user to do `capability`?
+    ///
+    /// Servers list the capabilities of a user when they log in. Known capabilities are
+    /// `"boost"` (move one's own requests to the front of the queue), `"moderator"` (remove and
+    /// move the requests of others), `"report"` (report wrong songs), `"edit"` (change the
+    /// metadata of songs) and `"admin"` (do anything).
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.logged_in && self.capabilities.iter().any(|x| x == capability || x == "admin")
+    }
+
+    /// May we move `request` to the front of the queue?
+    pub fn can_boost(&self, request: &Request) -> bool {
+        let own = match (request.by.as_ref(), self.username.as_ref()) {
+            (Some(by), Some(username)) => by == username,
+            _ => false,
+        };
+        self.has_capability("admin") || (own && self.has_capability("boost"))
+    }
+
+    /// May we remove `request`, or move it around in the queue?
+    ///
+    /// Everyone may do this with their own requests, moderators with all requests.
+    pub fn can_manage(&self, request: &Request) -> bool {
+        let own = match (request.by.as_ref(), self.username.as_ref()) {
+            (Some(by), Some(username)) => by == username,
+            _ => false,
+        };
+        (own && self.logged_in) || self.has_capability("moderator")
+    }
+
+    /// The votes to skip the playing song and the number of votes that is needed to skip it
+    ///
+    /// This is `None` until the server sends a tally, which servers without skip votes never do.
+    pub fn get_skip_votes(&self) -> Option<(usize, usize)> {
+        self.skip_votes
+    }
+
+    /// The results for the current search query, and whether all results have been received
     pub fn get_qm_results(&self) -> (&Vec<Media>, &bool) {
         (&self.qm_results, &self.qm_done)
     }
 
+    /// The results for the current search query, the most relevant first if ranking is on, see
+    /// `set_ranked` and the `rank` module
+    ///
+    /// Only the results that arrived so far are ranked, so a result that arrives later can still
+    /// end up above the ones that are shown.
+    pub fn get_ranked_results(&self) -> Vec<&Media> {
+        match self.qm_query {
+            Some(_) if self.qm_ranked => {
+                rank::rank(&self.qm_scope.server_query(), &self.qm_results)
+            },
+            _ => self.qm_results.iter().collect(),
+        }
+    }
+
+    /// Rank the search results by relevance (or not, to keep the order of the server), see
+    /// `get_ranked_results`
+    pub fn set_ranked(&mut self, ranked: bool) {
+        self.qm_ranked = ranked;
+    }
+
+    pub fn is_ranked(&self) -> bool {
+        self.qm_ranked
+    }
+
+    /// Did the current search stop before the server ran out of results?
+    ///
+    /// A search with terms for the album or the uploader asks the server for everything, and
+    /// leaves out what does not match. After `MAX_LEFT_OUT_RESULTS` were left out, it is done,
+    /// unless it is answered from the library (see `set_library`), which is quick to go through.
+    pub fn is_query_truncated(&self) -> bool {
+        self.qm_truncated
+    }
+
+    /// Start the background threads that send and receive messages
     pub fn serve(&self) -> Vec<thread::JoinHandle<Result<(), CometError>>> {
         comet_serve(&self.channel)
     }
 
+    /// Run `f` in the background, without blocking the threads that talk to the server
+    ///
+    /// When `f` is finished, it arrives on `task_receiver`, and `handle_task_completion` turns it
+    /// into a `Message::TaskDone` or `Message::TaskFailed` with the returned id. `name` describes
+    /// the task in the log.
+    pub fn spawn_task<F>(&self, name: &str, f: F) -> TaskId
+        where F : FnOnce() -> TaskResult + Send + 'static {
+        self.tasks.spawn(name, f)
+    }
+
+    /// The receiver on which the tasks from `spawn_task` arrive when they are finished
+    ///
+    /// It is apart from the receiver of the messages from the server, so that a server cannot
+    /// pass itself off as one of our tasks.
+    pub fn task_receiver(&self) -> chan::Receiver<TaskCompletion> {
+        self.task_r.clone()
+    }
+
+    /// Turn a task that arrived on `task_receiver` into a message
+    pub fn handle_task_completion(&self, done: TaskCompletion) -> Message {
+        match done.result {
+            Ok(result) => Message::TaskDone(done.id, result),
+            Err(err) => Message::TaskFailed(done.id, err),
+        }
+    }
+
+    /// Send `msg` to the server as it is
+    ///
+    /// This is for trying out messages that this client does not support yet; nothing checks
+    /// that the server understands `msg`. Answers of an unknown type arrive as `Message::Raw`.
+    pub fn send_raw(&mut self, msg: Json) {
+        self.audit(&msg, "sent");
+        self.send_message_s.send(msg)
+    }
+
     fn send_message<T: ToJson>(&mut self, obj: &T) {
-        self.send_message_s.send(obj.to_json())
+        let msg = obj.to_json();
+        self.audit(&msg, "sent");
+        self.send_message_s.send(msg)
     }
 
     fn send_message_after_login<T: ToJson>(&mut self, obj: &T) -> RequestStatus {
@@ -190,155 +764,270 @@ impl Client {
             self.send_message(obj);
             RequestStatus::Ok
         } else {
-            self.deferred_after_login.push(obj.to_json());
+            let msg = obj.to_json();
+            self.audit(&msg, "waiting for login");
+            self.deferred_after_login.push(msg);
             RequestStatus::Deferred
         }
     }
 
+    /// Append every message that asks the server to change something to the file at `path`
+    ///
+    /// The answers of the server to them are appended as well, see the `audit` module. Clients
+    /// that are not connected to a server (see `Client::offline`) do not write to it.
+    pub fn set_audit_log(&mut self, path: Option<PathBuf>) {
+        self.audit_log = path;
+    }
+
+    /// Write the outcome of `msg` to the audit log, if it changes something
+    fn audit(&self, msg: &Json, outcome: &str) {
+        let (action, target) = match audit::describe(msg) {
+            Some(description) => description,
+            None => return,
+        };
+        let song = match action {
+            "skip" => self.playing.as_ref().map(|x| &x.media),
+            _ => msg.find("mediaKey").and_then(|x| x.as_string())
+                    .and_then(|key| self.find_media(key))
+                    .or_else(|| msg.find("requestKey").and_then(|x| x.as_i64())
+                                   .and_then(|key| self.find_request(key)).map(|x| &x.media)),
+        };
+        self.audit_entry(action, &target, song, outcome);
+    }
+
+    /// Write an entry to the audit log, with the artist and title of `song` after `target`
+    fn audit_entry(&self, action: &str, target: &str, song: Option<&Media>, outcome: &str) {
+        let path = match self.audit_log {
+            Some(ref path) if !self.is_offline() => path,
+            _ => return,
+        };
+        let target = match song {
+            Some(media) if target.is_empty() => format!("{} - {}", media.artist, media.title),
+            Some(media) => format!("{} ({} - {})", target, media.artist, media.title),
+            None => target.to_string(),
+        };
+        let entry = AuditEntry {
+            time: get_time(),
+            url: self.get_url(),
+            username: self.username.clone().unwrap_or_default(),
+            action: action.to_string(),
+            target: target,
+            outcome: outcome.to_string(),
+        };
+        if let Err(err) = audit::append_entry(path, &entry) {
+            warn!("could not write to the audit log {:?}: {}", path, err);
+        }
+    }
+
+    /// Update the client state with a message that was received from the server
     pub fn handle_message(&mut self, msg: &Json) -> Result<Message, ClientError> {
-        let fail = || CometError::MalformedResponse(("found no msg type", msg.clone()));
-        let msg_type = try!(Some(msg)
-            .and_then(|x| x.as_object())
-            .and_then(|x| x.get("type"))
-            .and_then(|x| x.as_string())
-            .ok_or_else(&fail)
-        );
-        match msg_type {
-            "welcome" => Ok(Message::Welcome),
-            "playing" => self.handle_playing(msg),
-            "requests" => self.handle_requests(msg),
-            "login_token" => self.handle_login_token(msg),
-            "logged_in" => self.handle_logged_in(msg),
-            "error_login" => self.handle_login_error(msg),
-            "query_media_results" => self.handle_query_media_results(msg),
-            _ => {
+        let msg = try!(ServerMessage::decode(msg));
+        self.handle_server_message(msg)
+    }
+
+    /// Update the client state with a message from the server that was decoded already
+    pub fn handle_server_message(&mut self, msg: ServerMessage) -> Result<Message, ClientError> {
+        Ok(match msg {
+            ServerMessage::Welcome => Message::Welcome,
+            ServerMessage::Playing(playing) => self.handle_playing(playing),
+            ServerMessage::Requests(requests) => self.handle_requests(requests),
+            ServerMessage::LoginToken(token) => self.handle_login_token(token),
+            ServerMessage::LoggedIn(access_key, capabilities) => {
+                self.handle_logged_in(access_key, capabilities)
+            },
+            ServerMessage::LoginError(error_msg) => self.handle_login_error(error_msg),
+            ServerMessage::QueryMediaResults(token, results) => {
+                try!(self.handle_query_media_results(token, results))
+            },
+            ServerMessage::SkipVotes(votes, needed) => {
+                self.skip_votes = Some((votes, needed));
+                debug!("skip votes: {}/{}", votes, needed);
+                Message::SkipVotes(votes, needed)
+            },
+            ServerMessage::MediaReported(key) => {
+                self.audit_entry("report", &key, self.find_media(&key), "accepted");
+                Message::MediaReported(key)
+            },
+            ServerMessage::MediaUpdated(key, media) => self.handle_media_updated(key, media),
+            ServerMessage::UpdateMediaError(key, reason) => {
+                self.handle_update_media_error(key, reason)
+            },
+            ServerMessage::ConnectionState(state) => self.handle_connection_state(state),
+            ServerMessage::Unknown(msg) => {
                 debug!("unhandled message type in message: {}", msg);
-                panic!("unhandled message type {}", msg_type);
+                Message::Raw(msg)
             },
+        })
+    }
+
+    fn handle_connection_state(&mut self, state: ConnectionState) -> Message {
+        self.connected = state == ConnectionState::Connected;
+        if !self.connected {
+            return Message::ConnectionState(state);
+        }
+        // We are in a new session, so log in and follow the topics again
+        self.login_token = None;
+        self.waiting_for_login_token = false;
+        if let (true, Some(username), Some(access_key)) = (self.logged_in, self.username.clone(),
+                                                           self.access_key.clone()) {
+            self.logged_in = false;
+            self.do_login_accesskey(&username, &access_key);
+        }
+        if !self.suspended && !self.following.is_empty() {
+            let which = self.following.clone();
+            self.follow(which);
         }
+        // after logging in again, if we were logged in
+        let offline_requests = mem::replace(&mut self.offline_requests, Vec::new());
+        if !offline_requests.is_empty() {
+            info!("requesting {} songs that were requested while offline", offline_requests.len());
+        }
+        for key in offline_requests {
+            self.do_request_from_key(&key);
+        }
+        Message::ConnectionState(state)
     }
 
-    fn handle_playing(&mut self, msg: &Json) -> Result<Message, ClientError> {
-        let fail = || CometError::MalformedResponse(("found no playing object", msg.clone()));
-        let playing = try!(msg.as_object()
-            .and_then(|x| x.get("playing"))
-            .ok_or_else(&fail)
-            .map(|x| decode(&format!("{}", x)))
-        );
-        self.playing = Some(playing.unwrap());
+    fn handle_playing(&mut self, playing: Playing) -> Message {
+        let changed = self.playing.as_ref().map_or(true, |x| !x.is_same_play(&playing));
+        if changed {
+            // The votes were for the song that was playing before
+            self.skip_votes = None;
+        }
+        self.playing = Some(playing);
         debug!("currently playing: {:?}", self.playing);
-        Ok(Message::Playing)
+        if changed { Message::PlayingChanged } else { Message::Playing }
     }
 
-    fn handle_requests(&mut self, msg: &Json) -> Result<Message, ClientError> {
-        let fail = || CometError::MalformedResponse(("found no requests array", msg.clone()));
-        let requests_array = try!(msg.as_object()
-            .and_then(|x| x.get("requests"))
-            .and_then(|x| x.as_array())
-            .ok_or_else(&fail)
-        );
-        let mut requests = Vec::with_capacity(requests_array.len());
-        for x in requests_array.iter() {
-            requests.push(decode::<Request>(&format!("{}", x)).unwrap());
+    fn handle_requests(&mut self, requests: Vec<Request>) -> Message {
+        // A request of ours is the first new one for the song that we requested
+        self.queued_requests.clear();
+        for (key, before) in mem::replace(&mut self.pending_requests, Vec::new()) {
+            let queued = requests.iter().find(|x| {
+                x.media.key == key
+                    && before.as_ref().map_or(true, |before| !before.contains(&x.key))
+                    && !self.queued_requests.iter().any(|queued| queued.key == x.key)
+            });
+            match queued {
+                Some(request) => self.queued_requests.push(request.clone()),
+                None => self.pending_requests.push((key, before)),
+            }
+        }
+        for request in &self.queued_requests {
+            self.audit_entry("request", &request.media.key, Some(&request.media), "accepted");
         }
         self.requests = Some(requests);
+        self.reconcile_changes();
         debug!("current requests: {:?}", self.requests);
-        Ok(Message::Requests)
+        Message::Requests
     }
 
-    fn handle_login_token(&mut self, msg: &Json) -> Result<Message, ClientError> {
-        let fail = || CometError::MalformedResponse(("found no login_token string", msg.clone()));
-        let login_token = try!(msg.as_object()
-            .and_then(|x| x.get("login_token"))
-            .and_then(|x| x.as_string())
-            .ok_or_else(&fail)
-        );
-        self.login_token = Some(String::from(login_token));
+    fn handle_login_token(&mut self, login_token: String) -> Message {
+        self.login_token = Some(login_token);
         self.waiting_for_login_token = false;
         debug!("current login_token: {:?}", self.login_token);
         if let Some((ref username, ref secret, using_access_key)) = self.deferred_login.clone() {
             self.do_login_inner(username, secret, using_access_key);
         }
-        Ok(Message::LoginToken)
+        Message::LoginToken
     }
 
-    fn handle_logged_in(&mut self, msg: &Json) -> Result<Message, ClientError> {
+    fn handle_logged_in(&mut self, access_key: String, capabilities: Vec<String>) -> Message {
         self.waiting_for_login = false;
         self.logged_in = true;
-
-        let fail = || CometError::MalformedResponse(("found no accessKey string", msg.clone()));
-        self.access_key = Some(try!(msg.as_object()
-            .and_then(|x| x.get("accessKey"))
-            .and_then(|x| x.as_string())
-            .ok_or_else(&fail))
-            .to_owned()
-        );
+        self.access_key = Some(access_key);
+        self.capabilities = capabilities;
+        debug!("capabilities: {:?}", self.capabilities);
+        self.audit_entry("login", "", None, "logged in");
 
         let mut messages = Vec::with_capacity(self.deferred_after_login.len());
         messages.append(&mut self.deferred_after_login);
         for message in messages {
             self.send_message(&message);
+            self.expect_sent_change(&message);
         }
         self.deferred_after_login.clear();
-        Ok(Message::Login)
+        Message::Login
     }
 
-    fn handle_login_error(&mut self, msg: &Json) -> Result<Message, ClientError> {
-        let fail = || CometError::MalformedResponse(("found no message string", msg.clone()));
-        let error_msg = try!(msg.as_object()
-                                .and_then(|x| x.get("message"))
-                                .and_then(|x| x.as_string())
-                                .ok_or_else(&fail));
-
+    fn handle_login_error(&mut self, error_msg: String) -> Message {
+        self.waiting_for_login = false;
         debug!("login error: {}", error_msg);
-        Ok(Message::LoginError(error_msg.to_owned()))
+        self.audit_entry("login", "", None, &format!("refused: {}", error_msg));
+        Message::LoginError(error_msg)
     }
 
-    fn handle_query_media_results(&mut self, msg: &Json) -> Result<Message, ClientError> {
-        let fail = || CometError::MalformedResponse(("found no token string", msg.clone()));
-        let token = try!(msg.as_object()
-            .and_then(|x| x.get("token"))
-            .and_then(|x| x.as_u64())
-            .map(|x| x as usize)
-            .ok_or_else(&fail)
-        );
-        if self.qm_waiting_for_token.map_or(false, |x| x == token) {
-            self.qm_waiting_for_token = None;
-        } else {
-            // assert that this token is outdated
-            assert!(self.qm_waiting_for_token.map_or(true, |x| token < x));
-            return Ok(Message::QueryMediaResults);
+    fn handle_query_media_results(&mut self, token: usize, results: Vec<Media>)
+                                  -> Result<Message, ClientError> {
+        if let Some(stream) = self.qm_streams.get_mut(&token) {
+            stream.receive(results);
+            return Ok(Message::MediaPage(token));
         }
-
-        let results_array = try!(msg.as_object()
-            .and_then(|x| x.get("results"))
-            .and_then(|x| x.as_array())
-            .ok_or_else(&fail)
-        );
-
-        self.qm_results.reserve(results_array.len());
-        for x in results_array {
-            self.qm_results.push(decode::<Media>(&format!("{}", x)).unwrap());
+        match self.qm_waiting_for_token {
+            Some(waiting) if waiting == token => self.qm_waiting_for_token = None,
+            // the results of a query that was abandoned, see `cancel_query`
+            _ if token <= self.qm_token => {
+                debug!("ignoring the results of abandoned query {}", token);
+                return Ok(Message::QueryMediaResults);
+            },
+            _ => {
+                let what = format!("search results for query {}, which was not sent", token);
+                return Err(ClientError::UnknownMessage(what));
+            },
         }
 
-        if results_array.len() >= self.qm_requested_count.unwrap() {
-            // response was saturated
-            self.maybe_query_media();
-        } else {
+        let count = results.len();
+        self.qm_received += count;
+        let scope = &self.qm_scope;
+        self.qm_results.extend(results.into_iter().filter(|x| scope.matches(x)));
+
+        if self.qm_requested_count.map_or(true, |x| count < x) {
             self.qm_done = true;
+        } else if self.qm_received - self.qm_results.len() >= MAX_LEFT_OUT_RESULTS &&
+                  !self.answers_locally() {
+            debug!("stopping query {} after leaving out {} results", token,
+                   self.qm_received - self.qm_results.len());
+            self.qm_done = true;
+            self.qm_truncated = true;
         }
 
         self.maybe_query_media();
         Ok(Message::QueryMediaResults)
     }
 
+    fn handle_media_updated(&mut self, key: String, media: Option<Media>) -> Message {
+        self.pending_updates.remove(&key);
+        // The server may tell us how the song looks now
+        if let Some(media) = media {
+            self.replace_media(&media);
+        }
+        self.audit_entry("edit", &key, self.find_media(&key), "accepted");
+        Message::MediaUpdated(key)
+    }
+
+    fn handle_update_media_error(&mut self, key: String, reason: String) -> Message {
+        if let Some(media) = self.pending_updates.remove(&key) {
+            debug!("undoing the changes to {}", key);
+            self.replace_media(&media);
+        }
+        self.audit_entry("edit", &key, self.find_media(&key), &format!("refused: {}", reason));
+        Message::MediaUpdateError(key, reason)
+    }
+
+    /// Ask the server to keep us updated on what is playing and on the request queue
     pub fn follow_all(&mut self) {
         self.follow(vec!("playing".to_string(), "requests".to_string()))
     }
 
+    /// Ask the server to keep us updated on the topics in `which`
+    ///
+    /// Valid topics are `"playing"` and `"requests"`.
     pub fn follow(&mut self, which: Vec<String>) {
         for x in &which[..] {
             assert!(x == "playing" || x == "requests");
+            if !self.following.contains(x) {
+                self.following.push(x.clone());
+            }
         }
         let b = make_json_hashmap!(
             "type" => "follow",
@@ -347,30 +1036,103 @@ impl Client {
         self.send_message_s.send(b.to_json())
     }
 
+    /// Stop following the topics in `which`
+    pub fn unfollow(&mut self, which: Vec<String>) {
+        self.following.retain(|x| !which.contains(x));
+        let b = make_json_hashmap!(
+            "type" => "unfollow",
+            "which" => which
+        );
+        self.send_message_s.send(b.to_json())
+    }
+
+    /// Stop following and polling the server, e.g. while the front-end is in the background
+    ///
+    /// The topics we follow are remembered, so that `resume` can follow them again. Messages
+    /// can still be sent while suspended.
+    pub fn suspend(&mut self) {
+        if self.suspended {
+            return;
+        }
+        self.suspended = true;
+        if !self.following.is_empty() {
+            let b = make_json_hashmap!(
+                "type" => "unfollow",
+                "which" => self.following
+            );
+            self.send_message_s.send(b.to_json());
+        }
+        self.channel.set_paused(true);
+    }
+
+    /// Start polling again and follow the topics from before `suspend`
+    ///
+    /// Following a topic makes the server send its current state, so `get_playing` and
+    /// `get_requests` are up to date again after the responses are handled.
+    pub fn resume(&mut self) {
+        if !self.suspended {
+            return;
+        }
+        self.suspended = false;
+        self.channel.set_paused(false);
+        if !self.following.is_empty() {
+            let which = self.following.clone();
+            self.follow(which);
+        }
+    }
+
+    /// Is the client suspended?
+    pub fn is_suspended(&self) -> bool {
+        self.suspended
+    }
+
+    /// Wait `delay` before every long poll, e.g. while nobody is looking, or not if it is `None`
+    ///
+    /// Updates from the server arrive later, but we wake up less often. See
+    /// `CometChannel::set_poll_delay`.
+    pub fn set_poll_delay(&self, delay: Option<Duration>) {
+        self.channel.set_poll_delay(delay);
+    }
+
+    /// Request a new login token, which is needed to hash the login secret
     pub fn request_login_token(&mut self) {
         let b = make_json_hashmap!("type" => "request_login_token");
         self.waiting_for_login_token = true;
         self.send_message(&b)
     }
 
+    /// Hash the secrets that we log in with with `hasher`, for servers with another protocol
+    /// than the `Md5Hasher` that is used by default
+    pub fn set_login_hasher(&mut self, hasher: Arc<LoginHasher>) {
+        self.login_hasher = hasher;
+    }
+
+    /// The secret that `do_login` takes to log in with `password`
+    pub fn hash_password(&self, password: &str) -> String {
+        self.login_hasher.password_secret(password)
+    }
+
+    /// Log in using the hash of the user's password, see `hash_password`
     pub fn do_login(&mut self, username: &str, password_hash: &str) {
         self.do_login_inner(username, password_hash, false)
     }
 
+    /// Log in using an access key from an earlier session
     pub fn do_login_accesskey(&mut self, username: &str, access_key: &str) {
         self.do_login_inner(username, access_key, true)
     }
 
     fn do_login_inner(&mut self, username: &str, secret: &str, using_access_key: bool) {
+        self.username = Some(String::from(username));
         if let Some(ref login_token) = self.login_token {
             self.deferred_login = None;
             let b = make_json_hashmap!(
                 "type" => if using_access_key {"login_accessKey"} else {"login"},
                 "username" => username,
-                "hash" => md5(&format!("{}{}", secret, login_token))
+                "hash" => self.login_hasher.login_hash(secret, login_token)
             );
             self.waiting_for_login = true;
-            self.send_message_s.send(b.to_json())
+            self.send_message(&b)
         } else {
             self.deferred_login = Some((String::from(username), String::from(secret), using_access_key));
             if !self.waiting_for_login_token {
@@ -379,6 +1141,10 @@ impl Client {
         }
     }
 
+    /// Set the search query, and the amount of results that we would like to have for it
+    ///
+    /// Results arrive incrementally and are available through `get_qm_results`. Words can be
+    /// scoped to a field, e.g. `artist:radiohead`, see the `query` module.
     pub fn update_query(&mut self, new_query: Option<&str>, count: usize) {
         // At this point, we could be in any state (so no preconditions to be checked)
         match new_query {
@@ -389,22 +1155,56 @@ impl Client {
             },
             new_query if self.qm_query.as_ref().map(|x| x.as_str()) == new_query => {},
             new_query => {
+                self.cancel_query();
                 self.qm_done = false;
                 self.qm_query = new_query.map(|x| x.to_string());
-                self.qm_requested_count = None;
+                self.qm_scope = new_query.map_or_else(Query::default, query::parse);
                 self.qm_results_count = count;
-                self.qm_results.clear();
-                self.qm_waiting_for_token = None;
                 self.maybe_query_media();
             }
         }
     }
 
+    /// Abandon the current query: its results are dropped, and the ones that are still on their
+    /// way are ignored when they arrive
+    ///
+    /// The protocol has no message to tell the server about this, so it still answers; the
+    /// tokens of the queries tell which answers are for an abandoned query. `update_query` does
+    /// this for the old query when the query changes.
+    pub fn cancel_query(&mut self) {
+        if let Some(token) = self.qm_waiting_for_token.take() {
+            debug!("abandoning query {}", token);
+        }
+        self.qm_query = None;
+        self.qm_scope = Query::default();
+        self.qm_received = 0;
+        self.qm_requested_count = None;
+        self.qm_results_count = 0;
+        self.qm_results.clear();
+        self.qm_done = true;
+        self.qm_truncated = false;
+    }
+
+    /// Only ask for media that were added or changed after `since`, from the next query on
+    ///
+    /// Servers that do not support this send all results, so the results may still contain media
+    /// that did not change.
+    pub fn set_modified_since(&mut self, since: Option<Timespec>) {
+        self.qm_modified_since = since;
+    }
+
+    /// Answer the searches from `library` instead of asking the server, from the next query on
+    ///
+    /// An empty library is not used, e.g. while it is loaded. Queries with `set_modified_since`
+    /// always go to the server, as they are what keeps the library up to date.
+    pub fn set_library(&mut self, library: Option<Library>) {
+        self.library = library;
+    }
+
     fn maybe_query_media(&mut self) {
         match () {
             _ if self.qm_done => {},
             _ if self.qm_query.is_none() => {},
-            _ if self.qm_requested_count.map_or(false, |x| x >= self.qm_results_count) => {},
             _ if self.qm_results.len() >= self.qm_results_count => {},
             _ if self.qm_waiting_for_token.is_some() => {},
             _ => self.query_media(),
@@ -416,68 +1216,517 @@ impl Client {
         use std::cmp::min;
         assert!(self.qm_query.is_some());
 
-        let skip = self.qm_results.len();
+        // the results that were left out still count for the server
+        let skip = self.qm_received;
         self.qm_token += 1;
 
         // We don't want to make requests with more than `qm_chunk_size()` results,
         // because it would introduce too much lag. So if the user (interface)
         // requests more than `count` results, we do them in subsequent requests.
-        self.qm_requested_count = Some(min(self.qm_results_count - skip, self.qm_chunk_size()));
+        let count = min(self.qm_results_count - self.qm_results.len(), self.qm_chunk_size());
+        self.qm_requested_count = Some(count);
+        self.qm_waiting_for_token = Some(self.qm_token);
+        let (token, query) = (self.qm_token, self.qm_scope.server_query());
+        self.send_query_media(token, &query, skip, count);
+    }
 
-        let b = make_json_hashmap!(
+    /// Ask for `count` results of `query` after the first `skip`, which are answered with `token`
+    fn send_query_media(&mut self, token: usize, query: &str, skip: usize, count: usize) {
+        let mut b = make_json_hashmap!(
             "type" => "query_media",
-            "query" => self.qm_query,
-            "token" => self.qm_token,
+            "query" => query,
+            "token" => token,
             "skip" => skip,
-            "count" => self.qm_requested_count
+            "count" => count
         );
-        self.qm_waiting_for_token = Some(self.qm_token);
-        self.send_message(&b)
+        if let Some(since) = self.qm_modified_since {
+            b.insert("modifiedSince".to_string(), (since.sec as f64).to_json());
+        }
+        match self.library {
+            Some(ref library) if self.answers_locally() => {
+                let msg = make_json_hashmap!(
+                    "type" => "query_media_results",
+                    "token" => token,
+                    "results" => library.search(query, skip, count)
+                );
+                self.library_results_s.send(msg.to_json());
+            },
+            _ => self.send_message(&b),
+        }
+    }
+
+    /// Are the searches answered from the library instead of by the server?
+    fn answers_locally(&self) -> bool {
+        self.library.as_ref().map_or(false, |x| !x.is_empty()) && self.qm_modified_since.is_none()
+    }
+
+    /// Get the results of `query` page by page, with `page_size` results on every page
+    ///
+    /// Unlike `update_query`, this does not keep the results, see the `stream` module. When a
+    /// page arrives, `handle_message` returns a `Message::MediaPage` with the returned id, and
+    /// `next_media_page` takes the page and asks for the next one. Streams do not get in the way
+    /// of each other or of the query of `update_query`.
+    pub fn query_media_stream(&mut self, query: &str, page_size: usize) -> StreamId {
+        self.qm_token += 1;
+        let id = self.qm_token;
+        let mut stream = MediaStream::new(query, max(page_size, 1));
+        let (query, skip, count) = stream.next_query();
+        self.send_query_media(id, &query, skip, count);
+        self.qm_streams.insert(id, stream);
+        id
+    }
+
+    /// Take the page of stream `id` that arrived, and ask for the next one
+    ///
+    /// Returns `None` if no page arrived since the last one was taken. The last page has fewer
+    /// than `page_size` results, maybe none; once it is taken, the stream is done.
+    pub fn next_media_page(&mut self, id: StreamId) -> Option<Vec<Media>> {
+        let (page, next) = match self.qm_streams.get_mut(&id) {
+            Some(stream) => match stream.take_page() {
+                Some(page) if stream.is_done() => (page, None),
+                Some(page) => (page, Some(stream.next_query())),
+                None => return None,
+            },
+            None => return None,
+        };
+        match next {
+            Some((query, skip, count)) => self.send_query_media(id, &query, skip, count),
+            None => {
+                self.qm_streams.remove(&id);
+            },
+        }
+        Some(page)
+    }
+
+    /// Was the last page of stream `id` taken, or was it cancelled?
+    pub fn is_media_stream_done(&self, id: StreamId) -> bool {
+        !self.qm_streams.contains_key(&id)
+    }
+
+    /// Stop stream `id`; the page that is on its way is ignored when it arrives
+    pub fn cancel_media_stream(&mut self, id: StreamId) {
+        self.qm_streams.remove(&id);
     }
 
     fn qm_chunk_size(&self) -> usize {
-        match self.qm_results.len() {
-            x if x <= 50 => 25, // not too much lag
-            x if x <= 100 => 50,
-            x if x <= 200 => 100,
-            x if x <= 500 => 1000 - x,
-            _ => 1000
+        self.qm_chunk_policy.chunk_size(self.qm_results.len())
+    }
+
+    /// Vote to skip the song that is playing, if the server supports skip votes
+    ///
+    /// The server answers with the new tally, see `get_skip_votes`.
+    pub fn vote_skip(&mut self) -> RequestStatus {
+        let b = make_json_hashmap!("type" => "vote_skip");
+        self.send_message_after_login(&b)
+    }
+
+    /// Move the request with key `key` to the front of the queue
+    ///
+    /// Check `can_boost` first, the server refuses to boost requests that we may not boost.
+    pub fn do_boost(&mut self, key: i64) -> RequestStatus {
+        let b = make_json_hashmap!("type" => "boost", "requestKey" => key);
+        self.send_message_after_login(&b)
+    }
+
+    /// Skip the song that is playing right away, if the server allows us to
+    ///
+    /// Usually only moderators may do this, the others can vote with `vote_skip`. Once the
+    /// server skipped the song, a `PlayingChanged` message arrives.
+    pub fn do_skip(&mut self) -> RequestStatus {
+        let mut b = make_json_hashmap!("type" => "skip_playing");
+        // so that the server does not skip the next song if this one just ended
+        if let Some(ref playing) = self.playing {
+            b.insert("mediaKey".to_string(), playing.media.key.to_json());
+        }
+        self.send_message_after_login(&b)
+    }
+
+    /// Remove the request with key `key` from the queue
+    ///
+    /// Check `can_manage` first, the server refuses to remove requests that we may not remove.
+    /// The request is gone from `get_expected_requests` once the message is sent, which is after
+    /// logging in if it is deferred.
+    pub fn do_remove_request(&mut self, key: i64) -> RequestStatus {
+        let b = make_json_hashmap!("type" => "remove_request", "requestKey" => key);
+        self.send_queue_change(&b)
+    }
+
+    /// Move the request with key `key` by `amount` places, towards the front if it is negative
+    ///
+    /// Check `can_manage` first, like for `do_remove_request`.
+    pub fn do_move_request(&mut self, key: i64, amount: i64) -> RequestStatus {
+        let b = make_json_hashmap!("type" => "move_request", "requestKey" => key,
+                                   "amount" => amount);
+        self.send_queue_change(&b)
+    }
+
+    /// Send `msg`, which changes the queue, and expect the change once it is sent
+    fn send_queue_change<T: ToJson>(&mut self, msg: &T) -> RequestStatus {
+        let status = self.send_message_after_login(msg);
+        if let RequestStatus::Ok = status {
+            self.expect_sent_change(&msg.to_json());
+        }
+        status
+    }
+
+    /// Expect the change to the queue that the sent message `msg` asks for, if it asks for one
+    ///
+    /// A move is expected in the queue as it is when it is sent, because the server moves the
+    /// request in the queue as it is then.
+    fn expect_sent_change(&mut self, msg: &Json) {
+        let key = msg.find("requestKey").and_then(|x| x.as_i64());
+        match (msg.find("type").and_then(|x| x.as_string()), key) {
+            (Some("remove_request"), Some(key)) => self.expect_change(PendingChange::Remove(key)),
+            (Some("move_request"), Some(key)) => {
+                let amount = msg.find("amount").and_then(|x| x.as_i64()).unwrap_or(0);
+                let before = self.requests.iter().flat_map(|x| x.iter().map(|x| x.key))
+                                 .collect();
+                self.expect_change(PendingChange::Move(key, amount, before));
+            },
+            _ => {},
         }
     }
 
+    /// Report that the song with media key `key` is wrong, e.g. that it has wrong tags
+    ///
+    /// Only servers with the `"report"` capability take reports, see `has_capability`. They
+    /// answer with a `MediaReported` message.
+    pub fn do_report(&mut self, key: &str, reason: &str) -> RequestStatus {
+        let b = make_json_hashmap!("type" => "report_media", "mediaKey" => key,
+                                   "reason" => reason);
+        self.send_message_after_login(&b)
+    }
+
+    /// Change the metadata of the song with media key `key`, if the server allows it
+    ///
+    /// `fields` maps `"artist"`, `"title"` or `"album"` to their new value. The songs that we
+    /// know of (in the search results, the queue and what is playing) are changed right away.
+    /// If the server does not accept the changes, they are undone and we get a
+    /// `MediaUpdateError` message, and else a `MediaUpdated` message. Only users with the
+    /// `"edit"` capability may do this, see `has_capability`.
+    pub fn update_media(&mut self, key: &str, fields: &BTreeMap<String, String>) -> RequestStatus {
+        let b = make_json_hashmap!("type" => "update_media", "mediaKey" => key,
+                                   "fields" => fields);
+        let status = self.send_message_after_login(&b);
+        if let RequestStatus::Ok = status {
+            if let Some(media) = self.find_media(key).cloned() {
+                // Undo to how the song was before the first change that is not accepted yet
+                if !self.pending_updates.contains_key(key) {
+                    self.pending_updates.insert(key.to_string(), media.clone());
+                }
+                self.replace_media(&media.with_fields(fields));
+            }
+        }
+        status
+    }
+
+    /// Find the song with media key `key` in the songs that we know of
+    fn find_media(&self, key: &str) -> Option<&Media> {
+        self.playing.iter().map(|x| &x.media)
+            .chain(self.requests.iter().flat_map(|x| x.iter().map(|x| &x.media)))
+            .chain(self.qm_results.iter())
+            .find(|x| x.key == key)
+    }
+
+    /// Find the request with key `key` in the queue
+    fn find_request(&self, key: i64) -> Option<&Request> {
+        self.requests.iter().flat_map(|x| x.iter()).find(|x| x.key == key)
+    }
+
+    /// Replace every copy of the song with the same media key as `media` that we know of
+    fn replace_media(&mut self, media: &Media) {
+        let copies = self.playing.iter_mut().map(|x| &mut x.media)
+            .chain(self.requests.iter_mut().flat_map(|x| x.iter_mut().map(|x| &mut x.media)))
+            .chain(self.qm_results.iter_mut());
+        for copy in copies.filter(|x| x.key == media.key) {
+            *copy = media.clone();
+        }
+    }
+
+    /// Request a song to be played
     pub fn do_request(&mut self, media: &Media) -> RequestStatus {
         self.do_request_from_key(&media.key)
     }
 
+    /// Request the song with media key `key` to be played
+    ///
+    /// If the server cannot be reached, the request is queued, and sent once it can be again;
+    /// see `get_offline_requests` and `clear_offline_requests`.
     pub fn do_request_from_key(&mut self, key: &str) -> RequestStatus {
+        if !self.connected {
+            let b = make_json_hashmap!("type" => "request", "mediaKey" => key);
+            self.audit(&b.to_json(), "waiting for the server");
+            self.offline_requests.push(key.to_string());
+            return RequestStatus::Queued;
+        }
+        let before = self.requests.as_ref().map(|x| x.iter().map(|x| x.key).collect());
+        self.pending_requests.push((key.to_string(), before));
         let b = make_json_hashmap!("type" => "request", "mediaKey" => key);
-        self.send_message_after_login(&b)
-    }
-}
-
-pub fn md5(p: &str) -> String {
-    use openssl::crypto::hash::{hash, Type};
-    use std::fmt::Write;
-    let mut c = String::with_capacity(MD5_HASH_LENGTH);
-    for byte in hash(Type::MD5, p.as_bytes()) {
-        write!(c, "{:02x}", byte).unwrap();
+        let status = self.send_message_after_login(&b);
+        // we can only show the request if we know the song
+        let media = self.find_media(key).cloned();
+        if let (&RequestStatus::Ok, Some(media)) = (&status, media) {
+            self.last_pending_key -= 1;
+            let request = Request { by: self.username.clone(), key: self.last_pending_key,
+                                    media: media };
+            self.expect_change(PendingChange::Request(request));
+        }
+        status
     }
-    c
 }
 
 #[cfg(test)]
 mod tests {
     #[test]
-    fn md5() {
-        use super::md5;
-        assert_eq!(md5(""), "d41d8cd98f00b204e9800998ecf8427e");
-        assert_eq!(md5("a"), "0cc175b9c0f1b6a831c399e269772661");
-        assert_eq!(md5("abc"), "900150983cd24fb0d6963f7d28e17f72");
-        assert_eq!(md5("message digest"), "f96b697d7cb7938d525a2f31aaf161d0");
-        assert_eq!(md5("abcdefghijklmnopqrstuvwxyz"), "c3fcd3d76192e4007dfb496cca67e13b");
-        assert_eq!(md5("ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789"),
-                   "d174ab98d277d9f5a5611c2c9f419d9f");
-        assert_eq!(md5("12345678901234567890123456789012345678901234567890123456789012345678901234567890"),
-                   "57edf4a22be3c955ac49da2e2107b67a");
+    fn display_chain() {
+        use rustc_serialize::json::{decode, Json};
+        use super::{ClientError, CometError, DisplayChain, Request};
+
+        let err = ClientError::from(CometError::MalformedResponse(("found no requests array",
+                                                                   Json::Null)))
+            .context("while handling requests message");
+        assert_eq!(DisplayChain(&err).to_string(),
+                   "while handling requests message: \
+                    malformed response from the server: found no requests array");
+
+        let err = ClientError::from(decode::<Request>("{}").unwrap_err())
+            .context("while decoding request 0")
+            .context("while handling requests message");
+        assert!(DisplayChain(&err).to_string().starts_with(
+            "while handling requests message: while decoding request 0: could not decode message: "));
+    }
+
+    #[test]
+    fn display_comet_error() {
+        use std::io;
+        use hyper;
+        use rustc_serialize::json::Json;
+        use super::{CometError, DisplayChain};
+
+        assert_eq!(CometError::Recv.to_string(), "cannot read on channel");
+        assert_eq!(DisplayChain(&CometError::Hyper(hyper::Error::Status)).to_string(),
+                   "http request to the server failed: Invalid Status provided");
+        let err = CometError::IO(io::Error::new(io::ErrorKind::Other, "connection reset"));
+        assert_eq!(DisplayChain(&err).to_string(),
+                   "io error while talking to the server: connection reset");
+        let err = CometError::Json(Json::from_str("{").unwrap_err());
+        assert!(DisplayChain(&err).to_string().starts_with("the server sent invalid json: "));
+        let err = CometError::MalformedResponse(("found no session id", Json::Null));
+        assert_eq!(err.to_string(), "malformed response from the server: found no session id");
+    }
+
+    #[test]
+    fn display_client_error() {
+        use rustc_serialize::json::{decode, Json};
+        use super::{ClientError, CometError, Media};
+
+        // Comet errors are shown as they are
+        let err = ClientError::from(CometError::Recv);
+        assert_eq!(err.to_string(), "cannot read on channel");
+        let err = ClientError::from(decode::<Media>("{}").unwrap_err());
+        assert_eq!(err.to_string(), "could not decode message");
+        let err = ClientError::from(CometError::MalformedResponse(("", Json::Null)))
+            .context("while handling playing message");
+        assert_eq!(err.to_string(), "while handling playing message");
+    }
+
+    #[test]
+    fn unknown_messages() {
+        use rustc_serialize::json::Json;
+        use super::{Client, ClientError, Message};
+
+        let results = |token: usize| {
+            Json::from_str(&format!(r#"{{"type": "query_media_results", "token": {},
+                                        "results": []}}"#, token)).unwrap()
+        };
+        let (mut client, _, _) = Client::offline("test");
+        match client.handle_message(&Json::from_str(r#"{"playing": null}"#).unwrap()) {
+            Err(ClientError::UnknownMessage(_)) => {},
+            msg => panic!("unexpected {:?}", msg),
+        }
+        client.update_query(Some("queen"), 10);
+        match client.handle_message(&results(5)) {
+            Err(ClientError::UnknownMessage(what)) => {
+                assert_eq!(what, "search results for query 5, which was not sent");
+            },
+            msg => panic!("unexpected {:?}", msg),
+        }
+        match client.handle_message(&results(1)) {
+            Ok(Message::QueryMediaResults) => assert!(*client.get_qm_results().1),
+            msg => panic!("unexpected {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn cancel_query() {
+        use rustc_serialize::json::Json;
+        use super::{Client, Message};
+
+        let results = |token: usize| {
+            Json::from_str(&format!(r#"{{"type": "query_media_results", "token": {},
+                                        "results": [{{"key": "m1", "artist": "Queen",
+                                                      "title": "Bicycle", "length": 181,
+                                                      "uploadedByKey": "bob"}}]}}"#,
+                                    token)).unwrap()
+        };
+        let (mut client, _, _) = Client::offline("test");
+        client.update_query(Some("queen"), 10);
+        client.cancel_query();
+        // the answer to the abandoned query arrives anyway, also after a new query was sent
+        client.handle_message(&results(1)).unwrap();
+        assert!(client.get_qm_results().0.is_empty());
+        client.update_query(Some("bowie"), 10);
+        client.handle_message(&results(1)).unwrap();
+        assert!(client.get_qm_results().0.is_empty());
+        assert!(!*client.get_qm_results().1);
+        match client.handle_message(&results(2)) {
+            Ok(Message::QueryMediaResults) => assert_eq!(client.get_qm_results().0.len(), 1),
+            msg => panic!("unexpected {:?}", msg),
+        }
+        assert!(client.handle_message(&results(3)).is_err());
+    }
+
+    #[test]
+    fn truncated_query() {
+        use rustc_serialize::json::Json;
+        use super::{Client, MAX_LEFT_OUT_RESULTS};
+
+        let song = r#"{"key": "m1", "artist": "Queen", "title": "Bicycle", "length": 181,
+                       "uploadedByKey": "bob"}"#;
+        let results = |token: usize| {
+            let songs = vec![song; 100].join(",");
+            Json::from_str(&format!(r#"{{"type": "query_media_results", "token": {},
+                                        "results": [{}]}}"#, token, songs)).unwrap()
+        };
+        let (mut client, _, _) = Client::offline("test");
+        // none of the results match, so the client keeps asking for more until it gives up
+        client.update_query(Some("uploader:alice"), 10);
+        for token in 1..MAX_LEFT_OUT_RESULTS / 100 + 1 {
+            assert!(!*client.get_qm_results().1);
+            client.handle_message(&results(token)).unwrap();
+        }
+        assert!(*client.get_qm_results().1);
+        assert!(client.is_query_truncated());
+        assert!(client.get_qm_results().0.is_empty());
+        client.update_query(Some("uploader:bob"), 10);
+        assert!(!client.is_query_truncated());
+    }
+
+    #[test]
+    fn queued_requests() {
+        use rustc_serialize::json::Json;
+        use super::Client;
+
+        let requests = |keys: &[(i64, &str)]| {
+            let requests: Vec<String> = keys.iter().map(|&(key, media_key)| {
+                format!(r#"{{"key": {}, "byKey": "bob", "media": {{"key": "{}", "artist": "A",
+                           "title": "T", "length": 200, "uploadedByKey": "bob"}}}}"#,
+                        key, media_key)
+            }).collect();
+            let msg = format!(r#"{{"type": "requests", "requests": [{}]}}"#, requests.join(","));
+            Json::from_str(&msg).unwrap()
+        };
+        let (mut client, _, _) = Client::offline("test");
+        client.handle_message(&requests(&[(1, "m1")])).unwrap();
+        client.do_request_from_key("m1");
+        client.do_request_from_key("m2");
+        client.handle_message(&requests(&[(1, "m1"), (2, "m2")])).unwrap();
+        let queued: Vec<i64> = client.get_queued_requests().iter().map(|x| x.key).collect();
+        assert_eq!(queued, [2]);
+        // the request for m1 is not the one that was in the queue already
+        client.handle_message(&requests(&[(1, "m1"), (2, "m2"), (3, "m1")])).unwrap();
+        let queued: Vec<i64> = client.get_queued_requests().iter().map(|x| x.key).collect();
+        assert_eq!(queued, [3]);
+        client.handle_message(&requests(&[(2, "m2"), (3, "m1")])).unwrap();
+        assert!(client.get_queued_requests().is_empty());
+    }
+
+    #[test]
+    fn offline_requests() {
+        use {ConnectionState, RequestStatus};
+        use super::Client;
+
+        let (mut client, _, _) = Client::offline("test");
+        client.handle_message(&ConnectionState::Disconnected.to_message()).unwrap();
+        match client.do_request_from_key("m1") {
+            RequestStatus::Queued => {},
+            status => panic!("unexpected {:?}", status),
+        }
+        client.do_request_from_key("m2");
+        assert_eq!(client.get_offline_requests(), ["m1", "m2"]);
+        assert_eq!(client.clear_offline_requests(), ["m1", "m2"]);
+
+        client.do_request_from_key("m3");
+        client.handle_message(&ConnectionState::Reconnecting(1).to_message()).unwrap();
+        assert_eq!(client.get_offline_requests(), ["m3"]);
+        client.handle_message(&ConnectionState::Connected.to_message()).unwrap();
+        assert!(client.get_offline_requests().is_empty());
+        // we are not logged in, so now it waits for that
+        assert_eq!(client.deferred_after_login.len(), 1);
+    }
+
+    #[test]
+    fn expected_requests() {
+        use rustc_serialize::json::Json;
+        use super::Client;
+
+        let requests = |keys: &[i64]| {
+            // request 4 is another request for the song of request 2
+            let requests: Vec<String> = keys.iter().map(|&key| {
+                format!(r#"{{"key": {}, "byKey": "bob", "media": {{"key": "m{}", "artist": "A",
+                           "title": "T", "length": 200, "uploadedByKey": "bob"}}}}"#,
+                        key, if key == 4 { 2 } else { key })
+            }).collect();
+            let msg = format!(r#"{{"type": "requests", "requests": [{}]}}"#, requests.join(","));
+            Json::from_str(&msg).unwrap()
+        };
+        let keys = |client: &Client| -> Vec<i64> {
+            client.get_expected_requests().iter().map(|x| x.key).collect()
+        };
+        let (mut client, _, _) = Client::offline("test");
+        let logged_in = Json::from_str(r#"{"type": "logged_in", "accessKey": "a"}"#).unwrap();
+        client.handle_message(&logged_in).unwrap();
+        client.handle_message(&requests(&[1, 2, 3])).unwrap();
+
+        client.do_remove_request(1);
+        client.do_move_request(3, -1);
+        client.do_request_from_key("m2");
+        assert_eq!(keys(&client), [3, 2, -1]);
+        assert!(client.is_pending(3) && client.is_pending(-1) && !client.is_pending(2));
+        // the server removed 1 and added the request, but did not move 3 yet
+        client.handle_message(&requests(&[2, 3, 4])).unwrap();
+        assert_eq!(keys(&client), [3, 2, 4]);
+        // and then it never does
+        client.handle_message(&requests(&[2, 3, 4])).unwrap();
+        assert_eq!(keys(&client), [2, 3, 4]);
+        assert!(!client.is_pending(3));
+
+        // changes that wait for the login are expected once they are sent
+        let (mut client, _, _) = Client::offline("test");
+        client.handle_message(&requests(&[1, 2, 3])).unwrap();
+        client.do_remove_request(1);
+        client.do_move_request(2, 1);
+        assert_eq!(keys(&client), [1, 2, 3]);
+        client.handle_message(&logged_in).unwrap();
+        assert_eq!(keys(&client), [3, 2]);
+    }
+
+    #[test]
+    fn logging_in() {
+        use rustc_serialize::json::Json;
+        use super::Client;
+
+        let (mut client, _, _) = Client::offline("test");
+        assert!(!client.is_logging_in());
+        client.do_login("bob", "hash");
+        // the login waits for a login token
+        assert!(client.is_logging_in());
+        let token = Json::from_str(r#"{"type": "login_token", "login_token": "t"}"#).unwrap();
+        client.handle_message(&token).unwrap();
+        assert!(client.is_logging_in());
+        let error = Json::from_str(r#"{"type": "error_login", "message": "Wrong password"}"#);
+        client.handle_message(&error.unwrap()).unwrap();
+        assert!(!client.is_logging_in());
+        assert!(!client.is_logged_in());
     }
 }
@@ -0,0 +1,136 @@
+//! Answering searches from the local media index, instead of asking the server.
+//!
+//! A `Library` keeps the songs of a [`MediaCache`](../cache/struct.MediaCache.html) in memory,
+//! without holding on to the cache file, so that it can be synced in the meantime. A `Client`
+//! with a library (see `Client::set_library`) answers its searches from it: the results arrive
+//! as a `Message::QueryMediaResults` like the answers of the server, but without the round trip.
+//!
+//! The library is as fresh as the last sync of the cache, so update it after every sync (see
+//! `sync::sync_media`). It is a handle that can be cloned, e.g. into the task that syncs, and all
+//! clones see the update.
+//!
+//! Songs match a search like on the server: if their artist, title or album contain all words of
+//! the query. Case and accents do not matter, see [`text::fold`](../text/fn.fold.html). The
+//! results are ordered by artist and title.
+
+use std::sync::{Arc, RwLock};
+
+use cache::MediaCache;
+use media::Media;
+use text;
+
+#[derive(Debug, Default)]
+struct Songs {
+    /// Ordered by artist and title
+    media: Vec<Media>,
+    /// The folded artist, title and album of every song in `media`, to match queries against
+    folded: Vec<String>,
+}
+
+/// The songs of the media index, shared by its clones
+#[derive(Clone, Debug, Default)]
+pub struct Library {
+    songs: Arc<RwLock<Songs>>,
+}
+
+impl Library {
+    /// A library with the songs in `cache`
+    pub fn from_cache(cache: &MediaCache) -> Library {
+        let library = Library::default();
+        library.update(cache);
+        library
+    }
+
+    /// Replace the songs with the ones in `cache`
+    pub fn update(&self, cache: &MediaCache) {
+        let mut media: Vec<Media> = cache.media().cloned().collect();
+        media.sort_by(|a, b| a.collate(b));
+        let folded = media.iter().map(|x| {
+            let album = x.album.as_ref().map_or("", |x| &x[..]);
+            text::fold(&format!("{} {} {}", x.artist, x.title, album))
+        }).collect();
+        debug!("the library has {} songs", media.len());
+        *self.songs.write().unwrap() = Songs { media: media, folded: folded };
+    }
+
+    /// The number of songs in the library
+    pub fn len(&self) -> usize {
+        self.songs.read().unwrap().media.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The songs that match `query`, leaving out the first `skip` of them, at most `count`
+    pub fn search(&self, query: &str, skip: usize, count: usize) -> Vec<Media> {
+        let query = text::fold(query);
+        let words: Vec<&str> = query.split_whitespace().collect();
+        let songs = self.songs.read().unwrap();
+        songs.folded.iter().zip(songs.media.iter())
+                    .filter(|&(folded, _)| words.iter().all(|word| folded.contains(word)))
+                    .map(|(_, media)| media.clone())
+                    .skip(skip)
+                    .take(count)
+                    .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn search() {
+        use std::{env, fs, process};
+        use time::Duration;
+        use cache::MediaCache;
+        use media::Media;
+        use {Client, Message};
+        use super::Library;
+
+        let media = |key: &str, artist: &str, title: &str| Media {
+            key: key.to_string(),
+            artist: artist.to_string(),
+            title: title.to_string(),
+            length: Duration::seconds(200),
+            uploaded_by: "bob".to_string(),
+            album: None,
+            url: None,
+        };
+        let dir = env::temp_dir().join(format!("maruska-library-test-{}", process::id()));
+        let mut cache = MediaCache::open(&dir.join("media.idx")).unwrap();
+        cache.append(&[media("m1", "Motörhead", "Ace of Spades"), media("m2", "Blur", "Song 2"),
+                       media("m3", "Motorpsycho", "Mountain"), media("m4", "Björk", "Army of Me")])
+             .unwrap();
+
+        let library = Library::from_cache(&cache);
+        let keys = |results: Vec<Media>| -> Vec<String> {
+            results.into_iter().map(|x| x.key).collect()
+        };
+        assert_eq!(keys(library.search("", 0, 10)), ["m4", "m2", "m1", "m3"]);
+        assert_eq!(keys(library.search("MOTOR", 0, 10)), ["m1", "m3"]);
+        assert_eq!(keys(library.search("motor", 1, 10)), ["m3"]);
+        assert_eq!(keys(library.search("of", 0, 1)), ["m4"]);
+        assert!(library.search("motor spades 2", 0, 10).is_empty());
+
+        // the client answers its searches from the library, without asking the server
+        let (mut client, client_r, _) = Client::offline("test");
+        client.set_library(Some(library.clone()));
+        client.update_query(Some("motor"), 10);
+        match client.handle_message(&client_r.recv().unwrap()) {
+            Ok(Message::QueryMediaResults) => {},
+            msg => panic!("unexpected {:?}", msg),
+        }
+        let (results, done) = client.get_qm_results();
+        assert_eq!(keys(results.clone()), ["m1", "m3"]);
+        assert!(*done);
+
+        // the clones see the songs that are synced later
+        let clone = library.clone();
+        cache.remove(&["m1"]).unwrap();
+        library.update(&cache);
+        assert_eq!(clone.len(), 3);
+        drop(cache);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,7 +1,19 @@
+//! The models of the objects that are sent by the server.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
 use rustc_serialize::{Decodable, Decoder, Encodable, Encoder};
+use rustc_serialize::json::{Json, ToJson};
 use time::{Duration, Timespec, get_time};
 
+use text;
 
+/// The end times of the same song, as sent at different moments, differ by this many seconds at
+/// most, because they are corrected for the clock of the server
+const END_TIME_DRIFT_SECS: i64 = 2;
+
+/// A song in the media library
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Media {
     pub key: String,
@@ -9,6 +21,45 @@ pub struct Media {
     pub title: String,
     pub length: Duration,
     pub uploaded_by: String,
+    /// Not every server knows the album of a song
+    pub album: Option<String>,
+    /// Where the song can be downloaded, if the server tells; it may be relative to the url of
+    /// the server, and it is not kept in the local media index
+    pub url: Option<String>,
+}
+
+impl Media {
+    /// This song with the metadata in `fields` changed, as `Client::update_media` changes it
+    ///
+    /// The fields are `"artist"`, `"title"` and `"album"`, other fields are ignored. An empty
+    /// album means that there is none.
+    pub fn with_fields(&self, fields: &BTreeMap<String, String>) -> Media {
+        let mut media = self.clone();
+        for (field, value) in fields {
+            match &field[..] {
+                "artist" => media.artist = value.clone(),
+                "title" => media.title = value.clone(),
+                "album" if value.is_empty() => media.album = None,
+                "album" => media.album = Some(value.clone()),
+                _ => {},
+            }
+        }
+        media
+    }
+
+    /// Does the artist, title or album of this song contain all words of `query`?
+    ///
+    /// Case and accents do not matter, see [`text::fold`](../text/fn.fold.html).
+    pub fn matches(&self, query: &str) -> bool {
+        let album = self.album.as_ref().map_or("", |x| &x[..]);
+        text::matches(&format!("{} {} {}", self.artist, self.title, album), query)
+    }
+
+    /// Compare this song to `other` by artist and then by title, like a dictionary does
+    pub fn collate(&self, other: &Media) -> Ordering {
+        text::collate(&self.artist, &other.artist)
+            .then_with(|| text::collate(&self.title, &other.title))
+    }
 }
 
 impl Decodable for Media {
@@ -19,6 +70,8 @@ impl Decodable for Media {
             let mut title = Err(d.error("no media title field"));
             let mut length = Err(d.error("no media length field"));
             let mut uploaded_by = Err(d.error("no media uploadedByKey field"));
+            let mut album = Ok(None);
+            let mut url = Ok(None);
             for idx in 0..len {
                 let key = try!(d.read_map_elt_key(idx, |d| d.read_str()));
                 try!(d.read_map_elt_val(idx, |d| {
@@ -28,6 +81,8 @@ impl Decodable for Media {
                         "title" => title = Decodable::decode(d),
                         "length" => length = decode_duration(d),
                         "uploadedByKey" => uploaded_by = d.read_str(),
+                        "album" => album = Decodable::decode(d),
+                        "url" => url = Decodable::decode(d),
                         _ => {} // ignore
                     }
                     Ok(())
@@ -39,6 +94,8 @@ impl Decodable for Media {
                 title: try!(title),
                 length: try!(length),
                 uploaded_by: try!(uploaded_by),
+                album: try!(album),
+                url: try!(url),
             })
         })
     }
@@ -54,6 +111,8 @@ impl Encodable for Media {
             title: String,
             length: i64,
             uploadedByKey: String,
+            album: Option<String>,
+            url: Option<String>,
         }
         let m = EncodeMedia {
             key: self.key.clone(),
@@ -61,16 +120,44 @@ impl Encodable for Media {
             title: self.title.clone(),
             length: self.length.num_seconds(),
             uploadedByKey: self.uploaded_by.clone(),
+            album: self.album.clone(),
+            url: self.url.clone(),
         };
         m.encode(s)
     }
 }
 
+/// In the format of the server, like `Encodable`
+impl ToJson for Media {
+    fn to_json(&self) -> Json {
+        let mut obj = BTreeMap::new();
+        obj.insert("key".to_string(), self.key.to_json());
+        obj.insert("artist".to_string(), self.artist.to_json());
+        obj.insert("title".to_string(), self.title.to_json());
+        obj.insert("length".to_string(), self.length.num_seconds().to_json());
+        obj.insert("uploadedByKey".to_string(), self.uploaded_by.to_json());
+        obj.insert("album".to_string(), self.album.to_json());
+        obj.insert("url".to_string(), self.url.to_json());
+        Json::Object(obj)
+    }
+}
+
+/// The song that is currently playing
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Playing {
     pub requested_by: Option<String>,
     pub end_time: Timespec,
-    pub media: Media
+    pub media: Media,
+    /// Where the cover art of the song can be downloaded, if the server knows
+    pub cover_url: Option<String>,
+}
+
+impl Playing {
+    /// Is `other` the same song playing the same time, e.g. because the server sent it again?
+    pub fn is_same_play(&self, other: &Playing) -> bool {
+        self.media.key == other.media.key &&
+            (self.end_time - other.end_time).num_seconds().abs() <= END_TIME_DRIFT_SECS
+    }
 }
 
 impl Decodable for Playing {
@@ -80,11 +167,13 @@ impl Decodable for Playing {
             let mut media = Err(d.error("no media object"));
             let mut requested_by = Err(d.error("no byKey field "));
             let mut server_time = Err(d.error("no serverTime field"));
+            let mut cover_url = Ok(None);
             for idx in 0..len {
                 let key = try!(d.read_map_elt_key(idx, |d| d.read_str()));
                 try!(d.read_map_elt_val(idx, |d| {
                     match &key[..] {
                         "byKey" => requested_by = Decodable::decode(d),
+                        "coverUrl" => cover_url = Decodable::decode(d),
                         "endTime" => end_time = decode_timespec(d),
                         "media" => media = Decodable::decode(d),
                         "serverTime" => server_time = decode_timespec(d),
@@ -98,12 +187,14 @@ impl Decodable for Playing {
                 end_time: try!(end_time),
                 media: try!(media),
                 requested_by: try!(requested_by),
+                cover_url: try!(cover_url),
             })
         })
     }
 }
 
 
+/// A song in the request queue
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Request {
     pub by: Option<String>,
@@ -167,6 +258,8 @@ fn decode_duration<D: Decoder>(d: &mut D) -> Result<Duration, D::Error> {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
+
     use rustc_serialize::json::decode as json_decode;
     use time::{Duration, Timespec};
     use super::*;
@@ -178,6 +271,8 @@ mod tests {
             length: Duration::seconds(231),
             title: String::from("In the Fade"),
             uploaded_by: String::from("dsprenkels"),
+            album: None,
+            url: None,
         }
     }
 
@@ -186,6 +281,7 @@ mod tests {
             end_time: Timespec::new(1459420207, 0),
             requested_by: Some(String::from("bkoks")),
             media: expected_media(),
+            cover_url: None,
         }
     }
 
@@ -211,6 +307,21 @@ mod tests {
         assert_eq!(json_decode::<Media>(input).unwrap(), expected_media());
     }
 
+    #[test]
+    fn with_fields() {
+        let mut fields = BTreeMap::new();
+        fields.insert(String::from("album"), String::from("Lullabies to Paralyze"));
+        fields.insert(String::from("title"), String::from("In the Fade (live)"));
+        fields.insert(String::from("uploaded_by"), String::from("bkoks"));
+        let media = expected_media().with_fields(&fields);
+        assert_eq!(media.album, Some(String::from("Lullabies to Paralyze")));
+        assert_eq!(media.title, "In the Fade (live)");
+        assert_eq!(media.uploaded_by, "dsprenkels");
+
+        fields.insert(String::from("album"), String::new());
+        assert_eq!(media.with_fields(&fields).album, None);
+    }
+
     #[test]
     fn decode_playing() {
         let input = r#"
@@ -233,6 +344,21 @@ mod tests {
         assert_eq!(got.media, expected.media);
     }
 
+    #[test]
+    fn is_same_play() {
+        let playing = expected_playing();
+        let mut resent = expected_playing();
+        resent.end_time = resent.end_time + Duration::milliseconds(300);
+        assert!(playing.is_same_play(&resent));
+        // played again right after
+        let mut again = expected_playing();
+        again.end_time = again.end_time + again.media.length;
+        assert!(!playing.is_same_play(&again));
+        let mut other = expected_playing();
+        other.media.key = String::from("56bafc2c8dc01b4ea67fad9d");
+        assert!(!playing.is_same_play(&other));
+    }
+
     #[test]
     fn decode_request() {
         let input = r#"
@@ -0,0 +1,193 @@
+//! The messages that the server sends, decoded.
+//!
+//! Every message from the server is decoded here, in one place, into a
+//! [`ServerMessage`](enum.ServerMessage.html); `Client::handle_message` then updates the client
+//! state with it. The messages about the connection come from the same channel, although they
+//! are sent by the client itself.
+
+use rustc_serialize::json::{decode, Json};
+
+use comet::{CometError, ConnectionState};
+use media::{Media, Playing, Request};
+use ClientError;
+
+/// A message from the server, with what it says
+#[derive(Debug)]
+pub enum ServerMessage {
+    Welcome,
+    /// The song that is playing
+    Playing(Playing),
+    /// The request queue, the first request is played next
+    Requests(Vec<Request>),
+    /// The token to log in with
+    LoginToken(String),
+    /// We are logged in, with this access key and these capabilities
+    LoggedIn(String, Vec<String>),
+    /// The server did not log us in, for this reason
+    LoginError(String),
+    /// Search results for the query with this token
+    QueryMediaResults(usize, Vec<Media>),
+    /// The number of votes to skip the playing song, and the number that is needed to skip it
+    SkipVotes(usize, usize),
+    /// The server received our report about the song with this media key
+    MediaReported(String),
+    /// The server accepted the changes to the song with this media key, and may tell how the
+    /// song looks now
+    MediaUpdated(String, Option<Media>),
+    /// The server did not accept the changes to the song with this media key, for this reason
+    UpdateMediaError(String, String),
+    /// The server could no longer be reached, is being tried again, or can be reached again
+    ///
+    /// This message is sent by the client itself too, whenever the state changes.
+    ConnectionState(ConnectionState),
+    /// A message of a type that this client does not know, as it was received
+    Unknown(Json),
+}
+
+impl ServerMessage {
+    /// Decode `msg`, which is a message as the server sent it
+    pub fn decode(msg: &Json) -> Result<ServerMessage, ClientError> {
+        let fail = || ClientError::UnknownMessage(format!("{} has no type", msg));
+        let msg_type = try!(msg.find("type").and_then(|x| x.as_string()).ok_or_else(fail));
+        let result = match msg_type {
+            "welcome" => Ok(ServerMessage::Welcome),
+            "playing" => decode_playing(msg),
+            "requests" => decode_requests(msg),
+            "login_token" => {
+                get_string(msg, "login_token", "found no login_token string")
+                    .map(ServerMessage::LoginToken)
+            },
+            "logged_in" => decode_logged_in(msg),
+            "error_login" => {
+                get_string(msg, "message", "found no message string").map(ServerMessage::LoginError)
+            },
+            "query_media_results" => decode_query_media_results(msg),
+            "skip_votes" => decode_skip_votes(msg),
+            "media_reported" => {
+                get_string(msg, "mediaKey", "found no media key").map(ServerMessage::MediaReported)
+            },
+            "media_updated" => decode_media_updated(msg),
+            "error_update_media" => {
+                let key = try!(get_string(msg, "mediaKey", "found no media key"));
+                let reason = msg.find("message").and_then(|x| x.as_string())
+                                .unwrap_or("unknown error");
+                Ok(ServerMessage::UpdateMediaError(key, reason.to_string()))
+            },
+            "connection_state" => decode_connection_state(msg),
+            _ => Ok(ServerMessage::Unknown(msg.clone())),
+        };
+        result.map_err(|err| err.context(format!("while decoding {} message", msg_type)))
+    }
+}
+
+/// The string `key` of `msg`, or else a malformed response error that says `what` is missing
+fn get_string(msg: &Json, key: &str, what: &'static str) -> Result<String, ClientError> {
+    msg.find(key)
+       .and_then(|x| x.as_string())
+       .map(String::from)
+       .ok_or_else(|| ClientError::from(CometError::MalformedResponse((what, msg.clone()))))
+}
+
+fn decode_playing(msg: &Json) -> Result<ServerMessage, ClientError> {
+    let fail = || CometError::MalformedResponse(("found no playing object", msg.clone()));
+    let playing = try!(msg.find("playing").ok_or_else(&fail));
+    let playing: Playing = try!(decode(&format!("{}", playing)).map_err(|err| {
+        ClientError::from(err).context("while decoding the playing song")
+    }));
+    Ok(ServerMessage::Playing(playing))
+}
+
+fn decode_requests(msg: &Json) -> Result<ServerMessage, ClientError> {
+    let fail = || CometError::MalformedResponse(("found no requests array", msg.clone()));
+    let requests_array = try!(msg.find("requests").and_then(|x| x.as_array()).ok_or_else(&fail));
+    let mut requests = Vec::with_capacity(requests_array.len());
+    for (i, x) in requests_array.iter().enumerate() {
+        requests.push(try!(decode::<Request>(&format!("{}", x)).map_err(|err| {
+            ClientError::from(err).context(format!("while decoding request {}", i))
+        })));
+    }
+    Ok(ServerMessage::Requests(requests))
+}
+
+fn decode_logged_in(msg: &Json) -> Result<ServerMessage, ClientError> {
+    let access_key = try!(get_string(msg, "accessKey", "found no accessKey string"));
+    // Older servers do not send capabilities, so they allow nothing special
+    let capabilities = msg.find("capabilities").and_then(|x| x.as_array()).map_or(Vec::new(), |x| {
+        x.iter().filter_map(|x| x.as_string()).map(String::from).collect()
+    });
+    Ok(ServerMessage::LoggedIn(access_key, capabilities))
+}
+
+fn decode_query_media_results(msg: &Json) -> Result<ServerMessage, ClientError> {
+    let fail = || CometError::MalformedResponse(("found no token string", msg.clone()));
+    let token = try!(msg.find("token").and_then(|x| x.as_u64()).ok_or_else(&fail)) as usize;
+    let fail = || CometError::MalformedResponse(("found no results array", msg.clone()));
+    let results_array = try!(msg.find("results").and_then(|x| x.as_array()).ok_or_else(&fail));
+    let mut results = Vec::with_capacity(results_array.len());
+    for (i, x) in results_array.iter().enumerate() {
+        results.push(try!(decode::<Media>(&format!("{}", x)).map_err(|err| {
+            ClientError::from(err).context(format!("while decoding search result {}", i))
+        })));
+    }
+    Ok(ServerMessage::QueryMediaResults(token, results))
+}
+
+fn decode_skip_votes(msg: &Json) -> Result<ServerMessage, ClientError> {
+    let fail = || CometError::MalformedResponse(("found no vote counts", msg.clone()));
+    let get = |key| msg.find(key).and_then(|x| x.as_u64());
+    let (votes, needed) = try!(get("votes").and_then(|votes| get("needed").map(|needed| {
+        (votes as usize, needed as usize)
+    })).ok_or_else(&fail));
+    Ok(ServerMessage::SkipVotes(votes, needed))
+}
+
+fn decode_media_updated(msg: &Json) -> Result<ServerMessage, ClientError> {
+    let key = try!(get_string(msg, "mediaKey", "found no media key"));
+    let media = match msg.find("media") {
+        Some(media) => Some(try!(decode(&format!("{}", media)).map_err(|err| {
+            ClientError::from(err).context("while decoding the updated song")
+        }))),
+        None => None,
+    };
+    Ok(ServerMessage::MediaUpdated(key, media))
+}
+
+fn decode_connection_state(msg: &Json) -> Result<ServerMessage, ClientError> {
+    let fail = || CometError::MalformedResponse(("found no valid state", msg.clone()));
+    let state = try!(ConnectionState::from_message(msg).ok_or_else(&fail));
+    Ok(ServerMessage::ConnectionState(state))
+}
+
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn decode() {
+        use rustc_serialize::json::Json;
+        use comet::ConnectionState;
+        use super::ServerMessage;
+
+        let decode_str = |msg: &str| ServerMessage::decode(&Json::from_str(msg).unwrap());
+        match decode_str(r#"{"type": "logged_in", "accessKey": "k", "capabilities": ["boost"]}"#) {
+            Ok(ServerMessage::LoggedIn(key, capabilities)) => {
+                assert_eq!(key, "k");
+                assert_eq!(capabilities, ["boost"]);
+            },
+            msg => panic!("unexpected {:?}", msg),
+        }
+        match decode_str(r#"{"type": "skip_votes", "votes": 2, "needed": 3}"#) {
+            Ok(ServerMessage::SkipVotes(2, 3)) => {},
+            msg => panic!("unexpected {:?}", msg),
+        }
+        match decode_str(r#"{"type": "connection_state", "state": "reconnecting", "attempt": 2}"#) {
+            Ok(ServerMessage::ConnectionState(ConnectionState::Reconnecting(2))) => {},
+            msg => panic!("unexpected {:?}", msg),
+        }
+        match decode_str(r#"{"type": "history"}"#) {
+            Ok(ServerMessage::Unknown(_)) => {},
+            msg => panic!("unexpected {:?}", msg),
+        }
+        assert!(decode_str(r#"{"type": "requests"}"#).is_err());
+        assert!(decode_str(r#"{"requests": []}"#).is_err());
+    }
+}
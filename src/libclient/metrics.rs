@@ -0,0 +1,119 @@
+//! Counting what goes over the connection to the server, e.g. to sum up a session.
+//!
+//! Every comet channel keeps a `Recorder`, which its threads update as they send and receive
+//! packets. `Client::get_metrics` reads it. The bytes are counted by the transports (see
+//! `Transport::traffic`), as only they see the packets as they go over the wire.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// What went over the connection to the server since the client was created
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Metrics {
+    /// The packets that were sent, also the long polls
+    pub packets_sent: u64,
+    pub packets_received: u64,
+    /// The size of the JSON of the packets, without the HTTP or WebSocket around it
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// How often we connected again after the connection was lost
+    pub reconnects: u64,
+    /// How long the server could be reached
+    pub connected: Duration,
+}
+
+/// The bytes that a transport sent and received, see `Transport::traffic`
+#[derive(Debug, Default)]
+pub struct Traffic {
+    sent: AtomicUsize,
+    received: AtomicUsize,
+}
+
+impl Traffic {
+    pub fn sent(&self, bytes: usize) {
+        self.sent.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn received(&self, bytes: usize) {
+        self.received.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// The bytes that were sent and received so far
+    pub fn totals(&self) -> (u64, u64) {
+        (self.sent.load(Ordering::Relaxed) as u64, self.received.load(Ordering::Relaxed) as u64)
+    }
+}
+
+/// Keeps the metrics of a comet channel up to date
+#[derive(Debug, Default)]
+pub struct Recorder {
+    metrics: Metrics,
+    /// Since when we are connected, if we are
+    connected_since: Option<Instant>,
+}
+
+impl Recorder {
+    pub fn packet_sent(&mut self) {
+        self.metrics.packets_sent += 1;
+    }
+
+    pub fn packet_received(&mut self) {
+        self.metrics.packets_received += 1;
+    }
+
+    pub fn reconnected(&mut self) {
+        self.metrics.reconnects += 1;
+    }
+
+    /// Note whether the server can be reached, from now on
+    pub fn set_connected(&mut self, connected: bool) {
+        match (connected, self.connected_since) {
+            (true, None) => self.connected_since = Some(Instant::now()),
+            (false, Some(since)) => {
+                self.metrics.connected += since.elapsed();
+                self.connected_since = None;
+            },
+            _ => {},
+        }
+    }
+
+    /// The metrics until now, with the bytes that the transport counted
+    pub fn metrics(&self, bytes_sent: u64, bytes_received: u64) -> Metrics {
+        let mut metrics = self.metrics;
+        metrics.bytes_sent = bytes_sent;
+        metrics.bytes_received = bytes_received;
+        if let Some(since) = self.connected_since {
+            metrics.connected += since.elapsed();
+        }
+        metrics
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn recorder() {
+        use std::thread;
+        use std::time::Duration;
+        use super::Recorder;
+
+        let mut recorder = Recorder::default();
+        recorder.packet_sent();
+        recorder.packet_sent();
+        recorder.packet_received();
+        recorder.set_connected(true);
+        thread::sleep(Duration::from_millis(20));
+        recorder.set_connected(false);
+        recorder.reconnected();
+        let metrics = recorder.metrics(10, 20);
+        assert_eq!((metrics.packets_sent, metrics.packets_received), (2, 1));
+        assert_eq!((metrics.bytes_sent, metrics.bytes_received), (10, 20));
+        assert_eq!(metrics.reconnects, 1);
+        let connected = metrics.connected;
+        assert!(connected >= Duration::from_millis(20));
+        // the time that we were not connected does not count
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(recorder.metrics(10, 20).connected, connected);
+    }
+}
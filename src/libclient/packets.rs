@@ -0,0 +1,98 @@
+//! The packets that were exchanged with the server most recently, for debugging.
+//!
+//! Front-ends can show them, e.g. to find out what a new version of the server sends. Polls
+//! that carry no messages are left out: there is one every time the server has nothing to say.
+
+use std::collections::VecDeque;
+
+use rustc_serialize::json::Json;
+use time::{Timespec, get_time};
+
+/// How many packets are kept
+const PACKET_LOG_SIZE: usize = 200;
+
+/// Whether a packet was sent or received
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// A packet, as it was sent or received
+#[derive(Clone, Debug)]
+pub struct Packet {
+    pub direction: Direction,
+    pub time: Timespec,
+    pub json: Json,
+}
+
+/// The last `PACKET_LOG_SIZE` packets, the oldest first
+#[derive(Debug, Default)]
+pub struct PacketLog {
+    packets: VecDeque<Packet>,
+}
+
+impl PacketLog {
+    /// Add `json` to the log, unless it carries no messages
+    pub fn push(&mut self, direction: Direction, json: &Json) {
+        if !has_messages(direction, json) {
+            return;
+        }
+        if self.packets.len() == PACKET_LOG_SIZE {
+            self.packets.pop_front();
+        }
+        self.packets.push_back(Packet {
+            direction: direction,
+            time: get_time(),
+            json: json.clone(),
+        });
+    }
+
+    pub fn packets(&self) -> Vec<Packet> {
+        self.packets.iter().cloned().collect()
+    }
+}
+
+/// Does `json` carry any messages?
+///
+/// A sent packet is an array of the session id (except for the first one) and the messages, a
+/// received packet is an array of the session id and an array of the messages.
+pub fn has_messages(direction: Direction, json: &Json) -> bool {
+    let array = match json.as_array() {
+        Some(array) => array,
+        None => return true, // malformed, which is interesting
+    };
+    match direction {
+        Direction::Sent => array.iter().any(|x| !x.is_string()),
+        Direction::Received => {
+            array.get(1).and_then(|x| x.as_array()).map_or(true, |x| !x.is_empty())
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rustc_serialize::json::Json;
+
+    use super::*;
+
+    #[test]
+    fn push() {
+        let mut log = PacketLog::default();
+        log.push(Direction::Sent, &Json::from_str(r#"["s1"]"#).unwrap());
+        log.push(Direction::Received, &Json::from_str(r#"["s1", []]"#).unwrap());
+        assert!(log.packets().is_empty());
+
+        log.push(Direction::Sent, &Json::from_str(r#"["s1", {"type": "follow"}]"#).unwrap());
+        log.push(Direction::Received,
+                 &Json::from_str(r#"["s1", [{"type": "welcome"}]]"#).unwrap());
+        for _ in 0..PACKET_LOG_SIZE {
+            log.push(Direction::Received, &Json::from_str("null").unwrap());
+        }
+        let packets = log.packets();
+        assert_eq!(packets.len(), PACKET_LOG_SIZE);
+        // the oldest packets made room for the newest
+        assert_eq!(packets[0].json, Json::Null);
+    }
+}
@@ -0,0 +1,204 @@
+//! Search queries that look for words in one field, e.g. `artist:radiohead title:creep`.
+//!
+//! The server looks for every word of a query in all tags of a song. A word can be scoped to a
+//! field by putting the name of the field and a colon in front of it, or in front of a quoted
+//! phrase, e.g. `title:"no one knows"`. `parse` splits a query into the words for any field and
+//! the terms for one field; the client asks the server for all of them (see `server_query`) and
+//! leaves out the results in which a term is in another field (see `matches`).
+//!
+//! The server may not search the album and the uploader, so those terms are not sent to it, and
+//! are only used to leave results out. A query with only those, e.g. `uploader:bob`, goes through
+//! all songs, so the client gives up after leaving out `MAX_LEFT_OUT_RESULTS` of them (see
+//! `Client::is_query_truncated`). Words with a colon that are not after a field name are
+//! searched for as they are.
+
+use media::Media;
+use text;
+
+/// A field of a song that a term can be scoped to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    Artist,
+    Title,
+    Album,
+    Uploader,
+}
+
+impl Field {
+    /// The field that is called `name` in a query
+    pub fn from_name(name: &str) -> Option<Field> {
+        match &text::fold(name)[..] {
+            "artist" => Some(Field::Artist),
+            "title" => Some(Field::Title),
+            "album" => Some(Field::Album),
+            "uploader" => Some(Field::Uploader),
+            _ => None,
+        }
+    }
+
+    /// The value of this field of `media`
+    pub fn get<'a>(&self, media: &'a Media) -> &'a str {
+        match *self {
+            Field::Artist => &media.artist,
+            Field::Title => &media.title,
+            Field::Album => media.album.as_ref().map_or("", |x| x.as_str()),
+            Field::Uploader => &media.uploaded_by,
+        }
+    }
+
+    /// Does the server search this field?
+    fn is_searched(&self) -> bool {
+        match *self {
+            Field::Artist | Field::Title => true,
+            Field::Album | Field::Uploader => false,
+        }
+    }
+}
+
+/// A parsed search query, see `parse`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Query {
+    /// The words that may be in any field
+    pub words: Vec<String>,
+    /// The words or phrases that have to be in a field
+    pub terms: Vec<(Field, String)>,
+}
+
+impl Query {
+    /// What to ask the server for: the words, and the terms of the fields that it searches
+    pub fn server_query(&self) -> String {
+        let terms = self.terms.iter().filter(|x| x.0.is_searched()).map(|x| x.1.as_str());
+        self.words.iter().map(|x| x.as_str()).chain(terms).collect::<Vec<_>>().join(" ")
+    }
+
+    /// Are some words scoped to a field? Otherwise all results of the server match.
+    pub fn is_scoped(&self) -> bool {
+        !self.terms.is_empty()
+    }
+
+    /// Is every term in its field of `media`? The words are left to the server.
+    pub fn matches(&self, media: &Media) -> bool {
+        self.terms.iter().all(|&(field, ref term)| text::matches(field.get(media), term))
+    }
+}
+
+/// Split `query` into the words for any field and the terms for one field
+///
+/// A field name with nothing after it (e.g. while `artist:` is being typed) is left out.
+pub fn parse(query: &str) -> Query {
+    let mut parsed = Query::default();
+    let mut chars = query.chars().peekable();
+    loop {
+        while chars.peek().map_or(false, |x| x.is_whitespace()) {
+            chars.next();
+        }
+        if chars.peek().is_none() {
+            return parsed;
+        }
+        // a token ends at whitespace that is not between quotes
+        let mut token = String::new();
+        let mut quoted = false;
+        while let Some(&ch) = chars.peek() {
+            match ch {
+                '"' => quoted = !quoted,
+                _ if ch.is_whitespace() && !quoted => break,
+                _ => token.push(ch),
+            }
+            chars.next();
+        }
+        let field = token.find(':').and_then(|i| Field::from_name(&token[..i]).map(|x| (x, i)));
+        match field {
+            Some((field, i)) => {
+                let term = token[i + 1..].trim();
+                if !term.is_empty() {
+                    parsed.terms.push((field, term.to_string()));
+                }
+            },
+            None => parsed.words.push(token),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn parse() {
+        use time::Duration;
+        use media::Media;
+        use super::{parse, Field, Query};
+
+        let query = parse(" artist:radiohead  title:\"no surprises\" live Album:ok url:http://x");
+        assert_eq!(query, Query {
+            words: vec!["live".to_string(), "url:http://x".to_string()],
+            terms: vec![(Field::Artist, "radiohead".to_string()),
+                        (Field::Title, "no surprises".to_string()),
+                        (Field::Album, "ok".to_string())],
+        });
+        assert_eq!(query.server_query(), "live url:http://x radiohead no surprises");
+        assert_eq!(parse("uploader:bob").server_query(), "");
+        assert_eq!(parse("creep artist:"), Query { words: vec!["creep".to_string()],
+                                                   terms: vec![] });
+        assert!(!parse("creep").is_scoped());
+
+        let media = |artist: &str, title: &str| Media {
+            key: "m1".to_string(),
+            artist: artist.to_string(),
+            title: title.to_string(),
+            length: Duration::seconds(200),
+            uploaded_by: "bob".to_string(),
+            album: Some("OK Computer".to_string()),
+            url: None,
+        };
+        let query = parse("artist:radiohead title:\"No Surprises\" album:computer uploader:bob");
+        assert!(query.matches(&media("Radiohead", "No Surprises")));
+        assert!(!query.matches(&media("No Surprises", "Radiohead")));
+        assert!(parse("creep").matches(&media("Tool", "Schism")));
+    }
+
+    #[test]
+    fn scoped_search() {
+        use std::{env, fs, process};
+        use time::Duration;
+        use cache::MediaCache;
+        use library::Library;
+        use media::Media;
+        use {Client, Message};
+
+        let media = |key: &str, artist: &str, title: &str, uploaded_by: &str| Media {
+            key: key.to_string(),
+            artist: artist.to_string(),
+            title: title.to_string(),
+            length: Duration::seconds(200),
+            uploaded_by: uploaded_by.to_string(),
+            album: None,
+            url: None,
+        };
+        let dir = env::temp_dir().join(format!("maruska-query-test-{}", process::id()));
+        let mut cache = MediaCache::open(&dir.join("media.idx")).unwrap();
+        cache.append(&[media("m1", "Tool", "Schism", "bob"),
+                       media("m2", "Bob Tool", "Sober", "alice"),
+                       media("m3", "Tool", "Sober", "alice"),
+                       media("m4", "Tool", "Lateralus", "alice")]).unwrap();
+        let (mut client, client_r, _) = Client::offline("test");
+        client.set_library(Some(Library::from_cache(&cache)));
+        let mut search = |client: &mut Client, query: &str, count: usize| -> Vec<String> {
+            client.update_query(Some(query), count);
+            while !client.get_qm_results().1 && client.get_qm_results().0.len() < count {
+                match client.handle_message(&client_r.recv().unwrap()) {
+                    Ok(Message::QueryMediaResults) => {},
+                    msg => panic!("unexpected {:?}", msg),
+                }
+            }
+            client.get_qm_results().0.iter().map(|x| x.key.clone()).collect()
+        };
+
+        assert_eq!(search(&mut client, "artist:bob", 10), ["m2"]);
+        assert_eq!(search(&mut client, "tool title:sober", 10), ["m2", "m3"]);
+        // the results that are left out (m1) still count when more are asked for
+        assert_eq!(search(&mut client, "uploader:alice", 1), ["m2"]);
+        assert_eq!(search(&mut client, "uploader:alice", 3), ["m2", "m4", "m3"]);
+        drop(cache);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,239 @@
+//! Comparing two versions of the request queue, to tell what changed in between.
+//!
+//! The server sends the whole queue after every change, so a front-end that wants to show what
+//! happened (a request that was added, removed or moved) has to find that out itself. A
+//! front-end can also show the queue as it will be after moves that it did not send yet, and
+//! how the queue is shared between the people that requested its songs.
+
+use std::cmp::{max, min};
+use std::collections::{BTreeMap, BTreeSet};
+
+use time::Duration;
+
+use media::Request;
+
+/// How a request that is still in the queue changed
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Change {
+    Added,
+    MovedUp,
+    MovedDown,
+}
+
+/// The requests of one requester in the queue, see `by_requester`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Share {
+    /// Who requested the songs, `None` for the songs that the server queued itself
+    pub by: Option<String>,
+    /// The number of songs
+    pub songs: usize,
+    /// How long the songs take together
+    pub length: Duration,
+    /// The position of the first of the songs in the queue
+    pub first: usize,
+}
+
+/// The differences between two versions of the queue
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct QueueDiff {
+    /// The requests in the new queue that changed, by request key
+    pub changed: BTreeMap<i64, Change>,
+    /// The requests that are not in the new queue, in the order of the old queue
+    pub removed: Vec<Request>,
+}
+
+impl QueueDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Find out how `new` differs from `old`
+///
+/// Of the requests that are in both queues, the fewest possible are counted as moved: the ones
+/// that are not in the longest run of requests that kept their order. So if one request is
+/// boosted, only that one moved up, and not all requests that it passed moved down.
+pub fn diff(old: &[Request], new: &[Request]) -> QueueDiff {
+    let old_keys: BTreeSet<i64> = old.iter().map(|x| x.key).collect();
+    let new_keys: BTreeSet<i64> = new.iter().map(|x| x.key).collect();
+    let mut diff = QueueDiff::default();
+    diff.removed = old.iter().filter(|x| !new_keys.contains(&x.key)).cloned().collect();
+    for request in new.iter().filter(|x| !old_keys.contains(&x.key)) {
+        diff.changed.insert(request.key, Change::Added);
+    }
+
+    let old_kept: Vec<i64> = old.iter().map(|x| x.key).filter(|x| new_keys.contains(x)).collect();
+    let new_kept: Vec<i64> = new.iter().map(|x| x.key).filter(|x| old_keys.contains(x)).collect();
+    let stayed = longest_common_subsequence(&old_kept, &new_kept);
+    for (new_pos, key) in new_kept.iter().enumerate() {
+        if stayed.contains(key) {
+            continue;
+        }
+        let old_pos = old_kept.iter().position(|x| x == key).unwrap();
+        let change = if new_pos < old_pos { Change::MovedUp } else { Change::MovedDown };
+        diff.changed.insert(*key, change);
+    }
+    diff
+}
+
+/// The queue after the requests were moved by `moves`, which are `(request key, amount)` pairs
+///
+/// The moves are done in order, like the server does `Client::do_move_request`: a request ends up
+/// `amount` places further back, or towards the front if it is negative, but not past either end.
+/// Moves of requests that are not in the queue are skipped.
+pub fn apply_moves(requests: &[Request], moves: &[(i64, i64)]) -> Vec<Request> {
+    let mut requests = requests.to_vec();
+    for &(key, amount) in moves {
+        if let Some(i) = requests.iter().position(|x| x.key == key) {
+            let request = requests.remove(i);
+            let to = min(max(i as i64 + amount, 0) as usize, requests.len());
+            requests.insert(to, request);
+        }
+    }
+    requests
+}
+
+/// How much of `requests` every requester has, the one whose songs take longest first
+///
+/// Requesters whose songs take as long come in the order of their first request.
+pub fn by_requester(requests: &[Request]) -> Vec<Share> {
+    let mut shares: Vec<Share> = Vec::new();
+    for (i, request) in requests.iter().enumerate() {
+        match shares.iter().position(|x| x.by == request.by) {
+            Some(j) => {
+                shares[j].songs += 1;
+                shares[j].length = shares[j].length + request.media.length;
+            },
+            None => shares.push(Share {
+                by: request.by.clone(),
+                songs: 1,
+                length: request.media.length,
+                first: i,
+            }),
+        }
+    }
+    shares.sort_by(|a, b| b.length.cmp(&a.length).then_with(|| a.first.cmp(&b.first)));
+    shares
+}
+
+/// Add moving the request with key `key` by `amount` places to `moves`
+///
+/// Moving the request that was moved last is merged with that move, so that moving a request
+/// several places is sent as one move.
+pub fn add_move(moves: &mut Vec<(i64, i64)>, key: i64, amount: i64) {
+    match moves.last_mut() {
+        Some(last) if last.0 == key => last.1 += amount,
+        _ => moves.push((key, amount)),
+    }
+    if moves.last().map_or(false, |x| x.1 == 0) {
+        moves.pop();
+    }
+}
+
+/// The keys in the longest subsequence that `a` and `b` have in common
+fn longest_common_subsequence(a: &[i64], b: &[i64]) -> BTreeSet<i64> {
+    // lengths[i][j] is the length of the longest common subsequence of a[i..] and b[j..]
+    let mut lengths = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lengths[i][j] = if a[i] == b[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                max(lengths[i + 1][j], lengths[i][j + 1])
+            };
+        }
+    }
+    let mut keys = BTreeSet::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            keys.insert(a[i]);
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    keys
+}
+
+
+#[cfg(test)]
+mod tests {
+    use time::Duration;
+
+    use media::{Media, Request};
+    use super::*;
+
+    fn queue(keys: &[i64]) -> Vec<Request> {
+        keys.iter().map(|&key| Request {
+            by: Some("dsprenkels".to_string()),
+            key: key,
+            media: Media {
+                key: format!("m{}", key),
+                artist: "Queens Of The Stone Age".to_string(),
+                title: "No One Knows".to_string(),
+                length: Duration::seconds(279),
+                uploaded_by: "dsprenkels".to_string(),
+                album: None,
+                url: None,
+            },
+        }).collect()
+    }
+
+    #[test]
+    fn diff() {
+        assert!(super::diff(&queue(&[1, 2, 3]), &queue(&[1, 2, 3])).is_empty());
+
+        // 4 was boosted to the front, 1 started playing and 5 was added
+        let d = super::diff(&queue(&[1, 2, 3, 4]), &queue(&[4, 2, 3, 5]));
+        assert_eq!(d.changed.get(&4), Some(&Change::MovedUp));
+        assert_eq!(d.changed.get(&5), Some(&Change::Added));
+        assert_eq!(d.changed.len(), 2);
+        let removed: Vec<i64> = d.removed.iter().map(|x| x.key).collect();
+        assert_eq!(removed, [1]);
+
+        // 1 was moved down two places
+        let d = super::diff(&queue(&[1, 2, 3]), &queue(&[2, 3, 1]));
+        assert_eq!(d.changed.get(&1), Some(&Change::MovedDown));
+        assert_eq!(d.changed.len(), 1);
+        assert!(d.removed.is_empty());
+    }
+
+    #[test]
+    fn apply_moves() {
+        let keys = |requests: Vec<Request>| -> Vec<i64> {
+            requests.iter().map(|x| x.key).collect()
+        };
+        let mut moves = Vec::new();
+        add_move(&mut moves, 3, -1);
+        add_move(&mut moves, 3, -1);
+        add_move(&mut moves, 1, 1);
+        assert_eq!(moves, [(3, -2), (1, 1)]);
+        assert_eq!(keys(super::apply_moves(&queue(&[1, 2, 3, 4]), &moves)), [3, 2, 1, 4]);
+        add_move(&mut moves, 1, -1);
+        assert_eq!(moves, [(3, -2)]);
+
+        // not past the ends, and 5 is not in the queue
+        let moves = [(1, -1), (4, 3), (5, 1)];
+        assert_eq!(keys(super::apply_moves(&queue(&[1, 2, 3, 4]), &moves)), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn by_requester() {
+        let mut requests = queue(&[1, 2, 3, 4, 5]);
+        requests[0].by = Some("bkoks".to_string());
+        requests[2].by = None;
+        requests[4].media.length = Duration::seconds(600);
+        let shares = super::by_requester(&requests);
+        let by: Vec<Option<&str>> = shares.iter().map(|x| x.by.as_ref().map(|x| &x[..])).collect();
+        assert_eq!(by, [Some("dsprenkels"), Some("bkoks"), None]);
+        assert_eq!(shares[0].songs, 3);
+        assert_eq!(shares[0].length, Duration::seconds(279 + 279 + 600));
+        assert_eq!(shares[0].first, 1);
+        assert_eq!((shares[1].songs, shares[1].first), (1, 0));
+        assert!(super::by_requester(&[]).is_empty());
+    }
+}
@@ -0,0 +1,102 @@
+//! Scoring songs against a query, to put the search results that match it best first.
+//!
+//! The server returns the results of a query in no particular order. `rank` sorts them by their
+//! `relevance`, in which typos and words that are still being typed count as close. `similarity`
+//! compares a query with the whole artist and title instead, to find the one song that it names.
+//! Case, accents and punctuation are ignored by both.
+
+use std::cmp::{max, Ordering};
+
+use strsim::{jaro_winkler, levenshtein};
+
+use media::Media;
+use text::fold;
+
+/// How similar `query` is to the artist and title of `media`, from 0 to 1
+///
+/// A query can name the title or both the artist and the title.
+pub fn similarity(query: &str, media: &Media) -> f64 {
+    let query = words(query);
+    let title = words(&media.title);
+    let artist_title = format!("{} {}", words(&media.artist), title);
+    let similar = |s: &str| {
+        let len = max(query.chars().count(), s.chars().count());
+        if len == 0 { 0.0 } else { 1.0 - levenshtein(&query, s) as f64 / len as f64 }
+    };
+    similar(&artist_title).max(similar(&title))
+}
+
+/// How well `query` matches the tags of `media`, from 0 to 1
+///
+/// Every word of the query is scored by the word of the artist, title or album that is most like
+/// it, where the start of a word scores high. Their average is averaged with the `similarity`,
+/// so that of the songs that have all words, the one that the query names comes first.
+pub fn relevance(query: &str, media: &Media) -> f64 {
+    let query_words = words(query);
+    if query_words.is_empty() {
+        return 0.0;
+    }
+    let album = media.album.as_ref().map_or("", |x| x.as_str());
+    let tags = words(&format!("{} {} {}", media.artist, media.title, album));
+    let tag_words: Vec<&str> = tags.split(' ').filter(|x| !x.is_empty()).collect();
+    let scores: Vec<f64> = query_words.split(' ').map(|word| {
+        tag_words.iter().map(|x| jaro_winkler(word, x)).fold(0.0, f64::max)
+    }).collect();
+    let words_score = scores.iter().sum::<f64>() / scores.len() as f64;
+    (words_score + similarity(query, media)) / 2.0
+}
+
+/// `results`, the most relevant to `query` first
+///
+/// The sort is stable, so results that are as relevant stay in the order of the server.
+pub fn rank<'a>(query: &str, results: &'a [Media]) -> Vec<&'a Media> {
+    let mut scored: Vec<(f64, &Media)> = results.iter().map(|x| (relevance(query, x), x)).collect();
+    scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+    scored.into_iter().map(|x| x.1).collect()
+}
+
+/// The folded words of `s`, separated by single spaces
+fn words(s: &str) -> String {
+    let folded = fold(s);
+    let words: Vec<&str> = folded.split(|x: char| !x.is_alphanumeric())
+                                 .filter(|x| !x.is_empty())
+                                 .collect();
+    words.join(" ")
+}
+
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn rank() {
+        use time::Duration;
+        use media::Media;
+        use super::{rank, relevance, similarity};
+
+        let media = |artist: &str, title: &str| Media {
+            key: format!("{} - {}", artist, title),
+            artist: artist.to_string(),
+            title: title.to_string(),
+            length: Duration::seconds(200),
+            uploaded_by: "bob".to_string(),
+            album: None,
+            url: None,
+        };
+        let results = vec![media("Stone Temple Pilots", "Creep"),
+                           media("Queens Of The Stone Age", "Go with the Flow"),
+                           media("Queens Of The Stone Age", "No One Knows"),
+                           media("Radiohead", "Creep")];
+        let keys = |query: &str| -> Vec<String> {
+            rank(query, &results).into_iter().map(|x| x.key.clone()).collect()
+        };
+
+        assert_eq!(similarity("Queens of the Stone Age - No One Knows", &results[2]), 1.0);
+        assert_eq!(relevance("", &results[0]), 0.0);
+        assert_eq!(keys("no one knows")[0], "Queens Of The Stone Age - No One Knows");
+        // a word that is still being typed, and a typo
+        assert_eq!(keys("radiohead cre")[0], "Radiohead - Creep");
+        assert_eq!(keys("qeens flow")[0], "Queens Of The Stone Age - Go with the Flow");
+        // results that are as relevant stay in the order of the server
+        assert_eq!(keys("creep")[..2], ["Stone Temple Pilots - Creep", "Radiohead - Creep"]);
+    }
+}
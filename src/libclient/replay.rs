@@ -0,0 +1,94 @@
+//! Replaying what a server sent, from a trace that was logged with `--trace-protocol`.
+//!
+//! A trace is a log in which every packet that was received has a line with `received packet: `
+//! and the packet. The other lines are ignored, so a whole log can be replayed. The log has no
+//! times, so the packets are replayed one by one with a pause in between.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error as IOError, ErrorKind};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use rustc_serialize::json::Json;
+
+use Offline;
+use packets::{Direction, has_messages};
+
+/// What the lines with a received packet say before the packet
+const RECEIVED_PACKET: &'static str = "received packet: ";
+
+/// Read the packets that were received in the trace at `path`
+///
+/// Packets that carry no messages (polls in which nothing happened) are left out.
+pub fn read_trace(path: &Path) -> Result<Vec<Json>, IOError> {
+    let file = try!(File::open(path));
+    let mut packets = Vec::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = try!(line);
+        let start = match line.find(RECEIVED_PACKET) {
+            Some(idx) => idx + RECEIVED_PACKET.len(),
+            None => continue,
+        };
+        let packet = try!(Json::from_str(&line[start..]).map_err(|err| {
+            IOError::new(ErrorKind::InvalidData,
+                         format!("invalid packet on line {} of the trace: {}", i + 1, err))
+        }));
+        if has_messages(Direction::Received, &packet) {
+            packets.push(packet);
+        }
+    }
+    Ok(packets)
+}
+
+/// Replay `packets` to the client of `offline`, with `pause` in between
+///
+/// What the client sends is ignored.
+pub fn replay(packets: Vec<Json>, offline: Offline, pause: Duration) {
+    let Offline { packet_s, message_r } = offline;
+    thread::spawn(move || {
+        for packet in packets {
+            packet_s.send(packet);
+            thread::sleep(pause);
+        }
+        info!("replayed the whole trace");
+    });
+    thread::spawn(move || {
+        for message in message_r {
+            debug!("not replaying an answer to {}", message);
+        }
+    });
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::process;
+    use rustc_serialize::json::Json;
+
+    use super::*;
+
+    #[test]
+    fn read_trace() {
+        let dir = env::temp_dir().join(format!("maruska-replay-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("maruska.log");
+        File::create(&path).unwrap().write_all(br#"INFO:libclient::comet: Connecting to http://localhost/api
+TRACE:libclient::comet: received packet: ["s1",[{"type":"welcome"}]]
+TRACE:libclient::comet: sending packet: ["s1",{"type":"follow","which":["playing"]}]
+TRACE:libclient::comet: received packet: ["s1",[]]
+TRACE:libclient::comet: received packet: ["s1",[{"type":"requests","requests":[]}]]
+"#).unwrap();
+        let packets = super::read_trace(&path).unwrap();
+        assert_eq!(packets.len(), 2);
+        let requests = r#"["s1",[{"type":"requests","requests":[]}]]"#;
+        assert_eq!(packets[1], Json::from_str(requests).unwrap());
+
+        File::create(&path).unwrap().write_all(b"TRACE: received packet: [\"s1\",\n").unwrap();
+        assert_eq!(super::read_trace(&path).unwrap_err().kind(), ErrorKind::InvalidData);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,63 @@
+//! Reporting songs in the media index that are wrong, e.g. because of wrong tags or a corrupt file.
+//!
+//! Servers that support reports (the `"report"` capability) get them with
+//! `Client::do_report`. Otherwise, `append_report` keeps them in a local file, one report per
+//! line, so that they can be passed on to the people who run the server.
+
+use std::fs::{self, OpenOptions};
+use std::io::{Error as IOError, Write};
+use std::path::Path;
+
+use fs2::FileExt;
+use time::{at_utc, Timespec};
+
+/// Append a report about the song with key `media_key` on the server at `url` to the file at
+/// `path`
+///
+/// Every line holds the time of the report (in UTC), the server, the media key, a description of
+/// the song and the reason, separated by tabs.
+pub fn append_report(path: &Path, time: Timespec, url: &str, media_key: &str, description: &str,
+                     reason: &str) -> Result<(), IOError> {
+    if let Some(dir) = path.parent() {
+        try!(fs::create_dir_all(dir));
+    }
+    let mut file = try!(OpenOptions::new().append(true).create(true).open(path));
+    // the fields of a report may not span more than one line or field
+    let clean = |x: &str| x.replace(|c| c == '\t' || c == '\n' || c == '\r', " ");
+    // the format is valid, so this does not fail
+    let line = format!("{}\t{}\t{}\t{}\t{}\n", at_utc(time).strftime("%Y-%m-%dT%H:%M:%SZ").unwrap(),
+                       clean(url), clean(media_key), clean(description), clean(reason));
+    // another maruska could be reporting at the same time
+    try!(FileExt::lock_exclusive(&file));
+    let result = file.write_all(line.as_bytes());
+    try!(FileExt::unlock(&file));
+    result
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Read;
+    use std::process;
+    use time::Timespec;
+
+    #[test]
+    fn append_report() {
+        let dir = env::temp_dir().join(format!("maruska-report-test-{}", process::id()));
+        let path = dir.join("reports.tsv");
+        let time = Timespec::new(1459420207, 0);
+        super::append_report(&path, time, "http://localhost/api", "m1", "Tool - Schism",
+                             "wrong tags").unwrap();
+        super::append_report(&path, time, "http://localhost/api", "m2", "", "corrupt\tfile\n")
+            .unwrap();
+
+        let mut contents = String::new();
+        File::open(&path).unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "2016-03-31T10:30:07Z\thttp://localhost/api\tm1\tTool - Schism\t\
+                              wrong tags\n\
+                              2016-03-31T10:30:07Z\thttp://localhost/api\tm2\t\tcorrupt file \n");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
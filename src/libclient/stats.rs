@@ -0,0 +1,310 @@
+//! How often songs were requested and played, as far as we have seen.
+//!
+//! The server does not keep count, so the counts are made by watching the queue and the song
+//! that is playing while a client is connected, and kept in a local file (one for every server,
+//! because media keys are only unique on one server). Songs that were requested and played while
+//! no client was connected are not counted.
+//!
+//! The file holds one line per song, with its media key, the counts, its artist and title and
+//! when it was last played (if we saw that), separated by tabs. To count every request and every
+//! play once, also if the queue is seen again after a restart, the file also holds a line with the
+//! keys of the requests in the queue and a line with the song that is playing.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::collections::btree_map::Iter;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error as IOError, ErrorKind, Read, Write};
+use std::path::Path;
+
+use fs2::FileExt;
+
+use cache::with_suffix;
+use media::{Playing, Request};
+
+/// A song that starts playing again this many seconds from when it ended is played again; a
+/// smaller difference is the clock of the server drifting
+const REPLAY_MIN_SECS: i64 = 60;
+
+/// How often a song was requested and played
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Counts {
+    pub artist: String,
+    pub title: String,
+    /// By anyone
+    pub requested: u64,
+    /// By the user that we were logged in as
+    pub requested_by_you: u64,
+    pub played: u64,
+    /// When it ended the last time that it was played, in seconds since the epoch
+    pub last_played: Option<i64>,
+}
+
+/// The counts of all songs that we saw
+#[derive(Clone, Debug, Default)]
+pub struct Stats {
+    /// By media key
+    counts: BTreeMap<String, Counts>,
+    /// The keys of the requests in the queue, which were counted already
+    queued: BTreeSet<i64>,
+    /// The media key of the song that is playing and when it ends, which was counted already
+    playing: Option<(String, i64)>,
+}
+
+impl Stats {
+    /// The name of the stats file for the server at `url`, so that every server has its own
+    pub fn file_name(url: &str) -> String {
+        let name: String = url.split("://").last().unwrap_or(url).chars().map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '.' { c } else { '_' }
+        }).collect();
+        format!("stats-{}.tsv", name.trim_matches('_'))
+    }
+
+    /// Read the stats from the file at `path`, there are none yet if it does not exist
+    pub fn load(path: &Path) -> Result<Stats, IOError> {
+        let mut s = String::new();
+        match File::open(path).and_then(|mut f| f.read_to_string(&mut s)) {
+            Err(ref err) if err.kind() == ErrorKind::NotFound => return Ok(Stats::default()),
+            res => { try!(res); },
+        }
+        Stats::parse(&s).map_err(|msg| IOError::new(ErrorKind::InvalidData, msg))
+    }
+
+    /// Change the stats in the file at `path` using `f`, and return them
+    ///
+    /// `f` returns whether it changed anything, the file is only written if it did. An exclusive
+    /// lock is held the whole time, so that clients that are connected to the same server at
+    /// the same time count every request once.
+    pub fn update<F>(path: &Path, f: F) -> Result<Stats, IOError>
+        where F: FnOnce(&mut Stats) -> bool {
+        if let Some(dir) = path.parent() {
+            try!(fs::create_dir_all(dir));
+        }
+        let lock = try!(OpenOptions::new().write(true).create(true)
+                                          .open(with_suffix(path, ".lock")));
+        try!(FileExt::lock_exclusive(&lock));
+        let mut stats = try!(Stats::load(path));
+        if f(&mut stats) {
+            let tmp_path = with_suffix(path, ".tmp");
+            try!(File::create(&tmp_path).and_then(|mut f| f.write_all(stats.format().as_bytes())));
+            try!(fs::rename(&tmp_path, path));
+        }
+        Ok(stats)
+    }
+
+    /// Count the requests in `requests` that we did not see before
+    ///
+    /// Requests by `username` are also counted as yours. Returns whether anything was counted.
+    pub fn observe_requests(&mut self, requests: &[Request], username: Option<&str>) -> bool {
+        let queued: BTreeSet<i64> = requests.iter().map(|x| x.key).collect();
+        let mut changed = queued != self.queued;
+        let new: Vec<&Request> = requests.iter().filter(|x| !self.queued.contains(&x.key)).collect();
+        for request in new {
+            let counts = self.song(&request.media.key, &request.media.artist,
+                                   &request.media.title);
+            counts.requested += 1;
+            if username.is_some() && request.by.as_ref().map(|x| &x[..]) == username {
+                counts.requested_by_you += 1;
+            }
+            changed = true;
+        }
+        self.queued = queued;
+        changed
+    }
+
+    /// Count `playing` as played, unless we saw it playing already
+    ///
+    /// Returns whether it was counted.
+    pub fn observe_playing(&mut self, playing: &Playing) -> bool {
+        let media = &playing.media;
+        let end = playing.end_time.sec;
+        match self.playing {
+            Some((ref key, last_end)) if *key == media.key &&
+                                         (end - last_end).abs() < REPLAY_MIN_SECS => return false,
+            _ => {},
+        }
+        {
+            let counts = self.song(&media.key, &media.artist, &media.title);
+            counts.played += 1;
+            counts.last_played = Some(end);
+        }
+        self.playing = Some((media.key.clone(), end));
+        true
+    }
+
+    /// The counts of the song with media key `key`, if we saw it
+    pub fn get(&self, key: &str) -> Option<&Counts> {
+        self.counts.get(key)
+    }
+
+    /// The counts of all songs that we saw, by media key
+    pub fn songs<'a>(&'a self) -> Iter<'a, String, Counts> {
+        self.counts.iter()
+    }
+
+    /// How often the song with media key `key` was requested or played
+    ///
+    /// Most songs are played because they were requested, so requests that were played are
+    /// counted once.
+    pub fn popularity(&self, key: &str) -> u64 {
+        self.counts.get(key).map_or(0, |x| ::std::cmp::max(x.requested, x.played))
+    }
+
+    /// The `n` most popular songs, with their media keys
+    pub fn top(&self, n: usize) -> Vec<(&str, &Counts)> {
+        let mut top: Vec<(&str, &Counts)> = self.counts.iter().map(|(k, v)| (&k[..], v)).collect();
+        // the most popular first, and songs that are as popular by artist and title
+        top.sort_by(|a, b| {
+            self.popularity(b.0).cmp(&self.popularity(a.0))
+                .then_with(|| (&a.1.artist, &a.1.title).cmp(&(&b.1.artist, &b.1.title)))
+        });
+        top.truncate(n);
+        top
+    }
+
+    /// The counts of the song with media key `key`, which are created if we did not see it yet
+    ///
+    /// The artist and title are updated, because they can be edited.
+    fn song(&mut self, key: &str, artist: &str, title: &str) -> &mut Counts {
+        let counts = self.counts.entry(key.to_string()).or_insert_with(Counts::default);
+        counts.artist = artist.to_string();
+        counts.title = title.to_string();
+        counts
+    }
+
+    fn parse(s: &str) -> Result<Stats, String> {
+        let mut stats = Stats::default();
+        for (i, line) in s.lines().enumerate() {
+            let invalid = || format!("invalid line {} in the stats file", i + 1);
+            let mut fields = line.split('\t');
+            let mut next = || fields.next().ok_or_else(&invalid);
+            match try!(next()) {
+                "queue" => {
+                    while let Ok(key) = next() {
+                        stats.queued.insert(try!(key.parse().map_err(|_| invalid())));
+                    }
+                },
+                "playing" => {
+                    let key = try!(next()).to_string();
+                    let end = try!(try!(next()).parse().map_err(|_| invalid()));
+                    stats.playing = Some((key, end));
+                },
+                "song" => {
+                    let key = try!(next()).to_string();
+                    let requested = try!(try!(next()).parse().map_err(|_| invalid()));
+                    let requested_by_you = try!(try!(next()).parse().map_err(|_| invalid()));
+                    let played = try!(try!(next()).parse().map_err(|_| invalid()));
+                    let artist = try!(next()).to_string();
+                    let title = try!(next()).to_string();
+                    // older files do not have it
+                    let last_played = match next() {
+                        Ok(x) => Some(try!(x.parse().map_err(|_| invalid()))),
+                        Err(_) => None,
+                    };
+                    stats.counts.insert(key, Counts {
+                        artist: artist,
+                        title: title,
+                        requested: requested,
+                        requested_by_you: requested_by_you,
+                        played: played,
+                        last_played: last_played,
+                    });
+                },
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(stats)
+    }
+
+    fn format(&self) -> String {
+        // the fields may not span more than one line or field
+        let clean = |x: &str| x.replace(|c| c == '\t' || c == '\n' || c == '\r', " ");
+        let mut s = String::from("queue");
+        for key in &self.queued {
+            s.push_str(&format!("\t{}", key));
+        }
+        s.push('\n');
+        if let Some((ref key, end)) = self.playing {
+            s.push_str(&format!("playing\t{}\t{}\n", clean(key), end));
+        }
+        for (key, x) in &self.counts {
+            s.push_str(&format!("song\t{}\t{}\t{}\t{}\t{}\t{}", clean(key), x.requested,
+                                x.requested_by_you, x.played, clean(&x.artist), clean(&x.title)));
+            if let Some(time) = x.last_played {
+                s.push_str(&format!("\t{}", time));
+            }
+            s.push('\n');
+        }
+        s
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+    use time::{Duration, Timespec};
+
+    use media::{Media, Playing, Request};
+    use super::*;
+
+    fn request(key: i64, media_key: &str, by: &str) -> Request {
+        Request {
+            by: Some(by.to_string()),
+            key: key,
+            media: Media {
+                key: media_key.to_string(),
+                artist: "Queens Of The Stone Age".to_string(),
+                title: format!("Song {}", media_key),
+                length: Duration::seconds(231),
+                uploaded_by: "dsprenkels".to_string(),
+                album: None,
+                url: None,
+            },
+        }
+    }
+
+    #[test]
+    fn observe() {
+        let dir = env::temp_dir().join(format!("maruska-stats-test-{}", process::id()));
+        let path = dir.join("stats.tsv");
+        let queue = [request(1, "a", "bkoks"), request(2, "b", "dsprenkels")];
+        let you = Some("dsprenkels");
+
+        Stats::update(&path, |x| x.observe_requests(&queue, you)).unwrap();
+        // the same queue again, e.g. after a restart
+        Stats::update(&path, |x| x.observe_requests(&queue, you)).unwrap();
+        let queue = [request(2, "b", "dsprenkels"), request(3, "a", "dsprenkels")];
+        Stats::update(&path, |x| x.observe_requests(&queue, you)).unwrap();
+
+        let mut playing = Playing {
+            requested_by: None,
+            end_time: Timespec::new(1459420207, 0),
+            media: queue[1].media.clone(),
+            cover_url: None,
+        };
+        Stats::update(&path, |x| x.observe_playing(&playing)).unwrap();
+        playing.end_time = Timespec::new(1459420208, 0);
+        Stats::update(&path, |x| x.observe_playing(&playing)).unwrap();
+
+        let stats = Stats::load(&path).unwrap();
+        let a = stats.get("a").unwrap();
+        assert_eq!((a.requested, a.requested_by_you, a.played), (2, 1, 1));
+        assert_eq!(a.title, "Song a");
+        assert_eq!(a.last_played, Some(1459420207));
+        let b = stats.get("b").unwrap();
+        assert_eq!((b.requested, b.requested_by_you, b.played), (1, 1, 0));
+        assert_eq!(b.last_played, None);
+        assert_eq!(stats.popularity("c"), 0);
+        let top: Vec<&str> = stats.top(5).iter().map(|x| x.0).collect();
+        assert_eq!(top, ["a", "b"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn file_name() {
+        assert_eq!(Stats::file_name("http://marietje-noord.marie-curie.nl/api"),
+                   "stats-marietje-noord.marie-curie.nl_api.tsv");
+    }
+}
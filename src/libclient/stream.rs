@@ -0,0 +1,124 @@
+//! Getting the results of a query page by page, without keeping all of them.
+//!
+//! `Client::update_query` keeps every result of its query, which a front-end needs to scroll
+//! back through them. Broad queries have thousands of results though, e.g. the empty one that
+//! syncs the media index. A stream (see `Client::query_media_stream`) hands them out a page at
+//! a time instead: the next page is only asked for once the last one is taken with
+//! `Client::next_media_page`, so the client holds at most one page while another is on its way.
+//!
+//! When a page arrives, `Client::handle_message` returns a `Message::MediaPage` with the id of
+//! the stream, like it does for the other answers of the server. The results of a query with
+//! terms for one field (see the `query` module) that are not in that field are left out, so a
+//! page can have fewer results than the page size, or none.
+
+use media::Media;
+use query::{self, Query};
+
+/// Identifies a stream, it is the token of the queries of its pages
+pub type StreamId = usize;
+
+/// Where a stream is: what it asked for, and the page that was not taken yet
+#[derive(Clone, Debug)]
+pub struct MediaStream {
+    query: Query,
+    page_size: usize,
+    /// The number of results that were asked for so far, which the next page skips
+    asked: usize,
+    page: Option<Vec<Media>>,
+    /// Did a page arrive that was not full? Then the server has no more results.
+    done: bool,
+}
+
+impl MediaStream {
+    pub fn new(query: &str, page_size: usize) -> MediaStream {
+        MediaStream {
+            query: query::parse(query),
+            page_size: page_size,
+            asked: 0,
+            page: None,
+            done: false,
+        }
+    }
+
+    /// The query of the next page: what to ask the server for, how many results to skip and how
+    /// many to get
+    pub fn next_query(&mut self) -> (String, usize, usize) {
+        let skip = self.asked;
+        self.asked += self.page_size;
+        (self.query.server_query(), skip, self.page_size)
+    }
+
+    /// Keep the results of `page` that match the query until they are taken, it is the last page
+    /// if the server did not fill it
+    pub fn receive(&mut self, page: Vec<Media>) {
+        self.done = page.len() < self.page_size;
+        let query = &self.query;
+        self.page = Some(page.into_iter().filter(|x| query.matches(x)).collect());
+    }
+
+    pub fn take_page(&mut self) -> Option<Vec<Media>> {
+        self.page.take()
+    }
+
+    /// Did the last page arrive?
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn pages() {
+        use std::{env, fs, process};
+        use time::Duration;
+        use cache::MediaCache;
+        use library::Library;
+        use media::Media;
+        use {Client, Message};
+
+        let media = |key: &str| Media {
+            key: key.to_string(),
+            artist: "Tool".to_string(),
+            title: format!("Song {}", key),
+            length: Duration::seconds(200),
+            uploaded_by: "bob".to_string(),
+            album: None,
+            url: None,
+        };
+        let dir = env::temp_dir().join(format!("maruska-stream-test-{}", process::id()));
+        let mut cache = MediaCache::open(&dir.join("media.idx")).unwrap();
+        cache.append(&[media("m1"), media("m2"), media("m3")]).unwrap();
+        let (mut client, client_r, _) = Client::offline("test");
+        client.set_library(Some(Library::from_cache(&cache)));
+
+        // the next page is only asked for once the last one is taken
+        let stream = client.query_media_stream("tool", 2);
+        let mut pages = Vec::new();
+        while !client.is_media_stream_done(stream) {
+            match client.handle_message(&client_r.recv().unwrap()) {
+                Ok(Message::MediaPage(id)) if id == stream => {},
+                msg => panic!("unexpected {:?}", msg),
+            }
+            let page = client.next_media_page(stream).unwrap();
+            pages.push(page.into_iter().map(|x| x.key).collect::<Vec<_>>());
+            assert!(client.next_media_page(stream).is_none());
+        }
+        assert_eq!(pages, [vec!["m1", "m2"], vec!["m3"]]);
+
+        // a cancelled stream does not get in the way of the query of `update_query`
+        let stream = client.query_media_stream("tool", 2);
+        client.cancel_media_stream(stream);
+        client.update_query(Some("tool"), 10);
+        for _ in 0..2 {
+            match client.handle_message(&client_r.recv().unwrap()) {
+                Ok(Message::QueryMediaResults) => {},
+                msg => panic!("unexpected {:?}", msg),
+            }
+        }
+        assert_eq!(client.get_qm_results().0.len(), 3);
+        drop(cache);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
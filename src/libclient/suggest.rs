@@ -0,0 +1,141 @@
+//! Songs to request, ranked from what we saw being played and requested.
+//!
+//! Two kinds of songs are suggested: other songs by the artists of what was played lately, and
+//! the songs that you requested often but did not hear for a while. Both are found in the local
+//! [`Stats`](../stats/struct.Stats.html) and media index, so the server is not asked anything.
+//! Songs that were played lately are not suggested.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use cache::MediaCache;
+use media::Media;
+use stats::Stats;
+use text;
+
+/// Songs that ended at most this many seconds ago were played lately
+const RECENT_SECS: i64 = 6 * 60 * 60;
+
+/// Your requests are suggested again once they were not played for this many seconds
+const FORGOTTEN_SECS: i64 = 14 * 24 * 60 * 60;
+
+/// Why a song is suggested
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Reason {
+    /// Its artist had this many songs played lately
+    Artist(u64),
+    /// You requested it this many times, but did not hear it for a while
+    Favourite(u64),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Suggestion<'a> {
+    pub media: &'a Media,
+    pub reason: Reason,
+}
+
+/// The `n` songs in `cache` that fit best with what was played and requested, according to
+/// `stats`, the best first
+///
+/// `now` is the time in seconds since the epoch. The songs with media keys in `exclude`, e.g.
+/// the ones in the queue, are not suggested.
+pub fn suggest<'a>(stats: &Stats, cache: &'a MediaCache, exclude: &[&str], now: i64, n: usize)
+                   -> Vec<Suggestion<'a>> {
+    // was a song that was last played at `time` played in the last `secs` seconds?
+    let played_since = |time: Option<i64>, secs: i64| time.map_or(false, |x| x >= now - secs);
+    let mut artists: HashMap<String, u64> = HashMap::new();
+    for (_, counts) in stats.songs().filter(|x| played_since(x.1.last_played, RECENT_SECS)) {
+        *artists.entry(text::fold(&counts.artist)).or_insert(0) += 1;
+    }
+
+    let mut ranked: Vec<(u64, Suggestion)> = Vec::new();
+    for media in cache.media() {
+        if exclude.contains(&&media.key[..]) {
+            continue;
+        }
+        let (favourite, last_played) = match stats.get(&media.key) {
+            Some(counts) => (counts.requested_by_you, counts.last_played),
+            None => (0, None),
+        };
+        if played_since(last_played, RECENT_SECS) {
+            continue;
+        }
+        let favourite = if played_since(last_played, FORGOTTEN_SECS) { 0 } else { favourite };
+        let artist = artists.get(&text::fold(&media.artist)).cloned().unwrap_or(0);
+        // a song by an artist that is played now fits better than one that was requested once
+        let reason = match (2 * artist, favourite) {
+            (0, 0) => continue,
+            (artist_score, favourite) if artist_score >= favourite => Reason::Artist(artist),
+            (_, favourite) => Reason::Favourite(favourite),
+        };
+        ranked.push((2 * artist + favourite, Suggestion { media: media, reason: reason }));
+    }
+    ranked.sort_by(|a, b| match b.0.cmp(&a.0) {
+        Ordering::Equal => a.1.media.collate(b.1.media),
+        ordering => ordering,
+    });
+    ranked.into_iter().take(n).map(|x| x.1).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn suggest() {
+        use std::{env, fs, process};
+        use time::{Duration, Timespec};
+        use cache::MediaCache;
+        use media::{Media, Playing, Request};
+        use stats::Stats;
+        use super::{suggest, Reason};
+
+        let media = |key: &str, artist: &str| Media {
+            key: key.to_string(),
+            artist: artist.to_string(),
+            title: format!("Song {}", key),
+            length: Duration::seconds(200),
+            uploaded_by: "bob".to_string(),
+            album: None,
+            url: None,
+        };
+        let now = 1459420207;
+        let mut stats = Stats::default();
+        let play = |stats: &mut Stats, media: Media, ago: i64| {
+            stats.observe_playing(&Playing {
+                requested_by: None,
+                end_time: Timespec::new(now - ago, 0),
+                media: media,
+                cover_url: None,
+            });
+        };
+        let request = |key: i64, media: Media| Request {
+            by: Some("alice".to_string()),
+            key: key,
+            media: media,
+        };
+        // m1 was played just now, m5 was requested a lot but played a month ago, and m6 was
+        // requested last week
+        play(&mut stats, media("m1", "Tool"), 0);
+        play(&mut stats, media("m5", "Radiohead"), 30 * 24 * 60 * 60);
+        play(&mut stats, media("m6", "Radiohead"), 7 * 24 * 60 * 60);
+        let requests = [request(1, media("m5", "Radiohead")), request(2, media("m6", "Radiohead"))];
+        stats.observe_requests(&requests, Some("alice"));
+        stats.observe_requests(&requests[..0], Some("alice"));
+        stats.observe_requests(&requests[..1], Some("alice"));
+
+        let dir = env::temp_dir().join(format!("maruska-suggest-test-{}", process::id()));
+        let mut cache = MediaCache::open(&dir.join("media.idx")).unwrap();
+        cache.append(&[media("m1", "Tool"), media("m2", "TOOL"), media("m3", "Tool"),
+                       media("m4", "Björk"), media("m5", "Radiohead"), media("m6", "Radiohead")])
+             .unwrap();
+
+        let suggestions = suggest(&stats, &cache, &["m3"], now, 10);
+        let keys: Vec<&str> = suggestions.iter().map(|x| &x.media.key[..]).collect();
+        assert_eq!(keys, ["m5", "m2"]);
+        assert_eq!(suggestions[0].reason, Reason::Favourite(2));
+        assert_eq!(suggestions[1].reason, Reason::Artist(1));
+        assert_eq!(suggest(&stats, &cache, &[], now, 1).len(), 1);
+        drop(cache);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,161 @@
+//! Keeping a [`MediaCache`](../cache/struct.MediaCache.html) up to date with the server.
+//!
+//! The first sync downloads the whole media index. After that, only the media that were added or
+//! changed since the last sync are asked for, if the server supports that. Removed songs are only
+//! noticed by a full sync.
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+
+use chan;
+use time::get_time;
+
+use cache::{CacheError, MediaCache};
+use media::Media;
+use {Client, ClientError, CometError};
+
+
+/// How long to wait for the next batch of media from the server
+const SYNC_TIMEOUT_SECS: u64 = 30;
+
+/// How many media the server sends in one batch, which is all that we hold at a time
+const SYNC_PAGE_SIZE: usize = 1000;
+
+#[derive(Debug)]
+pub enum SyncError {
+    Client(ClientError),
+    Cache(CacheError),
+    /// The server did not send the next batch of media in time
+    Timeout,
+}
+
+impl fmt::Display for SyncError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SyncError::Client(ref err) => write!(f, "{}", err),
+            SyncError::Cache(ref err) => write!(f, "{}", err),
+            SyncError::Timeout => write!(f, "the server did not send the media index in time"),
+        }
+    }
+}
+
+impl Error for SyncError {
+    fn description(&self) -> &str {
+        match *self {
+            SyncError::Client(ref err) => err.description(),
+            SyncError::Cache(ref err) => err.description(),
+            SyncError::Timeout => "timed out",
+        }
+    }
+
+    fn source(&self) -> Option<&(Error + 'static)> {
+        match *self {
+            // The wrapped errors describe themselves, so we only pass on their causes
+            SyncError::Client(ref err) => err.source(),
+            SyncError::Cache(ref err) => err.source(),
+            SyncError::Timeout => None,
+        }
+    }
+}
+
+impl From<ClientError> for SyncError {
+    fn from(err: ClientError) -> Self {
+        SyncError::Client(err)
+    }
+}
+
+impl From<CacheError> for SyncError {
+    fn from(err: CacheError) -> Self {
+        SyncError::Cache(err)
+    }
+}
+
+/// What a sync changed
+#[derive(Debug)]
+pub struct SyncStats {
+    /// Was the whole index downloaded?
+    pub full: bool,
+    /// The number of songs that the server sent
+    pub received: usize,
+    /// The number of songs that were removed from the cache
+    pub removed: usize,
+    /// The songs that were not in the cache before, e.g. to look for them in a `Watchlist`; none
+    /// if the cache was never synced, as then every song is new to it
+    pub added: Vec<Media>,
+}
+
+/// Open the media cache at `path`, and download the index of the server at `url` into it if it
+/// was never synced
+///
+/// A damaged cache is started over, like `MediaCache::open_or_reset` does.
+pub fn open_synced(url: &str, path: &Path) -> Result<MediaCache, SyncError> {
+    let mut cache = try!(MediaCache::open_or_reset(path));
+    if cache.synced_at().is_none() {
+        try!(sync_media(url, &mut cache, false));
+    }
+    Ok(cache)
+}
+
+/// Update `cache` with the media index of the server at `url`
+///
+/// This opens its own connection to the server, so it does not get in the way of the searches
+/// of another `Client`. Pass `full` to download the whole index, even if the cache was synced
+/// before.
+pub fn sync_media(url: &str, cache: &mut MediaCache, full: bool) -> Result<SyncStats, SyncError> {
+    // Media that change while we are syncing are fetched again next time
+    let started_at = get_time();
+    let since = if full { None } else { cache.synced_at() };
+
+    let (mut client, client_r) = try!(Client::new(url));
+    client.serve();
+    client.set_modified_since(since);
+    let stream = client.query_media_stream("", SYNC_PAGE_SIZE);
+    // the songs are written to the cache a batch at a time, so that we do not hold the whole
+    // index twice; the keys are kept to find the songs that were removed
+    let first_sync = cache.synced_at().is_none();
+    let mut received = 0;
+    let mut added = Vec::new();
+    let mut keys: HashSet<String> = HashSet::new();
+    while !client.is_media_stream_done(stream) {
+        let timeout = chan::after(Duration::from_secs(SYNC_TIMEOUT_SECS));
+        let message;
+        chan_select! {
+            timeout.recv() => return Err(SyncError::Timeout),
+            client_r.recv() -> msg => message = msg,
+        }
+        let message = try!(message.ok_or(ClientError::from(CometError::Recv)));
+        try!(client.handle_message(&message));
+        if let Some(page) = client.next_media_page(stream) {
+            if !first_sync {
+                added.extend(page.iter().filter(|x| cache.get(&x.key).is_none()).cloned());
+            }
+            if since.is_none() {
+                keys.extend(page.iter().map(|x| x.key.clone()));
+            }
+            received += page.len();
+            try!(cache.append(&page));
+        }
+    }
+
+    debug!("received {} media from the server", received);
+    let mut removed = 0;
+    if since.is_none() {
+        // We have the whole index, so everything else was removed from the server
+        let gone: Vec<String> = cache.media()
+                                     .filter(|x| !keys.contains(&x.key[..]))
+                                     .map(|x| x.key.clone())
+                                     .collect();
+        try!(cache.remove(&gone.iter().map(|x| &x[..]).collect::<Vec<_>>()));
+        removed = gone.len();
+    }
+    try!(cache.set_synced_at(started_at));
+    Ok(SyncStats {
+        full: since.is_none(),
+        received: received,
+        removed: removed,
+        added: added,
+    })
+}
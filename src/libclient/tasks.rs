@@ -0,0 +1,129 @@
+//! Running slow jobs (syncing the media index, fetching info) in the background.
+//!
+//! Every `Client` owns an [`Executor`](struct.Executor.html) with a few worker threads, that are
+//! started when the first task is spawned. When a task is finished, a
+//! [`TaskCompletion`](struct.TaskCompletion.html) is delivered on a receiver of its own (see
+//! `Client::task_receiver`), apart from the messages of the server, so that a server cannot pass
+//! itself off as one of our tasks. `Client::handle_task_completion` turns it into a
+//! `Message::TaskDone` or `Message::TaskFailed`.
+
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use chan;
+use rustc_serialize::json::Json;
+
+
+/// The number of tasks that can run at the same time
+const WORKERS: usize = 2;
+
+/// Identifies a task, so that its result can be matched with the code that spawned it
+pub type TaskId = usize;
+
+/// What a task returns: a result to pass on to the front-end, or a description of what failed
+pub type TaskResult = Result<Json, String>;
+
+/// A task that is finished, with what it returned
+#[derive(Debug)]
+pub struct TaskCompletion {
+    pub id: TaskId,
+    pub result: TaskResult,
+}
+
+struct Job {
+    id: TaskId,
+    name: String,
+    f: Box<FnOnce() -> TaskResult + Send>,
+}
+
+struct Inner {
+    next_id: TaskId,
+    jobs_s: Option<chan::Sender<Job>>,
+}
+
+/// A small pool of worker threads
+#[derive(Clone)]
+pub struct Executor {
+    inner: Arc<Mutex<Inner>>,
+
+    /// Where to deliver the finished tasks
+    done_s: chan::Sender<TaskCompletion>,
+}
+
+impl fmt::Debug for Executor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Executor {{ next_id: {} }}", self.inner.lock().unwrap().next_id)
+    }
+}
+
+impl Executor {
+    /// Create an executor that delivers the finished tasks on `done_s`
+    pub fn new(done_s: chan::Sender<TaskCompletion>) -> Executor {
+        Executor {
+            inner: Arc::new(Mutex::new(Inner { next_id: 0, jobs_s: None })),
+            done_s: done_s,
+        }
+    }
+
+    /// Run `f` on a worker thread
+    ///
+    /// `name` describes the task in the log.
+    pub fn spawn<F>(&self, name: &str, f: F) -> TaskId
+        where F : FnOnce() -> TaskResult + Send + 'static {
+        let mut inner = self.inner.lock().unwrap();
+        inner.next_id += 1;
+        let id = inner.next_id;
+        if inner.jobs_s.is_none() {
+            inner.jobs_s = Some(self.start_workers());
+        }
+        debug!("spawning task {} ({})", id, name);
+        inner.jobs_s.as_ref().unwrap().send(Job { id: id, name: name.to_string(), f: Box::new(f) });
+        id
+    }
+
+    fn start_workers(&self) -> chan::Sender<Job> {
+        let (jobs_s, jobs_r) = chan::async::<Job>();
+        for _ in 0..WORKERS {
+            let jobs_r = jobs_r.clone();
+            let done_s = self.done_s.clone();
+            thread::spawn(move || {
+                // Stops when all executors are dropped
+                for job in jobs_r {
+                    let Job { id, name, f } = job;
+                    let result = panic::catch_unwind(AssertUnwindSafe(f))
+                        .unwrap_or_else(|_| Err("the task panicked".to_string()));
+                    if let Err(ref err) = result {
+                        warn!("task {} ({}) failed: {}", id, name, err);
+                    }
+                    done_s.send(TaskCompletion { id: id, result: result });
+                }
+            });
+        }
+        jobs_s
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chan;
+    use rustc_serialize::json::{Json, ToJson};
+    use super::{Executor, TaskCompletion};
+
+    #[test]
+    fn spawn() {
+        let (done_s, done_r) = chan::async();
+        let executor = Executor::new(done_s);
+        let ok = executor.spawn("answer", || Ok(42.to_json()));
+        let failed = executor.spawn("panic", || panic!("oops"));
+
+        let mut done: Vec<TaskCompletion> = vec![done_r.recv().unwrap(), done_r.recv().unwrap()];
+        done.sort_by_key(|x| x.id);
+        assert_eq!(done[0].id, ok);
+        assert_eq!(done[0].result, Ok(Json::I64(42)));
+        assert_eq!(done[1].id, failed);
+        assert_eq!(done[1].result, Err("the task panicked".to_string()));
+    }
+}
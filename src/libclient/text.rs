@@ -0,0 +1,78 @@
+//! Comparing the tags of songs the way people read them.
+//!
+//! Tags are typed in many ways: "Motörhead" is also searched for as "motorhead", and "Björk"
+//! should be sorted between "Bizet" and "Blur" (not after "Zappa", where it ends up when comparing
+//! bytes). So before matching or sorting, tags are folded: their case is folded and accents are
+//! removed, which is what the collation of most locales ignores at its first level. Characters
+//! that are only another way to write some letters (e.g. the ligature "ﬁ" or the full width "Ａ")
+//! are replaced with those letters as well.
+
+use std::cmp::Ordering;
+
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+
+/// Fold the case of `s` and remove its accents
+pub fn fold(s: &str) -> String {
+    let mut folded = String::with_capacity(s.len());
+    // the compatibility decomposition (NFKD) splits "ö" into "o" and a combining diaeresis, which
+    // is then left out, and "ﬁ" into "f" and "i"
+    for ch in s.nfkd().filter(|x| !is_combining_mark(*x)) {
+        match ch {
+            // the case folding of these is not the same as their lowercase
+            'ß' | 'ẞ' => folded.push_str("ss"),
+            'ς' => folded.push('σ'),
+            _ => folded.extend(ch.to_lowercase()),
+        }
+    }
+    folded
+}
+
+/// Does `s` contain all words of `query`, after folding both?
+pub fn matches(s: &str, query: &str) -> bool {
+    let s = fold(s);
+    fold(query).split_whitespace().all(|word| s.contains(word))
+}
+
+/// Compare `a` and `b` like a dictionary does
+///
+/// Strings that are equal after folding (e.g. "Beyonce" and "Beyoncé") are compared as they are,
+/// so that the order is always the same.
+pub fn collate(a: &str, b: &str) -> Ordering {
+    fold(a).cmp(&fold(b)).then_with(|| a.cmp(b))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use super::*;
+
+    #[test]
+    fn fold() {
+        assert_eq!(super::fold("Motörhead"), "motorhead");
+        assert_eq!(super::fold("Die Ärzte"), "die arzte");
+        assert_eq!(super::fold("Straße"), "strasse");
+        assert_eq!(super::fold("ΣΊΣΥΦΟΣ"), super::fold("σίσυφος"));
+        assert_eq!(super::fold("Ｓｉｇｕｒ Ｒóｓ ﬁve²"), "sigur ros five2");
+        assert_eq!(super::fold("Ǆ"), "dz");
+    }
+
+    #[test]
+    fn matches() {
+        assert!(super::matches("Motörhead Ace of Spades", "motorhead ace"));
+        assert!(super::matches("Beyoncé Halo", "BEYONCE"));
+        assert!(super::matches("Beyonce Halo", "beyoncé"));
+        assert!(super::matches("Beyoncé Halo", ""));
+        assert!(!super::matches("Beyoncé Halo", "beyonce creep"));
+    }
+
+    #[test]
+    fn collate() {
+        let mut artists = vec!["Zappa", "Björk", "blur", "Bizet", "Beyonce", "Beyoncé"];
+        artists.sort_by(|a, b| super::collate(a, b));
+        assert_eq!(artists, ["Beyonce", "Beyoncé", "Bizet", "Björk", "blur", "Zappa"]);
+        assert_eq!(super::collate("Blur", "blur"), Ordering::Less);
+    }
+}
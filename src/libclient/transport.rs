@@ -0,0 +1,340 @@
+//! The ways in which packets travel between the client and the server.
+//!
+//! A packet is a JSON array of the session id and the messages; the server starts a session when
+//! it gets an empty packet. The `CometChannel` keeps the session and passes the messages on, a
+//! `Transport` only moves the packets. There are two of them:
+//!
+//! - `Http`, for `http://` and `https://` URLs: every packet is a POST request, which the server
+//!   answers with a packet. It can only send something when it is asked, so the channel keeps a
+//!   long poll (an empty packet) outstanding.
+//! - `WebSocket` (see the `websocket` module), for `ws://` and `wss://` URLs: the packets go both
+//!   ways over one connection, whenever there is something to send.
+//!
+//! Both connect with the `Settings` that the client was built with, see `ClientBuilder`. A
+//! connection that cannot be made within the connect timeout, or a server that does not answer
+//! within the timeout, fails like a connection that was lost.
+//!
+//! `Http` goes through an HTTP proxy if it is given one with `ClientBuilder::proxy`, or if there
+//! is one in the `HTTPS_PROXY` or `HTTP_PROXY` environment variable for the scheme of the URL
+//! (and the server is not in `NO_PROXY`). `WebSocket` does not go through proxies.
+
+use std::env;
+use std::fmt;
+use std::io::{Error as IOError, ErrorKind, Read};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper;
+use hyper::Url;
+use hyper::header::UserAgent;
+use hyper::net::{HttpStream, NetworkConnector};
+use rustc_serialize::json::Json;
+
+use comet::CometError;
+use metrics::Traffic;
+use websocket::WebSocket;
+
+/// A connection to the server that packets can be sent over
+pub trait Transport: fmt::Debug + Send + Sync {
+    /// Send `packet` to the server, and wait for the packet that it answers with
+    ///
+    /// Transports over which the server sends packets by itself return `None`, their packets
+    /// arrive through `receive`.
+    fn send(&self, packet: &Json) -> Result<Option<Json>, CometError>;
+
+    /// Wait at most `timeout` for a packet that the server sends by itself
+    ///
+    /// Only transports that return `None` from `send` are asked for this.
+    fn receive(&self, timeout: Duration) -> Result<Option<Json>, CometError>;
+
+    /// Can the server send packets by itself, without us polling it?
+    fn pushes(&self) -> bool;
+
+    /// Connect again, after the connection was lost
+    fn reconnect(&self) -> Result<(), CometError>;
+
+    /// The bytes of the packets that went over this transport, if it counts them
+    fn traffic(&self) -> Option<&Traffic> {
+        None
+    }
+}
+
+/// How the transports connect to the server
+#[derive(Clone, Debug, Default)]
+pub struct Settings {
+    /// How long reading from or writing to the server may take, `None` waits forever
+    pub timeout: Option<Duration>,
+    /// How long connecting to the server may take, `None` waits as long as the operating system
+    pub connect_timeout: Option<Duration>,
+    /// What we tell the server that we are, instead of hyper's default
+    pub user_agent: Option<String>,
+    /// The certificates to check `https://` and `wss://` servers against, instead of the ones of
+    /// the system
+    pub ca_file: Option<PathBuf>,
+    /// The URL of the HTTP proxy to go through, instead of the one in the environment
+    pub proxy: Option<String>,
+}
+
+impl Settings {
+    /// The TLS client with our certificates, or `None` to use hyper's with the ones of the system
+    #[cfg(feature = "tls")]
+    fn ssl_client(&self) -> Result<Option<hyper::net::OpensslClient>, CometError> {
+        use hyper::net::OpensslClient;
+        use openssl::ssl::{SslContext, SslMethod};
+        use openssl::ssl::{SSL_OP_NO_COMPRESSION, SSL_OP_NO_SSLV2, SSL_OP_NO_SSLV3};
+
+        let ca_file = match self.ca_file {
+            Some(ref ca_file) => ca_file,
+            None => return Ok(None),
+        };
+        let tls_error = |err: ::openssl::ssl::error::SslError| CometError::Tls(err.to_string());
+        // like hyper's `OpensslClient::default`, but with our certificates
+        let mut context = try!(SslContext::new(SslMethod::Sslv23).map_err(&tls_error));
+        try!(context.set_CA_file(ca_file).map_err(&tls_error));
+        context.set_options(SSL_OP_NO_SSLV2 | SSL_OP_NO_SSLV3 | SSL_OP_NO_COMPRESSION);
+        try!(context.set_cipher_list("ALL!EXPORT!EXPORT40!EXPORT56!aNULL!LOW!RC4@STRENGTH")
+                    .map_err(&tls_error));
+        Ok(Some(OpensslClient::new(context)))
+    }
+
+    /// The connector that opens the connections to the server
+    #[cfg(feature = "tls")]
+    pub fn connector(&self) -> Result<Connector, CometError> {
+        use hyper::net::{HttpsConnector, OpensslClient};
+        let ssl_client = try!(self.ssl_client()).unwrap_or_else(OpensslClient::default);
+        Ok(HttpsConnector::with_connector(ssl_client, TcpConnector(self.connect_timeout)))
+    }
+
+    /// The connector that opens the connections to the server
+    #[cfg(not(feature = "tls"))]
+    pub fn connector(&self) -> Result<Connector, CometError> {
+        match self.ca_file {
+            Some(_) => Err(CometError::Tls("libclient was built without the tls feature"
+                                               .to_string())),
+            None => Ok(TcpConnector(self.connect_timeout)),
+        }
+    }
+
+    /// A hyper client that goes through the HTTP proxy at `host` and `port`
+    #[cfg(feature = "tls")]
+    fn proxied_client(&self, host: String, port: u16) -> Result<hyper::Client, CometError> {
+        use hyper::client::ProxyConfig;
+        Ok(match try!(self.ssl_client()) {
+            Some(ssl_client) => {
+                hyper::Client::with_proxy_config(ProxyConfig(host, port, ssl_client))
+            },
+            None => hyper::Client::with_http_proxy(host, port),
+        })
+    }
+
+    /// A hyper client that goes through the HTTP proxy at `host` and `port`
+    #[cfg(not(feature = "tls"))]
+    fn proxied_client(&self, host: String, port: u16) -> Result<hyper::Client, CometError> {
+        try!(self.connector());
+        Ok(hyper::Client::with_http_proxy(host, port))
+    }
+
+    /// The host and the port of the HTTP proxy to reach the server at `url` through, if any
+    ///
+    /// This is the proxy of `ClientBuilder::proxy`, or else the one in the environment for the
+    /// scheme of `url`. Only `http://` proxies can be used, but they can reach `https://` servers.
+    pub fn proxy_for(&self, url: &str) -> Result<Option<(String, u16)>, CometError> {
+        let proxy = match self.proxy {
+            Some(ref proxy) => proxy.clone(),
+            None => match env_proxy(url) {
+                Some(proxy) => proxy,
+                None => return Ok(None),
+            },
+        };
+        // a proxy in the environment often leaves out the scheme
+        let with_scheme = if proxy.contains("://") {
+            proxy.clone()
+        } else {
+            format!("http://{}", proxy)
+        };
+        let invalid = |reason: &str| CometError::Proxy(format!("{}: {}", proxy, reason));
+        let parsed = try!(Url::parse(&with_scheme).map_err(|err| invalid(&err.to_string())));
+        if parsed.scheme() != "http" {
+            return Err(invalid("only http:// proxies are supported"));
+        }
+        let host = try!(parsed.host_str().ok_or_else(|| invalid("there is no host")));
+        Ok(Some((host.to_string(), parsed.port_or_known_default().unwrap_or(80))))
+    }
+
+    /// The hyper client that sends the requests to the server at `url`
+    pub fn http_client(&self, url: &str) -> Result<hyper::Client, CometError> {
+        let mut client = match try!(self.proxy_for(url)) {
+            Some((host, port)) => {
+                debug!("connecting to {} through the proxy at {}:{}", url, host, port);
+                try!(self.proxied_client(host, port))
+            },
+            None => hyper::Client::with_connector(try!(self.connector())),
+        };
+        client.set_read_timeout(self.timeout);
+        client.set_write_timeout(self.timeout);
+        Ok(client)
+    }
+}
+
+/// Opens the connections to the server, over TLS for `https://` URLs
+#[cfg(feature = "tls")]
+pub type Connector = ::hyper::net::HttpsConnector<::hyper::net::OpensslClient, TcpConnector>;
+
+/// Opens the connections to the server
+#[cfg(not(feature = "tls"))]
+pub type Connector = TcpConnector;
+
+/// Opens TCP connections like hyper's `HttpConnector`, but gives up after the timeout
+///
+/// Every address of the host gets the whole timeout.
+#[derive(Clone, Copy, Debug)]
+pub struct TcpConnector(Option<Duration>);
+
+impl NetworkConnector for TcpConnector {
+    type Stream = HttpStream;
+
+    fn connect(&self, host: &str, port: u16, scheme: &str) -> hyper::Result<HttpStream> {
+        if scheme != "http" {
+            return Err(IOError::new(ErrorKind::InvalidInput, "Invalid scheme for Http").into());
+        }
+        let timeout = match self.0 {
+            Some(timeout) => timeout,
+            None => return Ok(HttpStream(try!(TcpStream::connect((host, port))))),
+        };
+        let mut last_err = None;
+        for addr in try!((host, port).to_socket_addrs()) {
+            match TcpStream::connect_timeout(&addr, timeout) {
+                Ok(stream) => return Ok(HttpStream(stream)),
+                Err(err) => last_err = Some(err),
+            }
+        }
+        let not_found = || IOError::new(ErrorKind::NotFound, "the host has no addresses");
+        Err(last_err.unwrap_or_else(not_found).into())
+    }
+}
+
+/// The proxy in the environment for `url`, which is an `http://` or `https://` URL
+///
+/// Servers of which the host is in `NO_PROXY`, or ends in a domain in it, are reached directly.
+fn env_proxy(url: &str) -> Option<String> {
+    let names = if url.starts_with("https://") {
+        ["HTTPS_PROXY", "https_proxy"]
+    } else if url.starts_with("http://") {
+        ["HTTP_PROXY", "http_proxy"]
+    } else {
+        return None;
+    };
+    let var = |name: &str| env::var(name).ok().and_then(|x| {
+        if x.trim().is_empty() { None } else { Some(x.trim().to_string()) }
+    });
+    let proxy = match names.iter().filter_map(|x| var(x)).next() {
+        Some(proxy) => proxy,
+        None => return None,
+    };
+    let host = match Url::parse(url).ok().and_then(|x| x.host_str().map(|x| x.to_string())) {
+        Some(host) => host,
+        None => return Some(proxy),
+    };
+    let no_proxy = var("NO_PROXY").or_else(|| var("no_proxy")).unwrap_or_default();
+    if no_proxy.split(',').map(|x| x.trim().trim_left_matches('.')).any(|x| {
+        x == "*" || (!x.is_empty() && (host == x || host.ends_with(&format!(".{}", x))))
+    }) {
+        return None;
+    }
+    Some(proxy)
+}
+
+/// The transport for `url`, which is connected if it needs a connection
+///
+/// WebSocket URLs (`ws://` and `wss://`) get a `WebSocket`, the others an `Http` transport.
+pub fn for_url(url: &str, settings: &Settings) -> Result<Arc<Transport>, CometError> {
+    if url.starts_with("ws://") || url.starts_with("wss://") {
+        if settings.proxy.is_some() {
+            return Err(CometError::Proxy("WebSocket connections cannot go through a proxy, \
+                                          use the http:// or https:// URL of the server"
+                                             .to_string()));
+        }
+        Ok(Arc::new(try!(WebSocket::connect(url, settings))))
+    } else {
+        Ok(Arc::new(Http::new(url, try!(settings.http_client(url)), settings)))
+    }
+}
+
+/// Long polling over HTTP, which works through every proxy
+#[derive(Debug)]
+pub struct Http {
+    client: hyper::Client,
+    url: String,
+    user_agent: Option<String>,
+    traffic: Traffic,
+}
+
+impl Http {
+    /// Send the packets to `url` with `client`, see `Settings::http_client`
+    pub fn new(url: &str, client: hyper::Client, settings: &Settings) -> Http {
+        Http {
+            client: client,
+            url: url.to_string(),
+            user_agent: settings.user_agent.clone(),
+            traffic: Traffic::default(),
+        }
+    }
+}
+
+impl Transport for Http {
+    fn send(&self, packet: &Json) -> Result<Option<Json>, CometError> {
+        let body = packet.to_string();
+        let mut req = self.client.post(&self.url).body(&body);
+        if let Some(ref user_agent) = self.user_agent {
+            req = req.header(UserAgent(user_agent.clone()));
+        }
+        let mut res = try!(req.send());
+        self.traffic.sent(body.len());
+        let mut answer = String::new();
+        try!(res.read_to_string(&mut answer));
+        self.traffic.received(answer.len());
+        Ok(Some(try!(Json::from_str(&answer))))
+    }
+
+    fn receive(&self, _: Duration) -> Result<Option<Json>, CometError> {
+        Ok(None)
+    }
+
+    fn pushes(&self) -> bool {
+        false
+    }
+
+    fn reconnect(&self) -> Result<(), CometError> {
+        Ok(()) // every request is a new connection anyway
+    }
+
+    fn traffic(&self) -> Option<&Traffic> {
+        Some(&self.traffic)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn proxy_for() {
+        use comet::CometError;
+        use super::Settings;
+
+        let proxy_for = |proxy: &str| {
+            let settings = Settings { proxy: Some(proxy.to_string()), ..Settings::default() };
+            settings.proxy_for("https://marietje.example.com/api")
+        };
+        assert_eq!(proxy_for("http://proxy.example.com:3128").unwrap(),
+                   Some(("proxy.example.com".to_string(), 3128)));
+        assert_eq!(proxy_for("proxy.example.com:8080/").unwrap(),
+                   Some(("proxy.example.com".to_string(), 8080)));
+        assert_eq!(proxy_for("http://10.0.0.1").unwrap(), Some(("10.0.0.1".to_string(), 80)));
+        match proxy_for("socks5://proxy.example.com:1080") {
+            Err(CometError::Proxy(_)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! Saved searches, of which new songs are looked for whenever the media index is synced.
+//!
+//! A saved search is made of words like a query in the search box: a song matches if its
+//! artist, title or album contain all of them. A word can be kept to one tag by putting the tag
+//! in front of it, e.g. `artist:tool` only matches songs of which the artist contains "tool";
+//! the tags are `artist`, `title`, `album` and `uploader`, like in a query (see the `query`
+//! module). Case and accents do not matter, see [`text::fold`](../text/fn.fold.html).
+//!
+//! The watchlist is kept in a file with one search per line, which is shared by all servers.
+
+use std::fs::{self, File};
+use std::io::{Error as IOError, ErrorKind, Read, Write};
+use std::path::Path;
+
+use media::Media;
+use query;
+
+/// The saved searches, in the order in which they were added
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Watchlist {
+    searches: Vec<String>,
+}
+
+impl Watchlist {
+    /// Read the watchlist from the file at `path`, it is empty if the file does not exist
+    pub fn load(path: &Path) -> Result<Watchlist, IOError> {
+        let mut s = String::new();
+        match File::open(path).and_then(|mut f| f.read_to_string(&mut s)) {
+            Err(ref err) if err.kind() == ErrorKind::NotFound => return Ok(Watchlist::default()),
+            res => { try!(res); },
+        }
+        let mut watchlist = Watchlist::default();
+        for line in s.lines() {
+            watchlist.add(line);
+        }
+        Ok(watchlist)
+    }
+
+    /// Write the watchlist to the file at `path`
+    pub fn save(&self, path: &Path) -> Result<(), IOError> {
+        if let Some(dir) = path.parent() {
+            try!(fs::create_dir_all(dir));
+        }
+        let mut f = try!(File::create(path));
+        for search in &self.searches {
+            try!(writeln!(f, "{}", search));
+        }
+        Ok(())
+    }
+
+    pub fn searches(&self) -> &[String] {
+        &self.searches
+    }
+
+    /// Save `search`, unless it is empty or saved already; returns whether it was added
+    ///
+    /// Whitespace between the words does not matter, so it is saved with single spaces.
+    pub fn add(&mut self, search: &str) -> bool {
+        let search = normalize(search);
+        if search.is_empty() || self.searches.contains(&search) {
+            return false;
+        }
+        self.searches.push(search);
+        true
+    }
+
+    /// Forget `search`; returns whether it was saved
+    pub fn remove(&mut self, search: &str) -> bool {
+        let search = normalize(search);
+        let len = self.searches.len();
+        self.searches.retain(|x| *x != search);
+        self.searches.len() != len
+    }
+
+    /// The first saved search that `media` matches
+    pub fn matching(&self, media: &Media) -> Option<&str> {
+        self.searches.iter().find(|x| search_matches(x, media)).map(|x| &x[..])
+    }
+
+    /// The songs of `media` that match a saved search, with the first search that they match
+    pub fn find<'a, 'b>(&'a self, media: &'b [Media]) -> Vec<(&'a str, &'b Media)> {
+        media.iter().filter_map(|x| self.matching(x).map(|search| (search, x))).collect()
+    }
+}
+
+/// Does `media` match the saved search `search`?
+///
+/// The search is parsed like a query, see `query::parse`; a word with a colon that is not after
+/// a tag, like "re:stacks", is looked for as it is.
+pub fn search_matches(search: &str, media: &Media) -> bool {
+    let query = query::parse(search);
+    (!query.words.is_empty() || query.is_scoped())
+        && query.words.iter().all(|word| media.matches(word))
+        && query.matches(media)
+}
+
+fn normalize(search: &str) -> String {
+    search.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn watchlist() {
+        use time::Duration;
+        use media::Media;
+        use super::{search_matches, Watchlist};
+
+        let media = |artist: &str, title: &str| Media {
+            key: format!("{} {}", artist, title),
+            artist: artist.to_string(),
+            title: title.to_string(),
+            length: Duration::seconds(200),
+            uploaded_by: "bob".to_string(),
+            album: Some("Lateralus".to_string()),
+            url: None,
+        };
+        let schism = media("Tool", "Schism");
+        assert!(search_matches("artist:tool", &schism));
+        assert!(search_matches("tool schism", &schism));
+        assert!(search_matches("album:lateralus uploader:bob", &schism));
+        assert!(!search_matches("title:tool", &schism));
+        assert!(!search_matches("", &schism));
+        assert!(!search_matches("re:stacks", &media("Bon Iver", "re: Stacks")));
+
+        let mut watchlist = Watchlist::default();
+        assert!(watchlist.add("  artist:tool "));
+        assert!(!watchlist.add("artist:tool"));
+        assert!(watchlist.add("title:creep"));
+        let creep = media("Radiohead", "Creep");
+        let songs = vec![media("Björk", "Hyperballad"), schism.clone(), creep.clone()];
+        assert_eq!(watchlist.find(&songs), vec![("artist:tool", &schism), ("title:creep", &creep)]);
+        assert!(watchlist.remove("artist:tool"));
+        assert!(!watchlist.remove("artist:tool"));
+        assert_eq!(watchlist.searches(), &["title:creep".to_string()]);
+    }
+}
@@ -0,0 +1,426 @@
+//! A WebSocket client (RFC 6455), with just enough of the protocol to exchange packets.
+//!
+//! Every packet is a text message. Only one thread uses the connection (see `comet::serve`): it
+//! sends what the front-end has to send, and in between it waits a short while for what the
+//! server sends. That way a TLS connection is never read from and written to at the same time.
+
+use std::cmp::min;
+use std::io::{Error as IOError, ErrorKind, Read, Write};
+use std::mem;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use hyper::Url;
+use hyper::net::{NetworkConnector, NetworkStream};
+use rand::{OsRng, Rng};
+use rustc_serialize::base64::{STANDARD, ToBase64};
+use rustc_serialize::json::Json;
+
+use comet::CometError;
+use metrics::Traffic;
+use transport::{Settings, Transport};
+
+/// Appended to the key of the handshake before the server hashes it, see `accept_key`
+const ACCEPT_GUID: &'static str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The header in which the server answers the handshake, see `accept_key`
+const ACCEPT_HEADER: &'static str = "sec-websocket-accept";
+
+/// The longest response to the handshake that we read, in bytes
+const MAX_HEAD_SIZE: usize = 16 << 10;
+
+/// The largest message that the server may send, in bytes
+const MAX_MESSAGE_SIZE: usize = 64 << 20;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+/// A WebSocket connection to the server at a `ws://` or `wss://` URL
+#[derive(Debug)]
+pub struct WebSocket {
+    url: String,
+    settings: Settings,
+    connection: Mutex<Connection>,
+    traffic: Traffic,
+}
+
+#[derive(Debug)]
+struct Connection {
+    stream: Box<NetworkStream + Send>,
+    /// What was read, but is not a whole frame yet
+    buffer: Vec<u8>,
+    /// The frames of a fragmented message that arrived so far
+    fragments: Vec<u8>,
+}
+
+impl WebSocket {
+    /// Connect to the server at `url`
+    pub fn connect(url: &str, settings: &Settings) -> Result<WebSocket, CometError> {
+        let connection = try!(Connection::open(url, settings));
+        Ok(WebSocket {
+            url: url.to_string(),
+            settings: settings.clone(),
+            connection: Mutex::new(connection),
+            traffic: Traffic::default(),
+        })
+    }
+}
+
+impl Transport for WebSocket {
+    fn send(&self, packet: &Json) -> Result<Option<Json>, CometError> {
+        let text = packet.to_string();
+        try!(self.connection.lock().unwrap().send_frame(OP_TEXT, text.as_bytes()));
+        self.traffic.sent(text.len());
+        Ok(None)
+    }
+
+    fn receive(&self, timeout: Duration) -> Result<Option<Json>, CometError> {
+        match try!(self.connection.lock().unwrap().receive(timeout)) {
+            Some(text) => {
+                self.traffic.received(text.len());
+                Ok(Some(try!(Json::from_str(&text))))
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn pushes(&self) -> bool {
+        true
+    }
+
+    fn reconnect(&self) -> Result<(), CometError> {
+        let connection = try!(Connection::open(&self.url, &self.settings));
+        *self.connection.lock().unwrap() = connection;
+        Ok(())
+    }
+
+    fn traffic(&self) -> Option<&Traffic> {
+        Some(&self.traffic)
+    }
+}
+
+impl Connection {
+    /// Connect to `url` and do the handshake
+    ///
+    /// The timeout of `settings` holds for the handshake and for sending, receiving waits as long
+    /// as it is asked to.
+    fn open(url: &str, settings: &Settings) -> Result<Connection, CometError> {
+        let invalid = || CometError::WebSocket(format!("invalid url {}", url));
+        let parsed = try!(Url::parse(url).map_err(|_| invalid()));
+        let host = try!(parsed.host_str().ok_or_else(invalid)).to_string();
+        let tls = parsed.scheme() == "wss";
+        let port = parsed.port_or_known_default().unwrap_or(if tls { 443 } else { 80 });
+        let mut stream = try!(open_stream(&host, port, tls, settings));
+        try!(stream.set_read_timeout(settings.timeout));
+        try!(stream.set_write_timeout(settings.timeout));
+
+        let mut path = parsed.path().to_string();
+        if let Some(query) = parsed.query() {
+            path.push('?');
+            path.push_str(query);
+        }
+        let host_header = match parsed.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.clone(),
+        };
+        let user_agent = match settings.user_agent {
+            Some(ref user_agent) => format!("User-Agent: {}\r\n", user_agent),
+            None => String::new(),
+        };
+        let key = try!(random_bytes(16)).to_base64(STANDARD);
+        try!(write!(stream, "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\n\
+                             Connection: Upgrade\r\nSec-WebSocket-Key: {}\r\n\
+                             Sec-WebSocket-Version: 13\r\n{}\r\n",
+                    path, host_header, key, user_agent));
+        try!(stream.flush());
+        let head = try!(read_head(&mut stream));
+        try!(check_handshake(&head, &key));
+        debug!("WebSocket connection to {} is open", url);
+        Ok(Connection { stream: stream, buffer: Vec::new(), fragments: Vec::new() })
+    }
+
+    fn send_frame(&mut self, opcode: u8, payload: &[u8]) -> Result<(), CometError> {
+        let mut mask = [0; 4];
+        mask.copy_from_slice(&try!(random_bytes(4)));
+        try!(self.stream.write_all(&encode_frame(opcode, payload, mask)));
+        try!(self.stream.flush());
+        Ok(())
+    }
+
+    /// Wait at most `timeout` for the next text message from the server
+    ///
+    /// Pings are answered on the way.
+    fn receive(&mut self, timeout: Duration) -> Result<Option<String>, CometError> {
+        try!(self.stream.set_read_timeout(Some(timeout)));
+        loop {
+            while let Some((fin, opcode, payload, len)) = try!(decode_frame(&self.buffer)) {
+                self.buffer.drain(..len);
+                match opcode {
+                    OP_PING => try!(self.send_frame(OP_PONG, &payload)),
+                    OP_PONG => {},
+                    OP_CLOSE => {
+                        // answer with the status code, if there is one
+                        let _ = self.send_frame(OP_CLOSE, &payload[..min(2, payload.len())]);
+                        return Err(CometError::WebSocket("the server closed the connection"
+                                                         .to_string()));
+                    },
+                    OP_TEXT | OP_CONTINUATION => {
+                        self.fragments.extend_from_slice(&payload);
+                        if self.fragments.len() > MAX_MESSAGE_SIZE {
+                            return Err(CometError::WebSocket("the message is too large"
+                                                             .to_string()));
+                        }
+                        if fin {
+                            let message = mem::replace(&mut self.fragments, Vec::new());
+                            return String::from_utf8(message).map(Some).map_err(|_| {
+                                CometError::WebSocket("the message is not UTF-8".to_string())
+                            });
+                        }
+                    },
+                    opcode => {
+                        return Err(CometError::WebSocket(format!("unexpected frame with \
+                                                                 opcode {}", opcode)));
+                    },
+                }
+            }
+            let mut chunk = [0; 4096];
+            match self.stream.read(&mut chunk) {
+                Ok(0) => {
+                    let err = IOError::new(ErrorKind::UnexpectedEof, "the connection was closed");
+                    return Err(CometError::IO(err));
+                },
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock ||
+                                err.kind() == ErrorKind::TimedOut => return Ok(None),
+                Err(err) => return Err(CometError::IO(err)),
+            }
+        }
+    }
+}
+
+/// A connection to `host`, over TLS if `tls`
+///
+/// This is the connector that hyper uses for requests, so TLS works and checks the certificate of
+/// the server like it does for `https://` URLs, and it is not supported when that is not.
+fn open_stream(host: &str, port: u16, tls: bool, settings: &Settings)
+               -> Result<Box<NetworkStream + Send>, CometError> {
+    let scheme = if tls { "https" } else { "http" };
+    Ok(try!(try!(settings.connector()).connect(host, port, scheme)).into())
+}
+
+/// Read the status line and the headers of the response to the handshake, and nothing more
+fn read_head<R: Read>(stream: &mut R) -> Result<String, CometError> {
+    let mut head = Vec::new();
+    let mut byte = [0];
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_HEAD_SIZE {
+            return Err(CometError::WebSocket("the handshake response is too long".to_string()));
+        }
+        match try!(stream.read(&mut byte)) {
+            0 => return Err(CometError::WebSocket("no answer to the handshake".to_string())),
+            _ => head.push(byte[0]),
+        }
+    }
+    Ok(String::from_utf8_lossy(&head).into_owned())
+}
+
+/// Check that `head`, the response to a handshake with `key`, accepts the connection
+fn check_handshake(head: &str, key: &str) -> Result<(), CometError> {
+    let mut lines = head.lines();
+    let status = lines.next().unwrap_or("");
+    if status.split_whitespace().nth(1) != Some("101") {
+        return Err(CometError::WebSocket(format!("the server refused the connection: {}",
+                                                 status.trim())));
+    }
+    let accept = lines.filter_map(|line| {
+        let mut parts = line.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some(name), Some(value)) if name.trim().eq_ignore_ascii_case(ACCEPT_HEADER) => {
+                Some(value.trim())
+            },
+            _ => None,
+        }
+    }).next();
+    if accept != Some(&accept_key(key)) {
+        return Err(CometError::WebSocket("the server did not accept the handshake".to_string()));
+    }
+    Ok(())
+}
+
+/// What the server answers to a handshake with `key`
+fn accept_key(key: &str) -> String {
+    sha1_digest(format!("{}{}", key, ACCEPT_GUID).as_bytes()).to_base64(STANDARD)
+}
+
+/// `n` bytes from the random number generator of the operating system, for the key of the
+/// handshake and the masks of the frames
+fn random_bytes(n: usize) -> Result<Vec<u8>, CometError> {
+    let mut bytes = vec![0; n];
+    try!(OsRng::new()).fill_bytes(&mut bytes);
+    Ok(bytes)
+}
+
+/// The SHA-1 digest of `data`, see RFC 3174
+///
+/// The handshake is the only use of SHA-1, so it is implemented here instead of pulling in a
+/// crypto library (which would need OpenSSL).
+fn sha1_digest(data: &[u8]) -> [u8; 20] {
+    // like MD5, but the length in bits is big endian
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    let bits = (data.len() as u64).wrapping_mul(8);
+    message.extend((0..8).rev().map(|i| (bits >> (8 * i)) as u8));
+
+    let mut state: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    for chunk in message.chunks(64) {
+        let mut words = [0u32; 80];
+        for i in 0..16 {
+            words[i] = (chunk[4 * i] as u32) << 24 | (chunk[4 * i + 1] as u32) << 16 |
+                       (chunk[4 * i + 2] as u32) << 8 | chunk[4 * i + 3] as u32;
+        }
+        for i in 16..80 {
+            words[i] = (words[i - 3] ^ words[i - 8] ^ words[i - 14] ^ words[i - 16]).rotate_left(1);
+        }
+        let (mut a, mut b, mut c, mut d, mut e) =
+            (state[0], state[1], state[2], state[3], state[4]);
+        for (i, word) in words.iter().enumerate() {
+            let (f, k) = match i / 20 {
+                0 => ((b & c) | (!b & d), 0x5a827999),
+                1 => (b ^ c ^ d, 0x6ed9eba1),
+                2 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k)
+                        .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        state[0] = state[0].wrapping_add(a);
+        state[1] = state[1].wrapping_add(b);
+        state[2] = state[2].wrapping_add(c);
+        state[3] = state[3].wrapping_add(d);
+        state[4] = state[4].wrapping_add(e);
+    }
+
+    let mut digest = [0; 20];
+    for (i, word) in state.iter().enumerate() {
+        for j in 0..4 {
+            digest[4 * i + j] = (word >> (24 - 8 * j)) as u8;
+        }
+    }
+    digest
+}
+
+/// A frame with `opcode` and `payload`, masked with `mask` as frames from clients have to be
+fn encode_frame(opcode: u8, payload: &[u8], mask: [u8; 4]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    let len = payload.len();
+    if len < 126 {
+        frame.push(0x80 | len as u8);
+    } else if len <= 0xffff {
+        frame.push(0x80 | 126);
+        frame.extend_from_slice(&[(len >> 8) as u8, len as u8]);
+    } else {
+        frame.push(0x80 | 127);
+        frame.extend((0..8).rev().map(|i| (len as u64 >> (8 * i)) as u8));
+    }
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().enumerate().map(|(i, x)| x ^ mask[i % 4]));
+    frame
+}
+
+/// The first frame in `buffer` if it is whole: whether it is the last of its message, its
+/// opcode, its payload, and its length in bytes
+fn decode_frame(buffer: &[u8]) -> Result<Option<(bool, u8, Vec<u8>, usize)>, CometError> {
+    if buffer.len() < 2 {
+        return Ok(None);
+    }
+    let fin = buffer[0] & 0x80 != 0;
+    let opcode = buffer[0] & 0x0f;
+    let masked = buffer[1] & 0x80 != 0;
+    let (len, mut offset) = match buffer[1] & 0x7f {
+        126 if buffer.len() >= 4 => ((buffer[2] as u64) << 8 | buffer[3] as u64, 4),
+        127 if buffer.len() >= 10 => {
+            (buffer[2..10].iter().fold(0, |acc, &x| acc << 8 | x as u64), 10)
+        },
+        126 | 127 => return Ok(None),
+        len => (len as u64, 2),
+    };
+    if len > MAX_MESSAGE_SIZE as u64 {
+        return Err(CometError::WebSocket("the message is too large".to_string()));
+    }
+    let len = len as usize;
+    // the server should not mask its frames, but then we unmask them
+    let mask = if masked {
+        if buffer.len() < offset + 4 {
+            return Ok(None);
+        }
+        offset += 4;
+        Some(&buffer[offset - 4..offset])
+    } else {
+        None
+    };
+    if buffer.len() < offset + len {
+        return Ok(None);
+    }
+    let payload = buffer[offset..offset + len].iter().enumerate().map(|(i, x)| {
+        mask.map_or(*x, |mask| x ^ mask[i % 4])
+    }).collect();
+    Ok(Some((fin, opcode, payload, offset + len)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn handshake() {
+        use super::{accept_key, check_handshake};
+
+        // the example of RFC 6455
+        let key = "dGhlIHNhbXBsZSBub25jZQ==";
+        assert_eq!(accept_key(key), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+        let head = "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+                    Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n";
+        assert!(check_handshake(head, key).is_ok());
+        assert!(check_handshake(head, "c29tZSBvdGhlciBub25jZQ==").is_err());
+        assert!(check_handshake("HTTP/1.1 404 Not Found\r\n\r\n", key).is_err());
+    }
+
+    #[test]
+    fn sha1() {
+        use super::sha1_digest;
+        let hex = |data: &[u8]| -> String {
+            sha1_digest(data).iter().map(|x| format!("{:02x}", x)).collect()
+        };
+        assert_eq!(hex(b""), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(hex(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+                   "84983e441c3bd26ebaae4aa1f95129e5e54670f1");
+        assert_eq!(hex(&[b'a'; 1000]), "291e9a6c66994949b57ba5e650361e98fc36b1ba");
+    }
+
+    #[test]
+    fn frames() {
+        use super::{decode_frame, encode_frame, OP_TEXT};
+
+        let payload = vec![b'x'; 300];
+        let frame = encode_frame(OP_TEXT, &payload, [1, 2, 3, 4]);
+        assert_eq!(&frame[..4], &[0x81, 0x80 | 126, 1, 44]);
+        // a masked frame decodes to what was in it, but not before it is whole
+        assert_eq!(decode_frame(&frame[..100]).unwrap(), None);
+        assert_eq!(decode_frame(&frame).unwrap(), Some((true, OP_TEXT, payload, frame.len())));
+        // "Hello" from the server, unmasked and in two fragments
+        let frames = [0x01, 0x03, b'H', b'e', b'l', 0x80, 0x02, b'l', b'o'];
+        assert_eq!(decode_frame(&frames).unwrap(), Some((false, OP_TEXT, b"Hel".to_vec(), 5)));
+        assert_eq!(decode_frame(&frames[5..]).unwrap(), Some((true, 0, b"lo".to_vec(), 4)));
+    }
+}
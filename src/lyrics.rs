@@ -0,0 +1,76 @@
+//! Lyrics of the playing song, looked up with a command from the configuration file.
+//!
+//! The command gets the artist and the title of the song as its last two arguments and prints the
+//! lyrics, so that any provider can be used (e.g. a script that asks lrclib.net). Lyrics are kept
+//! in the cache directory, also if there are none, so the command runs once for every song.
+
+use std::fs::{self, File};
+use std::io::{Error as IOError, ErrorKind, Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use libclient::{md5, Media};
+
+/// Get the lyrics of `media`, a song of the server at `server`
+///
+/// The lyrics are taken from the cache in `dir`, or else from the output of `command`. That is
+/// empty if the command found no lyrics. A command that fails is run again the next time.
+///
+/// The command is split on whitespace, quoting is not supported.
+pub fn fetch(dir: &Path, server: &str, media: &Media, command: &str) -> Result<String, IOError> {
+    // media keys are only unique on one server
+    let path = dir.join(format!("{}.txt", md5(&format!("{} {}", server, media.key))));
+    let mut lyrics = String::new();
+    if File::open(&path).and_then(|mut f| f.read_to_string(&mut lyrics)).is_ok() {
+        return Ok(lyrics);
+    }
+
+    let mut words = command.split_whitespace();
+    let program = words.next().unwrap_or("");
+    debug!("looking up the lyrics of {:?} with {}", media.title, program);
+    let output = try!(Command::new(program)
+        .args(words)
+        .arg(&media.artist)
+        .arg(&media.title)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output());
+    if !output.status.success() {
+        let msg = format!("{} exited with {}", program, output.status);
+        return Err(IOError::new(ErrorKind::Other, msg));
+    }
+    let lyrics = String::from_utf8_lossy(&output.stdout).trim_end().to_string();
+    try!(fs::create_dir_all(dir));
+    try!(File::create(&path).and_then(|mut f| f.write_all(lyrics.as_bytes())));
+    Ok(lyrics)
+}
+
+
+#[cfg(unix)]
+#[test]
+fn test() {
+    use std::env;
+    use std::process;
+    use time::Duration;
+
+    let dir = env::temp_dir().join(format!("maruska-lyrics-test-{}", process::id()));
+    let server = "http://localhost/api";
+    let mut media = Media {
+        artist: String::from("Queens Of The Stone Age"),
+        key: String::from("56bafc2c8dc01b4ea67fad9c"),
+        length: Duration::seconds(231),
+        title: String::from("In the Fade"),
+        uploaded_by: String::from("dsprenkels"),
+        album: None,
+        url: None,
+    };
+    let expected = "la la Queens Of The Stone Age In the Fade";
+    assert_eq!(fetch(&dir, server, &media, "echo la la").unwrap(), expected);
+    // the second time, the lyrics come from the cache
+    assert_eq!(fetch(&dir, server, &media, "false").unwrap(), expected);
+    media.key = String::from("56bafc2c8dc01b4ea67fad9d");
+    assert!(fetch(&dir, server, &media, "false").is_err());
+    assert_eq!(fetch(&dir, server, &media, "true").unwrap(), "");
+    assert_eq!(fetch(&dir, server, &media, "false").unwrap(), "");
+    fs::remove_dir_all(dir).unwrap();
+}
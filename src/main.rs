@@ -1,35 +1,85 @@
 #[macro_use] extern crate chan;
+#[cfg(unix)] extern crate chan_signal;
+#[cfg(any(windows, feature = "crossterm"))] extern crate crossterm;
+extern crate crypto;
+extern crate dirs;
 extern crate docopt;
 extern crate env_logger;
+extern crate fs2;
+extern crate hyper;
+#[cfg(feature = "cover-art")] extern crate image;
 #[macro_use] extern crate lazy_static;
+#[cfg(unix)] extern crate libc;
 extern crate libclient;
 #[macro_use] extern crate log;
 extern crate lru_time_cache;
+extern crate rand;
 extern crate regex;
 extern crate rustc_serialize;
 extern crate strsim;
-extern crate termbox_sys as termbox;
+#[cfg(all(unix, not(feature = "crossterm")))] extern crate termbox_sys as termbox;
 extern crate time;
 extern crate toml;
 
+mod actions;
+mod aliases;
+mod clipboard;
+mod commands;
+mod config;
+mod cover;
+mod crash;
+mod credentials;
+mod demo;
+mod fifo;
+mod format;
+mod hooks;
+mod instance;
+mod lyrics;
+mod notify;
+mod paths;
+mod preview;
+mod selection;
+mod serialize;
+mod signals;
 mod store;
+mod template;
+mod term;
+mod ticks;
 mod tui;
 mod utils;
 
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+use std::time::Duration;
+
 use docopt::Docopt;
 
+use config::Config;
+use libclient::{Client, DisplayChain};
+use libclient::replay::{read_trace, replay};
 use tui::{TUI, TUIError};
-use utils::show_version_and_exit;
+use utils::{init_logger, show_version_and_exit};
 
 const DEFAULT_HOST: &'static str = "http://marietje-noord.marie-curie.nl/api";
 
+/// The pause between the packets of a trace that is replayed
+const REPLAY_PAUSE_MILLIS: u64 = 1000;
+
 const USAGE: &'static str = "
 Usage:
-  maruska [ --host=HOST ]
+  maruska [options]
   maruska ( --help | --version )
 
 Options:
   -H --host HOST        Hostname of marietje server
+  --log-level LEVEL     Log messages of at least LEVEL (error, warn, info, debug or trace)
+  --log-file FILE       Append log messages to FILE instead of printing them
+  --trace-protocol      Log every packet that is exchanged with the server
+  --low-bandwidth       Redraw less often and without colors or cover art, for slow connections
+  --replay TRACE        Do not connect, but show what the server sent in TRACE, a log that was
+                        written with --trace-protocol
+  --demo                Do not connect, but show a made up server with a few songs
   -h --help             Display this message
   --version             Print version info and exit
 ";
@@ -37,39 +87,96 @@ Options:
 #[derive(Debug, RustcDecodable)]
 pub struct Args {
     flag_host: Option<String>,
+    flag_log_level: Option<String>,
+    flag_log_file: Option<String>,
+    flag_trace_protocol: bool,
+    flag_low_bandwidth: bool,
+    flag_replay: Option<String>,
+    flag_demo: bool,
     flag_help: bool,
     flag_version: bool,
 }
 
 fn main() {
-    // initialize logger
-    if let Err(err) = env_logger::init() {
-        panic!("Failed to initialize logger: {}", err);
-    }
-
     let args: Args = Docopt::new(USAGE)
         .map(|d| d.help(true))
         .and_then(|d| d.decode())
         .unwrap_or_else(|e| e.exit());
 
+    // initialize logger
+    if let Err(err) = init_logger(args.flag_log_level.as_ref().map(|x| x.as_str()),
+                                  args.flag_log_file.as_ref().map(|x| x.as_str()),
+                                  args.flag_trace_protocol) {
+        panic!("Failed to initialize logger: {}", err);
+    }
+
     if args.flag_version {
         show_version_and_exit();
     }
 
     let host = &args.flag_host.unwrap_or_else(|| String::from(DEFAULT_HOST));
-    let (mut tui, event_receivers) = match TUI::new(host) {
-        Ok((tui, event_receivers)) => (tui, event_receivers),
-        Err(err) => panic!("initialization error: {}", err),
+    // Only one TUI should be running, and it should be the only one that touches the state files
+    let _instance_lock = match instance::acquire() {
+        Ok(lock) => lock,
+        Err(_) => {
+            let _ = writeln!(io::stderr(), "maruska is already running in another terminal");
+            process::exit(1);
+        },
     };
-    let (client_r, tui_r, tick_r) = event_receivers;
+
+    // If the last session crashed, maybe because of a bad config, start in safe mode
+    let crash_msg = crash::check_previous_session();
+    let safe_mode = crash_msg.is_some();
+    let config = if safe_mode { Config::default() } else { Config::load() };
+    crash::install_panic_hook();
+    crash::mark_running();
+
+    // Before TUI::new, because that starts threads that should not get the signals
+    let signal_r = signals::notify();
+
+    let (mut tui, event_receivers) = if let Some(ref trace) = args.flag_replay {
+        let packets = match read_trace(Path::new(trace)) {
+            Ok(packets) => packets,
+            Err(err) => {
+                let _ = writeln!(io::stderr(), "could not read the trace {}: {}", trace, err);
+                process::exit(1);
+            },
+        };
+        let (client, client_r, offline) = Client::offline(host);
+        replay(packets, offline, Duration::from_millis(REPLAY_PAUSE_MILLIS));
+        TUI::with_client(client, client_r, &config, args.flag_low_bandwidth, safe_mode)
+    } else if args.flag_demo {
+        let (client, client_r, offline) = Client::offline(demo::DEMO_URL);
+        demo::serve(offline);
+        TUI::with_client(client, client_r, &config, args.flag_low_bandwidth, safe_mode)
+    } else {
+        match TUI::new(host, &config, args.flag_low_bandwidth, safe_mode) {
+            Ok((tui, event_receivers)) => (tui, event_receivers),
+            Err(err) => panic!("initialization error: {}", DisplayChain(&err)),
+        }
+    };
+    if let Some(ref msg) = crash_msg {
+        tui.show_crash(msg);
+    }
+    let (client_r, task_r, tui_r, tick_r) = event_receivers;
+    // Replays and demos cannot be controlled from the outside
+    let remote = config.command_fifo && args.flag_replay.is_none() && !args.flag_demo;
+    let (_fifo, command_r) = fifo::listen(remote);
 
     let mut exit_err: Option<TUIError> = None;
     loop {
+        let mut redraw = true;
         chan_select! {
             client_r.recv() -> message => {
                 if let Err(err) = tui.handle_message_from_client(&message.unwrap()) {
                     drop(tui);
-                    panic!("{}", err)
+                    panic!("{}", DisplayChain(&err))
+                }
+            },
+            task_r.recv() -> done => {
+                if let Err(err) = tui.handle_task_completion(done.unwrap()) {
+                    drop(tui);
+                    panic!("{}", DisplayChain(&err))
                 }
             },
             tui_r.recv() -> event => match tui.handle_event(event.unwrap()) {
@@ -80,11 +187,38 @@ fn main() {
                     break;
                 }
             },
-            tick_r.recv() => {},
+            tick_r.recv() => redraw = tui.handle_tick(),
+            command_r.recv() -> command => match tui.handle_remote_command(command.unwrap()) {
+                Ok(()) => {},
+                Err(TUIError::Quit) => break,
+                Err(err) => {
+                    exit_err = Some(err);
+                    break;
+                }
+            },
+            signal_r.recv() -> signal => match tui.handle_signal(signal.unwrap()) {
+                Ok(()) => {},
+                Err(TUIError::Quit) => break,
+                Err(err) => {
+                    exit_err = Some(err);
+                    break;
+                }
+            },
         }
-        tui.draw();
+        if redraw {
+            tui.draw();
+        }
+        tui.schedule_tick();
     }
     if let Some(err) = exit_err {
-        panic!("{}", err);
+        panic!("{}", DisplayChain(&err));
+    }
+    tui.save_session();
+    let summary = tui.session_summary();
+    // the summary is printed after the terminal is restored, or it would be wiped
+    drop(tui);
+    if let Some(summary) = summary {
+        println!("{}", summary);
     }
+    crash::mark_stopped();
 }
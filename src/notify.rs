@@ -0,0 +1,161 @@
+//! Desktop notifications, which are shown by the notification daemon of the desktop.
+//!
+//! They are sent with `notify-send` (libnotify), or with `osascript` on macOS. Sending one takes
+//! a moment, so that is done on another thread, and a failure is only logged.
+//!
+//! A `Notifier` holds notifications back during the quiet hours of the configuration, and while
+//! do not disturb is switched on with `:dnd`. The hooks are held back with them, see
+//! `Notifier::is_quiet`.
+
+use std::process::{Command, Stdio};
+use std::thread;
+
+use time::{at, get_time};
+
+/// A part of every day, e.g. the night, from `start` up to `end` in minutes after midnight
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QuietHours {
+    start: i32,
+    end: i32,
+}
+
+impl QuietHours {
+    /// The quiet hours in `s`, e.g. `"22:30-7:00"`, or `None` if it is not two times like that
+    pub fn parse(s: &str) -> Option<QuietHours> {
+        let minutes = |s: &str| -> Option<i32> {
+            let mut parts = s.trim().splitn(2, ':');
+            let hours: Option<i32> = parts.next().and_then(|x| x.parse().ok());
+            let minutes: Option<i32> = parts.next().and_then(|x| x.parse().ok());
+            match (hours, minutes) {
+                (Some(h), Some(m)) if h >= 0 && h < 24 && m >= 0 && m < 60 => Some(60 * h + m),
+                _ => None,
+            }
+        };
+        let mut times = s.splitn(2, '-');
+        match (times.next().and_then(&minutes), times.next().and_then(&minutes)) {
+            (Some(start), Some(end)) => Some(QuietHours { start: start, end: end }),
+            _ => None,
+        }
+    }
+
+    /// Is `hour:minute` within the quiet hours? They go on past midnight if they end before they
+    /// start.
+    pub fn contains(&self, hour: i32, minute: i32) -> bool {
+        let t = 60 * hour + minute;
+        if self.start <= self.end {
+            t >= self.start && t < self.end
+        } else {
+            t >= self.start || t < self.end
+        }
+    }
+}
+
+/// Sends the notifications, unless the user does not want to be disturbed
+#[derive(Clone, Debug, Default)]
+pub struct Notifier {
+    quiet_hours: Option<QuietHours>,
+    /// Do not disturb as switched with `toggle`, and whether it was the quiet hours then; it
+    /// counts until the quiet hours start or end
+    switched: Option<(bool, bool)>,
+}
+
+impl Notifier {
+    pub fn set_quiet_hours(&mut self, quiet_hours: Option<QuietHours>) {
+        self.quiet_hours = quiet_hours;
+    }
+
+    fn in_quiet_hours(&self) -> bool {
+        let now = at(get_time());
+        self.quiet_hours.map_or(false, |x| x.contains(now.tm_hour, now.tm_min))
+    }
+
+    /// Should notifications and hooks be held back now?
+    pub fn is_quiet(&mut self) -> bool {
+        let quiet_hours = self.in_quiet_hours();
+        match self.switched {
+            Some((quiet, since_quiet_hours)) if since_quiet_hours == quiet_hours => quiet,
+            _ => {
+                self.switched = None;
+                quiet_hours
+            },
+        }
+    }
+
+    /// Switch do not disturb on or off until the quiet hours start or end, and tell whether it is
+    /// on now
+    pub fn toggle(&mut self) -> bool {
+        let quiet = !self.is_quiet();
+        self.switched = Some((quiet, self.in_quiet_hours()));
+        quiet
+    }
+
+    /// Show a notification with `summary` and `body`, unless it is quiet now
+    pub fn send(&mut self, summary: &str, body: &str) {
+        if self.is_quiet() {
+            debug!("holding back a notification: {}", summary);
+            return;
+        }
+        send(summary, body);
+    }
+}
+
+/// Show a notification with `summary` and `body`
+pub fn send(summary: &str, body: &str) {
+    let mut command = command(summary, body);
+    command.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null());
+    thread::spawn(move || match command.status() {
+        Ok(status) if !status.success() => warn!("could not send a notification: {}", status),
+        Ok(_) => {},
+        Err(err) => warn!("could not send a notification: {}", err),
+    });
+}
+
+#[cfg(not(target_os = "macos"))]
+fn command(summary: &str, body: &str) -> Command {
+    let mut command = Command::new("notify-send");
+    command.arg("--app-name=maruska").arg(summary).arg(body);
+    command
+}
+
+#[cfg(target_os = "macos")]
+fn command(summary: &str, body: &str) -> Command {
+    let mut command = Command::new("osascript");
+    command.arg("-e").arg(format!("display notification {} with title {}",
+                                  applescript_string(body), applescript_string(summary)));
+    command
+}
+
+/// Quote `s` as a string for AppleScript
+#[cfg(target_os = "macos")]
+fn applescript_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+
+#[test]
+fn test() {
+    let night = QuietHours::parse("22:30-7:00").unwrap();
+    assert!(night.contains(23, 0));
+    assert!(night.contains(0, 0));
+    assert!(night.contains(22, 30));
+    assert!(!night.contains(7, 0));
+    assert!(!night.contains(12, 0));
+    let lunch = QuietHours::parse(" 12:00 - 13:00 ").unwrap();
+    assert!(lunch.contains(12, 59));
+    assert!(!lunch.contains(13, 0));
+    assert!(!lunch.contains(11, 59));
+    assert_eq!(QuietHours::parse("22:00"), None);
+    assert_eq!(QuietHours::parse("24:00-7:00"), None);
+    assert_eq!(QuietHours::parse("10pm-7am"), None);
+
+    let mut notifier = Notifier::default();
+    assert!(!notifier.is_quiet());
+    assert!(notifier.toggle());
+    assert!(notifier.is_quiet());
+    assert!(!notifier.toggle());
+    // all day
+    notifier.set_quiet_hours(Some(QuietHours { start: 0, end: 24 * 60 }));
+    assert!(notifier.is_quiet());
+    assert!(!notifier.toggle());
+    assert!(!notifier.is_quiet());
+}
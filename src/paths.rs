@@ -0,0 +1,164 @@
+//! Platform dependent locations of the files that maruska reads and writes.
+
+use std::fs;
+use std::io::Error as IOError;
+use std::path::{Path, PathBuf};
+
+use dirs;
+use libclient::cache::MediaCache;
+use libclient::stats::Stats;
+
+/// The directory in which we keep cached data (e.g. `~/.cache` on Linux)
+pub fn cache_dir() -> Option<PathBuf> {
+    dirs::cache_dir()
+}
+
+/// The directory that holds the configuration file (e.g. `~/.config/maruska` on Linux)
+pub fn config_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|x| x.join("maruska"))
+}
+
+/// The directory in which we keep state that should survive a restart
+///
+/// This is `~/.local/state/maruska` on Linux. Other platforms do not have a state directory, so
+/// there we use the local data directory instead.
+pub fn state_dir() -> Option<PathBuf> {
+    dirs::state_dir().or_else(dirs::data_local_dir).map(|x| x.join("maruska"))
+}
+
+/// The file in which the login credentials are stored
+pub fn credentials_file() -> Option<PathBuf> {
+    cache_dir().map(|x| x.join("maruska.toml"))
+}
+
+/// Move the login credentials from `~/.cache/maruska.toml`, where older versions kept them on
+/// every platform, to `credentials_file`
+///
+/// Only on macOS and Windows do they differ. Nothing is moved if there are credentials in the
+/// new place already.
+pub fn migrate_credentials_file() {
+    let (old, new) = match (dirs::home_dir(), credentials_file()) {
+        (Some(home), Some(new)) => (home.join(".cache").join("maruska.toml"), new),
+        _ => return,
+    };
+    match move_if_missing(&old, &new) {
+        Ok(true) => info!("moved the credentials from {:?} to {:?}", old, new),
+        Ok(false) => {},
+        Err(err) => warn!("could not move the credentials from {:?} to {:?}: {}", old, new, err),
+    }
+}
+
+/// Move the file at `old` to `new`, unless they are the same, `old` does not exist or `new`
+/// does; returns whether it was moved
+fn move_if_missing(old: &Path, new: &Path) -> Result<bool, IOError> {
+    if old == new || !old.is_file() || new.exists() {
+        return Ok(false);
+    }
+    if let Some(dir) = new.parent() {
+        try!(fs::create_dir_all(dir));
+    }
+    // renaming fails if they are on different file systems
+    try!(fs::rename(old, new).or_else(|_| fs::copy(old, new).and_then(|_| fs::remove_file(old))));
+    Ok(true)
+}
+
+/// The file in which the login credentials are stored when they are encrypted
+pub fn encrypted_credentials_file() -> Option<PathBuf> {
+    cache_dir().map(|x| x.join("maruska.enc.toml"))
+}
+
+/// The file in which reports about wrong songs are kept, if the server does not take them
+pub fn report_file() -> Option<PathBuf> {
+    state_dir().map(|x| x.join("reports.tsv"))
+}
+
+/// The file to which we append what we asked the servers to change
+pub fn audit_file() -> Option<PathBuf> {
+    state_dir().map(|x| x.join("audit.tsv"))
+}
+
+/// The file in which we count how often songs of the server at `url` were requested and played
+pub fn stats_file(url: &str) -> Option<PathBuf> {
+    state_dir().map(|x| x.join(Stats::file_name(url)))
+}
+
+/// The file with the saved searches of which new songs are announced
+pub fn watchlist_file() -> Option<PathBuf> {
+    state_dir().map(|x| x.join("watchlist.txt"))
+}
+
+/// The local copy of the media index of the server at `url`
+pub fn media_cache_file(url: &str) -> Option<PathBuf> {
+    cache_dir().map(|x| x.join("maruska").join(MediaCache::file_name(url)))
+}
+
+/// The directory in which cover art is kept
+pub fn cover_dir() -> Option<PathBuf> {
+    cache_dir().map(|x| x.join("maruska").join("covers"))
+}
+
+/// The directory in which lyrics are kept
+pub fn lyrics_dir() -> Option<PathBuf> {
+    cache_dir().map(|x| x.join("maruska").join("lyrics"))
+}
+
+/// The configuration file
+pub fn config_file() -> Option<PathBuf> {
+    config_dir().map(|x| x.join("config.toml"))
+}
+
+/// The named pipe through which other programs can send commands to the TUI
+///
+/// The runtime directory only exists on Linux and some other Unix-like systems.
+pub fn command_fifo() -> Option<PathBuf> {
+    dirs::runtime_dir().map(|x| x.join("maruska.cmd"))
+}
+
+/// The file in which the state of the last TUI session is stored
+pub fn session_file() -> Option<PathBuf> {
+    state_dir().map(|x| x.join("session.toml"))
+}
+
+/// The file that exists while the TUI is running
+pub fn sentinel_file() -> Option<PathBuf> {
+    state_dir().map(|x| x.join("running"))
+}
+
+/// The file to which the message of the last panic is written
+pub fn crash_log_file() -> Option<PathBuf> {
+    state_dir().map(|x| x.join("crash.log"))
+}
+
+/// The file that the running TUI holds a lock on
+pub fn lock_file() -> Option<PathBuf> {
+    state_dir().map(|x| x.join("tui.lock"))
+}
+
+/// The directory with the hook scripts
+pub fn hooks_dir() -> Option<PathBuf> {
+    config_dir().map(|x| x.join("hooks"))
+}
+
+
+#[test]
+fn test() {
+    use std::env;
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::process;
+
+    let dir = env::temp_dir().join(format!("maruska-paths-test-{}", process::id()));
+    let (old, new) = (dir.join("maruska.toml"), dir.join("Caches").join("maruska.toml"));
+    fs::create_dir_all(&dir).unwrap();
+    File::create(&old).unwrap().write_all(b"old").unwrap();
+    assert!(move_if_missing(&old, &new).unwrap());
+    assert!(!old.exists());
+    // credentials in the new place are not overwritten
+    File::create(&old).unwrap().write_all(b"older").unwrap();
+    assert!(!move_if_missing(&old, &new).unwrap());
+    let mut contents = String::new();
+    File::open(&new).unwrap().read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "old");
+    assert!(!move_if_missing(&new, &new).unwrap());
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,90 @@
+//! Previewing songs by playing the start of them with an external player.
+//!
+//! This only works with servers that tell where a song can be downloaded (the `url` of a song).
+//! The player is a command from the configuration file, e.g. `mpv --no-video`, which gets the
+//! url as its last argument. It is stopped after a while, or when the `Preview` is dropped.
+
+use std::io::Error as IOError;
+use std::process::{Child, Command, Stdio};
+
+use time::{Duration, get_time, Timespec};
+
+/// A player that is playing the start of a song
+pub struct Preview {
+    child: Child,
+    media_key: String,
+    ends_at: Timespec,
+}
+
+impl Preview {
+    /// Start playing `url` with `command` for `duration`
+    ///
+    /// The command is split on whitespace, quoting is not supported.
+    pub fn start(command: &str, url: &str, media_key: &str,
+                 duration: Duration) -> Result<Preview, IOError> {
+        let mut words = command.split_whitespace();
+        let program = words.next().unwrap_or("");
+        // The player should not draw over the TUI or read its keys
+        let child = try!(Command::new(program)
+            .args(words)
+            .arg(url)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn());
+        debug!("started preview player {} for {}", child.id(), url);
+        Ok(Preview {
+            child: child,
+            media_key: media_key.to_string(),
+            ends_at: get_time() + duration,
+        })
+    }
+
+    /// The media key of the song that is previewed
+    pub fn media_key(&self) -> &str {
+        &self.media_key
+    }
+
+    /// Is the preview done, because its time is up or because the player quit?
+    pub fn is_over(&mut self) -> bool {
+        get_time() >= self.ends_at || self.child.try_wait().map(|x| x.is_some()).unwrap_or(true)
+    }
+}
+
+impl Drop for Preview {
+    fn drop(&mut self) {
+        // The player may have quit already, then there is nothing to kill
+        let _ = self.child.kill();
+        if let Err(err) = self.child.wait() {
+            warn!("could not wait for the preview player: {}", err);
+        }
+    }
+}
+
+/// Resolve the `url` of a song, which may be relative, against the url of the server
+pub fn resolve_url(server: &str, url: &str) -> String {
+    if url.contains("://") {
+        return url.to_string();
+    }
+    // e.g. "http://example.com/api", which has the origin "http://example.com"
+    let origin_len = server.find("://")
+                           .and_then(|i| server[i + 3..].find('/').map(|j| i + 3 + j))
+                           .unwrap_or(server.len());
+    if url.starts_with('/') {
+        format!("{}{}", &server[..origin_len], url)
+    } else {
+        let dir_len = server.rfind('/').filter(|&i| i >= origin_len).unwrap_or(origin_len);
+        format!("{}/{}", &server[..dir_len], url)
+    }
+}
+
+
+#[test]
+fn test() {
+    let server = "http://marietje-noord.marie-curie.nl/api";
+    let expected = "http://marietje-noord.marie-curie.nl/media/a.mp3";
+    assert_eq!(resolve_url(server, "https://example.com/a.mp3"), "https://example.com/a.mp3");
+    assert_eq!(resolve_url(server, "/media/a.mp3"), expected);
+    assert_eq!(resolve_url(server, "media/a.mp3"), expected);
+    assert_eq!(resolve_url("http://localhost:8080", "a.mp3"), "http://localhost:8080/a.mp3");
+}
@@ -0,0 +1,350 @@
+//! Python bindings for libclient.
+//!
+//! ```python
+//! import maruska
+//!
+//! client = maruska.Client("http://marietje-noord.marie-curie.nl/api")
+//! client.follow_all()
+//! for event in client.events():
+//!     if event["type"] == "playing" and event["changed"]:
+//!         print(event["playing"]["media"]["title"])
+//! ```
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use pyo3::exceptions::{PyConnectionError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+
+use rustc_serialize::json::Json;
+
+use libclient::{
+    Client as InnerClient, ConnectionState, DisplayChain, Media, Message, Playing, Request,
+    RequestStatus,
+};
+
+/// A connection to a marietje server
+#[pyclass(module = "maruska")]
+struct Client {
+    inner: Arc<Mutex<InnerClient>>,
+    receiver: chan::Receiver<Json>,
+}
+
+/// Iterator over the events that are received from the server
+#[pyclass(module = "maruska")]
+struct Events {
+    inner: Arc<Mutex<InnerClient>>,
+    receiver: chan::Receiver<Json>,
+}
+
+#[pymethods]
+impl Client {
+    #[new]
+    fn new(url: &str) -> PyResult<Self> {
+        let (client, receiver) = InnerClient::new(url)
+            .map_err(|err| PyConnectionError::new_err(DisplayChain(&err).to_string()))?;
+        client.serve();
+        Ok(Client {
+            inner: Arc::new(Mutex::new(client)),
+            receiver,
+        })
+    }
+
+    /// The url of the server
+    #[getter]
+    fn url(&self) -> String {
+        self.inner.lock().unwrap().get_url()
+    }
+
+    /// The song that is currently playing, or None
+    #[getter]
+    fn playing<'py>(&self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyDict>>> {
+        let client = self.inner.lock().unwrap();
+        client.get_playing().as_ref().map(|x| playing_to_dict(py, x)).transpose()
+    }
+
+    /// The current request queue, or None if it has not been received yet
+    #[getter]
+    fn requests<'py>(&self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyList>>> {
+        let client = self.inner.lock().unwrap();
+        match *client.get_requests() {
+            Some(ref requests) => requests_to_list(py, requests).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// The search results that have been received for the current query, the ones that match it
+    /// best first if `ranked` is set
+    #[getter]
+    fn results<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyList>> {
+        let client = self.inner.lock().unwrap();
+        let list = PyList::empty(py);
+        for media in client.get_ranked_results() {
+            list.append(media_to_dict(py, media)?)?;
+        }
+        Ok(list)
+    }
+
+    /// Are the search results ranked by relevance, instead of in the order of the server?
+    #[getter]
+    fn ranked(&self) -> bool {
+        self.inner.lock().unwrap().is_ranked()
+    }
+
+    #[setter]
+    fn set_ranked(&self, ranked: bool) {
+        self.inner.lock().unwrap().set_ranked(ranked)
+    }
+
+    /// Keep receiving updates on what is playing and on the request queue
+    fn follow_all(&self) {
+        self.inner.lock().unwrap().follow_all()
+    }
+
+    /// Log in with a password, or with an access key if `access_key` is true
+    #[pyo3(signature = (username, secret, access_key=false))]
+    fn login(&self, username: &str, secret: &str, access_key: bool) {
+        let mut client = self.inner.lock().unwrap();
+        if access_key {
+            client.do_login_accesskey(username, secret)
+        } else {
+            let hash = client.hash_password(secret);
+            client.do_login(username, &hash)
+        }
+    }
+
+    /// Search for songs; results arrive as "query_media_results" events
+    #[pyo3(signature = (query, count=100))]
+    fn search(&self, query: &str, count: usize) {
+        self.inner.lock().unwrap().update_query(Some(query), count)
+    }
+
+    /// Stop searching; the results of the search that still arrive are ignored
+    fn cancel_search(&self) {
+        self.inner.lock().unwrap().cancel_query()
+    }
+
+    /// Search for songs a page at a time, without keeping the results; returns the id of the
+    /// stream, and the pages arrive as "media_page" events
+    #[pyo3(signature = (query, page_size=100))]
+    fn search_pages(&self, query: &str, page_size: usize) -> usize {
+        self.inner.lock().unwrap().query_media_stream(query, page_size)
+    }
+
+    /// Take the page of a stream that arrived, which asks for the next one
+    ///
+    /// Returns None if no page arrived, and an empty or short list for the last page.
+    fn next_page<'py>(&self, py: Python<'py>, stream: usize)
+                      -> PyResult<Option<Bound<'py, PyList>>> {
+        match self.inner.lock().unwrap().next_media_page(stream) {
+            Some(page) => {
+                let list = PyList::empty(py);
+                for media in &page {
+                    list.append(media_to_dict(py, media)?)?;
+                }
+                Ok(Some(list))
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Request a song by its media key
+    ///
+    /// Returns False if the request was deferred until we are logged in, or until the server can
+    /// be reached.
+    fn request(&self, key: &str) -> bool {
+        match self.inner.lock().unwrap().do_request_from_key(key) {
+            RequestStatus::Ok => true,
+            RequestStatus::Deferred | RequestStatus::Queued => false,
+        }
+    }
+
+    /// Report that the song with this media key is wrong, if the server takes reports
+    ///
+    /// Returns False if the report was deferred until we are logged in.
+    fn report(&self, key: &str, reason: &str) -> bool {
+        match self.inner.lock().unwrap().do_report(key, reason) {
+            RequestStatus::Ok => true,
+            RequestStatus::Deferred | RequestStatus::Queued => false,
+        }
+    }
+
+    /// Change the "artist", "title" or "album" of the song with this media key
+    ///
+    /// The server answers with a "media_updated" or a "media_update_error" event. Returns False
+    /// if the change was deferred until we are logged in.
+    fn update_media(&self, key: &str, fields: BTreeMap<String, String>) -> bool {
+        match self.inner.lock().unwrap().update_media(key, &fields) {
+            RequestStatus::Ok => true,
+            RequestStatus::Deferred | RequestStatus::Queued => false,
+        }
+    }
+
+    /// Vote to skip the song that is playing, if the server supports skip votes
+    ///
+    /// Returns False if the vote was deferred until we are logged in.
+    fn vote_skip(&self) -> bool {
+        match self.inner.lock().unwrap().vote_skip() {
+            RequestStatus::Ok => true,
+            RequestStatus::Deferred | RequestStatus::Queued => false,
+        }
+    }
+
+    /// Send a message (a JSON string) to the server as it is, for messages that are not supported
+    ///
+    /// Answers of a type that is not supported arrive as "raw" events.
+    fn send_raw(&self, message: &str) -> PyResult<()> {
+        let message = Json::from_str(message)
+            .map_err(|err| PyValueError::new_err(err.to_string()))?;
+        self.inner.lock().unwrap().send_raw(message);
+        Ok(())
+    }
+
+    /// An iterator over the events from the server
+    ///
+    /// Every event is a dict with a "type" key. Iterating blocks until the next event arrives.
+    fn events(&self) -> Events {
+        Events {
+            inner: self.inner.clone(),
+            receiver: self.receiver.clone(),
+        }
+    }
+}
+
+#[pymethods]
+impl Events {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__<'py>(&self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyDict>>> {
+        let receiver = self.receiver.clone();
+        let msg = match py.allow_threads(move || receiver.recv()) {
+            Some(msg) => msg,
+            None => return Ok(None), // the connection is gone, stop iterating
+        };
+        let mut client = self.inner.lock().unwrap();
+        let message = client.handle_message(&msg)
+            .map_err(|err| PyValueError::new_err(DisplayChain(&err).to_string()))?;
+
+        let event = PyDict::new(py);
+        match message {
+            Message::Welcome => event.set_item("type", "welcome")?,
+            Message::Playing | Message::PlayingChanged => {
+                event.set_item("type", "playing")?;
+                event.set_item("changed", matches!(message, Message::PlayingChanged))?;
+                if let Some(ref playing) = *client.get_playing() {
+                    event.set_item("playing", playing_to_dict(py, playing)?)?;
+                }
+            },
+            Message::Requests => {
+                event.set_item("type", "requests")?;
+                if let Some(ref requests) = *client.get_requests() {
+                    event.set_item("requests", requests_to_list(py, requests)?)?;
+                }
+            },
+            Message::LoginToken => event.set_item("type", "login_token")?,
+            Message::Login => event.set_item("type", "login")?,
+            Message::LoginError(msg) => {
+                event.set_item("type", "login_error")?;
+                event.set_item("message", msg)?;
+            },
+            Message::QueryMediaResults => {
+                let (_, done) = client.get_qm_results();
+                event.set_item("type", "query_media_results")?;
+                event.set_item("done", *done)?;
+            },
+            Message::MediaPage(stream) => {
+                event.set_item("type", "media_page")?;
+                event.set_item("stream", stream)?;
+            },
+            Message::SkipVotes(votes, needed) => {
+                event.set_item("type", "skip_votes")?;
+                event.set_item("votes", votes)?;
+                event.set_item("needed", needed)?;
+            },
+            Message::MediaReported(key) => {
+                event.set_item("type", "media_reported")?;
+                event.set_item("key", key)?;
+            },
+            Message::MediaUpdated(key) => {
+                event.set_item("type", "media_updated")?;
+                event.set_item("key", key)?;
+            },
+            Message::MediaUpdateError(key, msg) => {
+                event.set_item("type", "media_update_error")?;
+                event.set_item("key", key)?;
+                event.set_item("message", msg)?;
+            },
+            Message::TaskDone(id, result) => {
+                event.set_item("type", "task_done")?;
+                event.set_item("task", id)?;
+                event.set_item("result", result.to_string())?;
+            },
+            Message::TaskFailed(id, err) => {
+                event.set_item("type", "task_failed")?;
+                event.set_item("task", id)?;
+                event.set_item("error", err)?;
+            },
+            Message::ConnectionState(state) => {
+                event.set_item("type", "connection_state")?;
+                match state {
+                    ConnectionState::Connected => event.set_item("state", "connected")?,
+                    ConnectionState::Disconnected => event.set_item("state", "disconnected")?,
+                    ConnectionState::Reconnecting(attempt) => {
+                        event.set_item("state", "reconnecting")?;
+                        event.set_item("attempt", attempt)?;
+                    },
+                }
+            },
+            Message::Raw(msg) => {
+                event.set_item("type", "raw")?;
+                event.set_item("message", msg.to_string())?;
+            },
+        }
+        Ok(Some(event))
+    }
+}
+
+fn media_to_dict<'py>(py: Python<'py>, media: &Media) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new(py);
+    dict.set_item("key", &media.key)?;
+    dict.set_item("artist", &media.artist)?;
+    dict.set_item("title", &media.title)?;
+    dict.set_item("length", media.length.num_milliseconds() as f64 / 1000.0)?;
+    dict.set_item("uploaded_by", &media.uploaded_by)?;
+    dict.set_item("album", &media.album)?;
+    dict.set_item("url", &media.url)?;
+    Ok(dict)
+}
+
+fn playing_to_dict<'py>(py: Python<'py>, playing: &Playing) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new(py);
+    let end_time = playing.end_time.sec as f64 + playing.end_time.nsec as f64 / 1e9;
+    dict.set_item("requested_by", &playing.requested_by)?;
+    dict.set_item("end_time", end_time)?;
+    dict.set_item("media", media_to_dict(py, &playing.media)?)?;
+    dict.set_item("cover_url", &playing.cover_url)?;
+    Ok(dict)
+}
+
+fn requests_to_list<'py>(py: Python<'py>, requests: &[Request]) -> PyResult<Bound<'py, PyList>> {
+    let list = PyList::empty(py);
+    for request in requests {
+        let dict = PyDict::new(py);
+        dict.set_item("key", request.key)?;
+        dict.set_item("by", &request.by)?;
+        dict.set_item("media", media_to_dict(py, &request.media)?)?;
+        list.append(dict)?;
+    }
+    Ok(list)
+}
+
+/// Python bindings for libclient, the marietje client library
+#[pymodule]
+fn maruska(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Client>()?;
+    m.add_class::<Events>()?;
+    Ok(())
+}
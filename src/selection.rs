@@ -0,0 +1,128 @@
+//! What is selected in a list of the terminal UI, by the key of the selected item.
+//!
+//! The lists change under the selection: search results arrive in parts and are sorted again, and
+//! the queue changes whenever the server says so. So a `Selection` remembers the key of the
+//! selected item (a media key or a request key) rather than its row, and finds the row in the list
+//! that is drawn. When the item is gone, the row where it was is selected instead.
+
+use std::cmp::{max, min};
+
+/// The selected item of a list, and how far the list is scrolled
+#[derive(Clone, Debug, PartialEq)]
+pub struct Selection<K> {
+    /// The key of the selected item, if there is one
+    key: Option<K>,
+    /// The row of the selected item when it was selected
+    index: usize,
+    /// The first row that is on the screen
+    offset: usize,
+}
+
+impl<K: Clone + PartialEq> Selection<K> {
+    /// Nothing selected, at the top of the list
+    pub fn new() -> Selection<K> {
+        Selection::at(0, 0)
+    }
+
+    /// Select row `index`, with the list scrolled to `offset`, before the items are known
+    pub fn at(index: usize, offset: usize) -> Selection<K> {
+        Selection { key: None, index: index, offset: offset }
+    }
+
+    /// The key of the selected item
+    pub fn key(&self) -> Option<&K> {
+        self.key.as_ref()
+    }
+
+    /// The row of the selected item in the list with `keys`, if it is in it
+    pub fn position(&self, keys: &[K]) -> Option<usize> {
+        self.key.as_ref().and_then(|key| keys.iter().position(|x| x == key))
+    }
+
+    /// The row that is selected in the list with `keys`: the row of the selected item, or the row
+    /// where it was if it is gone
+    pub fn index(&self, keys: &[K]) -> usize {
+        self.position(keys).unwrap_or_else(|| min(self.index, keys.len().saturating_sub(1)))
+    }
+
+    /// The first row of the list with `keys` that is on the screen, if `h` rows fit on it
+    ///
+    /// The list is scrolled so far that the selected row is on the screen.
+    pub fn offset(&self, keys: &[K], h: usize) -> usize {
+        let index = self.index(keys);
+        max(index.saturating_sub(h.saturating_sub(1)), min(self.offset, index))
+    }
+
+    /// Select row `row` of the list with `keys`, or nothing
+    pub fn select(&mut self, keys: &[K], row: Option<usize>) {
+        self.key = row.and_then(|i| keys.get(i).cloned());
+        self.index = row.unwrap_or(0);
+    }
+
+    /// Move the selection by `x` rows in the list with `keys`, of which `h` rows fit on the screen
+    ///
+    /// With `fix_offset`, the list is scrolled along with the selection.
+    pub fn move_by(&mut self, keys: &[K], h: usize, x: isize, fix_offset: bool) {
+        let (index, offset) = move_list_focus(self.index(keys), self.offset(keys, h), keys.len(),
+                                              h, x, fix_offset);
+        self.select(keys, Some(index));
+        self.offset = offset;
+    }
+}
+
+/// Move `focus` by `x` places in a list of `len` items, of which `h` fit on the screen
+///
+/// Returns the new focus and the new offset of the first item on the screen, which keeps the focus
+/// in view. With `fix_offset`, the list is scrolled along with the focus.
+fn move_list_focus(focus: usize, offset: usize, len: usize, h: usize, x: isize,
+                   fix_offset: bool) -> (usize, usize) {
+    fn bounded<T: Ord>(v1: T, v2: T, v3: T) -> T {
+        max(v1, min(v2, v3))
+    }
+    let max_index = len.saturating_sub(1);
+
+    let new_focus = if x >= 0 {
+        focus.saturating_add(x as usize)
+    } else {
+        focus.saturating_sub(-x as usize)
+    };
+    let focus = bounded(0, new_focus, max_index);
+
+    let new_offset = if fix_offset {
+        if x >= 0 {
+            offset.saturating_add(x as usize)
+        } else {
+            offset.saturating_sub(-x as usize)
+        }
+    } else {
+        offset
+    };
+    (focus, bounded(focus.saturating_sub(h.saturating_sub(1)), new_offset, focus))
+}
+
+
+#[test]
+fn test() {
+    let keys = ["a", "b", "c", "d", "e"];
+    let mut selection = Selection::new();
+    assert_eq!(selection.key(), None);
+    assert_eq!(selection.index(&keys), 0);
+    selection.move_by(&keys, 2, 3, false);
+    assert_eq!(selection.key(), Some(&"d"));
+    assert_eq!(selection.offset(&keys, 2), 2);
+
+    // the selection follows the item when the list changes
+    let keys = ["e", "d", "c", "b", "a"];
+    assert_eq!(selection.index(&keys), 1);
+    assert_eq!(selection.offset(&keys, 2), 1);
+
+    // and stays on its row when it is gone
+    let keys = ["a", "b", "c", "e"];
+    assert_eq!(selection.position(&keys), None);
+    assert_eq!(selection.index(&keys), 3);
+    assert_eq!(selection.index(&keys[..2]), 1);
+
+    selection.select(&keys, None);
+    assert_eq!(selection.key(), None);
+    assert_eq!(selection.position(&keys), None);
+}
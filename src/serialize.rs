@@ -0,0 +1,41 @@
+//! Songs, requests and the song that is playing as JSON, the same way in the API of the daemon
+//! and for the hook scripts.
+//!
+//! The keys are not the ones that the server uses: they are snake case, and times are in
+//! seconds since the epoch.
+
+use std::collections::BTreeMap;
+
+use rustc_serialize::json::{Json, ToJson};
+
+use libclient::{Media, Playing, Request};
+
+pub fn media_json(media: &Media) -> Json {
+    let mut obj = BTreeMap::new();
+    obj.insert("key".to_string(), media.key.to_json());
+    obj.insert("artist".to_string(), media.artist.to_json());
+    obj.insert("title".to_string(), media.title.to_json());
+    obj.insert("length".to_string(), media.length.num_seconds().to_json());
+    obj.insert("uploaded_by".to_string(), media.uploaded_by.to_json());
+    obj.insert("album".to_string(), media.album.to_json());
+    obj.insert("url".to_string(), media.url.to_json());
+    Json::Object(obj)
+}
+
+pub fn playing_json(playing: &Playing) -> Json {
+    let end_time = playing.end_time.sec as f64 + playing.end_time.nsec as f64 / 1e9;
+    let mut obj = BTreeMap::new();
+    obj.insert("requested_by".to_string(), playing.requested_by.to_json());
+    obj.insert("end_time".to_string(), end_time.to_json());
+    obj.insert("media".to_string(), media_json(&playing.media));
+    obj.insert("cover_url".to_string(), playing.cover_url.to_json());
+    Json::Object(obj)
+}
+
+pub fn request_json(request: &Request) -> Json {
+    let mut obj = BTreeMap::new();
+    obj.insert("key".to_string(), request.key.to_json());
+    obj.insert("by".to_string(), request.by.to_json());
+    obj.insert("media".to_string(), media_json(&request.media));
+    Json::Object(obj)
+}
@@ -0,0 +1,80 @@
+//! Signals that the TUI reacts to, delivered on a channel so that they fit in the main loop.
+//!
+//! `notify` has to be called before any threads are started: the signals are blocked in the
+//! calling thread, and new threads inherit that. This also means that signal handlers of other
+//! libraries never run for these signals; e.g. termbox does not notice when the terminal is
+//! resized, so that is up to us.
+
+use chan;
+
+/// A signal that was sent to the process
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Signal {
+    /// Stop the process, e.g. because the user pressed Ctrl-Z in the shell (SIGTSTP)
+    Suspend,
+    /// The process was continued after it was stopped (SIGCONT)
+    Continue,
+    /// The terminal was resized (SIGWINCH)
+    Resize,
+    /// We are asked to quit (SIGTERM or SIGINT)
+    Terminate,
+    /// Reload the configuration file (SIGHUP)
+    Reload,
+}
+
+/// Start listening for signals
+#[cfg(unix)]
+pub fn notify() -> chan::Receiver<Signal> {
+    use std::thread;
+    use chan_signal::{self, Signal as RawSignal};
+
+    let raw_r = chan_signal::notify(&[RawSignal::TSTP, RawSignal::CONT, RawSignal::WINCH,
+                                      RawSignal::TERM, RawSignal::INT, RawSignal::HUP]);
+    let (signal_s, signal_r) = chan::async();
+    thread::spawn(move || {
+        for raw in raw_r {
+            let signal = match raw {
+                RawSignal::TSTP => Signal::Suspend,
+                RawSignal::CONT => Signal::Continue,
+                RawSignal::WINCH => Signal::Resize,
+                RawSignal::TERM | RawSignal::INT => Signal::Terminate,
+                RawSignal::HUP => Signal::Reload,
+                _ => continue,
+            };
+            signal_s.send(signal);
+        }
+    });
+    signal_r
+}
+
+/// Start listening for signals
+///
+/// This platform has no signals, so nothing is ever received.
+#[cfg(not(unix))]
+pub fn notify() -> chan::Receiver<Signal> {
+    use std::mem;
+
+    let (signal_s, signal_r) = chan::async();
+    // A closed channel would be ready all the time, so the sender is never dropped
+    mem::forget(signal_s);
+    signal_r
+}
+
+/// Stop the process until it is continued, like the default action of SIGTSTP does
+///
+/// Returns false if the process could not be stopped.
+#[cfg(unix)]
+pub fn stop_self() -> bool {
+    use libc;
+
+    // SIGTSTP would only end up on our own channel again
+    unsafe { libc::kill(libc::getpid(), libc::SIGSTOP) == 0 }
+}
+
+/// Stop the process until it is continued, like the default action of SIGTSTP does
+///
+/// Returns false if the process could not be stopped, which is always the case here.
+#[cfg(not(unix))]
+pub fn stop_self() -> bool {
+    false
+}
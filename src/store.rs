@@ -1,18 +1,69 @@
 use std::collections::BTreeMap;
-use std::io::{Error as IOError, Read, Write};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{Error as IOError, ErrorKind, Read, Write};
 use std::iter::FromIterator;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
 
+use fs2::FileExt;
 use toml::{encode, Parser, ParserError, Value};
 
+use libclient::cache::with_suffix;
+
 
 #[derive(Debug)]
 pub enum StoreError {
     IO(IOError),
-    Parser(Vec<ParserError>)
+    Parser(Vec<ParserError>),
+
+    /// The file has a version that we do not know of
+    Version(Value),
+
+    /// A migration could not upgrade the file from this version
+    Migration(i64, String),
 }
 
 pub type StoreResult<T> = Result<T, StoreError>;
 
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StoreError::IO(_) => write!(f, "could not read or write the file"),
+            StoreError::Parser(ref errs) => {
+                let descs: Vec<&str> = errs.iter().map(|x| x.desc.as_str()).collect();
+                write!(f, "invalid toml: {}", descs.join(", "))
+            },
+            StoreError::Version(ref version) => {
+                write!(f, "unknown version {}, the file was written by a newer maruska", version)
+            },
+            StoreError::Migration(version, ref msg) => {
+                write!(f, "could not upgrade the file from version {}: {}", version, msg)
+            },
+        }
+    }
+}
+
+impl Error for StoreError {
+    fn description(&self) -> &str {
+        match *self {
+            StoreError::IO(ref err) => err.description(),
+            StoreError::Parser(_) => "invalid toml",
+            StoreError::Version(_) => "unknown version",
+            StoreError::Migration(_, _) => "could not upgrade the file",
+        }
+    }
+
+    fn source(&self) -> Option<&(Error + 'static)> {
+        match *self {
+            StoreError::IO(ref err) => Some(err),
+            StoreError::Parser(_) | StoreError::Version(_) | StoreError::Migration(_, _) => None,
+        }
+    }
+}
+
 impl From<IOError> for StoreError {
     fn from(err: IOError) -> Self {
         StoreError::IO(err)
@@ -25,6 +76,45 @@ impl From<Vec<ParserError>> for StoreError {
     }
 }
 
+/// The key that holds the version of a store file
+const VERSION_KEY: &'static str = "version";
+
+/// Upgrades the contents of a store file by one version
+pub type Migration = fn(&mut BTreeMap<String, Value>) -> Result<(), String>;
+
+/// Describes the format of one kind of store file
+///
+/// Files without a `version` key are version 1, the format from before files were versioned.
+/// `migrations[i]` upgrades a file from version `i + 1` to version `i + 2`, so when the format
+/// changes, append a migration to the list. Old files are then upgraded when they are loaded.
+pub struct Schema {
+    pub migrations: &'static [Migration],
+}
+
+impl Schema {
+    /// The version of the files that we write
+    pub fn version(&self) -> i64 {
+        self.migrations.len() as i64 + 1
+    }
+
+    /// Upgrade `store_obj` to the current version, and remove the version key from it
+    ///
+    /// Returns the version that `store_obj` had.
+    fn migrate(&self, store_obj: &mut BTreeMap<String, Value>) -> StoreResult<i64> {
+        let version = match store_obj.remove(VERSION_KEY) {
+            Some(Value::Integer(x)) if x >= 1 && x <= self.version() => x,
+            Some(x) => return Err(StoreError::Version(x)),
+            None => 1,
+        };
+        for (i, migration) in self.migrations.iter().enumerate().skip(version as usize - 1) {
+            let from = i as i64 + 1;
+            debug!("upgrading store from version {} to {}", from, from + 1);
+            try!(migration(store_obj).map_err(|msg| StoreError::Migration(from, msg)));
+        }
+        Ok(version)
+    }
+}
+
 pub fn load<F>(reader: &mut F) -> StoreResult<BTreeMap<String, Value>>
     where F : Read {
     let mut s = String::new();
@@ -40,9 +130,210 @@ pub fn save<F>(btreemap: BTreeMap<String, Value>, writer: &mut F) -> Result<(),
     write!(writer, "{}", encode(&btreemap))
 }
 
+/// Load the file at `path`, while holding a shared lock on it
+///
+/// Only a lock file that a writer left behind is used, so loading does not create one, and works
+/// in a directory that we cannot write to. Without a lock file there is no writer to wait for.
+///
+/// If the file has an older version than `schema`, it is upgraded and saved again (see
+/// `update_file`). The version key is not included in the result.
+pub fn load_file(path: &Path, schema: &Schema) -> StoreResult<BTreeMap<String, Value>> {
+    let mut store_obj = {
+        let lock = fs::File::open(with_suffix(path, ".lock")).ok();
+        if let Some(ref lock) = lock {
+            try!(FileExt::lock_shared(lock));
+        }
+        let mut file = try!(fs::File::open(path));
+        try!(load(&mut file))
+    };
+    if try!(schema.migrate(&mut store_obj)) == schema.version() {
+        return Ok(store_obj);
+    }
+
+    // Upgrade the file on disk too, so that we only have to do this once
+    let mut result = None;
+    match update_file(path, schema, |store_obj| result = Some(store_obj.clone())) {
+        Ok(()) => Ok(result.unwrap()),
+        Err(err) => {
+            warn!("could not save the upgraded {}: {}", path.display(), err);
+            Ok(store_obj)
+        },
+    }
+}
+
+/// Replace the contents of the file at `path`, while holding an exclusive lock on it
+///
+/// See `update_file`.
+pub fn save_file(path: &Path, schema: &Schema, btreemap: BTreeMap<String, Value>)
+                 -> StoreResult<()> {
+    update_file(path, schema, |store_obj| *store_obj = btreemap)
+}
+
+/// Load the file at `path`, change it using `f`, and save it again
+///
+/// An exclusive lock is held the whole time, so that another maruska process cannot change the
+/// file in the meantime. A missing or invalid file is treated as empty. The new version is
+/// written to a temporary file, which then replaces the old version at once, so a crash cannot
+/// leave a half written file behind. The previous version is kept as a `.bak` file.
+///
+/// A file with an older version is upgraded before `f` sees it, and a copy of the old version is
+/// kept as a `.v<version>.bak` file. A file with a newer version, or one that cannot be upgraded,
+/// is left alone and an error is returned.
+///
+/// The file is only readable by the current user, because it may contain credentials.
+pub fn update_file<F>(path: &Path, schema: &Schema, f: F) -> StoreResult<()>
+    where F : FnOnce(&mut BTreeMap<String, Value>) {
+    if let Some(dir) = path.parent() {
+        try!(fs::create_dir_all(dir));
+    }
+    let lock = try!(open_lock(path));
+    try!(lock.lock_exclusive());
+
+    let loaded = fs::File::open(path).map_err(StoreError::from)
+                                     .and_then(|mut file| load(&mut file));
+    let mut store_obj = match loaded {
+        Ok(mut store_obj) => {
+            let version = try!(schema.migrate(&mut store_obj));
+            if version != schema.version() {
+                try!(fs::copy(path, with_suffix(path, &format!(".v{}.bak", version))));
+            }
+            store_obj
+        },
+        Err(_) => BTreeMap::new(),
+    };
+    f(&mut store_obj);
+    store_obj.insert(VERSION_KEY.to_string(), Value::Integer(schema.version()));
+
+    let tmp_path = with_suffix(path, ".tmp");
+    {
+        let mut open_options = fs::OpenOptions::new();
+        open_options.write(true).truncate(true).create(true);
+        #[cfg(unix)]
+        open_options.mode(0o600);
+        let mut tmp_file = try!(open_options.open(&tmp_path));
+        try!(save(store_obj, &mut tmp_file));
+        try!(tmp_file.sync_all());
+    }
+    match fs::copy(path, backup_path(path)) {
+        Err(ref err) if err.kind() == ErrorKind::NotFound => {}, // there is no previous version
+        res => { try!(res); },
+    }
+    try!(fs::rename(&tmp_path, path));
+    Ok(())
+}
+
+/// The file in which `update_file` keeps the previous version of the file at `path`
+pub fn backup_path(path: &Path) -> PathBuf {
+    with_suffix(path, ".bak")
+}
+
+/// Open or create the lock file that belongs to the file at `path`
+///
+/// We cannot lock the file itself, because it is replaced when it is saved.
+fn open_lock(path: &Path) -> Result<fs::File, IOError> {
+    fs::OpenOptions::new().write(true).create(true).open(with_suffix(path, ".lock"))
+}
+
 
 #[test]
 fn test() {
     let mut input = r#"key = "value""#.as_bytes();
     load(&mut input).unwrap();
 }
+
+#[test]
+fn test_display() {
+    use std::io::ErrorKind;
+
+    let err = StoreError::from(IOError::new(ErrorKind::NotFound, "no such file"));
+    assert_eq!(err.to_string(), "could not read or write the file");
+    assert_eq!(err.source().unwrap().to_string(), "no such file");
+
+    let mut input = "key = ".as_bytes();
+    let err = load(&mut input).unwrap_err();
+    assert!(err.to_string().starts_with("invalid toml: "));
+    assert!(err.source().is_none());
+
+    let err = StoreError::Version(Value::Integer(3));
+    assert_eq!(err.to_string(), "unknown version 3, the file was written by a newer maruska");
+    assert!(err.source().is_none());
+
+    let err = StoreError::Migration(1, "missing key".to_string());
+    assert_eq!(err.to_string(), "could not upgrade the file from version 1: missing key");
+    assert!(err.source().is_none());
+}
+
+#[test]
+fn test_update_file() {
+    use std::env;
+    use std::process;
+
+    const SCHEMA: Schema = Schema { migrations: &[] };
+    let dir = env::temp_dir().join(format!("maruska-store-test-{}", process::id()));
+    let path = dir.join("store.toml");
+    update_file(&path, &SCHEMA, |x| { x.insert("key".to_string(), Value::Integer(1)); }).unwrap();
+    update_file(&path, &SCHEMA, |x| { x.insert("key".to_string(), Value::Integer(2)); }).unwrap();
+
+    let store_obj = load_file(&path, &SCHEMA).unwrap();
+    assert_eq!(store_obj.get("key"), Some(&Value::Integer(2)));
+    let backup = load(&mut fs::File::open(with_suffix(&path, ".bak")).unwrap()).unwrap();
+    assert_eq!(backup.get("key"), Some(&Value::Integer(1)));
+    assert!(!with_suffix(&path, ".tmp").exists());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_migrate() {
+    use std::env;
+    use std::process;
+
+    fn rename_key(store_obj: &mut BTreeMap<String, Value>) -> Result<(), String> {
+        let value = try!(store_obj.remove("old").ok_or("missing key old".to_string()));
+        store_obj.insert("new".to_string(), value);
+        Ok(())
+    }
+    const OLD: Schema = Schema { migrations: &[] };
+    const NEW: Schema = Schema { migrations: &[rename_key] };
+
+    let dir = env::temp_dir().join(format!("maruska-store-migrate-test-{}", process::id()));
+    let path = dir.join("store.toml");
+    fs::create_dir_all(&dir).unwrap();
+    fs::File::create(&path).unwrap().write_all(b"old = 1").unwrap();
+
+    let store_obj = load_file(&path, &NEW).unwrap();
+    assert_eq!(store_obj.get("new"), Some(&Value::Integer(1)));
+    assert_eq!(store_obj.get("version"), None);
+    let saved = load(&mut fs::File::open(&path).unwrap()).unwrap();
+    assert_eq!(saved.get("version"), Some(&Value::Integer(2)));
+    let backup = load(&mut fs::File::open(with_suffix(&path, ".v1.bak")).unwrap()).unwrap();
+    assert_eq!(backup.get("old"), Some(&Value::Integer(1)));
+
+    // An older maruska must not touch the upgraded file
+    match update_file(&path, &OLD, |_| {}) {
+        Err(StoreError::Version(Value::Integer(2))) => {},
+        res => panic!("unexpected result {:?}", res),
+    }
+    assert_eq!(load_file(&path, &NEW).unwrap().get("new"), Some(&Value::Integer(1)));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+#[cfg(unix)]
+fn test_load_read_only() {
+    use std::env;
+    use std::os::unix::fs::PermissionsExt;
+    use std::process;
+
+    const SCHEMA: Schema = Schema { migrations: &[] };
+    let dir = env::temp_dir().join(format!("maruska-store-read-only-test-{}", process::id()));
+    let path = dir.join("store.toml");
+    fs::create_dir_all(&dir).unwrap();
+    fs::File::create(&path).unwrap().write_all(b"key = 1").unwrap();
+    fs::set_permissions(&dir, fs::Permissions::from_mode(0o555)).unwrap();
+
+    let loaded = load_file(&path, &SCHEMA);
+    fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+    assert_eq!(loaded.unwrap().get("key"), Some(&Value::Integer(1)));
+    assert!(!with_suffix(&path, ".lock").exists());
+    fs::remove_dir_all(dir).unwrap();
+}
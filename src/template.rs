@@ -0,0 +1,124 @@
+//! Templates with placeholders between braces, e.g. `"{artist} - {title}"`.
+//!
+//! The custom actions fill in the tags of a song. The `header` and `status` templates of
+//! `config.toml` and `maruska-cli status --format` fill in what is playing, see `StatusFields`,
+//! so a template looks the same in the terminal UI and on the command line.
+
+use std::cmp::max;
+
+use time::{Duration, get_time, Timespec};
+
+use format::TimeFormat;
+use libclient::{Client, Playing, Request};
+
+/// Replace the placeholders in `template` with their values from `lookup`, which gets the name
+/// between the braces
+///
+/// Anything between braces that `lookup` does not know is left alone.
+pub fn expand<F>(template: &str, lookup: F) -> String where F: Fn(&str) -> Option<String> {
+    let mut out = String::new();
+    let mut rest = template;
+    // Everything is replaced in one pass, so that a value that looks like a placeholder (e.g. the
+    // title "{key}") is never replaced again
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = rest.find('}').and_then(|end| lookup(&rest[1..end]).map(|x| (x, end)));
+        match value {
+            Some((value, end)) => {
+                out.push_str(&value);
+                rest = &rest[end + 1..];
+            },
+            None => {
+                out.push('{');
+                rest = &rest[1..];
+            },
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// What the placeholders of a status template stand for
+///
+/// They are `user`, `server`, `playing_artist`, `playing_title`, `requested_by`, `remaining` (when
+/// the song that is playing ends), `queue_length`, `queue_end` and `time` (of day).
+pub struct StatusFields<'a> {
+    client: &'a Client,
+    time_format: TimeFormat,
+    now: Timespec,
+}
+
+impl<'a> StatusFields<'a> {
+    pub fn new(client: &'a Client, time_format: TimeFormat) -> StatusFields<'a> {
+        StatusFields { client: client, time_format: time_format, now: get_time() }
+    }
+
+    /// The value of the placeholder `name`, if it is one
+    ///
+    /// What is not known (yet), like the song that is playing before the server told it, is
+    /// empty.
+    pub fn get(&self, name: &str) -> Option<String> {
+        let playing = self.client.get_playing().as_ref();
+        let requests = self.client.get_requests().as_ref();
+        Some(match name {
+            "user" => self.client.get_username().unwrap_or("").to_string(),
+            "server" => host(&self.client.get_url()).to_string(),
+            "playing_artist" => playing.map_or(String::new(), |x| x.media.artist.clone()),
+            "playing_title" => playing.map_or(String::new(), |x| x.media.title.clone()),
+            "requested_by" => {
+                playing.and_then(|x| x.requested_by.clone()).unwrap_or_default()
+            },
+            "remaining" => playing.map_or(String::new(), |x| {
+                let remaining = x.end_time - self.now;
+                if remaining < Duration::zero() {
+                    String::new()
+                } else {
+                    self.time_format.eta(remaining)
+                }
+            }),
+            "queue_length" => requests.map_or(String::new(), |x| x.len().to_string()),
+            "queue_end" => match (playing, requests) {
+                (Some(playing), Some(requests)) => {
+                    self.time_format.time(queue_end(playing, requests, self.now))
+                },
+                _ => String::new(),
+            },
+            "time" => self.time_format.time(self.now),
+            _ => return None,
+        })
+    }
+}
+
+/// When the last of `requests` will be done, if `playing` is playing at `now`
+pub fn queue_end(playing: &Playing, requests: &[Request], now: Timespec) -> Timespec {
+    let length = requests.iter().fold(Duration::zero(), |acc, x| acc + x.media.length);
+    max(playing.end_time, now) + length
+}
+
+/// The host of the server at `url`, e.g. `"example.com"` for `"https://example.com/api"`
+fn host(url: &str) -> &str {
+    let url = url.find("://").map_or(url, |i| &url[i + 3..]);
+    url.split('/').next().unwrap_or(url)
+}
+
+
+#[test]
+fn test() {
+    use format::{Clock, Eta};
+
+    let lookup = |name: &str| if name == "title" { Some(String::from("Schism")) } else { None };
+    assert_eq!(expand("{title} {{title}} {album} {", lookup), "Schism {Schism} {album} {");
+    assert_eq!(host("http://127.0.0.1:8765/api"), "127.0.0.1:8765");
+
+    let (mut client, _, _) = Client::offline("https://example.com/api");
+    let msg = r#"{"type": "requests", "requests": [{"byKey": "bob", "key": 1, "media":
+                 {"key": "m1", "artist": "Tool", "title": "Schism", "length": 407,
+                  "uploadedByKey": "alice"}}]}"#;
+    client.handle_message(&::rustc_serialize::json::Json::from_str(msg).unwrap()).unwrap();
+    let time_format = TimeFormat { clock: Clock::TwentyFourHour, eta: Eta::Duration };
+    let fields = StatusFields::new(&client, time_format);
+    assert_eq!(expand("{server}: {queue_length} requests{playing_title}, {colour}",
+                      |name| fields.get(name)),
+               "example.com: 1 requests, {colour}");
+}
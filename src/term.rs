@@ -0,0 +1,278 @@
+//! The terminal backend used by the TUI.
+//!
+//! On Unix-like systems we draw using termbox. On Windows, or when built with the `crossterm`
+//! feature, the same (termbox style) interface is implemented on top of crossterm, so that
+//! `tui.rs` does not have to know which backend it is talking to.
+
+#[cfg(all(unix, not(feature = "crossterm")))]
+pub use termbox::*;
+
+#[cfg(any(windows, feature = "crossterm"))]
+pub use self::crossterm_backend::*;
+
+#[cfg(any(windows, feature = "crossterm"))]
+#[allow(dead_code)]
+mod crossterm_backend {
+    use std::io::{stdout, Write};
+    use std::os::raw::c_int;
+    use std::sync::Mutex;
+
+    use crossterm::{cursor, event, queue, style, terminal};
+    use crossterm::event::{Event, KeyCode, KeyEventKind, KeyModifiers};
+    use crossterm::style::{Attribute, Color};
+
+    #[derive(Clone, Copy)]
+    pub struct RawEvent {
+        pub etype: u8,
+        pub emod: u8,
+        pub key: u16,
+        pub ch: u32,
+        pub w: i32,
+        pub h: i32,
+        pub x: i32,
+        pub y: i32,
+    }
+
+    pub const TB_KEY_F1: u16 = 0xffff - 0;
+    pub const TB_KEY_INSERT: u16 = 0xffff - 12;
+    pub const TB_KEY_DELETE: u16 = 0xffff - 13;
+    pub const TB_KEY_HOME: u16 = 0xffff - 14;
+    pub const TB_KEY_END: u16 = 0xffff - 15;
+    pub const TB_KEY_PGUP: u16 = 0xffff - 16;
+    pub const TB_KEY_PGDN: u16 = 0xffff - 17;
+    pub const TB_KEY_ARROW_UP: u16 = 0xffff - 18;
+    pub const TB_KEY_ARROW_DOWN: u16 = 0xffff - 19;
+    pub const TB_KEY_ARROW_LEFT: u16 = 0xffff - 20;
+    pub const TB_KEY_ARROW_RIGHT: u16 = 0xffff - 21;
+
+    pub const TB_KEY_CTRL_A: u16 = 0x01;
+    pub const TB_KEY_CTRL_B: u16 = 0x02;
+    pub const TB_KEY_CTRL_C: u16 = 0x03;
+    pub const TB_KEY_BACKSPACE: u16 = 0x08;
+    pub const TB_KEY_TAB: u16 = 0x09;
+    pub const TB_KEY_CTRL_J: u16 = 0x0a;
+    pub const TB_KEY_CTRL_K: u16 = 0x0b;
+    pub const TB_KEY_ENTER: u16 = 0x0d;
+    pub const TB_KEY_CTRL_O: u16 = 0x0f;
+    pub const TB_KEY_CTRL_P: u16 = 0x10;
+    pub const TB_KEY_CTRL_T: u16 = 0x14;
+    pub const TB_KEY_CTRL_U: u16 = 0x15;
+    pub const TB_KEY_CTRL_W: u16 = 0x17;
+    pub const TB_KEY_CTRL_Z: u16 = 0x1a;
+    pub const TB_KEY_ESC: u16 = 0x1b;
+    pub const TB_KEY_SPACE: u16 = 0x20;
+    pub const TB_KEY_BACKSPACE2: u16 = 0x7f;
+
+    pub const TB_MOD_ALT: u8 = 0x01;
+
+    pub const TB_DEFAULT: u16 = 0x00;
+    pub const TB_BLACK: u16 = 0x01;
+    pub const TB_RED: u16 = 0x02;
+    pub const TB_GREEN: u16 = 0x03;
+    pub const TB_YELLOW: u16 = 0x04;
+    pub const TB_BLUE: u16 = 0x05;
+    pub const TB_MAGENTA: u16 = 0x06;
+    pub const TB_CYAN: u16 = 0x07;
+    pub const TB_WHITE: u16 = 0x08;
+
+    pub const TB_BOLD: u16 = 0x0100;
+    pub const TB_UNDERLINE: u16 = 0x0200;
+    pub const TB_REVERSE: u16 = 0x0400;
+
+    pub const TB_EVENT_KEY: u8 = 1;
+    pub const TB_EVENT_RESIZE: u8 = 2;
+    pub const TB_EVENT_MOUSE: u8 = 3;
+
+    pub const TB_HIDE_CURSOR: c_int = -1;
+
+    /// The back buffer, which is written to the terminal by `tb_present`
+    struct Screen {
+        width: c_int,
+        height: c_int,
+        cells: Vec<(u32, u16, u16)>,
+        cursor: Option<(c_int, c_int)>,
+    }
+
+    impl Screen {
+        fn resize(&mut self, width: c_int, height: c_int) {
+            self.width = width;
+            self.height = height;
+            self.cells = vec![(' ' as u32, TB_DEFAULT, TB_DEFAULT); (width * height) as usize];
+        }
+    }
+
+    lazy_static! {
+        static ref SCREEN: Mutex<Screen> = Mutex::new(Screen {
+            width: 0,
+            height: 0,
+            cells: Vec::new(),
+            cursor: None,
+        });
+    }
+
+    pub unsafe fn tb_init() -> c_int {
+        let (w, h) = match terminal::size() {
+            Ok(size) => size,
+            Err(_) => return -1,
+        };
+        if terminal::enable_raw_mode().is_err() {
+            return -1;
+        }
+        let mut out = stdout();
+        if queue!(out, terminal::EnterAlternateScreen, cursor::Hide).is_err() ||
+           out.flush().is_err() {
+            return -1;
+        }
+        SCREEN.lock().unwrap().resize(w as c_int, h as c_int);
+        0
+    }
+
+    pub unsafe fn tb_shutdown() {
+        let mut out = stdout();
+        let _ = queue!(out, style::ResetColor, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = out.flush();
+        let _ = terminal::disable_raw_mode();
+    }
+
+    pub unsafe fn tb_width() -> c_int {
+        SCREEN.lock().unwrap().width
+    }
+
+    pub unsafe fn tb_height() -> c_int {
+        SCREEN.lock().unwrap().height
+    }
+
+    pub unsafe fn tb_clear() {
+        let mut screen = SCREEN.lock().unwrap();
+        for cell in screen.cells.iter_mut() {
+            *cell = (' ' as u32, TB_DEFAULT, TB_DEFAULT);
+        }
+    }
+
+    pub unsafe fn tb_set_cursor(cx: c_int, cy: c_int) {
+        let mut screen = SCREEN.lock().unwrap();
+        screen.cursor = if cx == TB_HIDE_CURSOR || cy == TB_HIDE_CURSOR {
+            None
+        } else {
+            Some((cx, cy))
+        };
+    }
+
+    pub unsafe fn tb_change_cell(x: c_int, y: c_int, ch: u32, fg: u16, bg: u16) {
+        let mut screen = SCREEN.lock().unwrap();
+        if x < 0 || y < 0 || x >= screen.width || y >= screen.height {
+            return;
+        }
+        let idx = (y * screen.width + x) as usize;
+        screen.cells[idx] = (ch, fg, bg);
+    }
+
+    pub unsafe fn tb_present() {
+        let screen = SCREEN.lock().unwrap();
+        let mut out = stdout();
+        let _ = queue!(out, cursor::Hide);
+        let mut last_style = None;
+        for y in 0..screen.height {
+            let _ = queue!(out, cursor::MoveTo(0, y as u16));
+            for x in 0..screen.width {
+                let (ch, fg, bg) = screen.cells[(y * screen.width + x) as usize];
+                if last_style != Some((fg, bg)) {
+                    let _ = queue!(out,
+                                   style::SetAttribute(Attribute::Reset),
+                                   style::SetForegroundColor(color(fg)),
+                                   style::SetBackgroundColor(color(bg)));
+                    if fg & TB_BOLD != 0 {
+                        let _ = queue!(out, style::SetAttribute(Attribute::Bold));
+                    }
+                    if fg & TB_UNDERLINE != 0 {
+                        let _ = queue!(out, style::SetAttribute(Attribute::Underlined));
+                    }
+                    if fg & TB_REVERSE != 0 {
+                        let _ = queue!(out, style::SetAttribute(Attribute::Reverse));
+                    }
+                    last_style = Some((fg, bg));
+                }
+                let ch = ::std::char::from_u32(ch).unwrap_or('?');
+                let _ = queue!(out, style::Print(ch));
+            }
+        }
+        let _ = queue!(out, style::SetAttribute(Attribute::Reset));
+        if let Some((cx, cy)) = screen.cursor {
+            let _ = queue!(out, cursor::MoveTo(cx as u16, cy as u16), cursor::Show);
+        }
+        let _ = out.flush();
+    }
+
+    pub unsafe fn tb_poll_event(ev: *mut RawEvent) -> c_int {
+        loop {
+            let event = match event::read() {
+                Ok(event) => event,
+                Err(_) => return -1,
+            };
+            let mut raw = RawEvent { etype: 0, emod: 0, key: 0, ch: 0, w: 0, h: 0, x: 0, y: 0 };
+            match event {
+                Event::Key(key) => {
+                    if key.kind == KeyEventKind::Release {
+                        continue;
+                    }
+                    if key.modifiers.contains(KeyModifiers::ALT) {
+                        raw.emod = TB_MOD_ALT;
+                    }
+                    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+                    match key.code {
+                        KeyCode::Char(c) if ctrl && c.is_ascii_alphabetic() => {
+                            raw.key = TB_KEY_CTRL_A + (c.to_ascii_lowercase() as u16 - 'a' as u16);
+                        },
+                        KeyCode::Char(' ') => raw.key = TB_KEY_SPACE,
+                        KeyCode::Char(c) => raw.ch = c as u32,
+                        KeyCode::Backspace => raw.key = TB_KEY_BACKSPACE2,
+                        KeyCode::Enter => raw.key = TB_KEY_ENTER,
+                        KeyCode::Tab => raw.key = TB_KEY_TAB,
+                        KeyCode::Esc => raw.key = TB_KEY_ESC,
+                        KeyCode::Left => raw.key = TB_KEY_ARROW_LEFT,
+                        KeyCode::Right => raw.key = TB_KEY_ARROW_RIGHT,
+                        KeyCode::Up => raw.key = TB_KEY_ARROW_UP,
+                        KeyCode::Down => raw.key = TB_KEY_ARROW_DOWN,
+                        KeyCode::Home => raw.key = TB_KEY_HOME,
+                        KeyCode::End => raw.key = TB_KEY_END,
+                        KeyCode::PageUp => raw.key = TB_KEY_PGUP,
+                        KeyCode::PageDown => raw.key = TB_KEY_PGDN,
+                        KeyCode::Delete => raw.key = TB_KEY_DELETE,
+                        KeyCode::Insert => raw.key = TB_KEY_INSERT,
+                        KeyCode::F(n) if n >= 1 && n <= 12 => raw.key = TB_KEY_F1 - (n as u16 - 1),
+                        _ => continue,
+                    }
+                    raw.etype = TB_EVENT_KEY;
+                },
+                Event::Resize(w, h) => {
+                    SCREEN.lock().unwrap().resize(w as c_int, h as c_int);
+                    raw.etype = TB_EVENT_RESIZE;
+                    raw.w = w as i32;
+                    raw.h = h as i32;
+                },
+                Event::Mouse(mouse) => {
+                    raw.etype = TB_EVENT_MOUSE;
+                    raw.x = mouse.column as i32;
+                    raw.y = mouse.row as i32;
+                },
+                _ => continue,
+            }
+            *ev = raw;
+            return raw.etype as c_int;
+        }
+    }
+
+    fn color(attr: u16) -> Color {
+        match attr & 0xff {
+            TB_BLACK => Color::Black,
+            TB_RED => Color::DarkRed,
+            TB_GREEN => Color::DarkGreen,
+            TB_YELLOW => Color::DarkYellow,
+            TB_BLUE => Color::DarkBlue,
+            TB_MAGENTA => Color::DarkMagenta,
+            TB_CYAN => Color::DarkCyan,
+            TB_WHITE => Color::Grey,
+            _ => Color::Reset,
+        }
+    }
+}
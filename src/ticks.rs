@@ -0,0 +1,73 @@
+//! Waking up the main loop when something on the screen is due to change.
+//!
+//! Instead of ticking every second, the TUI says when it needs the next tick (see
+//! `TUI::next_tick`), and a timer thread sends it on a channel at that time, so that it fits in
+//! the main loop. When nothing changes, e.g. when the queue is shown and nothing is playing, it
+//! does not wake up at all until an event arrives.
+
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use chan;
+use time::{get_time, Timespec};
+
+/// Tells the timer thread when to send the next tick
+pub struct Ticker {
+    deadline_s: mpsc::Sender<Option<Timespec>>,
+    /// The deadline that the timer thread was told about last
+    deadline: Option<Timespec>,
+}
+
+impl Ticker {
+    /// Send the next tick at `deadline` at the latest, or never if it is `None`
+    ///
+    /// A tick that is due earlier is kept: one that turns out to be too early does no harm, but
+    /// one that is put off by every event might never come. A deadline in the past sends the
+    /// tick right away.
+    pub fn wake_at(&mut self, deadline: Option<Timespec>) {
+        let earlier = match (deadline, self.deadline) {
+            (Some(new), Some(old)) => new < old,
+            (new, old) => new.is_some() && old.is_none(),
+        };
+        if earlier {
+            self.deadline = deadline;
+            // the thread only quits when we are dropped
+            let _ = self.deadline_s.send(deadline);
+        }
+    }
+
+    /// Note that a tick was received, after which the timer thread waits for a new deadline
+    pub fn ticked(&mut self) {
+        self.deadline = None;
+    }
+}
+
+/// Start the timer thread
+pub fn ticker() -> (Ticker, chan::Receiver<()>) {
+    let (deadline_s, deadline_r) = mpsc::channel::<Option<Timespec>>();
+    let (tick_s, tick_r) = chan::sync(0);
+    thread::spawn(move || {
+        let mut deadline: Option<Timespec> = None;
+        loop {
+            let received = match deadline {
+                None => deadline_r.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                Some(deadline) => {
+                    // a deadline that passed already only waits for a newer one that was sent
+                    let timeout = (deadline - get_time()).to_std();
+                    let timeout = timeout.unwrap_or(Duration::from_secs(0));
+                    deadline_r.recv_timeout(timeout)
+                },
+            };
+            match received {
+                Ok(new_deadline) => deadline = new_deadline,
+                Err(RecvTimeoutError::Timeout) => {
+                    tick_s.send(());
+                    deadline = None;
+                },
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    });
+    (Ticker { deadline_s: deadline_s, deadline: None }, tick_r)
+}
@@ -1,26 +1,57 @@
 use std::borrow::Cow;
+use std::cell::Cell;
 use std::char;
 use std::cmp::{max, min};
 use std::collections::BTreeMap;
-use std::env;
 use std::error::Error;
 use std::fmt;
-use std::fs;
-use std::iter::repeat;
-use std::os::unix::fs::OpenOptionsExt;
+use std::iter::{once, repeat};
+use std::mem;
+use std::path::PathBuf;
+use std::process::Child;
 use std::thread;
 
 use chan;
 use lru_time_cache::LruCache;
 use regex::Regex;
-use rustc_serialize::json::Json;
-use strsim::levenshtein;
-use termbox::*;
-use time::{Duration, get_time};
+use rustc_serialize::Decodable;
+use rustc_serialize::json::{self, Json, ToJson};
+use term::*;
+use time::{Duration, get_time, Timespec};
 use toml;
 
-use libclient::{Client, ClientError, md5, Message, RequestStatus};
+use libclient::{Client, ClientError, ConnectionState, DisplayChain, Media, Message,
+                MAX_LEFT_OUT_RESULTS, Request};
+use libclient::app::{AppState, LoginPrompt};
+use libclient::cache::MediaCache;
+use libclient::library::Library;
+use libclient::packets::Direction;
+use libclient::queue::{self, Change};
+use libclient::report::append_report;
+use libclient::stats::Stats;
+use libclient::suggest::suggest;
+use libclient::sync::{open_synced, sync_media};
+use libclient::tasks::{TaskCompletion, TaskId};
+use libclient::watchlist::Watchlist;
+use actions;
+use aliases;
+use clipboard::{self, Copied};
+use commands::{self, Resolved};
+use config::Config;
+use cover::{self, Graphics};
+use fifo::RemoteCommand;
+use format::{format_bytes, format_duration, Eta, TimeFormat};
+use credentials::{CredentialError, CredentialStore, Credentials, EncryptedStore, PlainStore};
+use hooks::Hooks;
+use lyrics;
+use notify::Notifier;
+use paths;
+use preview::{Preview, resolve_url};
+use selection::Selection;
+use signals::{self, Signal};
 use store;
+use template::{self, StatusFields};
+use ticks::{self, Ticker};
 
 macro_rules! cleanup {
     ( $ret:expr ) => {
@@ -53,16 +84,49 @@ macro_rules! clean_assert {
     }
 }
 
-const CMD_USERNAME: &'static str = "username";
-const CMD_PASSWORD: &'static str = "password";
-const CMD_QUIT: &'static str = "quit";
-const COMMANDS: [&'static str; 3] = [
-    CMD_USERNAME, CMD_PASSWORD, CMD_QUIT,
+/// The fields of the `:edit` form, by the name that the server knows them by and their label
+const EDIT_FIELDS: [(&'static str, &'static str); 3] = [
+    ("artist", "Artist"), ("title", "Title"), ("album", "Album"),
 ];
+
+/// The actions that are built in: copying "Artist – Title" and copying the media key
+///
+/// An action with the same key in the `[actions]` of the config file replaces them.
+const BUILTIN_ACTIONS: [char; 2] = ['y', 'Y'];
+
+/// The smallest terminal that we can draw in, anything smaller shows a placeholder
+const MIN_WIDTH: i32 = 20;
+const MIN_HEIGHT: i32 = 5;
 const MIN_STATUS_WIDTH: usize = 30;
 const MAX_STATUS_WIDTH: usize = 60;
 const STATUS_TIMEOUT_MILLIS: u64 = 5000;
-const QM_BUFFER_SIZE: usize = 5000;
+const TOAST_SECS: i64 = 6;
+const MAX_TOASTS: usize = 3;
+const MAX_TOAST_WIDTH: usize = 50;
+const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+/// The size of the cover art in the `:playing` view, in cells
+const COVER_COLS: i32 = 24;
+const COVER_ROWS: i32 = 12;
+/// The split layout is only used if both panes are at least this wide
+const SPLIT_MIN_PANE_WIDTH: i32 = 60;
+/// How much of the width the queue gets in the split layout at least and at most, in percent,
+/// and how much `<` and `>` change it
+const SPLIT_MIN_PERCENT: i32 = 20;
+const SPLIT_MAX_PERCENT: i32 = 80;
+const SPLIT_STEP_PERCENT: i32 = 5;
+/// How long requests that changed in the queue are highlighted
+const QUEUE_CHANGE_SECS: i64 = 3;
+/// How many songs the `:suggest` view shows
+const SUGGESTIONS: usize = 50;
+/// How often the screen is redrawn at most in low bandwidth mode
+const LOW_BANDWIDTH_DRAW_MILLIS: u64 = 250;
+/// How long typing has to stop before the query is searched for
+const SEARCH_DEBOUNCE_MILLIS: i64 = 150;
+/// How long to wait between polls of the server while idle, see `go_idle`
+const IDLE_POLL_DELAY_SECS: u64 = 30;
+
+/// The format of the session file
+const SESSION_SCHEMA: store::Schema = store::Schema { migrations: &[] };
 
 #[derive(Debug)]
 pub enum TUIError {
@@ -70,20 +134,28 @@ pub enum TUIError {
     Quit,
 }
 
-enum Secret {
-    AccessKey(String),
-    PasswordHash(String),
+/// The `:edit` form, for changing the metadata of a song
+struct EditForm {
+    /// The song as it was when the form was opened
+    media: Media,
+    /// The values of `EDIT_FIELDS`
+    values: Vec<String>,
+    /// The field that is being edited
+    focus: usize,
 }
 
-impl fmt::Debug for Secret {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            Secret::AccessKey(_) => write!(f, "Secret::AccessKey(*****)"),
-            Secret::PasswordHash(_) => write!(f, "Secret::PasswordHash(*****)"),
-        }
-    }
+/// The `:console` view, with the packets that were exchanged with the server
+#[derive(Default)]
+struct Console {
+    /// The JSON of a message to send, as it is typed
+    input: String,
+    /// The message that was typed, which is sent once the user confirms it
+    pending: Option<Json>,
+    /// How many packets the view is scrolled back from the newest
+    offset: usize,
 }
 
+#[derive(Clone, Copy)]
 enum StatusType {
     Info,    // blue
     Success, // green
@@ -91,19 +163,421 @@ enum StatusType {
     Error,   // red
 }
 
+impl StatusType {
+    fn color(&self) -> u16 {
+        match *self {
+            StatusType::Info => TB_BLUE,
+            StatusType::Success => TB_GREEN,
+            StatusType::Warning => TB_YELLOW,
+            StatusType::Error => TB_RED,
+        }
+    }
+}
+
+/// A view that is open over the queue view, see `TUI::views`
+enum View {
+    /// A search that was left to open another view, with its query, the selection and the row
+    /// that was selected; it is searched again when that view is closed
+    Search(String, Selection<String>, usize),
+    /// The `:playing` view, with the details of the song that is playing
+    Playing,
+    /// The details of a song
+    Details(Media),
+    /// The songs of an artist, from the local media index
+    Artist(String, Vec<Media>, Selection<String>),
+    /// The songs that a user uploaded, from the local media index
+    Uploads(String, Vec<Media>, Selection<String>),
+    /// The `:suggest` view, with songs like the ones that were played lately
+    Suggestions(Vec<Media>, Selection<String>),
+    /// The `:party` view, with how the queue is shared between the requesters
+    Party,
+    /// The `:help` view, with the commands and the aliases
+    Help,
+}
+
+/// A message about something that happened on the server, shown at the top right for a while
+struct Toast {
+    msg: String,
+    ty: StatusType,
+    until: Timespec,
+}
+
+/// What a `:` command takes after its name, with the name that its usage gives it
+#[derive(Clone, Copy, Debug)]
+enum CommandArgs {
+    None,
+    Optional(&'static str),
+    Required(&'static str),
+}
+
+/// Does a `:` command, with the rest of the line after its name
+type CommandHandler = fn(&mut TUI, Option<&str>) -> Result<(), TUIError>;
+
+/// A `:` command of the terminal UI
+struct Command {
+    name: &'static str,
+    args: CommandArgs,
+    /// What it does, for `:help`
+    help: &'static str,
+    /// Is the argument hidden while it is typed?
+    secret: bool,
+    handler: CommandHandler,
+}
+
+impl Command {
+    /// How the command is typed, e.g. `:uploads [USER]`
+    fn usage(&self) -> String {
+        match self.args {
+            CommandArgs::None => format!(":{}", self.name),
+            CommandArgs::Optional(arg) => format!(":{} [{}]", self.name, arg),
+            CommandArgs::Required(arg) => format!(":{} {}", self.name, arg),
+        }
+    }
+
+    /// Can it only be run with an argument?
+    fn needs_args(&self) -> bool {
+        match self.args {
+            CommandArgs::Required(_) => true,
+            CommandArgs::None | CommandArgs::Optional(_) => false,
+        }
+    }
+}
+
+/// The `:` commands, in the order in which `:help` lists them
+///
+/// Running a command, completing its name, expanding aliases, `:help` and the `command` of the
+/// command pipe all look the commands up here, so a new command only has to be registered.
+struct CommandRegistry {
+    commands: Vec<Command>,
+}
+
+impl CommandRegistry {
+    /// The commands that are built in
+    fn builtin() -> CommandRegistry {
+        let mut registry = CommandRegistry { commands: Vec::new() };
+        let commands: [(&'static str, CommandArgs, &'static str, bool, CommandHandler); 16] = [
+            ("username", CommandArgs::Required("USERNAME"), "Log in as USERNAME", false,
+             TUI::do_command_username),
+            ("password", CommandArgs::Required("PASSWORD"), "Log in with PASSWORD", true,
+             TUI::do_command_password),
+            ("passphrase", CommandArgs::Required("PASSPHRASE"), "Unlock the saved credentials",
+             true, TUI::do_command_passphrase),
+            ("uploads", CommandArgs::Optional("USER"), "Show the songs that USER (or you) uploaded",
+             false, TUI::do_command_uploads),
+            ("suggest", CommandArgs::None, "Suggest songs like the ones that were played lately",
+             false, TUI::do_command_suggest),
+            ("party", CommandArgs::None, "Show how the queue is shared between the requesters",
+             false, TUI::do_command_party),
+            ("dnd", CommandArgs::None, "Switch notifications and hooks off, or on again", false,
+             TUI::do_command_dnd),
+            ("relevance", CommandArgs::None, "Show the results that match the query best first",
+             false, TUI::do_command_relevance),
+            ("playing", CommandArgs::None, "Show the song that is playing", false,
+             TUI::do_command_playing),
+            ("report", CommandArgs::Optional("REASON"), "Report the selected song as broken",
+             false, TUI::do_command_report),
+            ("edit", CommandArgs::None, "Change the tags of the selected song", false,
+             TUI::do_command_edit),
+            ("suspend", CommandArgs::None, "Go back to the shell, until `fg`", false,
+             TUI::do_command_suspend),
+            ("quit", CommandArgs::None, "Quit maruska", false, TUI::do_command_quit),
+            ("raw", CommandArgs::Required("MESSAGE"), "Send MESSAGE, a JSON object, to the server",
+             false, TUI::do_command_raw),
+            ("console", CommandArgs::None, "Type messages to the server and see its packets",
+             false, TUI::do_command_console),
+            ("help", CommandArgs::Optional("COMMAND"), "Show the commands, or what COMMAND does",
+             false, TUI::do_command_help),
+        ];
+        for &(name, args, help, secret, handler) in commands.iter() {
+            registry.register(name, args, help, secret, handler);
+        }
+        registry
+    }
+
+    /// Add a command, which replaces the command with the same name if there is one
+    fn register(&mut self, name: &'static str, args: CommandArgs, help: &'static str,
+                secret: bool, handler: CommandHandler) {
+        let command = Command { name: name, args: args, help: help, secret: secret,
+                                handler: handler };
+        match self.commands.iter().position(|x| x.name == name) {
+            Some(idx) => self.commands[idx] = command,
+            None => self.commands.push(command),
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<&Command> {
+        self.commands.iter().find(|x| x.name == name)
+    }
+
+    /// Is the argument hidden while it is typed, for a command that `prefix` may be the start of?
+    fn hides_args(&self, prefix: &str) -> bool {
+        !prefix.is_empty() && self.commands.iter().any(|x| x.secret && x.name.starts_with(prefix))
+    }
+
+    /// The names of the commands, e.g. for `commands::resolve`
+    fn names(&self) -> Vec<&'static str> {
+        self.commands.iter().map(|x| x.name).collect()
+    }
+}
+
 pub struct TUI {
-    client: Client,
-    username: Option<String>,
-    secret: Option<Secret>,
-    results_offset: usize,
-    results_focus: usize,
+    /// The client, and who we log in as
+    app: AppState,
+    /// The selected search result, see `search_results`
+    results_selection: Selection<String>,
     query: String,
+
+    /// Were the search results browsed with the arrow keys since the query was last edited?
+    /// Then Space marks the selected song, instead of typing a space.
+    browsing_results: bool,
+
+    /// The songs that were marked in the search results, to be requested together, in the order
+    /// in which they were marked
+    marked: Vec<Media>,
+
+    /// Is the list of marked songs shown, to confirm that they should be requested?
+    confirm_marked: bool,
+
+    /// The request that is selected in the queue view, if any
+    requests_selection: Selection<i64>,
+
+    /// Where the selected request was in the queue view after the last key, to tell when an
+    /// update of the queue moves it
+    requests_focus_position: Option<usize>,
+
+    /// When an update of the queue last moved the selected request
+    requests_focus_moved_at: Timespec,
+
+    /// The request that Delete was pressed for right after it moved, see `do_manage_request`
+    confirm_remove: Option<i64>,
+
+    /// The moves of requests in the queue view that were not sent to the server yet, as
+    /// `(request key, amount)` in the order in which they were made
+    staged_moves: Vec<(i64, i64)>,
+
+    /// The queue as the server last sent it, to find out what changed when it sends a new one
+    last_queue: Option<Vec<Request>>,
+
+    /// The requests that changed in the last update of the queue and how, highlighted until
+    /// `QUEUE_CHANGE_SECS` after `queue_changed_at`
+    queue_changes: BTreeMap<i64, Change>,
+    queue_changed_at: Timespec,
+
+    /// Show the queue and the search results side by side, if the terminal is wide enough
+    split_layout: bool,
+
+    /// The template of the line at the top of the screen, if there is one
+    header: Option<String>,
+
+    /// The template of the status line when there is no message, instead of the clock
+    status_template: Option<String>,
+
+    /// In the split layout, do the keys go to the queue instead of to the search results?
+    queue_focused: bool,
+
+    /// How much of the width the queue gets in the split layout, in percent
+    split_percent: i32,
+
+    /// The columns that the queue or the search results are drawn in, as the first column and
+    /// the width, while the split layout is drawn
+    pane: Cell<Option<(i32, i32)>>,
+
+    /// The views that are open over the queue view, the one that is shown last
+    ///
+    /// Escape and Backspace close the view that is shown, and go back to the one before it as it
+    /// was left.
+    views: Vec<View>,
+
+    /// The task that is looking up uploads for the `:uploads` view, and whose uploads they are
+    uploads_task: Option<(TaskId, String)>,
+
+    /// The task that is looking up the songs of an artist for an artist view, and the artist
+    artist_task: Option<(TaskId, String)>,
+
+    /// The task that is looking for songs for the `:suggest` view
+    suggest_task: Option<TaskId>,
+
+    /// The `:edit` form, while it is open
+    edit_form: Option<EditForm>,
+
+    /// The `:console` view, if it is open
+    console: Option<Console>,
+
+    /// The songs that we changed as they were before, by media key, until the server accepts the
+    /// changes; to undo them in the `:uploads` view, the client undoes them everywhere else
+    pending_edits: BTreeMap<String, Media>,
+
     status: LruCache<(), (Cow<'static, str>, StatusType)>,
+
+    /// The toasts that are shown, the newest last
+    toasts: Vec<Toast>,
+
+    /// Show events from the server in toasts, instead of on the status line
+    show_toasts: bool,
+
+    /// Did we tell that the connection to the server was lost, and not that it is back yet?
+    connection_lost: bool,
+
+    /// The frame of the spinner that shows that we are waiting for something, see `busy`
+    spinner_frame: usize,
+
+    /// Save the session on exit, so that it can be restored on the next launch
+    restore_session: bool,
+
+    /// The selected row from the restored session, which is selected once enough results have
+    /// arrived
+    restored_focus: Option<usize>,
+
+    /// Why the previous session crashed, shown until the user presses a key
+    crash_message: Option<String>,
+
+    /// Keep the saved credentials in the encrypted store
+    encrypt_credentials: bool,
+
+    /// The passphrase of the encrypted store, once the user has entered it
+    passphrase: Option<String>,
+
+    /// The credentials should be saved as soon as the user has chosen a passphrase
+    credentials_pending: bool,
+
+    /// Show the messages that the client does not know, since the user sent one with `:raw`
+    show_raw: bool,
+
+    /// The local media index, if there is one and we should keep it up to date
+    media_cache_file: Option<PathBuf>,
+
+    /// How often to sync the local media index
+    media_sync_interval: Duration,
+
+    /// When to start the next sync of the local media index
+    next_media_sync: Timespec,
+
+    /// The task that is syncing the local media index, while it is running
+    media_sync_task: Option<TaskId>,
+
+    /// The songs of the local media index, which answer the searches if `local_search` is set
+    library: Option<Library>,
+
+    /// How to show times
+    time_format: TimeFormat,
+
+    /// The command that previews songs, if the user set one
+    preview_command: Option<String>,
+
+    /// How long previews take
+    preview_duration: Duration,
+
+    /// The song that is being previewed
+    preview: Option<Preview>,
+
+    /// How to draw cover art, if the terminal can draw images at all
+    cover_graphics: Graphics,
+
+    /// Look up cover art on MusicBrainz if the server has none
+    cover_lookup: bool,
+
+    /// The media key of the song whose cover we fetched, and the file with it if there is one
+    cover: Option<(String, Option<PathBuf>)>,
+
+    /// The task that is fetching a cover, and the media key of its song
+    cover_task: Option<(TaskId, String)>,
+
+    /// The cover that is on the screen, so that we do not send it again with every redraw
+    cover_drawn: Option<PathBuf>,
+
+    /// The command that looks up lyrics, if the user set one
+    lyrics_command: Option<String>,
+
+    /// The media key of the song whose lyrics we looked up, and the lines of the lyrics
+    lyrics: Option<(String, Vec<String>)>,
+
+    /// The task that is looking up lyrics, and the media key of its song
+    lyrics_task: Option<(TaskId, String)>,
+
+    /// The first line of the lyrics that is on the screen
+    lyrics_offset: usize,
+
+    /// The commands from the `[actions]` table of the config file, by the key that runs them
+    actions: BTreeMap<char, String>,
+
+    /// What the names of the `[aliases]` table of the config file stand for
+    aliases: BTreeMap<String, String>,
+    /// The `:` commands
+    commands: CommandRegistry,
+
+    /// Was Ctrl-A pressed, so that the next key runs an action?
+    action_pending: bool,
+
+    /// The actions that are still running, with their keys
+    running_actions: Vec<(char, Child)>,
+
+    /// The file in which we count how often songs were requested and played, if there is one
+    stats_file: Option<PathBuf>,
+
+    /// How often songs were requested and played
+    stats: Stats,
+
+    /// Show the most popular songs first in the search results
+    popular_first: bool,
+
+    /// Send a desktop notification when one of our requests is up next
+    notify_next_up: bool,
+
+    /// Send a desktop notification when a sync finds a new song that matches a saved search
+    notify_watchlist: bool,
+
+    /// Sends the desktop notifications, except in the quiet hours or when `:dnd` is on
+    notifier: Notifier,
+
+    /// The key of our request that we last told is up next, so that we tell it once
+    next_up: Option<i64>,
+
+    /// Runs the scripts in the hooks directory
+    hooks: Hooks,
+
+    /// Draw as little as possible, without colors, for slow connections
+    low_bandwidth: bool,
+
+    /// When the screen was last drawn
+    last_draw: Timespec,
+
+    /// Was a draw skipped because the last one was too recent? Then the next tick draws.
+    draw_pending: bool,
+
+    /// Sends the ticks when something on the screen changes or a job is due, see `next_tick`
+    ticker: Ticker,
+
+    /// When the query that is being typed is searched for, see `search_soon`
+    search_due: Option<Timespec>,
+
+    /// The status message that was shown last and when it is gone, to draw the screen then
+    status_shown: Option<(Cow<'static, str>, Timespec)>,
+
+    /// Go idle after this long without input or a new song, or never if it is `None`
+    idle_after: Option<Duration>,
+
+    /// When a key was last pressed or a new song started
+    last_activity: Timespec,
+
+    /// Are we idle? Then the server is polled less often and nothing ticks, see `go_idle`.
+    idle: bool,
+
+    /// Print the summary of the session on quit, see `session_summary`
+    print_summary: bool,
+
+    /// When we started, the songs that started playing since then and the requests from here
+    /// that were accepted
+    session_started: Timespec,
+    songs_played: u64,
+    songs_requested: u64,
 }
 
 impl fmt::Display for TUIError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.description())
+        match *self {
+            TUIError::Client(ref err) => write!(f, "{}", err),
+            TUIError::Quit => write!(f, "quit"),
+        }
     }
 }
 
@@ -120,49 +594,163 @@ impl Error for TUIError {
             TUIError::Quit => "quit",
         }
     }
+
+    fn source(&self) -> Option<&(Error + 'static)> {
+        match *self {
+            // The client error describes itself, so we only pass on its cause
+            TUIError::Client(ref err) => err.source(),
+            TUIError::Quit => None,
+        }
+    }
 }
 
 impl TUI {
-    pub fn new(url: &str) -> Result<(TUI, (chan::Receiver<Json>,
-                                    chan::Receiver<RawEvent>,
-                                    chan::Receiver<chan::Sender<()>>)), TUIError> {
-        // shadow the `Duration` from the one of the `time` crate
-        use std::time::Duration;
-
+    pub fn new(url: &str, config: &Config, low_bandwidth: bool, safe_mode: bool)
+               -> Result<(TUI, (chan::Receiver<Json>,
+                                chan::Receiver<TaskCompletion>,
+                                chan::Receiver<RawEvent>,
+                                chan::Receiver<()>)), TUIError> {
         // initialize client
-        let (mut client, client_r) = match Client::new(url) {
+        let (client, client_r) = match Client::new(url) {
             Ok((client, client_r)) => (client, client_r),
             Err(err) => return Err(TUIError::from(err)),
         };
+        Ok(TUI::with_client(client, client_r, config, low_bandwidth, safe_mode))
+    }
+
+    /// The terminal UI for `client`, which does not have to be connected to a server
+    ///
+    /// If it is not (see `Client::offline`), we do not log in, and what it shows does not count
+    /// in the stats or end up in the local media index of a real server. In `safe_mode`, after a
+    /// crash, no hook scripts are run.
+    pub fn with_client(mut client: Client, client_r: chan::Receiver<Json>, config: &Config,
+                       low_bandwidth: bool, safe_mode: bool) -> (TUI, (chan::Receiver<Json>,
+                                                     chan::Receiver<TaskCompletion>,
+                                                     chan::Receiver<RawEvent>,
+                                                     chan::Receiver<()>)) {
+        // shadow the `Duration` from the one of the `time` crate
+        use std::time::Duration;
+
+        let offline = client.is_offline();
+        let url = client.get_url();
+        client.set_audit_log(paths::audit_file());
         client.follow_all();
         client.serve();
+        let task_r = client.task_receiver();
 
         // initialize (user) event listener
         let tui_r = TUI::serve_events();
 
-        // initialize event clock
-        let tick_r = chan::tick(Duration::from_secs(1));
-
+        // initialize event clock, which only ticks when something is due, see `next_tick`
+        let (ticker, tick_r) = ticks::ticker();
 
         // initialize user interface
         unsafe { tb_init(); }
 
         let status_ttl = Duration::from_millis(STATUS_TIMEOUT_MILLIS);
         let mut status = LruCache::with_expiry_duration_and_capacity(status_ttl, 1);
-        status.insert((), (Cow::from(format!("Connected to {}", url)), StatusType::Success));
+        let msg = if offline {
+            String::from("Not connected to a server")
+        } else {
+            format!("Connected to {}", url)
+        };
+        status.insert((), (Cow::from(msg), StatusType::Success));
         let mut tui = TUI {
-            client: client,
-            username: None,
-            secret: None,
-            results_offset: 0,
-            results_focus: 0,
+            app: AppState::new(client),
+            results_selection: Selection::new(),
             query: String::new(),
+            browsing_results: false,
+            marked: Vec::new(),
+            confirm_marked: false,
+            requests_selection: Selection::new(),
+            requests_focus_position: None,
+            requests_focus_moved_at: Timespec::new(0, 0),
+            confirm_remove: None,
+            staged_moves: Vec::new(),
+            last_queue: None,
+            queue_changes: BTreeMap::new(),
+            queue_changed_at: Timespec::new(0, 0),
+            split_layout: false,
+            header: None,
+            status_template: None,
+            queue_focused: false,
+            split_percent: 50,
+            pane: Cell::new(None),
+            views: Vec::new(),
+            uploads_task: None,
+            artist_task: None,
+            suggest_task: None,
+            edit_form: None,
+            console: None,
+            pending_edits: BTreeMap::new(),
             status: status,
+            toasts: Vec::new(),
+            show_toasts: false,
+            connection_lost: false,
+            spinner_frame: 0,
+            restore_session: false,
+            restored_focus: None,
+            crash_message: None,
+            encrypt_credentials: false,
+            passphrase: None,
+            credentials_pending: false,
+            show_raw: false,
+            media_cache_file: None,
+            media_sync_interval: ::time::Duration::zero(),
+            next_media_sync: get_time(),
+            media_sync_task: None,
+            library: None,
+            time_format: config.time_format,
+            preview_command: None,
+            preview_duration: ::time::Duration::zero(),
+            preview: None,
+            cover_graphics: Graphics::None,
+            cover_lookup: false,
+            cover: None,
+            cover_task: None,
+            cover_drawn: None,
+            lyrics_command: None,
+            lyrics: None,
+            lyrics_task: None,
+            lyrics_offset: 0,
+            actions: BTreeMap::new(),
+            aliases: BTreeMap::new(),
+            commands: CommandRegistry::builtin(),
+            action_pending: false,
+            running_actions: Vec::new(),
+            stats_file: None,
+            stats: Stats::default(),
+            popular_first: false,
+            notify_next_up: false,
+            notify_watchlist: false,
+            notifier: Notifier::default(),
+            next_up: None,
+            hooks: Hooks::new(if safe_mode { None } else { paths::hooks_dir() }),
+            low_bandwidth: low_bandwidth,
+            last_draw: Timespec::new(0, 0),
+            draw_pending: false,
+            ticker: ticker,
+            search_due: None,
+            status_shown: None,
+            idle_after: None,
+            last_activity: get_time(),
+            idle: false,
+            print_summary: false,
+            session_started: get_time(),
+            songs_played: 0,
+            songs_requested: 0,
         };
-        tui.load_credentials();
-        tui.try_login();
+        tui.apply_config(config);
+        if !offline {
+            tui.load_stats();
+            tui.load_credentials();
+            tui.app.try_login();
+        }
+        if tui.restore_session && tui.query.is_empty() {
+            tui.load_session();
+        }
 
-        Ok((tui, (client_r, tui_r, tick_r)))
+        (tui, (client_r, task_r, tui_r, tick_r))
     }
 
     pub fn serve_events() -> chan::Receiver<RawEvent> {
@@ -190,162 +778,1565 @@ impl TUI {
         }
     }
 
-    fn try_login(&mut self) -> bool {
-        match (&self.username, &self.secret) {
-            (&Some(ref username), &Some(Secret::PasswordHash(ref secret))) =>
-                self.client.do_login(username, secret),
-            (&Some(ref username), &Some(Secret::AccessKey(ref secret))) =>
-                self.client.do_login_accesskey(username, secret),
-            _ => return false,
-        };
-        true
-        // TODO show some visual feedback "logging in..."
+    /// Tell the user that what they did waits for a login, and ask for `prompt` if it is needed
+    fn ask_login(&mut self, prompt: Option<LoginPrompt>) {
+        match prompt {
+            Some(prompt) => {
+                self.status.insert((), (Cow::from("Not logged in"), StatusType::Warning));
+                self.prompt_login(prompt);
+            },
+            None => {
+                let msg = "Logging in, it is sent after that";
+                self.status.insert((), (Cow::from(msg), StatusType::Info));
+            },
+        }
     }
 
-    fn update_client_query(&mut self) {
-        if self.query.starts_with('/') {
-            self.client.update_query(Some(&self.query[1..]), self.results_offset + QM_BUFFER_SIZE);
-        } else {
-            self.client.update_query(None, 0);
+    /// Start typing the command that gives what `prompt` asks for
+    fn prompt_login(&mut self, prompt: LoginPrompt) {
+        self.query = String::from(match prompt {
+            LoginPrompt::Username => ":username ",
+            LoginPrompt::Password => ":password ",
+        });
+    }
+
+    /// Start the background jobs that are due, this is called when `next_tick` asked for it
+    ///
+    /// Returns whether the screen should be redrawn. Normally it is, to show what changed, but in
+    /// low bandwidth mode only if a draw was skipped.
+    pub fn handle_tick(&mut self) -> bool {
+        self.ticker.ticked();
+        if !self.idle && self.idle_after.map_or(false, |x| get_time() - self.last_activity >= x) {
+            self.go_idle();
+        }
+        if self.search_due.map_or(false, |x| x <= get_time()) {
+            self.search_due = None;
+            self.update_client_query();
         }
+        if self.media_cache_file.is_some() && self.media_sync_task.is_none() &&
+           get_time() >= self.next_media_sync {
+            self.next_media_sync = get_time() + self.media_sync_interval;
+            self.start_media_sync();
+        }
+        if self.preview.as_mut().map_or(false, |x| x.is_over()) {
+            self.preview = None; // stops the player
+        }
+        self.reap_actions();
+        let now = get_time();
+        self.toasts.retain(|x| x.until > now);
+        let busy = self.busy().is_some();
+        if busy {
+            self.spinner_frame = (self.spinner_frame + 1) % SPINNER.len();
+        }
+        !self.low_bandwidth || self.draw_pending || busy
     }
 
-    fn do_request(&mut self) -> Result<(), TUIError> {
-        clean_assert!(self.query.starts_with('/'));
-        let media_key = {
-            let ref results = self.client.get_qm_results().0;
-            if results.len() == 0 {
-                self.status.insert((), (Cow::from("No song selected"), StatusType::Warning));
-                return Ok(());
-            }
-            results[self.results_focus].key.clone()
-        };
+    /// Have the next tick sent when it is needed, call this after every event
+    pub fn schedule_tick(&mut self) {
+        let next = self.next_tick();
+        self.ticker.wake_at(next);
+    }
 
-        self.query.clear();
-        match self.client.do_request_from_key(&media_key) {
-            RequestStatus::Ok => {},
-            RequestStatus::Deferred => {
-                // Tell the user that logging in is needed
-                self.status.insert((), (Cow::from("Not logged in"), StatusType::Warning));
-                self.query.push_str(":username ");
-            },
+    /// When the screen changes next by itself or a background job is due, if ever
+    ///
+    /// Only what is shown counts: the progress of the song that is playing changes every second,
+    /// but the clock only every minute. When nothing is shown that changes, e.g. no song is
+    /// playing, we do not wake up until an event arrives.
+    fn next_tick(&mut self) -> Option<Timespec> {
+        if self.idle {
+            return None;
         }
-        Ok(())
+        let now = get_time();
+        let mut due: Vec<Timespec> = Vec::new();
+        due.extend(self.idle_after.map(|x| self.last_activity + x));
+        // the time that is left of the song that is playing, and the spinner
+        let playing = self.app.client().get_playing().is_some();
+        let queue_eta = self.time_format.eta == Eta::Duration && self.queue_shown();
+        let templates = self.header.iter().chain(self.status_template.iter())
+                                   .any(|x| x.contains("{remaining}"));
+        let polled = self.preview.is_some() || !self.running_actions.is_empty();
+        if playing && (self.in_playing_view() || queue_eta || templates) || polled ||
+           self.busy().is_some() {
+            due.push(now + Duration::seconds(1));
+        }
+        // the clock shows no seconds
+        due.push(Timespec::new((now.sec / 60 + 1) * 60, 0));
+        due.extend(self.toasts.iter().map(|x| x.until));
+        let changes_until = self.queue_changed_at + Duration::seconds(QUEUE_CHANGE_SECS);
+        if changes_until > now {
+            due.push(changes_until);
+        }
+        if self.media_cache_file.is_some() && self.media_sync_task.is_none() {
+            due.push(self.next_media_sync);
+        }
+        due.extend(self.search_due);
+        due.extend(self.status_expiry(now));
+        if self.draw_pending {
+            due.push(self.last_draw + Duration::milliseconds(LOW_BANDWIDTH_DRAW_MILLIS as i64));
+        }
+        due.into_iter().min()
     }
 
-    fn do_command(&mut self) -> Result<(), TUIError> {
-        lazy_static! {
-            static ref WORD: Regex = Regex::new(r#"\S+"#).unwrap();
+    /// Poll the server less often and stop ticking, until a key is pressed or a new song starts
+    ///
+    /// This saves battery when maruska is left open and nobody looks at it. The screen is still
+    /// drawn when an update from the server arrives, but the time that is left of the song that
+    /// is playing is not counted down.
+    fn go_idle(&mut self) {
+        info!("going idle");
+        self.idle = true;
+        let delay = ::std::time::Duration::from_secs(IDLE_POLL_DELAY_SECS);
+        self.app.client().set_poll_delay(Some(delay));
+    }
+
+    /// Note that the user is there, or that something happened that they want to see
+    fn wake_up(&mut self) {
+        self.last_activity = get_time();
+        if self.idle {
+            info!("waking up");
+            self.idle = false;
+            self.app.client().set_poll_delay(None);
         }
-        clean_assert!(self.query.starts_with(':'));
-        let (_, idx) = if let Some(m) = WORD.find(&self.query[1..]) {
-            m
-        } else {
-            return Ok(()) // empty command, do nothing
+    }
+
+    /// When the status message that is shown is gone
+    ///
+    /// The status does not tell when it was set, so a message that we did not see yet is gone
+    /// after `STATUS_TIMEOUT_MILLIS`, and one that is still there after that (because it was set
+    /// again) is looked at every second.
+    fn status_expiry(&mut self, now: Timespec) -> Option<Timespec> {
+        let msg = match self.status.peek(&()) {
+            Some(&(ref msg, _)) => msg.clone(),
+            None => {
+                self.status_shown = None;
+                return None;
+            },
         };
-        let query = self.query.clone();
-        let (command, rest) = query[1..].split_at(idx);
-        let args = if rest.len() >= 1 {
-            Some(&rest[1..])
-        } else {
-            None
+        let until = match self.status_shown.take() {
+            Some((ref shown, until)) if *shown == msg && until > now => until,
+            Some((ref shown, _)) if *shown == msg => now + Duration::seconds(1),
+            _ => now + Duration::milliseconds(STATUS_TIMEOUT_MILLIS as i64),
         };
+        self.status_shown = Some((msg, until));
+        Some(until)
+    }
 
-        match (command, args) {
-            (CMD_USERNAME, args) => self.do_command_username(args),
-            (CMD_PASSWORD, args) => self.do_command_password(args),
-            (CMD_QUIT, args) => self.do_command_quit(args),
-            (cmd, args) => self.do_invalid_command(cmd, args),
+    /// What we are waiting for, if anything, which is shown with a spinner so that the UI does
+    /// not look frozen
+    fn busy(&self) -> Option<&'static str> {
+        match self.app.client().get_connection_state() {
+            ConnectionState::Disconnected => Some("Disconnected"),
+            ConnectionState::Reconnecting(_) => Some("Reconnecting"),
+            _ if !self.app.client().is_offline() && self.app.client().get_requests().is_none() => {
+                Some("Connecting")
+            },
+            _ if self.app.client().is_logging_in() => Some("Logging in"),
+            _ if self.uploads_task.is_some() => Some("Looking up uploads"),
+            _ if self.suggest_task.is_some() => Some("Looking for suggestions"),
+            _ if self.query.starts_with('/') && self.app.is_searching(self.results_scroll()) => {
+                Some("Searching")
+            },
+            _ => None,
         }
     }
 
-    fn do_command_username(&mut self, username_option: Option<&str>) -> Result<(), TUIError> {
-        let username = username_option.unwrap_or_else(|| cleanup!(panic!("no username provided")));
-        self.username = Some(username.to_string());
-        self.query.clear();
-        if !self.try_login() {
-            self.query.push_str(":password ");
+    /// React to a signal that was sent to the process
+    pub fn handle_signal(&mut self, signal: Signal) -> Result<(), TUIError> {
+        match signal {
+            Signal::Suspend => self.suspend(),
+            Signal::Continue => self.resume(),
+            Signal::Resize => self.handle_resize(),
+            Signal::Terminate => return Err(TUIError::Quit),
+            Signal::Reload => {
+                info!("reloading the configuration");
+                self.apply_config(&Config::load());
+                self.status.insert((), (Cow::from("Reloaded the configuration"), StatusType::Info));
+            },
         }
         Ok(())
     }
 
-    fn do_command_password(&mut self, password_option: Option<&str>) -> Result<(), TUIError> {
-        if let Some(ref password) = password_option {
-            self.secret = Some(Secret::PasswordHash(md5(password)));
-            self.status.insert((), (Cow::from("Logging in"), StatusType::Info));
-            self.try_login();
-        } else {
-            self.status.insert((), (Cow::from("No password provided"), StatusType::Error));
+    /// Handle a command that another program wrote to the command pipe
+    pub fn handle_remote_command(&mut self, command: RemoteCommand) -> Result<(), TUIError> {
+        self.wake_up();
+        match command {
+            RemoteCommand::Request(key) => match self.app.request(&[&key]) {
+                Ok(()) => {
+                    self.status.insert((), (Cow::from("Requested a song"), StatusType::Info));
+                    self.tell_if_queued();
+                },
+                Err(prompt) => self.ask_login(prompt),
+            },
+            RemoteCommand::Search(query) => {
+                self.close_views();
+                self.query = format!("/{}", query);
+                self.results_selection = Selection::new();
+                self.update_client_query();
+            },
+            RemoteCommand::Command(line) => {
+                self.query = format!(":{}", line);
+                return self.do_command();
+            },
         }
-        self.query.clear();
         Ok(())
     }
 
-    fn do_command_quit(&self, _: Option<&str>) -> Result<(), TUIError> {
-        Err(TUIError::Quit)
-    }
-
-    fn do_invalid_command(&mut self, cmd: &str, _: Option<&str>) -> Result<(), TUIError> {
-        let commands = COMMANDS;
-        let (other_cmd, dist) = commands.iter().map(|x| (x, levenshtein(x, &cmd)))
-                                               .min_by_key(|x| x.1).unwrap();
-        let msg = if dist < 3 {
-            format!(r#"Not a command. Did you mean "{}"?"#, other_cmd)
+    /// Use the settings in `config`, also the ones that were changed after startup
+    fn apply_config(&mut self, config: &Config) {
+        self.restore_session = config.restore_session;
+        self.encrypt_credentials = config.encrypt_credentials;
+        // The index is created by `maruska sync`, we only keep it up to date
+        self.media_cache_file = paths::media_cache_file(&self.app.client().get_url()).and_then(|x| {
+            let sync = config.media_sync_interval > 0 && !self.app.client().is_offline();
+            if sync && x.exists() { Some(x) } else { None }
+        });
+        self.media_sync_interval = Duration::minutes(config.media_sync_interval as i64);
+        // the library is filled by the next sync, see `start_media_sync`
+        self.library = match self.library.take() {
+            _ if !config.local_search || self.media_cache_file.is_none() => None,
+            library => Some(library.unwrap_or_default()),
+        };
+        self.app.client_mut().set_library(self.library.clone());
+        self.time_format = config.time_format;
+        self.preview_command = config.preview_command.clone();
+        self.preview_duration = Duration::seconds(config.preview_seconds as i64);
+        // images are the last thing to send over a slow connection
+        self.cover_graphics = if self.low_bandwidth {
+            Graphics::None
         } else {
-            format!(r#"Not a maruska command: "{}""#, cmd)
+            Graphics::from_config(&config.cover_art)
         };
-        self.status.insert((), (Cow::from(msg), StatusType::Error));
-        self.query.clear();
-        Ok(())
+        self.cover_lookup = config.cover_art_lookup;
+        self.lyrics_command = config.lyrics_command.clone();
+        self.actions = config.actions.clone();
+        self.aliases = config.aliases.clone();
+        self.popular_first = config.popular_first;
+        self.app.client_mut().set_ranked(config.relevance_first);
+        self.notify_next_up = config.notify_next_up;
+        self.notify_watchlist = config.notify_watchlist;
+        self.notifier.set_quiet_hours(config.quiet_hours);
+        self.idle_after = match config.idle_minutes {
+            0 => None,
+            minutes => Some(Duration::minutes(minutes as i64)),
+        };
+        if self.idle_after.is_none() {
+            self.wake_up();
+        }
+        self.print_summary = config.session_summary;
+        // toasts are drawn over the other views, which costs too much on a slow connection
+        self.show_toasts = config.toasts && !self.low_bandwidth;
+        self.split_layout = config.split_layout;
+        self.header = config.header.clone();
+        self.status_template = config.status.clone();
+        self.cover_drawn = None;
     }
 
-    fn move_focus(&mut self, x: isize, fix_offset: bool) {
-        if self.query.starts_with('/') {
-            self.move_results_focus(x, fix_offset)
+    fn handle_resize(&mut self) {
+        if self.app.client().is_suspended() {
+            return; // we start over when we are continued
+        }
+        // termbox only looks at the size of the terminal in its own SIGWINCH handler, which
+        // does not run because we handle the signal, so we start over with the new size
+        unsafe {
+            tb_shutdown();
+            tb_init();
         }
+        self.cover_drawn = None;
     }
 
-    fn move_results_focus(&mut self, x: isize, fix_offset: bool) {
-        fn bounded<T: Ord>(v1: T, v2: T, v3: T) -> T {
-            max(v1, min(v2, v3))
+    /// Give the terminal back to the shell and stop the process, until it is continued
+    ///
+    /// While we are stopped, we do not follow what is playing, so that the server does not
+    /// have to keep us updated.
+    fn suspend(&mut self) {
+        if self.app.client().is_suspended() {
+            return;
         }
-        let max_index = self.client.get_qm_results().0.len().saturating_sub(1);
-        let h = self.get_viewport_height();
+        info!("suspending");
+        self.preview = None;
+        self.app.client_mut().suspend();
+        unsafe { tb_shutdown() };
+        if !signals::stop_self() {
+            self.resume();
+            let msg = "Suspending is not supported on this platform";
+            self.status.insert((), (Cow::from(msg), StatusType::Error));
+        }
+        // When we are continued, `handle_signal` gets a `Signal::Continue`
+    }
 
-        let new_results_focus = if x >= 0 {
-            (self.results_focus).saturating_add(x as usize)
-        } else {
-            (self.results_focus).saturating_sub(-x as usize)
-        };
-        self.results_focus = bounded(0, new_results_focus, max_index);
+    fn resume(&mut self) {
+        if !self.app.client().is_suspended() {
+            return;
+        }
+        info!("resuming");
+        unsafe { tb_init() };
+        self.cover_drawn = None;
+        self.app.client_mut().resume();
+    }
 
-        let new_results_offset = if fix_offset {
-            if x >= 0 {
-                (self.results_offset).saturating_add(x as usize)
-            } else {
-                (self.results_offset).saturating_sub(-x as usize)
+    /// Sync the local media index in the background, update the library with it, and look for
+    /// the new songs that match a saved search, see `announce_watched`
+    fn start_media_sync(&mut self) {
+        let path = self.media_cache_file.clone().unwrap();
+        let url = self.app.client().get_url();
+        let library = self.library.clone();
+        self.media_sync_task = Some(self.app.client().spawn_task("media index sync", move || {
+            let mut cache = try!(MediaCache::open_or_reset(&path)
+                .map_err(|err| DisplayChain(&err).to_string()));
+            if let Some(ref library) = library {
+                // what we have already is better than nothing if the server cannot be reached
+                if library.is_empty() {
+                    library.update(&cache);
+                }
             }
+            let stats = try!(sync_media(&url, &mut cache, false)
+                .map_err(|err| DisplayChain(&err).to_string()));
+            if let Some(ref library) = library {
+                library.update(&cache);
+            }
+            let watchlist = match paths::watchlist_file().map(|x| Watchlist::load(&x)) {
+                Some(Ok(watchlist)) => watchlist,
+                Some(Err(err)) => {
+                    warn!("could not read the watchlist: {}", err);
+                    Watchlist::default()
+                },
+                None => Watchlist::default(),
+            };
+            let matches = watchlist.find(&stats.added);
+            let watched: Vec<Json> = matches.into_iter().map(|(search, media)| {
+                let mut obj = BTreeMap::new();
+                obj.insert("search".to_string(), search.to_json());
+                obj.insert("artist".to_string(), media.artist.to_json());
+                obj.insert("title".to_string(), media.title.to_json());
+                Json::Object(obj)
+            }).collect();
+            let mut obj = BTreeMap::new();
+            obj.insert("received".to_string(), stats.received.to_json());
+            obj.insert("removed".to_string(), stats.removed.to_json());
+            obj.insert("watched".to_string(), Json::Array(watched));
+            Ok(Json::Object(obj))
+        }));
+    }
+
+    /// Search for the query once typing stops for `SEARCH_DEBOUNCE_MILLIS`, instead of for every
+    /// key that is typed
+    fn search_soon(&mut self) {
+        self.search_due = Some(get_time() + Duration::milliseconds(SEARCH_DEBOUNCE_MILLIS));
+    }
+
+    fn update_client_query(&mut self) {
+        self.search_due = None;
+        let shown = self.results_scroll();
+        if self.query.starts_with('/') {
+            self.app.search(Some(&self.query[1..]), shown);
         } else {
-            self.results_offset
+            self.app.search(None, shown);
+        }
+    }
+
+    fn do_request(&mut self) -> Result<(), TUIError> {
+        clean_assert!(self.query.starts_with('/'));
+        if !self.marked.is_empty() {
+            self.confirm_marked = true;
+            return Ok(());
+        }
+        let media_key = match self.focused_result() {
+            Some(media) => media.key,
+            None => {
+                self.status.insert((), (Cow::from("No song selected"), StatusType::Warning));
+                return Ok(());
+            },
         };
-        self.results_offset = bounded(self.results_focus.saturating_sub(h as usize - 1),
-                                      new_results_offset, self.results_focus);
 
-        self.update_client_query();
+        self.query.clear();
+        if let Err(prompt) = self.app.request(&[&media_key]) {
+            self.ask_login(prompt);
+        }
+        self.tell_if_queued();
+        Ok(())
     }
 
-    pub fn handle_message_from_client(&mut self, message: &Json) -> Result<(), ClientError> {
-        self.client.handle_message(message).map(|x| match x {
-            Message::QueryMediaResults => {
-                self.move_results_focus(0, false); // reinit focus inside the new bounds
+    /// Mark the selected search result, or unmark it if it is marked, and select the next one
+    fn toggle_mark(&mut self) -> Result<(), TUIError> {
+        let media = match self.focused_result() {
+            Some(media) => media,
+            None => {
+                self.status.insert((), (Cow::from("No song selected"), StatusType::Warning));
+                return Ok(());
             },
-            Message::Login => {
-                self.status.insert((), (Cow::from("Succesfully logged in"), StatusType::Success));
-                self.save_credentials(); // save creds for later use
+        };
+        match self.marked.iter().position(|x| x.key == media.key) {
+            Some(i) => {
+                self.marked.remove(i);
             },
-            Message::LoginError(ref msg) if msg == "User does not exist" => {
+            None => self.marked.push(media),
+        }
+        self.move_focus(1, false);
+        Ok(())
+    }
+
+    /// Request the marked songs, in the order in which they were marked
+    fn request_marked(&mut self) -> Result<(), TUIError> {
+        let marked = mem::replace(&mut self.marked, Vec::new());
+        let keys: Vec<&str> = marked.iter().map(|x| x.key.as_str()).collect();
+        self.query.clear();
+        match self.app.request(&keys) {
+            Ok(()) => {
+                let msg = format!("Requesting {} songs", marked.len());
+                self.status.insert((), (Cow::from(msg), StatusType::Info));
+                self.tell_if_queued();
+            },
+            // they are requested once we are logged in
+            Err(prompt) => self.ask_login(prompt),
+        }
+        Ok(())
+    }
+
+    /// Handle a key while the marked songs are shown, `y` or Enter requests them
+    fn handle_confirm_input(&mut self, key: u16, ch: u32) -> Result<(), TUIError> {
+        self.confirm_marked = false;
+        if key == TB_KEY_CTRL_C {
+            return Err(TUIError::Quit);
+        }
+        if ch == 'y' as u32 || (ch == 0 && key == TB_KEY_ENTER) {
+            self.request_marked()
+        } else {
+            self.status.insert((), (Cow::from("Not requested"), StatusType::Info));
+            Ok(())
+        }
+    }
+
+    /// Request the song that is selected in the view that is shown, or whose details it shows
+    fn do_request_from_view(&mut self) -> Result<(), TUIError> {
+        let media_key = match self.focused_media() {
+            Some(_) if self.in_playing_view() => return Ok(()),
+            Some(media) => media.key,
+            None => {
+                self.status.insert((), (Cow::from("No song selected"), StatusType::Warning));
+                return Ok(());
+            },
+        };
+        if let Err(prompt) = self.app.request(&[&media_key]) {
+            self.ask_login(prompt);
+        }
+        self.tell_if_queued();
+        Ok(())
+    }
+
+    /// Tell the user that their requests wait until the server can be reached, if they do
+    fn tell_if_queued(&mut self) {
+        let queued = self.app.client().get_offline_requests().len();
+        if queued > 0 {
+            let msg = format!("Cannot reach the server, requesting {} songs once it can", queued);
+            self.status.insert((), (Cow::from(msg), StatusType::Warning));
+        }
+    }
+
+    /// Move the user's next request to the front of the queue, if the server allows it
+    fn do_boost(&mut self) -> Result<(), TUIError> {
+        let key = {
+            let username = self.app.client().get_username();
+            let requests = self.app.client().get_requests().as_ref().map_or(&[][..], |x| &x[..]);
+            // the first request is already at the front
+            requests.iter().skip(1).find(|x| {
+                x.by.as_ref().map(|x| x.as_str()) == username && self.app.client().can_boost(x)
+            }).map(|x| x.key)
+        };
+        let status = match key {
+            Some(key) => {
+                self.app.client_mut().do_boost(key);
+                ("Moving your request to the front", StatusType::Info)
+            },
+            None if !self.app.client().has_capability("boost") => {
+                ("You are not allowed to move requests", StatusType::Warning)
+            },
+            None => ("You have no request to move to the front", StatusType::Warning),
+        };
+        self.status.insert((), (Cow::from(status.0), status.1));
+        Ok(())
+    }
+
+    /// The request that is selected in the queue view, if it is still in the queue
+    fn focused_request(&self) -> Option<Request> {
+        let key = match self.requests_selection.key() {
+            Some(&key) => key,
+            None => return None,
+        };
+        self.queue_preview().into_iter().find(|x| x.key == key)
+    }
+
+    /// Remove the selected request, or move it by `amount` places if that is `Some`
+    ///
+    /// Moves are only shown at first, see `stage_move`. If the request was just moved by someone
+    /// else, Delete has to be pressed twice, in case the user did not see that it moved.
+    fn do_manage_request(&mut self, amount: Option<i64>) -> Result<(), TUIError> {
+        let request = match self.focused_request() {
+            Some(request) => request,
+            None => {
+                let msg = "No request selected, use the arrow keys to select one";
+                self.status.insert((), (Cow::from(msg), StatusType::Warning));
+                return Ok(());
+            },
+        };
+        if request.key < 0 {
+            // its key is made up until the server sends the queue with it
+            let msg = "This request is not in the queue yet";
+            self.status.insert((), (Cow::from(msg), StatusType::Warning));
+            return Ok(());
+        }
+        if self.app.client().is_logged_in() && !self.app.client().can_manage(&request) {
+            let msg = format!("You are not allowed to change the request of {}",
+                              unwrap_requested_by(&request.by));
+            self.status.insert((), (Cow::from(msg), StatusType::Warning));
+            return Ok(());
+        }
+        if let Some(amount) = amount {
+            self.stage_move(&request, amount);
+            return Ok(());
+        }
+        let just_moved = get_time() - self.requests_focus_moved_at
+                         < Duration::seconds(QUEUE_CHANGE_SECS);
+        if just_moved && self.confirm_remove != Some(request.key) {
+            self.confirm_remove = Some(request.key);
+            let msg = format!("The queue just changed, press Delete again to remove \"{}\"",
+                              request.media.title);
+            self.status.insert((), (Cow::from(msg), StatusType::Warning));
+            return Ok(());
+        }
+        self.confirm_remove = None;
+        let status = self.app.client_mut().do_remove_request(request.key);
+        match self.app.prompt_after(status) {
+            Ok(()) => {
+                let msg = format!("Removing \"{}\"", request.media.title);
+                self.status.insert((), (Cow::from(msg), StatusType::Info));
+            },
+            Err(prompt) => self.ask_login(prompt),
+        }
+        Ok(())
+    }
+
+    /// Move `request` by `amount` places in the queue view, without telling the server yet
+    ///
+    /// Enter sends all moves at once (see `send_moves`), Escape undoes them.
+    fn stage_move(&mut self, request: &Request, amount: i64) {
+        let h = self.get_viewport_height() as usize;
+        let preview = self.queue_preview();
+        // only the requests that fit on the screen can be selected, so it has to stay there
+        let visible = min(preview.len(), h.saturating_sub(1)) as i64;
+        let to = preview.iter().position(|x| x.key == request.key)
+                        .map_or(-1, |i| i as i64 + amount);
+        if to < 0 || to >= visible {
+            return;
+        }
+        queue::add_move(&mut self.staged_moves, request.key, amount);
+        let msg = "Enter moves the requests, Escape puts them back";
+        self.status.insert((), (Cow::from(msg), StatusType::Info));
+    }
+
+    /// The queue as it is after the changes that the server did not make yet, and after the
+    /// moves that were not sent yet
+    fn queue_preview(&self) -> Vec<Request> {
+        queue::apply_moves(&self.app.client().get_expected_requests(), &self.staged_moves)
+    }
+
+    /// Send the moves that were made in the queue view to the server
+    fn send_moves(&mut self) -> Result<(), TUIError> {
+        let moves = mem::replace(&mut self.staged_moves, Vec::new());
+        match self.app.move_requests(&moves) {
+            Ok(()) => {
+                self.status.insert((), (Cow::from("Moving the requests"), StatusType::Info));
+            },
+            // they are sent once we are logged in
+            Err(prompt) => self.ask_login(prompt),
+        }
+        Ok(())
+    }
+
+    fn do_command(&mut self) -> Result<(), TUIError> {
+        lazy_static! {
+            static ref WORD: Regex = Regex::new(r#"\S+"#).unwrap();
+        }
+        clean_assert!(self.query.starts_with(':'));
+        let query = self.expand_alias(&self.query);
+        let (_, idx) = if let Some(m) = WORD.find(&query[1..]) {
+            m
+        } else {
+            return Ok(()) // empty command, do nothing
+        };
+        let (command, rest) = query[1..].split_at(idx);
+        let args = if rest.len() >= 1 {
+            Some(&rest[1..])
+        } else {
+            None
+        };
+
+        let names = self.commands.names();
+        let name = match commands::resolve(&names, command) {
+            Resolved::Command(name) => name,
+            Resolved::Ambiguous(matching) => {
+                let matching: Vec<String> = matching.iter().map(|x| format!(":{}", x)).collect();
+                let msg = format!("Ambiguous command, did you mean {}?",
+                                  commands::or_list(&matching));
+                self.status.insert((), (Cow::from(msg), StatusType::Error));
+                return Ok(());
+            },
+            Resolved::Unknown(suggestion) => return self.do_invalid_command(command, suggestion),
+        };
+
+        // `resolve` only gives the names of registered commands
+        let (handler, usage, required) = match self.commands.get(name) {
+            Some(command) => (command.handler, command.usage(), command.needs_args()),
+            None => cleanup!(unreachable!()),
+        };
+        if required && args.map_or(true, |x| x.trim().is_empty()) {
+            let msg = format!("Usage: {}", usage);
+            self.status.insert((), (Cow::from(msg), StatusType::Error));
+            self.query = format!(":{} ", name);
+            return Ok(());
+        }
+        handler(self, args)
+    }
+
+    /// `query`, a `:` command, with the alias that it starts with replaced by what it stands for
+    fn expand_alias(&self, query: &str) -> String {
+        let name = query[1..].split(char::is_whitespace).next().unwrap_or("");
+        match aliases::resolve(&self.aliases, &self.commands.names(), name) {
+            Some(command) => format!(":{}{}", command, &query[1 + name.len()..]),
+            None => query.to_string(),
+        }
+    }
+
+    fn do_command_username(&mut self, username_option: Option<&str>) -> Result<(), TUIError> {
+        let username = username_option.unwrap_or_else(|| cleanup!(panic!("no username provided")));
+        self.query.clear();
+        if let Some(prompt) = self.app.log_in_as(username) {
+            self.prompt_login(prompt);
+        }
+        Ok(())
+    }
+
+    fn do_command_password(&mut self, password_option: Option<&str>) -> Result<(), TUIError> {
+        self.query.clear();
+        if let Some(password) = password_option {
+            match self.app.log_in_with_password(password) {
+                Some(prompt) => self.prompt_login(prompt),
+                None => {
+                    self.status.insert((), (Cow::from("Logging in"), StatusType::Info));
+                },
+            }
+        } else {
+            self.status.insert((), (Cow::from("No password provided"), StatusType::Error));
+        }
+        Ok(())
+    }
+
+    fn do_command_passphrase(&mut self, passphrase_option: Option<&str>) -> Result<(), TUIError> {
+        self.query.clear();
+        let passphrase = match passphrase_option {
+            Some(x) if !x.is_empty() => x.to_string(),
+            _ => {
+                self.status.insert((), (Cow::from("No passphrase provided"), StatusType::Error));
+                return Ok(());
+            },
+        };
+        if !self.encrypt_credentials {
+            let msg = "Credentials are not encrypted, enable encrypt_credentials in the config";
+            self.status.insert((), (Cow::from(msg), StatusType::Warning));
+            return Ok(());
+        }
+
+        if self.credentials_pending {
+            self.passphrase = Some(passphrase);
+            self.credentials_pending = false;
+            self.save_credentials();
+            return Ok(());
+        }
+
+        let path = match paths::encrypted_credentials_file() {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        match EncryptedStore::new(path, &passphrase).load(&self.app.client().get_url()) {
+            Ok(credentials) => {
+                self.passphrase = Some(passphrase);
+                if let Some(credentials) = credentials {
+                    self.app.set_credentials(credentials.username, credentials.secret);
+                    self.status.insert((), (Cow::from("Logging in"), StatusType::Info));
+                    self.app.try_login();
+                } else {
+                    let msg = "Credentials unlocked, but there are none for this server";
+                    self.status.insert((), (Cow::from(msg), StatusType::Info));
+                }
+            },
+            Err(CredentialError::Decrypt) => {
+                self.status.insert((), (Cow::from("Wrong passphrase"), StatusType::Error));
+                self.query.push_str(":passphrase ");
+            },
+            Err(err) => {
+                error!("Could not unlock credentials: {}", DisplayChain(&err));
+                let msg = "Could not unlock credentials, see the log";
+                self.status.insert((), (Cow::from(msg), StatusType::Error));
+            },
+        }
+        Ok(())
+    }
+
+    /// Open the `:uploads` view with the uploads of another user, or else our own
+    fn do_command_uploads(&mut self, user_option: Option<&str>) -> Result<(), TUIError> {
+        self.query.clear();
+        let user = match user_option.map(|x| x.trim()).filter(|x| !x.is_empty()) {
+            Some(user) => user.to_string(),
+            None => match self.app.client().get_username().map(|x| x.to_string())
+                              .or_else(|| self.app.username().map(|x| x.to_string())) {
+                Some(user) => user,
+                None => {
+                    let msg = "Whose uploads? Use :uploads <name>";
+                    self.status.insert((), (Cow::from(msg), StatusType::Warning));
+                    return Ok(());
+                },
+            },
+        };
+        let task_user = user.clone();
+        if let Some(id) = self.look_up_media("uploads lookup", move |x| x.uploaded_by(&task_user)) {
+            self.uploads_task = Some((id, user.clone()));
+            let msg = format!("Looking up the uploads of {}", user);
+            self.status.insert((), (Cow::from(msg), StatusType::Info));
+        }
+        Ok(())
+    }
+
+    /// Start a task that finds songs in the local media index with `find`, syncing it first if
+    /// needed; the songs are the result of the task
+    fn look_up_media<F>(&mut self, name: &'static str, find: F) -> Option<TaskId>
+        where F: for<'a> FnOnce(&'a MediaCache) -> Vec<&'a Media> + Send + 'static
+    {
+        let path = match paths::media_cache_file(&self.app.client().get_url()) {
+            Some(path) => path,
+            None => {
+                let msg = "Could not find a cache directory for the media index";
+                self.status.insert((), (Cow::from(msg), StatusType::Error));
+                return None;
+            },
+        };
+
+        let url = self.app.client().get_url();
+        Some(self.app.client().spawn_task(name, move || {
+            let cache = try!(open_synced(&url, &path)
+                .map_err(|err| DisplayChain(&err).to_string()));
+            // Task results are JSON, so the media travel in the format of the server
+            let media = try!(json::encode(&find(&cache)).map_err(|err| err.to_string()));
+            Json::from_str(&media).map_err(|err| err.to_string())
+        }))
+    }
+
+    fn is_uploads_task(&self, id: TaskId) -> bool {
+        self.uploads_task.as_ref().map_or(false, |x| x.0 == id)
+    }
+
+    /// Show the songs that `user` uploaded, the result of the task from `do_command_uploads`
+    fn show_uploads(&mut self, user: String, result: &Json) {
+        let media: Vec<Media> = match Decodable::decode(&mut json::Decoder::new(result.clone())) {
+            Ok(media) => media,
+            Err(err) => {
+                error!("could not decode the uploads of {}: {}", user, err);
+                return;
+            },
+        };
+        let msg = format!("{} uploaded {} songs", user, media.len());
+        self.status.insert((), (Cow::from(msg), StatusType::Success));
+        self.open_view(View::Uploads(user, media, Selection::new()));
+    }
+
+    fn is_artist_task(&self, id: TaskId) -> bool {
+        self.artist_task.as_ref().map_or(false, |x| x.0 == id)
+    }
+
+    /// Show the songs of `artist`, the result of the task from `open_focused`
+    fn show_artist(&mut self, artist: String, result: &Json) {
+        let media: Vec<Media> = match Decodable::decode(&mut json::Decoder::new(result.clone())) {
+            Ok(media) => media,
+            Err(err) => {
+                error!("could not decode the songs of {}: {}", artist, err);
+                return;
+            },
+        };
+        let msg = format!("The media index has {} songs by {}", media.len(), artist);
+        self.status.insert((), (Cow::from(msg), StatusType::Success));
+        self.open_view(View::Artist(artist, media, Selection::new()));
+    }
+
+    /// Open the `:suggest` view, with songs by the artists that were played lately and requests
+    /// of ours that were not played for a while, found in the local media index
+    fn do_command_suggest(&mut self, _: Option<&str>) -> Result<(), TUIError> {
+        self.query.clear();
+        // the songs in the queue are heard soon enough
+        let mut exclude: Vec<String> = self.app.client().get_requests().iter()
+                                           .flat_map(|x| x.iter().map(|x| x.media.key.clone()))
+                                           .collect();
+        exclude.extend(self.app.client().get_playing().iter().map(|x| x.media.key.clone()));
+        let stats = self.stats.clone();
+        let now = get_time().sec;
+        if let Some(id) = self.look_up_media("suggestions", move |cache| {
+            let exclude: Vec<&str> = exclude.iter().map(|x| &x[..]).collect();
+            suggest(&stats, cache, &exclude, now, SUGGESTIONS).into_iter().map(|x| x.media)
+                                                              .collect()
+        }) {
+            self.suggest_task = Some(id);
+            let msg = "Looking for songs like the ones that were played lately";
+            self.status.insert((), (Cow::from(msg), StatusType::Info));
+        }
+        Ok(())
+    }
+
+    fn is_suggest_task(&self, id: TaskId) -> bool {
+        self.suggest_task == Some(id)
+    }
+
+    /// Show the suggestions, the result of the task from `do_command_suggest`
+    fn show_suggestions(&mut self, result: &Json) {
+        let media: Vec<Media> = match Decodable::decode(&mut json::Decoder::new(result.clone())) {
+            Ok(media) => media,
+            Err(err) => {
+                error!("could not decode the suggestions: {}", err);
+                return;
+            },
+        };
+        if media.is_empty() {
+            let msg = "Nothing to suggest yet, the suggestions come from what maruska saw playing";
+            self.status.insert((), (Cow::from(msg), StatusType::Warning));
+            return;
+        }
+        let msg = format!("{} suggestions, press Enter to request one", media.len());
+        self.status.insert((), (Cow::from(msg), StatusType::Success));
+        self.open_view(View::Suggestions(media, Selection::new()));
+    }
+
+    /// Switch do not disturb on or off, until the quiet hours start or end
+    fn do_command_dnd(&mut self, _: Option<&str>) -> Result<(), TUIError> {
+        self.query.clear();
+        let msg = if self.notifier.toggle() {
+            "Do not disturb is on, no notifications or hooks until :dnd"
+        } else {
+            "Do not disturb is off"
+        };
+        self.status.insert((), (Cow::from(msg), StatusType::Info));
+        Ok(())
+    }
+
+    /// Switch ranking the search results by relevance on, or off again
+    fn do_command_relevance(&mut self, _: Option<&str>) -> Result<(), TUIError> {
+        self.query.clear();
+        let ranked = !self.app.client().is_ranked();
+        self.app.client_mut().set_ranked(ranked);
+        let msg = if ranked {
+            "Showing the results that match the query best first, until :relevance"
+        } else if self.popular_first {
+            "Showing the most popular results first"
+        } else {
+            "Showing the results in the order of the server"
+        };
+        self.status.insert((), (Cow::from(msg), StatusType::Info));
+        Ok(())
+    }
+
+    /// Open the `:party` view, which follows the queue as it changes
+    fn do_command_party(&mut self, _: Option<&str>) -> Result<(), TUIError> {
+        self.query.clear();
+        self.open_view(View::Party);
+        Ok(())
+    }
+
+    /// Open the `:playing` view, with the details and the cover art of the song that is playing
+    fn do_command_playing(&mut self, _: Option<&str>) -> Result<(), TUIError> {
+        self.query.clear();
+        self.open_view(View::Playing);
+        self.fetch_cover();
+        self.fetch_lyrics();
+        Ok(())
+    }
+
+    /// Open the details of the selected song, or the songs of its artist if the details are shown
+    fn open_focused(&mut self) -> Result<(), TUIError> {
+        let media = match self.focused_media() {
+            Some(media) => media,
+            None => {
+                self.status.insert((), (Cow::from("No song selected"), StatusType::Warning));
+                return Ok(());
+            },
+        };
+        match self.views.last() {
+            Some(&View::Details(_)) | Some(&View::Playing) if self.query.is_empty() => {},
+            _ => {
+                self.open_view(View::Details(media));
+                return Ok(());
+            },
+        }
+        let artist = media.artist;
+        let task_artist = artist.clone();
+        if let Some(id) = self.look_up_media("artist lookup", move |x| x.by_artist(&task_artist)) {
+            self.artist_task = Some((id, artist.clone()));
+            let msg = format!("Looking up the songs of {}", artist);
+            self.status.insert((), (Cow::from(msg), StatusType::Info));
+        }
+        Ok(())
+    }
+
+    /// Show `view` over the current one, which is shown again as it is now when `view` is closed
+    fn open_view(&mut self, view: View) {
+        if self.query.starts_with('/') {
+            let row = self.results_selection.index(&self.result_keys());
+            let query = mem::replace(&mut self.query, String::new());
+            self.views.push(View::Search(query, self.results_selection.clone(), row));
+            self.update_client_query();
+        }
+        self.views.push(view);
+    }
+
+    /// Close the view that is shown, and go back to the one before it
+    fn close_view(&mut self) {
+        self.views.pop();
+        let search = match self.views.last() {
+            Some(&View::Search(..)) => self.views.pop(),
+            _ => None,
+        };
+        if let Some(View::Search(query, selection, row)) = search {
+            // the results are looked up again, wait for them before the selection is moved
+            self.restored_focus = Some(row);
+            self.query = query;
+            self.results_selection = selection;
+            self.update_client_query();
+        }
+    }
+
+    /// Go back to the queue view
+    fn close_views(&mut self) {
+        self.views.clear();
+        self.console = None;
+    }
+
+    /// Is the queue shown, also next to the search results?
+    fn queue_shown(&self) -> bool {
+        !self.confirm_marked && (self.is_split() ||
+                                 self.views.is_empty() && !self.query.starts_with('/'))
+    }
+
+    /// Is the `:playing` view shown?
+    fn in_playing_view(&self) -> bool {
+        match self.views.last() {
+            Some(&View::Playing) => true,
+            _ => false,
+        }
+    }
+
+    /// Start fetching the cover of the song that is playing, unless we have it already
+    fn fetch_cover(&mut self) {
+        if self.cover_graphics == Graphics::None {
+            return;
+        }
+        let (media, cover_url) = match *self.app.client().get_playing() {
+            Some(ref playing) => (playing.media.clone(), playing.cover_url.clone()),
+            None => return,
+        };
+        if self.cover.as_ref().map_or(false, |x| x.0 == media.key) ||
+           self.cover_task.as_ref().map_or(false, |x| x.1 == media.key) {
+            return;
+        }
+        let dir = match paths::cover_dir() {
+            Some(dir) => dir,
+            None => {
+                warn!("could not find a cache directory for cover art");
+                return;
+            },
+        };
+        let url = self.app.client().get_url();
+        let lookup = self.cover_lookup;
+        let key = media.key.clone();
+        let id = self.app.client().spawn_task("cover art", move || {
+            let path = try!(cover::fetch(&dir, &url, &media, cover_url.as_ref().map(|x| &x[..]),
+                                         lookup));
+            Ok(path.map_or(Json::Null, |x| Json::String(x.to_string_lossy().into_owned())))
+        });
+        self.cover_task = Some((id, key));
+    }
+
+    fn is_cover_task(&self, id: TaskId) -> bool {
+        self.cover_task.as_ref().map_or(false, |x| x.0 == id)
+    }
+
+    /// Start looking up the lyrics of the song that is playing, unless we have them already
+    fn fetch_lyrics(&mut self) {
+        let command = match self.lyrics_command {
+            Some(ref command) => command.clone(),
+            None => return,
+        };
+        let media = match *self.app.client().get_playing() {
+            Some(ref playing) => playing.media.clone(),
+            None => return,
+        };
+        if self.lyrics.as_ref().map_or(false, |x| x.0 == media.key) ||
+           self.lyrics_task.as_ref().map_or(false, |x| x.1 == media.key) {
+            return;
+        }
+        let dir = match paths::lyrics_dir() {
+            Some(dir) => dir,
+            None => {
+                warn!("could not find a cache directory for lyrics");
+                return;
+            },
+        };
+        let url = self.app.client().get_url();
+        let key = media.key.clone();
+        let id = self.app.client().spawn_task("lyrics lookup", move || {
+            lyrics::fetch(&dir, &url, &media, &command)
+                .map(Json::String)
+                .map_err(|err| err.to_string())
+        });
+        self.lyrics_task = Some((id, key));
+    }
+
+    fn is_lyrics_task(&self, id: TaskId) -> bool {
+        self.lyrics_task.as_ref().map_or(false, |x| x.0 == id)
+    }
+
+    /// Scroll the lyrics in the `:playing` view by `x` lines
+    fn scroll_lyrics(&mut self, x: isize) {
+        let len = self.lyrics.as_ref().map_or(0, |x| x.1.len());
+        let offset = max(self.lyrics_offset as isize + x, 0) as usize;
+        self.lyrics_offset = min(offset, len.saturating_sub(1));
+    }
+
+    /// Report that the selected song is wrong, to the server if it takes reports and else in the
+    /// local report file
+    ///
+    /// In the queue view without a selected request, this reports the song that is playing.
+    fn do_command_report(&mut self, reason_option: Option<&str>) -> Result<(), TUIError> {
+        self.query.clear();
+        let media = match self.focused_media() {
+            Some(media) => media,
+            None => {
+                self.status.insert((), (Cow::from("No song selected"), StatusType::Warning));
+                return Ok(());
+            },
+        };
+        let reason = reason_option.map_or("", |x| x.trim());
+
+        if self.app.client().has_capability("report") {
+            self.app.client_mut().do_report(&media.key, reason);
+            let msg = format!("Reporting \"{}\"", media.title);
+            self.status.insert((), (Cow::from(msg), StatusType::Info));
+            return Ok(());
+        }
+        let path = match paths::report_file() {
+            Some(path) => path,
+            None => {
+                let msg = "Could not find a directory for the report file";
+                self.status.insert((), (Cow::from(msg), StatusType::Error));
+                return Ok(());
+            },
+        };
+        let description = format!("{} - {}", media.artist, media.title);
+        let url = self.app.client().get_url();
+        let status = match append_report(&path, get_time(), &url, &media.key, &description,
+                                         reason) {
+            Ok(()) => (format!("Saved the report in {}", path.display()), StatusType::Success),
+            Err(err) => {
+                error!("could not save the report in {}: {}", path.display(), err);
+                (format!("Could not save the report: {}", err), StatusType::Error)
+            },
+        };
+        self.status.insert((), (Cow::from(status.0), status.1));
+        Ok(())
+    }
+
+    /// Open the `:edit` form for the selected song, if the server allows us to edit songs
+    fn do_command_edit(&mut self, _: Option<&str>) -> Result<(), TUIError> {
+        self.query.clear();
+        if !self.app.client().has_capability("edit") {
+            let msg = "You are not allowed to edit songs";
+            self.status.insert((), (Cow::from(msg), StatusType::Warning));
+            return Ok(());
+        }
+        let media = match self.focused_media() {
+            Some(media) => media,
+            None => {
+                self.status.insert((), (Cow::from("No song selected"), StatusType::Warning));
+                return Ok(());
+            },
+        };
+        let values = vec!(media.artist.clone(), media.title.clone(),
+                          media.album.clone().unwrap_or_default());
+        self.edit_form = Some(EditForm { media: media, values: values, focus: 0 });
+        let msg = "Enter saves, Escape cancels";
+        self.status.insert((), (Cow::from(msg), StatusType::Info));
+        Ok(())
+    }
+
+    /// Send the changes in the `:edit` form to the server, and show them until it answers
+    fn submit_edit_form(&mut self) -> Result<(), TUIError> {
+        let form = self.edit_form.take().unwrap();
+        let old_values = [&form.media.artist[..], &form.media.title[..],
+                          form.media.album.as_ref().map_or("", |x| &x[..])];
+        let fields: BTreeMap<String, String> = EDIT_FIELDS.iter()
+            .zip(old_values.iter().zip(form.values.iter().map(|x| x.trim())))
+            .filter(|&(_, (old, new))| *old != new)
+            .map(|(&(name, _), (_, new))| (name.to_string(), new.to_string()))
+            .collect();
+        if fields.is_empty() {
+            self.status.insert((), (Cow::from("Nothing changed"), StatusType::Info));
+            return Ok(());
+        }
+
+        self.app.client_mut().update_media(&form.media.key, &fields);
+        let key = form.media.key.clone();
+        if !self.pending_edits.contains_key(&key) {
+            self.pending_edits.insert(key.clone(), form.media.clone());
+        }
+        self.replace_in_views(&form.media.with_fields(&fields));
+        let msg = format!("Saving the changes to \"{}\"", form.media.title);
+        self.status.insert((), (Cow::from(msg), StatusType::Info));
+        Ok(())
+    }
+
+    /// Replace the song with the same media key as `media` in the open views
+    fn replace_in_views(&mut self, media: &Media) {
+        for view in &mut self.views {
+            let list = match *view {
+                View::Details(ref mut details) if details.key == media.key => {
+                    *details = media.clone();
+                    continue;
+                },
+                View::Artist(_, ref mut list, _) | View::Uploads(_, ref mut list, _) |
+                View::Suggestions(ref mut list, _) => list,
+                _ => continue,
+            };
+            for song in list.iter_mut().filter(|x| x.key == media.key) {
+                *song = media.clone();
+            }
+        }
+    }
+
+    /// Preview the selected song, or stop the preview if that song is already being previewed
+    fn do_preview(&mut self) -> Result<(), TUIError> {
+        let media = match self.focused_media() {
+            Some(media) => media,
+            None => {
+                self.status.insert((), (Cow::from("No song selected"), StatusType::Warning));
+                return Ok(());
+            },
+        };
+        // Stop the preview that is playing, also when we start another one
+        if let Some(preview) = self.preview.take() {
+            if preview.media_key() == media.key {
+                self.status.insert((), (Cow::from("Stopped the preview"), StatusType::Info));
+                return Ok(());
+            }
+        }
+
+        let command = match self.preview_command {
+            Some(ref command) => command.clone(),
+            None => {
+                let msg = "Set preview_command in the config file to preview songs";
+                self.status.insert((), (Cow::from(msg), StatusType::Warning));
+                return Ok(());
+            },
+        };
+        let url = match media.url {
+            Some(ref url) => resolve_url(&self.app.client().get_url(), url),
+            None => {
+                let msg = "The server does not tell where this song can be played";
+                self.status.insert((), (Cow::from(msg), StatusType::Warning));
+                return Ok(());
+            },
+        };
+        let status = match Preview::start(&command, &url, &media.key, self.preview_duration) {
+            Ok(preview) => {
+                self.preview = Some(preview);
+                (format!("Previewing \"{}\"", media.title), StatusType::Info)
+            },
+            Err(err) => {
+                error!("could not start {:?}: {}", command, err);
+                (format!("Could not start the player: {}", err), StatusType::Error)
+            },
+        };
+        self.status.insert((), (Cow::from(status.0), status.1));
+        Ok(())
+    }
+
+    /// Wait for the key of an action, which runs it on the selected song
+    fn start_action(&mut self) -> Result<(), TUIError> {
+        self.action_pending = true;
+        let mut keys: Vec<char> = self.actions.keys().cloned().collect();
+        keys.extend(BUILTIN_ACTIONS.iter().filter(|x| !self.actions.contains_key(x)));
+        keys.sort();
+        let keys: String = keys.into_iter().collect();
+        let msg = format!("Run which action? ({})", keys);
+        self.status.insert((), (Cow::from(msg), StatusType::Info));
+        Ok(())
+    }
+
+    /// Run the action with the key `ch` on the selected song, any other key cancels
+    fn do_action(&mut self, ch: u32) -> Result<(), TUIError> {
+        self.status.clear();
+        let key = char::from_u32(ch).unwrap_or('\0');
+        if !self.actions.contains_key(&key) && !BUILTIN_ACTIONS.contains(&key) {
+            return Ok(());
+        }
+        let media = match self.focused_media() {
+            Some(media) => media,
+            None => {
+                self.status.insert((), (Cow::from("No song selected"), StatusType::Warning));
+                return Ok(());
+            },
+        };
+        let command = match self.actions.get(&key) {
+            Some(command) => command.clone(),
+            None => {
+                self.yank(&media, key == 'Y');
+                return Ok(());
+            },
+        };
+        let status = match actions::run(&actions::expand(&command, &media)) {
+            Ok(child) => {
+                self.running_actions.push((key, child));
+                (format!("Running {} on \"{}\"", key, media.title), StatusType::Info)
+            },
+            Err(err) => {
+                error!("could not run action {:?}: {}", command, err);
+                (format!("Could not run {}: {}", key, err), StatusType::Error)
+            },
+        };
+        self.status.insert((), (Cow::from(status.0), status.1));
+        Ok(())
+    }
+
+    /// Copy "Artist – Title" of `media` to the clipboard, or its media key if `key` is set
+    fn yank(&mut self, media: &Media, key: bool) {
+        let text = if key {
+            media.key.clone()
+        } else {
+            format!("{} – {}", media.artist, media.title)
+        };
+        let status = match clipboard::copy(&text) {
+            Ok(Copied::Tool) => (format!("Copied \"{}\"", text), StatusType::Success),
+            // we cannot tell if the terminal did it
+            Ok(Copied::Terminal) => {
+                (format!("Asked the terminal to copy \"{}\"", text), StatusType::Info)
+            },
+            Err(err) => {
+                error!("could not copy {:?}: {}", text, err);
+                (format!("Could not copy: {}", err), StatusType::Error)
+            },
+        };
+        self.status.insert((), (Cow::from(status.0), status.1));
+    }
+
+    /// Forget the actions that are done, and tell if one of them failed
+    fn reap_actions(&mut self) {
+        let mut failed = None;
+        for (key, mut child) in mem::replace(&mut self.running_actions, Vec::new()) {
+            match child.try_wait() {
+                Ok(None) => self.running_actions.push((key, child)),
+                Ok(Some(status)) if !status.success() => {
+                    failed = Some(format!("Action {} failed ({})", key, status));
+                },
+                Ok(Some(_)) => {},
+                Err(err) => warn!("could not wait for action {}: {}", key, err),
+            }
+        }
+        if let Some(msg) = failed {
+            self.status.insert((), (Cow::from(msg), StatusType::Error));
+        }
+    }
+
+    /// The song that is selected in the current view
+    fn focused_media(&self) -> Option<Media> {
+        if self.query.starts_with('/') && !self.in_queue_view() {
+            return self.focused_result();
+        }
+        match self.views.last() {
+            Some(&View::Details(ref media)) => return Some(media.clone()),
+            Some(&View::Artist(_, ref media, ref selection)) |
+            Some(&View::Uploads(_, ref media, ref selection)) |
+            Some(&View::Suggestions(ref media, ref selection)) => {
+                let keys: Vec<String> = media.iter().map(|x| x.key.clone()).collect();
+                return media.get(selection.index(&keys)).cloned();
+            },
+            Some(_) => {},
+            None if self.requests_selection.key().is_some() => {
+                return self.focused_request().map(|x| x.media);
+            },
+            None => {},
+        }
+        self.app.client().get_playing().as_ref().map(|x| x.media.clone())
+    }
+
+    /// Send a message that is typed as JSON to the server, to try out what it can do
+    fn do_command_raw(&mut self, msg_option: Option<&str>) -> Result<(), TUIError> {
+        self.query.clear();
+        let msg = match Json::from_str(msg_option.unwrap_or("").trim()) {
+            Ok(msg) => msg,
+            Err(err) => {
+                let msg = format!("Not a valid message: {}", err);
+                self.status.insert((), (Cow::from(msg), StatusType::Error));
+                return Ok(());
+            },
+        };
+        let msg_type = match msg.find("type").and_then(|x| x.as_string()) {
+            Some(msg_type) => msg_type.to_string(),
+            None => {
+                let msg = "Not a valid message: it has no \"type\"";
+                self.status.insert((), (Cow::from(msg), StatusType::Error));
+                return Ok(());
+            },
+        };
+        self.app.client_mut().send_raw(msg);
+        self.show_raw = true;
+        let msg = format!("Sent a \"{}\" message", msg_type);
+        self.status.insert((), (Cow::from(msg), StatusType::Info));
+        Ok(())
+    }
+
+    fn do_command_console(&mut self, _: Option<&str>) -> Result<(), TUIError> {
+        self.query.clear();
+        self.close_views();
+        self.console = Some(Console::default());
+        let msg = "Type a message as JSON, Enter sends it, Escape closes the console";
+        self.status.insert((), (Cow::from(msg), StatusType::Info));
+        Ok(())
+    }
+
+    /// Ask to confirm sending the message that was typed in the console
+    fn submit_console(&mut self) -> Result<(), TUIError> {
+        let console = self.console.as_mut().unwrap();
+        let msg = match Json::from_str(console.input.trim()) {
+            Ok(msg) => msg,
+            Err(err) => {
+                let msg = format!("Not a valid message: {}", err);
+                self.status.insert((), (Cow::from(msg), StatusType::Error));
+                return Ok(());
+            },
+        };
+        let msg_type = match msg.find("type").and_then(|x| x.as_string()) {
+            Some(msg_type) => msg_type.to_string(),
+            None => {
+                let msg = "Not a valid message: it has no \"type\"";
+                self.status.insert((), (Cow::from(msg), StatusType::Error));
+                return Ok(());
+            },
+        };
+        console.pending = Some(msg);
+        let msg = format!("Send this \"{}\" message to the server? Press y to send it", msg_type);
+        self.status.insert((), (Cow::from(msg), StatusType::Warning));
+        Ok(())
+    }
+
+    fn do_command_suspend(&mut self, _: Option<&str>) -> Result<(), TUIError> {
+        self.query.clear();
+        self.suspend();
+        Ok(())
+    }
+
+    fn do_command_quit(&mut self, _: Option<&str>) -> Result<(), TUIError> {
+        Err(TUIError::Quit)
+    }
+
+    /// Open the `:help` view, or show how `command_option` is used
+    fn do_command_help(&mut self, command_option: Option<&str>) -> Result<(), TUIError> {
+        self.query.clear();
+        let name = command_option.map_or("", |x| x.trim()).trim_left_matches(':');
+        if name.is_empty() {
+            self.open_view(View::Help);
+            return Ok(());
+        }
+        let names = self.commands.names();
+        if let Some(command) = aliases::resolve(&self.aliases, &names, name) {
+            let msg = format!(":{} stands for :{}", name, command);
+            self.status.insert((), (Cow::from(msg), StatusType::Info));
+            return Ok(());
+        }
+        match commands::resolve(&names, name) {
+            Resolved::Command(name) => {
+                let msg = self.commands.get(name).map(|x| format!("{}: {}", x.usage(), x.help));
+                let msg = msg.unwrap_or_else(|| cleanup!(unreachable!()));
+                self.status.insert((), (Cow::from(msg), StatusType::Info));
+                Ok(())
+            },
+            Resolved::Ambiguous(matching) => {
+                let matching: Vec<String> = matching.iter().map(|x| format!(":{}", x)).collect();
+                let msg = format!("Ambiguous command, did you mean {}?",
+                                  commands::or_list(&matching));
+                self.status.insert((), (Cow::from(msg), StatusType::Error));
+                Ok(())
+            },
+            Resolved::Unknown(suggestion) => self.do_invalid_command(name, suggestion),
+        }
+    }
+
+    fn do_invalid_command(&mut self, cmd: &str, suggestion: Option<&str>)
+                          -> Result<(), TUIError> {
+        let msg = match suggestion {
+            Some(other_cmd) => format!(r#"Not a command. Did you mean ":{}"?"#, other_cmd),
+            None => format!(r#"Not a maruska command: "{}""#, cmd),
+        };
+        self.status.insert((), (Cow::from(msg), StatusType::Error));
+        self.query.clear();
+        Ok(())
+    }
+
+    fn move_focus(&mut self, x: isize, fix_offset: bool) {
+        if self.query.starts_with('/') && !self.in_queue_view() {
+            self.browsing_results = true;
+            self.move_results_focus(x, fix_offset)
+        } else if self.in_playing_view() && self.query.is_empty() {
+            self.scroll_lyrics(x)
+        } else if self.in_queue_view() {
+            self.move_requests_focus(x)
+        } else if self.query.is_empty() {
+            let h = self.get_viewport_height() as usize;
+            match self.views.last_mut() {
+                Some(&mut View::Artist(_, ref media, ref mut selection)) |
+                Some(&mut View::Uploads(_, ref media, ref mut selection)) |
+                Some(&mut View::Suggestions(ref media, ref mut selection)) => {
+                    let keys: Vec<String> = media.iter().map(|x| x.key.clone()).collect();
+                    selection.move_by(&keys, h, x, fix_offset);
+                },
+                _ => {},
+            }
+        }
+    }
+
+    /// Where the selected request is in the queue view, if it is there
+    fn requests_focus_position(&self) -> Option<usize> {
+        self.requests_selection.position(&self.request_keys())
+    }
+
+    /// Tell the user if an update of the queue removed or moved the selected request, which was
+    /// in the queue `old`
+    ///
+    /// The selection stays on the same request wherever it goes, so that Delete and the moves
+    /// never change another request than the one that the user selected.
+    fn check_requests_focus(&mut self, old: &[Request]) {
+        let before = self.requests_focus_position;
+        let after = self.requests_focus_position();
+        self.requests_focus_position = after;
+        let msg = match (before, after) {
+            (Some(_), None) => {
+                let media_key = self.requests_selection.key().and_then(|key| {
+                    old.iter().find(|x| x.key == *key).map(|x| x.media.key.clone())
+                });
+                self.requests_selection = Selection::new();
+                let playing = self.app.client().get_playing().as_ref().map(|x| &x.media.key);
+                if before == Some(0) && media_key.is_some() && playing == media_key.as_ref() {
+                    String::from("The selected request is playing now")
+                } else {
+                    String::from("The selected request was removed from the queue")
+                }
+            },
+            (Some(before), Some(after)) if before != after => {
+                self.requests_focus_moved_at = get_time();
+                format!("The selected request moved to place {}", after + 1)
+            },
+            _ => return,
+        };
+        self.status.insert((), (Cow::from(msg), StatusType::Warning));
+    }
+
+    /// Select another request in the queue view, moving up from the first one clears the selection
+    fn move_requests_focus(&mut self, x: isize) {
+        let h = self.get_viewport_height();
+        let keys = self.request_keys();
+        // only the requests that fit on the screen can be selected
+        let visible = min(keys.len(), (h as usize).saturating_sub(1));
+        let current = self.requests_selection.position(&keys).map_or(-1, |i| i as isize);
+        let new = min(current.saturating_add(x), visible as isize - 1);
+        let row = if new < 0 { None } else { Some(new as usize) };
+        self.requests_selection.select(&keys, row);
+    }
+
+    fn move_results_focus(&mut self, x: isize, fix_offset: bool) {
+        let h = self.get_viewport_height() as usize;
+        let keys = self.result_keys();
+        self.results_selection.move_by(&keys, h, x, fix_offset);
+
+        self.update_client_query();
+    }
+
+    /// The first search result that is on the screen
+    fn results_scroll(&self) -> usize {
+        let h = self.get_viewport_height() as usize;
+        self.results_selection.offset(&self.result_keys(), h)
+    }
+
+    /// The media keys of the search results, in the order in which they are shown
+    fn result_keys(&self) -> Vec<String> {
+        self.search_results().iter().map(|x| x.key.clone()).collect()
+    }
+
+    /// The keys of the requests in the queue view, see `queue_preview`
+    fn request_keys(&self) -> Vec<i64> {
+        self.queue_preview().iter().map(|x| x.key).collect()
+    }
+
+    /// The selected search result, if there are results
+    fn focused_result(&self) -> Option<Media> {
+        let results = self.search_results();
+        let keys: Vec<String> = results.iter().map(|x| x.key.clone()).collect();
+        results.get(self.results_selection.index(&keys)).map(|x| (*x).clone())
+    }
+
+
+    pub fn handle_message_from_client(&mut self, message: &Json) -> Result<(), ClientError> {
+        // the requests that wait for the server, which are sent once we are connected again
+        let queued = self.app.client().get_offline_requests().len();
+        let message = match self.app.handle_message(message) {
+            Ok(message) => message,
+            Err(err) => {
+                // one odd message from the server is no reason to stop
+                warn!("ignoring a message from the server: {}", DisplayChain(&err));
+                let msg = "The server sent a message that maruska cannot read, see the log";
+                self.status.insert((), (Cow::from(msg), StatusType::Warning));
+                return Ok(());
+            },
+        };
+        self.handle_client_message(message, queued)
+    }
+
+    /// Show what came of a task that was started with `Client::spawn_task`
+    pub fn handle_task_completion(&mut self, done: TaskCompletion) -> Result<(), ClientError> {
+        let queued = self.app.client().get_offline_requests().len();
+        let message = self.app.client().handle_task_completion(done);
+        self.handle_client_message(message, queued)
+    }
+
+    /// Act on `message`, with `queued` the number of requests that waited for the server before
+    fn handle_client_message(&mut self, message: Message, queued: usize)
+                             -> Result<(), ClientError> {
+        let quiet = self.notifier.is_quiet();
+        self.hooks.handle_message(self.app.client(), &message, quiet);
+        Ok(match message {
+            Message::QueryMediaResults => {
+                if self.app.client().is_query_truncated() {
+                    let msg = format!("Stopped searching after leaving out {} songs, search for \
+                                       words of the artist or title too", MAX_LEFT_OUT_RESULTS);
+                    self.status.insert((), (Cow::from(msg), StatusType::Warning));
+                }
+                if let Some(focus) = self.restored_focus {
+                    let (results, qm_done) = self.app.client().get_qm_results();
+                    if results.len() <= focus && !*qm_done {
+                        return Ok(()); // wait for more results before moving the focus
+                    }
+                    self.restored_focus = None;
+                }
+                self.move_results_focus(0, false); // reinit focus inside the new bounds
+            },
+            Message::Login => {
+                self.status.insert((), (Cow::from("Succesfully logged in"), StatusType::Success));
+                self.save_credentials(); // save creds for later use
+                self.announce_next_up();
+            },
+            Message::LoginError(ref msg) if msg == "User does not exist" => {
                 let msg = format!("Login failed: user \"{}\" does not exist",
-                                  self.username.as_ref().unwrap());
+                                  self.app.username().unwrap_or(""));
                 self.status.insert((), (Cow::from(msg), StatusType::Error));
 
                 // If the user has not given any input yet, reinsert ":username " into self.query
@@ -362,82 +2353,446 @@ impl TUI {
                     self.query.push_str(":password ");
                 }
             },
+            Message::TaskDone(id, ref result) if Some(id) == self.media_sync_task => {
+                info!("synced the media index: {}", result);
+                self.media_sync_task = None;
+                self.announce_watched(result);
+            },
+            Message::TaskFailed(id, ref err) if Some(id) == self.media_sync_task => {
+                warn!("could not sync the media index: {}", err);
+                self.media_sync_task = None;
+            },
+            Message::MediaUpdated(ref key) => {
+                self.pending_edits.remove(key);
+                self.status.insert((), (Cow::from("Saved the changes"), StatusType::Success));
+            },
+            Message::MediaUpdateError(ref key, ref err) => {
+                if let Some(media) = self.pending_edits.remove(key) {
+                    self.replace_in_views(&media);
+                }
+                let msg = format!("The changes were not saved: {}", err);
+                self.status.insert((), (Cow::from(msg), StatusType::Error));
+            },
+            Message::MediaReported(_) => {
+                let msg = "The server received the report";
+                self.status.insert((), (Cow::from(msg), StatusType::Success));
+            },
+            Message::Requests => {
+                self.count_requests();
+                self.mark_queue_changes();
+                self.announce_accepted_requests();
+                self.announce_next_up();
+            },
+            Message::PlayingChanged => {
+                self.wake_up();
+                self.songs_played += 1;
+                self.count_requests();
+                if self.in_playing_view() {
+                    self.fetch_cover();
+                    self.fetch_lyrics();
+                }
+            },
+            Message::TaskDone(id, ref result) if self.is_cover_task(id) => {
+                let (_, key) = self.cover_task.take().unwrap();
+                self.cover = Some((key, result.as_string().map(PathBuf::from)));
+            },
+            Message::TaskFailed(id, ref err) if self.is_cover_task(id) => {
+                // do not try again for the same song
+                let (_, key) = self.cover_task.take().unwrap();
+                warn!("could not fetch the cover art: {}", err);
+                self.cover = Some((key, None));
+            },
+            Message::TaskDone(id, ref result) if self.is_lyrics_task(id) => {
+                let (_, key) = self.lyrics_task.take().unwrap();
+                let lines = result.as_string().unwrap_or("").lines().map(String::from).collect();
+                self.lyrics = Some((key, lines));
+                self.lyrics_offset = 0;
+            },
+            Message::TaskFailed(id, ref err) if self.is_lyrics_task(id) => {
+                let (_, key) = self.lyrics_task.take().unwrap();
+                warn!("could not look up the lyrics: {}", err);
+                self.lyrics = Some((key, Vec::new()));
+                self.lyrics_offset = 0;
+                let msg = format!("Could not look up the lyrics: {}", err);
+                self.status.insert((), (Cow::from(msg), StatusType::Error));
+            },
+            Message::TaskDone(id, ref result) if self.is_uploads_task(id) => {
+                let (_, user) = self.uploads_task.take().unwrap();
+                self.show_uploads(user, result);
+            },
+            Message::TaskFailed(id, ref err) if self.is_uploads_task(id) => {
+                self.uploads_task = None;
+                let msg = format!("Could not look up the uploads: {}", err);
+                self.status.insert((), (Cow::from(msg), StatusType::Error));
+            },
+            Message::TaskDone(id, ref result) if self.is_artist_task(id) => {
+                let (_, artist) = self.artist_task.take().unwrap();
+                self.show_artist(artist, result);
+            },
+            Message::TaskFailed(id, ref err) if self.is_artist_task(id) => {
+                self.artist_task = None;
+                let msg = format!("Could not look up the songs of the artist: {}", err);
+                self.status.insert((), (Cow::from(msg), StatusType::Error));
+            },
+            Message::TaskDone(id, ref result) if self.is_suggest_task(id) => {
+                self.suggest_task = None;
+                self.show_suggestions(result);
+            },
+            Message::TaskFailed(id, ref err) if self.is_suggest_task(id) => {
+                self.suggest_task = None;
+                let msg = format!("Could not look for suggestions: {}", err);
+                self.status.insert((), (Cow::from(msg), StatusType::Error));
+            },
+            // Every failed attempt to reconnect disconnects again, which is told only once
+            Message::ConnectionState(ConnectionState::Disconnected) if !self.connection_lost => {
+                self.connection_lost = true;
+                self.toast("Lost the connection to the server".to_string(), StatusType::Error);
+            },
+            Message::ConnectionState(ConnectionState::Connected) if self.connection_lost => {
+                self.connection_lost = false;
+                let msg = match queued {
+                    0 => "Connected to the server again".to_string(),
+                    n => format!("Connected to the server again, requested {} songs", n),
+                };
+                self.toast(msg, StatusType::Success);
+            },
+            Message::Raw(ref msg) if self.show_raw => {
+                let msg = format!("Received: {}", msg);
+                self.status.insert((), (Cow::from(msg), StatusType::Info));
+            },
             msg => {
                 debug!("unhandled message from client: {:?}", msg);
             },
         })
     }
 
-    fn save_credentials(&self) {
-        if let Some(home_dir) = env::home_dir() {
-            let cache_dir = home_dir.join(".cache");;
-            let config_filename = cache_dir.join("maruska.toml");
-            let mut store_obj = if let Ok(mut store_file) = fs::File::open(&config_filename) {
-                store::load(&mut store_file).unwrap_or_else(|_| BTreeMap::new())
-            } else {
-                BTreeMap::new()
-            };
+    fn load_stats(&mut self) {
+        self.stats_file = paths::stats_file(&self.app.client().get_url());
+        if let Some(ref path) = self.stats_file {
+            match Stats::load(path) {
+                Ok(stats) => self.stats = stats,
+                Err(err) => warn!("could not read the stats from {:?}: {}", path, err),
+            }
+        }
+    }
 
-            let mut toml_creds = BTreeMap::new();
-            let toml_username = toml::Value::String(self.username.clone().unwrap());
-            toml_creds.insert("username".to_string(), toml_username);
-            match self.secret {
-                Some(Secret::AccessKey(ref x)) => {
-                    let toml = toml::Value::String(x.clone());
-                    toml_creds.insert("access_key".to_string(), toml);
-                },
-                Some(Secret::PasswordHash(ref x)) => {
-                    let toml = toml::Value::String(x.clone());
-                    toml_creds.insert("password_hash".to_string(), toml);
-                },
-                None => return,
+    /// Count the requests in the queue and the song that is playing, if we did not count them yet
+    fn count_requests(&mut self) {
+        let path = match self.stats_file {
+            Some(ref path) => path.clone(),
+            None => return,
+        };
+        let client = self.app.client();
+        let username = if client.is_logged_in() { client.get_username() } else { None };
+        let requests = client.get_requests();
+        let playing = client.get_playing();
+        let result = Stats::update(&path, |stats| {
+            let mut changed = false;
+            if let Some(ref requests) = *requests {
+                changed |= stats.observe_requests(requests, username);
+            }
+            if let Some(ref playing) = *playing {
+                changed |= stats.observe_playing(playing);
             }
-            store_obj.insert(self.client.get_url(), toml::Value::Table(toml_creds));
+            changed
+        });
+        match result {
+            Ok(stats) => self.stats = stats,
+            Err(err) => warn!("could not count the requests in {:?}: {}", path, err),
+        }
+    }
 
-            if fs::create_dir_all(cache_dir).is_err() {
-                return; // fail silently on IO error
+    /// Find out what changed in the queue, so that it can be highlighted
+    fn mark_queue_changes(&mut self) {
+        let queue = match *self.app.client().get_requests() {
+            Some(ref requests) => requests.clone(),
+            None => return,
+        };
+        // the first queue that we get did not change
+        if let Some(old) = self.last_queue.take() {
+            let diff = queue::diff(&old, &queue);
+            if !diff.is_empty() {
+                self.queue_changes = diff.changed;
+                self.queue_changed_at = get_time();
+            }
+            // the first request leaves the queue when it starts playing, which is not news
+            let removed: Vec<&Request> = diff.removed.iter()
+                .filter(|x| old.first().map_or(true, |first| first.key != x.key))
+                .collect();
+            let msg = match removed.len() {
+                0 => None,
+                1 => Some(format!("Removed from the queue: {} - {}", removed[0].media.artist,
+                                  removed[0].media.title)),
+                n => Some(format!("Removed {} requests from the queue", n)),
             };
-            let mut open_options = fs::OpenOptions::new();
-            open_options.write(true);
-            open_options.truncate(true);
-            open_options.create(true);
-            open_options.mode(0o600);
-            if let Ok(mut store_file) = open_options.open(&config_filename) {
-                if store::save(store_obj, &mut store_file).is_err() {
-                    return; // fail silently on IO error
-                };
-            } else {
-                error!("Could not open file \"{:?}\" for writing", config_filename);
+            if let Some(msg) = msg {
+                self.status.insert((), (Cow::from(msg), StatusType::Info));
+            }
+            if self.in_queue_view() {
+                self.check_requests_focus(&old);
+            }
+        }
+        self.last_queue = Some(queue);
+    }
+
+    /// Tell the user that the requests they made from here are in the queue now
+    fn announce_accepted_requests(&mut self) {
+        let msgs: Vec<String> = self.app.client().get_queued_requests().iter().map(|request| {
+            format!("Request accepted: {} - {}", request.media.artist, request.media.title)
+        }).collect();
+        self.songs_requested += msgs.len() as u64;
+        for msg in msgs {
+            self.toast(msg, StatusType::Info);
+        }
+    }
+
+    /// Tell the user about something that happened on the server, in a toast or else on the
+    /// status line
+    fn toast(&mut self, msg: String, ty: StatusType) {
+        if !self.show_toasts {
+            self.status.insert((), (Cow::from(msg), ty));
+            return;
+        }
+        if self.toasts.len() == MAX_TOASTS {
+            self.toasts.remove(0);
+        }
+        self.toasts.push(Toast {
+            msg: msg,
+            ty: ty,
+            until: get_time() + Duration::seconds(TOAST_SECS),
+        });
+    }
+
+    /// Tell the user about the new songs that match a saved search, which a sync of the media
+    /// index found (see `start_media_sync`)
+    fn announce_watched(&mut self, result: &Json) {
+        let watched = match result.find("watched").and_then(|x| x.as_array()) {
+            Some(watched) => watched.clone(),
+            None => return,
+        };
+        for obj in &watched {
+            let field = |name: &str| obj.find(name).and_then(|x| x.as_string()).unwrap_or("");
+            let song = format!("{} - {}", field("artist"), field("title"));
+            self.toast(format!("New song for \"{}\": {}", field("search"), song),
+                       StatusType::Info);
+            if self.notify_watchlist {
+                self.notifier.send(&format!("New song for \"{}\"", field("search")), &song);
+            }
+        }
+    }
+
+    /// Tell the user when the first request in the queue is theirs, so that they can be there
+    /// when it plays
+    fn announce_next_up(&mut self) {
+        let request = match (self.app.client().get_username(), self.app.client().get_requests()) {
+            (Some(username), &Some(ref requests)) if self.app.client().is_logged_in() => {
+                match requests.first() {
+                    Some(request) if request.by.as_ref().map(|x| &x[..]) == Some(username) => {
+                        request.clone()
+                    },
+                    _ => return,
+                }
+            },
+            _ => return,
+        };
+        if self.next_up == Some(request.key) {
+            return;
+        }
+        self.next_up = Some(request.key);
+        let media = &request.media;
+        let msg = format!("Your request is up next: {} - {}", media.artist, media.title);
+        self.toast(msg, StatusType::Success);
+        if self.notify_next_up {
+            let song = format!("{} - {}", media.artist, media.title);
+            self.notifier.send("Your request is up next", &song);
+        }
+    }
+
+    /// The store in which the credentials are kept, if it can be used right now
+    ///
+    /// The encrypted store cannot be used until the user has entered the passphrase.
+    fn credential_store(&self) -> Option<Box<CredentialStore>> {
+        if !self.encrypt_credentials {
+            return paths::credentials_file().map(|x| Box::new(PlainStore::new(x)) as Box<CredentialStore>);
+        }
+        match (paths::encrypted_credentials_file(), &self.passphrase) {
+            (Some(path), &Some(ref passphrase)) => Some(Box::new(EncryptedStore::new(path, passphrase))),
+            _ => None,
+        }
+    }
+
+    fn save_credentials(&mut self) {
+        let credentials = match self.app.credentials() {
+            Some((username, secret)) => Credentials {
+                username: username.to_string(),
+                secret: secret.clone(),
+            },
+            None => return,
+        };
+        if self.encrypt_credentials && self.passphrase.is_none() {
+            // `do_command_passphrase` saves the credentials once the user has chosen one
+            self.credentials_pending = true;
+            let msg = "Choose a passphrase to encrypt your credentials";
+            self.status.insert((), (Cow::from(msg), StatusType::Info));
+            if self.query.is_empty() {
+                self.query.push_str(":passphrase ");
+            }
+            return;
+        }
+
+        let url = self.app.client().get_url();
+        if let Some(store) = self.credential_store() {
+            if let Err(err) = store.save(&url, &credentials) {
+                error!("Could not save credentials: {}", DisplayChain(&err));
+                return;
+            }
+        }
+        if self.encrypt_credentials {
+            // Do not leave a readable copy behind in the plain credentials file
+            if let Some(path) = paths::credentials_file() {
+                if let Err(err) = PlainStore::new(path).remove(&url) {
+                    error!("Could not remove plain credentials: {}", DisplayChain(&err));
+                }
             }
         }
     }
 
     fn load_credentials(&mut self) {
-        if let Some(home_dir) = env::home_dir() {
-            let cache_dir = home_dir.join(".cache");;
-            let config_filename = cache_dir.join("maruska.toml");
-            if let Ok(mut store_file) = fs::File::open(&config_filename) {
-                let store_obj = store::load(&mut store_file).unwrap_or_else(|_| BTreeMap::new());
-                debug!("{:?}", store_obj);
-                let url = self.client.get_url();
-                if let Some(host) = store_obj.get(&url) {
-                    if let Some(val) = host.lookup("username").and_then(|x| x.as_str()) {
-                        self.username = Some(val.to_string());
-                    }
-                    if let Some(val) = host.lookup("password_hash").and_then(|x| x.as_str()) {
-                        self.secret = Some(Secret::PasswordHash(val.to_string()));
-                    } else if let Some(val) = host.lookup("access_key").and_then(|x| x.as_str()) {
-                        self.secret = Some(Secret::AccessKey(val.to_string()));
-                    }
+        paths::migrate_credentials_file();
+        let store = match self.credential_store() {
+            Some(x) => x,
+            None => {
+                // Encryption is enabled, but we do not have the passphrase yet
+                if paths::encrypted_credentials_file().map_or(false, |x| x.exists()) {
+                    let msg = "Enter your passphrase to unlock your credentials";
+                    self.status.insert((), (Cow::from(msg), StatusType::Info));
+                    self.query.push_str(":passphrase ");
+                    return;
+                }
+                // Nothing is encrypted yet, use the plain credentials until they are saved again
+                match paths::credentials_file() {
+                    Some(path) => Box::new(PlainStore::new(path)),
+                    None => return,
+                }
+            },
+        };
+        match store.load(&self.app.client().get_url()) {
+            Ok(Some(credentials)) => {
+                debug!("loaded credentials {:?}", credentials);
+                self.app.set_credentials(credentials.username, credentials.secret);
+            },
+            Ok(None) => {},
+            Err(err) => warn!("Could not load credentials: {}", DisplayChain(&err)),
+        }
+    }
+
+    /// Tell the user that the previous session crashed, and that we started in safe mode
+    pub fn show_crash(&mut self, msg: &str) {
+        let msg = msg.lines().map(|x| x.trim()).collect::<Vec<_>>().join(" ");
+        self.crash_message = Some(format!("Safe mode (no config or hooks), last session \
+                                           crashed: {}", msg));
+    }
+
+    /// Log a summary of the session, and return it if it should be printed on quit
+    pub fn session_summary(&self) -> Option<String> {
+        let client = self.app.client();
+        let metrics = client.get_metrics();
+        let connected = Duration::from_std(metrics.connected).unwrap_or(Duration::zero());
+        let lines = [
+            format!("Session of {} on {}", format_duration(get_time() - self.session_started),
+                    client.get_url()),
+            format!("Connected for {}, reconnected {} time{}", format_duration(connected),
+                    metrics.reconnects, if metrics.reconnects == 1 { "" } else { "s" }),
+            format!("{} songs played, {} requested from here", self.songs_played,
+                    self.songs_requested),
+            format!("{} packets sent ({}), {} received ({})",
+                    metrics.packets_sent, format_bytes(metrics.bytes_sent),
+                    metrics.packets_received, format_bytes(metrics.bytes_received)),
+        ];
+        info!("session summary: {}", lines.join("; "));
+        if self.print_summary { Some(lines.join("\n")) } else { None }
+    }
+
+    /// Save the query, the scroll position and the layout, if session restoring is enabled
+    pub fn save_session(&self) {
+        if !self.restore_session {
+            return;
+        }
+        if let Some(session_filename) = paths::session_file() {
+            // Only searches are restored, a half typed command could contain a password
+            let query = if self.query.starts_with('/') { self.query.clone() } else { String::new() };
+            let mut store_obj = BTreeMap::new();
+            store_obj.insert("query".to_string(), toml::Value::String(query));
+            let keys = self.result_keys();
+            let focus = self.results_selection.index(&keys);
+            store_obj.insert("results_offset".to_string(),
+                             toml::Value::Integer(self.results_scroll() as i64));
+            store_obj.insert("results_focus".to_string(), toml::Value::Integer(focus as i64));
+            store_obj.insert("split_percent".to_string(),
+                             toml::Value::Integer(self.split_percent as i64));
+            store_obj.insert("queue_focused".to_string(), toml::Value::Boolean(self.queue_focused));
+
+            if let Err(err) = store::save_file(&session_filename, &SESSION_SCHEMA, store_obj) {
+                error!("Could not save session to {:?}: {}", session_filename, DisplayChain(&err));
+            }
+        }
+    }
+
+    fn load_session(&mut self) {
+        if let Some(session_filename) = paths::session_file() {
+            if let Ok(store_obj) = store::load_file(&session_filename, &SESSION_SCHEMA) {
+                debug!("restoring session {:?}", store_obj);
+                if let Some(percent) = store_obj.get("split_percent").and_then(|x| x.as_integer()) {
+                    let percent = min(percent, SPLIT_MAX_PERCENT as i64);
+                    self.split_percent = max(SPLIT_MIN_PERCENT as i64, percent) as i32;
+                }
+                if let Some(focused) = store_obj.get("queue_focused").and_then(|x| x.as_bool()) {
+                    self.queue_focused = focused;
                 }
+                let query = store_obj.get("query").and_then(|x| x.as_str()).unwrap_or("");
+                if !query.starts_with('/') {
+                    return;
+                }
+                let get_usize = |key| store_obj.get(key)
+                                               .and_then(|x: &toml::Value| x.as_integer())
+                                               .map_or(0, |x| max(x, 0) as usize);
+                self.query = query.to_string();
+                let focus = get_usize("results_focus");
+                self.results_selection = Selection::at(focus, get_usize("results_offset"));
+                self.restored_focus = Some(focus);
+                self.update_client_query();
             }
         }
     }
 
     pub fn handle_event(&mut self, event: RawEvent) -> Result<(), TUIError> {
+        self.wake_up();
+        // the other keys act on the search results, which have to be of what was typed
+        let typing = event.ch != 0 || [TB_KEY_SPACE, TB_KEY_BACKSPACE, TB_KEY_BACKSPACE2,
+                                       TB_KEY_CTRL_W, TB_KEY_CTRL_U].contains(&event.key);
+        if event.etype == TB_EVENT_KEY && !typing && self.search_due.is_some() {
+            self.update_client_query();
+        }
+        let result = self.handle_event_inner(event);
+        // the user saw what the key did to the selection
+        self.requests_focus_position = self.requests_focus_position();
+        result
+    }
+
+    fn handle_event_inner(&mut self, event: RawEvent) -> Result<(), TUIError> {
         match event.etype {
             TB_EVENT_KEY => {
-                if event.ch == 0 {
+                self.crash_message = None;
+                if self.action_pending {
+                    self.action_pending = false;
+                    self.do_action(event.ch)
+                } else if self.edit_form.is_some() {
+                    self.handle_form_input(event.key, event.ch)
+                } else if self.console.is_some() {
+                    self.handle_console_input(event.key, event.ch)
+                } else if self.confirm_marked {
+                    self.handle_confirm_input(event.key, event.ch)
+                } else if event.ch == 0 {
                     self.handle_input_key(event.key)
                 } else {
                     self.handle_input_ch(event.ch)
@@ -458,6 +2813,85 @@ impl TUI {
         }
     }
 
+    /// Handle a key while the `:edit` form is open
+    fn handle_form_input(&mut self, key: u16, ch: u32) -> Result<(), TUIError> {
+        if ch == 0 {
+            match key {
+                TB_KEY_ENTER => return self.submit_edit_form(),
+                TB_KEY_ESC => {
+                    self.edit_form = None;
+                    return Ok(());
+                },
+                TB_KEY_CTRL_C => return Err(TUIError::Quit),
+                _ => {},
+            }
+        }
+        let form = self.edit_form.as_mut().unwrap();
+        let n = form.values.len();
+        match (key, char::from_u32(ch)) {
+            (_, Some(ch)) if ch != '\0' => form.values[form.focus].push(ch),
+            (TB_KEY_SPACE, _) => form.values[form.focus].push(' '),
+            (TB_KEY_BACKSPACE, _) | (TB_KEY_BACKSPACE2, _) => {
+                form.values[form.focus].pop();
+            },
+            (TB_KEY_CTRL_U, _) => form.values[form.focus].clear(),
+            (TB_KEY_TAB, _) | (TB_KEY_ARROW_DOWN, _) => form.focus = (form.focus + 1) % n,
+            (TB_KEY_ARROW_UP, _) => form.focus = (form.focus + n - 1) % n,
+            (key, _) => warn!("ignoring unhandled keycode {} in the edit form", key),
+        }
+        Ok(())
+    }
+
+    /// Handle a key while the `:console` is open
+    fn handle_console_input(&mut self, key: u16, ch: u32) -> Result<(), TUIError> {
+        if key == TB_KEY_CTRL_C {
+            return Err(TUIError::Quit);
+        }
+        if let Some(msg) = self.console.as_mut().unwrap().pending.take() {
+            if ch == 'y' as u32 {
+                self.app.client_mut().send_raw(msg);
+                self.show_raw = true;
+                self.console.as_mut().unwrap().input.clear();
+                self.status.insert((), (Cow::from("Sent the message"), StatusType::Info));
+            } else {
+                self.status.insert((), (Cow::from("Not sent"), StatusType::Info));
+            }
+            return Ok(());
+        }
+        let packets = self.app.client().get_packets().len();
+        let page = max(self.get_viewport_size().1 - 1, 1) as usize;
+        {
+            let console = self.console.as_mut().unwrap();
+            let scroll = |offset: usize, by: isize| {
+                min(max(offset as isize + by, 0) as usize, packets.saturating_sub(1))
+            };
+            match (key, char::from_u32(ch)) {
+                (_, Some(ch)) if ch != '\0' => console.input.push(ch),
+                (TB_KEY_SPACE, _) => console.input.push(' '),
+                (TB_KEY_BACKSPACE, _) | (TB_KEY_BACKSPACE2, _) => {
+                    console.input.pop();
+                },
+                (TB_KEY_CTRL_U, _) => console.input.clear(),
+                (TB_KEY_ARROW_UP, _) => console.offset = scroll(console.offset, 1),
+                (TB_KEY_ARROW_DOWN, _) => console.offset = scroll(console.offset, -1),
+                (TB_KEY_PGUP, _) => console.offset = scroll(console.offset, page as isize),
+                (TB_KEY_PGDN, _) => console.offset = scroll(console.offset, -(page as isize)),
+                (TB_KEY_ENTER, _) => {},
+                (TB_KEY_ESC, _) => {},
+                (key, _) => warn!("ignoring unhandled keycode {} in the console", key),
+            }
+        }
+        match key {
+            TB_KEY_ENTER => self.submit_console(),
+            TB_KEY_ESC => {
+                self.console = None;
+                self.status.clear();
+                Ok(())
+            },
+            _ => Ok(()),
+        }
+    }
+
     fn handle_input_ch(&mut self, ch: u32) -> Result<(), TUIError> {
         let ret = match ch {
             47 | 58 => self.handle_input_cmdtypechar(ch),
@@ -472,7 +2906,7 @@ impl TUI {
     }
 
     fn handle_input_key(&mut self, key: u16) -> Result<(), TUIError> {
-        // TODO Page {up, down} should self.results_offset -= (-)self.height()
+        // TODO Page {up, down} should scroll the results by (-)self.height()
         //      and put the current focus at the entry closes to the new bounds
         match key {
             TB_KEY_ARROW_UP => self.handle_arrow_up(),
@@ -480,10 +2914,39 @@ impl TUI {
             TB_KEY_PGUP => self.handle_page_up(),
             TB_KEY_PGDN => self.handle_page_down(),
             TB_KEY_ENTER => self.handle_input_submit(key),
+            TB_KEY_SPACE if self.query.starts_with('/') && self.browsing_results => {
+                self.toggle_mark()
+            },
             TB_KEY_SPACE => self.handle_input_alphanum(' ' as u32),
             TB_KEY_BACKSPACE | TB_KEY_BACKSPACE2 => self.handle_input_backspace(key),
             TB_KEY_TAB => self.handle_input_tab(key),
+            TB_KEY_ESC if self.in_queue_view() && !self.staged_moves.is_empty() => {
+                self.staged_moves.clear();
+                self.status.insert((), (Cow::from("Put the requests back"), StatusType::Info));
+                Ok(())
+            },
+            TB_KEY_ESC if self.query.is_empty() => {
+                self.close_view();
+                Ok(())
+            },
+            TB_KEY_ESC if self.query.starts_with('/') && !self.marked.is_empty() => {
+                self.marked.clear();
+                self.status.insert((), (Cow::from("Unmarked all songs"), StatusType::Info));
+                Ok(())
+            },
+            TB_KEY_CTRL_B if self.in_queue_view() => self.do_boost(),
+            TB_KEY_DELETE if self.in_queue_view() => self.do_manage_request(None),
+            TB_KEY_CTRL_K if self.in_queue_view() => self.do_manage_request(Some(-1)),
+            TB_KEY_CTRL_J if self.in_queue_view() => self.do_manage_request(Some(1)),
             TB_KEY_CTRL_C => Err(TUIError::Quit),
+            TB_KEY_CTRL_O => self.open_focused(),
+            TB_KEY_CTRL_P => self.do_preview(),
+            TB_KEY_CTRL_T => self.toggle_eta(),
+            TB_KEY_CTRL_A => self.start_action(),
+            TB_KEY_CTRL_Z => {
+                self.suspend();
+                Ok(())
+            },
             TB_KEY_CTRL_W => self.handle_input_delword(key),
             TB_KEY_CTRL_U => self.handle_input_nak(key),
             key => {
@@ -516,15 +2979,25 @@ impl TUI {
     }
 
     fn handle_input_backspace(&mut self, _: u16) -> Result<(), TUIError> {
+        if self.query.is_empty() {
+            self.close_view();
+            return Ok(());
+        }
+        self.browsing_results = false;
         self.query.pop();
-        self.update_client_query();
+        self.search_soon();
         Ok(())
     }
 
     fn handle_input_tab(&mut self, _: u16) -> Result<(), TUIError> {
+        if self.is_split() && !self.query.starts_with(':') {
+            self.queue_focused = !self.queue_focused;
+            return Ok(());
+        }
         // TODO implement tab completion for search queries
         if self.query.starts_with(':') {
-            let mut matching_commands: Vec<&str> = COMMANDS.iter()
+            let names = self.commands.names();
+            let mut matching_commands: Vec<&str> = names.iter()
                     .filter(|x| x.starts_with(&self.query[1..]))
                     .map(|x| &x[self.query[1..].len()..])
                     .collect();
@@ -541,6 +3014,11 @@ impl TUI {
             }
             if matching_commands.len() == 1 {
                 self.query.push(' ');
+                let msg = self.commands.get(self.query[1..].trim_right())
+                                       .map(|x| format!("{}: {}", x.usage(), x.help));
+                if let Some(msg) = msg {
+                    self.status.insert((), (Cow::from(msg), StatusType::Info));
+                }
             }
         }
         Ok(())
@@ -548,9 +3026,14 @@ impl TUI {
 
     fn handle_input_submit(&mut self, _: u16) -> Result<(), TUIError> {
         match &self.query.chars().nth(0) {
+            &Some('/') if self.in_queue_view() => {
+                if self.staged_moves.is_empty() { Ok(()) } else { self.send_moves() }
+            },
             &Some('/') => self.do_request(),
             &Some(':') => self.do_command(),
             &Some(_) => cleanup!(unreachable!()),
+            &None if !self.views.is_empty() => self.do_request_from_view(),
+            &None if self.in_queue_view() && !self.staged_moves.is_empty() => self.send_moves(),
             &None => Ok(()), // do nothing
         }
     }
@@ -559,31 +3042,38 @@ impl TUI {
         lazy_static! {
             static ref WORD: Regex = Regex::new(r#"\S+"#).unwrap();
         }
+        self.browsing_results = false;
         match WORD.find_iter(&self.query).last() {
             _ if self.query.len() == 1 => self.query.clear(),
             Some((start, _)) => self.query.truncate(max(start, 1)),
             None => {},
         };
-        self.update_client_query();
+        self.search_soon();
         Ok(())
     }
 
     fn handle_input_nak(&mut self, _: u16) -> Result<(), TUIError> {
+        self.browsing_results = false;
         if self.query.len() > 1 {
             self.query.truncate(1);
         } else {
             self.query.clear();
         }
-        self.update_client_query();
+        self.search_soon();
         Ok(())
     }
 
     fn handle_input_cmdtypechar(&mut self, ch: u32) -> Result<(), TUIError> {
+        if self.query.starts_with(':') {
+            // part of the arguments of a command, e.g. the JSON of `:raw`
+            return self.handle_input_alphanum(ch);
+        }
         if !self.query.is_empty() { return Ok(()); }
 
         if self.query.len() == 0 {
             match ch {
                 47 => {
+                    self.close_views();
                     self.query.push('/');
                     self.update_client_query();
                 },
@@ -595,26 +3085,44 @@ impl TUI {
     }
 
     fn handle_input_alphanum(&mut self, input_ch: u32) -> Result<(), TUIError> {
+        if self.is_split() && self.in_queue_view() {
+            // with the focus on the queue, < and > are not typed but move the line next to it
+            if input_ch == '<' as u32 || input_ch == '>' as u32 {
+                let sign = if input_ch == '<' as u32 { -1 } else { 1 };
+                self.resize_split(sign * SPLIT_STEP_PERCENT);
+                return Ok(());
+            }
+        }
+        self.browsing_results = false;
+        self.queue_focused = false;
         let ch_option = char::from_u32(input_ch as u32);
         match ch_option {
             Some(ch) => {
-                if self.query.is_empty() { self.query.push('/') };
+                if self.query.is_empty() {
+                    // typing in any view starts a search
+                    self.close_views();
+                    self.query.push('/');
+                }
                 self.query.push(ch);
             },
             None => cleanup!(unreachable!()),
         }
-        self.update_client_query();
+        self.search_soon();
         Ok(())
     }
 
     unsafe fn print(&self, x: i32, y: i32, fg: u16, bg: u16, s: &str, maxlen: usize,
                              trunc_fg: u16, trunc_bg: u16, trunc_s: &str) {
+        let (fg, bg) = self.style(fg, bg);
+        let (trunc_fg, trunc_bg) = self.style(trunc_fg, trunc_bg);
+        // the rows of the viewport start below the header
+        let y = y + self.header_rows();
         if s.len() <= maxlen || s.is_empty() {
             for (i, ch) in s.chars().chain(repeat(' ')).take(maxlen).enumerate() {
                 tb_change_cell(x+i as i32, y, ch as u32, fg, bg);
             }
         } else {
-            let print_len = max(maxlen - trunc_s.len(), 0);
+            let print_len = maxlen.saturating_sub(trunc_s.len());
             for (i, ch) in s.chars().take(print_len).enumerate() {
                 tb_change_cell(x+i as i32, y, ch as u32, fg, bg);
             }
@@ -624,83 +3132,531 @@ impl TUI {
         }
     }
 
+    /// Leave out the colors in low bandwidth mode, a background color becomes reverse video
+    fn style(&self, fg: u16, bg: u16) -> (u16, u16) {
+        if !self.low_bandwidth {
+            return (fg, bg);
+        }
+        let attributes = fg & (TB_BOLD | TB_UNDERLINE | TB_REVERSE);
+        if bg & 0xff != TB_DEFAULT {
+            (attributes | TB_REVERSE, TB_DEFAULT)
+        } else {
+            (attributes, TB_DEFAULT)
+        }
+    }
+
+    /// Redraw the screen, in low bandwidth mode at most every `LOW_BANDWIDTH_DRAW_MILLIS`
+    ///
+    /// In low bandwidth mode, draws that follow each other quickly (e.g. while typing, or for a
+    /// burst of updates from the server) are skipped and done at once on the next tick.
     pub fn draw(&mut self) {
+        if self.low_bandwidth {
+            let interval = Duration::milliseconds(LOW_BANDWIDTH_DRAW_MILLIS as i64);
+            if get_time() - self.last_draw < interval {
+                self.draw_pending = true;
+                return;
+            }
+            self.last_draw = get_time();
+            self.draw_pending = false;
+        }
+        if self.cover_drawn.is_some() && self.cover_drawn != self.visible_cover() {
+            self.erase_cover();
+        }
         unsafe { tb_clear(); }
-        if self.query.starts_with('/') {
+        if self.is_too_small() {
+            self.draw_too_small();
+            unsafe { tb_present(); }
+            return;
+        }
+        if self.edit_form.is_some() {
+            self.draw_edit_form();
+            self.draw_status();
+            unsafe { tb_present(); }
+            return;
+        }
+        if self.console.is_some() {
+            self.draw_console();
+            self.draw_status();
+            unsafe { tb_present(); }
+            return;
+        }
+        if self.confirm_marked {
+            self.draw_marked();
+        } else if self.is_split() {
+            self.draw_split();
+        } else if self.query.starts_with('/') {
             self.draw_search_results();
-        } else {
+        } else if self.views.is_empty() {
             self.draw_current_requests();
+        } else {
+            self.draw_view();
         }
+        self.draw_header();
         self.draw_query();
         self.draw_status();
+        self.draw_toasts();
         unsafe { tb_present(); }
+        self.draw_cover();
+    }
+
+    /// Switch between showing when songs start and end as the time until then and as the time
+    /// of day
+    fn toggle_eta(&mut self) -> Result<(), TUIError> {
+        let (eta, msg) = match self.time_format.eta {
+            Eta::Duration => (Eta::Time, "Showing the time of day at which songs start"),
+            Eta::Time => (Eta::Duration, "Showing the time until songs start"),
+        };
+        self.time_format.eta = eta;
+        self.status.insert((), (Cow::from(msg), StatusType::Info));
+        Ok(())
+    }
+
+    /// Draw a placeholder instead of the UI, until the terminal is resized
+    fn draw_too_small(&self) {
+        let (w, h) = self.get_size();
+        let msg = format!("terminal too small (need ≥ {}x{})", MIN_WIDTH, MIN_HEIGHT);
+        unsafe {
+            if h > 0 {
+                self.print(0, -self.header_rows(), TB_RED | TB_BOLD, TB_DEFAULT, &msg, w as usize,
+                           TB_BLUE, TB_DEFAULT, "$");
+            }
+            tb_set_cursor(TB_HIDE_CURSOR, TB_HIDE_CURSOR);
+        }
     }
 
     fn draw_current_requests<'a>(&'a mut self) {
         let (w, h) = self.get_viewport_size();
         let mut str_table: Vec<Vec<Cow<'a, str>>> = Vec::new();
 
-        // first line shows currently playing song
+        let time_format = self.time_format;
+
+        // first line shows currently playing song and when it ends, the requests show when they
+        // start
         let mut queue_length = Duration::zero();
-        str_table.push(if let &Some(ref playing) = self.client.get_playing() {
+        str_table.push(if let &Some(ref playing) = self.app.client().get_playing() {
             let requested_by = String::from(unwrap_requested_by(&playing.requested_by));
             queue_length = queue_length + (playing.end_time - get_time());
+            let title = match self.app.client().get_skip_votes() {
+                Some((votes, needed)) => {
+                    Cow::from(format!("{} [skip {}/{}]", playing.media.title, votes, needed))
+                },
+                None => Cow::from(playing.media.title.as_ref()),
+            };
             vec!(Cow::from(requested_by),
                  Cow::from(playing.media.artist.as_ref()),
-                 Cow::from(playing.media.title.as_ref()),
-                 Cow::from(format_duration(queue_length)))
+                 title,
+                 Cow::from(time_format.eta(queue_length)))
         } else {
             repeat(Cow::from("")).take(4).collect()
         });
 
-        // rest shows the current request queue, offset is ignored (-> 0)
-        if let Some(ref requests) = *self.client.get_requests() {
-            for request in requests.iter().take(h as usize - 1) {
-                let requested_by = String::from(unwrap_requested_by(&request.by));
-                let media = &request.media;
-                queue_length = queue_length + media.length;;
-                str_table.push(vec!(Cow::from(requested_by),
-                                    Cow::from(media.artist.clone()),
-                                    Cow::from(media.title.clone()),
-                                    Cow::from(format_duration(queue_length))))
-            }
+        // rest shows the current request queue, offset is ignored (-> 0), with the moves that
+        // were not sent yet
+        let requests = self.queue_preview();
+        for request in requests.iter().take((h as usize).saturating_sub(1)) {
+            let requested_by = String::from(unwrap_requested_by(&request.by));
+            let media = &request.media;
+            str_table.push(vec!(Cow::from(requested_by),
+                                Cow::from(media.artist.clone()),
+                                Cow::from(media.title.clone()),
+                                Cow::from(time_format.eta(queue_length))));
+            queue_length = queue_length + media.length;
         }
 
         // get optimal column widths
         let col_widths = fit_columns(&str_table, &[1f32, 4f32, 4f32, 1f32], w as usize);
 
+        // the selected request is one row down, below the playing song
+        let keys: Vec<i64> = requests.iter().map(|x| x.key).collect();
+        let selected = if self.is_split() && !self.in_queue_view() {
+            None // the search results have the focus
+        } else {
+            self.requests_selection.position(&keys)
+        };
+        let selection = selected.map(|i| (i + 1, (TB_BLACK, TB_BLUE, TB_WHITE)));
+
         // do the actual drawing
-        self.draw_table(0, str_table.iter(), &col_widths, (TB_DEFAULT, TB_BLUE, TB_DEFAULT), None);
+        self.draw_table(0, str_table.iter(), &col_widths, (TB_DEFAULT, TB_BLUE, TB_DEFAULT),
+                        selection);
+
+        // and draw the requests that were moved but not sent, the ones that the server did not
+        // change yet, and the ones that just changed, over it in another color
+        let since_change = get_time() - self.queue_changed_at;
+        let changes_shown = since_change < Duration::seconds(QUEUE_CHANGE_SECS);
+        for (i, request) in requests.iter().enumerate().take(str_table.len() - 1) {
+            let fg = match self.queue_changes.get(&request.key) {
+                _ if selected == Some(i) => continue,
+                _ if self.staged_moves.iter().any(|x| x.0 == request.key) => {
+                    TB_MAGENTA | TB_BOLD
+                },
+                _ if self.app.client().is_pending(request.key) => TB_CYAN,
+                _ if !changes_shown => continue,
+                Some(&Change::Added) => TB_GREEN | TB_BOLD,
+                Some(&Change::MovedUp) | Some(&Change::MovedDown) => TB_YELLOW | TB_BOLD,
+                None => continue,
+            };
+            self.draw_table(i + 1, once(&str_table[i + 1]), &col_widths,
+                            (fg, TB_BLUE, TB_DEFAULT), None);
+        }
+    }
+
+    fn draw_search_results(&self) {
+        let qm_done = *self.app.client().get_qm_results().1;
+        let results = self.search_results();
+        let keys: Vec<String> = results.iter().map(|x| x.key.clone()).collect();
+        let focused = !self.in_queue_view();
+        self.draw_media_list(&results, &self.results_selection, &keys, focused, qm_done,
+                             &self.marked);
+    }
+
+    /// Draw the queue on the left and the search results on the right, with a line between them
+    fn draw_split(&mut self) {
+        let (w, h) = self.get_viewport_size();
+        let left = self.split_column();
+        self.pane.set(Some((0, left)));
+        self.draw_current_requests();
+        self.pane.set(Some((left + 1, w - left - 1)));
+        if self.query.starts_with('/') {
+            self.draw_search_results();
+        } else {
+            unsafe {
+                self.print(left + 1, 0, TB_BLUE, TB_DEFAULT, "Type to search",
+                           (w - left - 1) as usize, TB_BLUE, TB_DEFAULT, "$");
+            }
+        }
+        self.pane.set(None);
+        for y in 0..h {
+            unsafe { self.print(left, y, TB_BLUE, TB_DEFAULT, "│", 1, TB_BLUE, TB_DEFAULT, "$"); }
+        }
+    }
+
+    /// Draw the marked songs, which are requested if the user confirms it
+    fn draw_marked(&self) {
+        let (w, h) = self.get_viewport_size();
+        let title = format!("Request these {} songs? (y/n)", self.marked.len());
+        unsafe {
+            self.print(0, 0, TB_BOLD, TB_DEFAULT, &title, w as usize, TB_BLUE, TB_DEFAULT, "$");
+        }
+        let rows = (h as usize).saturating_sub(2);
+        let mut str_table: Vec<Vec<Cow<str>>> = self.marked.iter().take(rows).map(|media| {
+            vec!(Cow::from(media.artist.as_ref()), Cow::from(media.title.as_ref()))
+        }).collect();
+        if self.marked.len() > rows && rows > 0 {
+            // the last row that fits tells how many do not
+            let more = self.marked.len() - rows + 1;
+            str_table[rows - 1] = vec!(Cow::from(format!("and {} more", more)));
+        }
+        let col_widths = fit_columns(&str_table, &[1f32, 1f32], w as usize);
+        self.draw_table(2, str_table.iter(), &col_widths, (TB_DEFAULT, TB_BLUE, TB_DEFAULT),
+                        None);
+        unsafe { tb_set_cursor(TB_HIDE_CURSOR, TB_HIDE_CURSOR); }
+    }
+
+    /// The results of the search query, in the order that the user chose
+    ///
+    /// Ranking by relevance (see `:relevance`) goes before `popular_first`. Only the results that
+    /// arrived so far are sorted, so a song can still move up when more results arrive.
+    fn search_results(&self) -> Vec<&Media> {
+        let client = self.app.client();
+        let mut results = client.get_ranked_results();
+        if self.popular_first && !client.is_ranked() {
+            // the sort is stable, so songs that are as popular stay in the order of the server
+            results.sort_by(|a, b| self.stats.popularity(&b.key).cmp(&self.stats.popularity(&a.key)));
+        }
+        results
+    }
+
+    /// Draw the view that is open over the queue view
+    fn draw_view(&self) {
+        match self.views.last() {
+            Some(&View::Playing) => self.draw_playing(),
+            Some(&View::Details(ref media)) => self.draw_details(media),
+            Some(&View::Artist(_, ref media, ref selection)) |
+            Some(&View::Uploads(_, ref media, ref selection)) |
+            Some(&View::Suggestions(ref media, ref selection)) => {
+                let keys: Vec<String> = media.iter().map(|x| x.key.clone()).collect();
+                let media: Vec<&Media> = media.iter().collect();
+                self.draw_media_list(&media, selection, &keys, true, true, &[]);
+            },
+            Some(&View::Party) => self.draw_party(),
+            Some(&View::Help) => self.draw_help(),
+            // a search is only on the stack when another view is open over it
+            Some(&View::Search(..)) | None => {},
+        }
+    }
+
+    /// Draw the `:help` view: how every command is used and what it does, and the aliases
+    fn draw_help(&self) {
+        let (w, h) = self.get_viewport_size();
+        unsafe {
+            self.print(0, 0, TB_BOLD, TB_DEFAULT, "Commands", w as usize, TB_BLUE, TB_DEFAULT,
+                       "$");
+        }
+        let mut str_table: Vec<Vec<Cow<str>>> = self.commands.commands.iter().map(|x| {
+            vec!(Cow::from(x.usage()), Cow::from(x.help))
+        }).collect();
+        let names = self.commands.names();
+        for (name, command) in &self.aliases {
+            if aliases::resolve(&self.aliases, &names, name).is_some() {
+                str_table.push(vec!(Cow::from(format!(":{}", name)),
+                                    Cow::from(format!("Alias for :{}", command))));
+            }
+        }
+        let rows = (h as usize).saturating_sub(2);
+        if str_table.len() > rows && rows > 0 {
+            let more = str_table.len() - rows + 1;
+            str_table.truncate(rows - 1);
+            str_table.push(vec!(Cow::from(format!("and {} more", more))));
+        }
+        str_table.truncate(rows);
+        let usage_width = str_table.iter().map(|x| x[0].chars().count()).max().unwrap_or(0) + 2;
+        let col_widths = vec!(usage_width, (w as usize).saturating_sub(usage_width));
+        self.draw_table(2, str_table.iter(), &col_widths, (TB_DEFAULT, TB_BLUE, TB_DEFAULT),
+                        None);
+    }
+
+    /// Draw the `:party` view: the songs and the time that every requester has in the queue,
+    /// with a bar for their part of the time
+    fn draw_party(&self) {
+        let (w, h) = self.get_viewport_size();
+        let requests = self.queue_preview();
+        let shares = queue::by_requester(&requests);
+        let total = requests.iter().fold(Duration::zero(), |a, x| a + x.media.length);
+        let title = if shares.is_empty() {
+            String::from("The queue is empty")
+        } else {
+            format!("{} songs, {} from {} requesters", requests.len(), format_duration(total),
+                    shares.len())
+        };
+        unsafe {
+            self.print(0, 0, TB_BOLD, TB_DEFAULT, &title, w as usize, TB_BLUE, TB_DEFAULT, "$");
+        }
+        if shares.is_empty() {
+            return;
+        }
+
+        // a requester has more than a fair share with over twice the time of an equal share
+        let total_secs = max(total.num_seconds(), 1);
+        let fair_secs = 2 * total_secs / shares.len() as i64;
+        let bar_width = 20;
+        let str_table: Vec<Vec<Cow<str>>> = shares.iter().map(|x| {
+            let secs = x.length.num_seconds();
+            let bar = "#".repeat((bar_width * secs / total_secs) as usize);
+            vec!(Cow::from(unwrap_requested_by(&x.by)),
+                 Cow::from(format!("{} songs", x.songs)),
+                 Cow::from(format_duration(x.length)),
+                 Cow::from(format!("{:<2$} {}%", bar, 100 * secs / total_secs,
+                                   bar_width as usize)))
+        }).collect();
+        let rows = (h as usize).saturating_sub(4);
+        let name_width = str_table.iter().map(|x| x[0].chars().count()).max().unwrap_or(0) + 2;
+        let col_widths = vec!(name_width, 11, 10, (w as usize).saturating_sub(name_width + 21));
+        self.draw_table(2, str_table.iter().take(rows), &col_widths,
+                        (TB_DEFAULT, TB_BLUE, TB_DEFAULT), None);
+
+        let over: Vec<&str> = shares.iter().filter(|x| shares.len() > 1 &&
+                                                       x.length.num_seconds() > fair_secs)
+                                    .map(|x| unwrap_requested_by(&x.by)).collect();
+        let y = 3 + min(rows, str_table.len()) as i32;
+        if !over.is_empty() && y < h {
+            let msg = format!("More than twice an equal share: {}", over.join(", "));
+            unsafe {
+                self.print(0, y, TB_YELLOW, TB_DEFAULT, &msg, w as usize, TB_BLUE, TB_DEFAULT,
+                           "$");
+            }
+        }
+    }
+
+    /// Draw the `:playing` view, with room for the cover on the left if there is one
+    fn draw_playing(&self) {
+        let w = self.get_viewport_width();
+        let playing = match *self.app.client().get_playing() {
+            Some(ref playing) => playing,
+            None => {
+                unsafe {
+                    self.print(0, 0, TB_BOLD, TB_DEFAULT, "Nothing is playing", w as usize,
+                               TB_BLUE, TB_DEFAULT, "$");
+                }
+                return;
+            },
+        };
+        let x = if self.visible_cover().is_some() { COVER_COLS + 2 } else { 0 };
+        let media = &playing.media;
+        let left = max(playing.end_time - get_time(), Duration::zero());
+        let mut details = vec!(
+            ("Requested by", String::from(unwrap_requested_by(&playing.requested_by))),
+            ("Length", format_duration(media.length)),
+            ("Ends", format!("{} ({} left)", self.time_format.time(playing.end_time),
+                             format_duration(left))),
+            ("Uploaded by", media.uploaded_by.clone()),
+        );
+        if let Some((votes, needed)) = self.app.client().get_skip_votes() {
+            details.push(("Skip votes", format!("{}/{}", votes, needed)));
+        }
+        self.draw_song(x, media, &details);
+        self.draw_lyrics(x, 5 + details.len() as i32, &media.key);
+    }
+
+    /// Draw the details view of `media`
+    fn draw_details(&self, media: &Media) {
+        let mut details = vec!(
+            ("Length", format_duration(media.length)),
+            ("Uploaded by", media.uploaded_by.clone()),
+        );
+        if let Some(counts) = self.stats.get(&media.key) {
+            details.push(("Requested", format!("{} times, {} by you", counts.requested,
+                                               counts.requested_by_you)));
+            details.push(("Played", format!("{} times", counts.played)));
+        }
+        self.draw_song(0, media, &details);
+        let (w, h) = self.get_viewport_size();
+        let y = 5 + details.len() as i32;
+        if y < h {
+            let hint = format!("Enter requests it, Ctrl-O shows the songs of {}", media.artist);
+            unsafe {
+                self.print(0, y, TB_BLUE, TB_DEFAULT, &hint, w as usize, TB_BLUE, TB_DEFAULT, "$");
+            }
+        }
+    }
+
+    /// Draw the title, the artist and the album of `media` from column `x` on, and below them
+    /// the `details` as labels and values
+    fn draw_song(&self, x: i32, media: &Media, details: &[(&str, String)]) {
+        let (w, h) = self.get_viewport_size();
+        let width = (w - x) as usize;
+        let label_width = details.iter().map(|x| x.0.len()).max().unwrap_or(0) + 2;
+        let value_width = width.saturating_sub(label_width);
+
+        unsafe {
+            self.print(x, 0, TB_BOLD, TB_DEFAULT, &media.title, width, TB_BLUE, TB_DEFAULT, "$");
+            self.print(x, 1, TB_DEFAULT, TB_DEFAULT, &media.artist, width,
+                       TB_BLUE, TB_DEFAULT, "$");
+            if let Some(ref album) = media.album {
+                self.print(x, 2, TB_DEFAULT, TB_DEFAULT, album, width, TB_BLUE, TB_DEFAULT, "$");
+            }
+            for (i, &(label, ref value)) in details.iter().enumerate() {
+                let y = 4 + i as i32;
+                if y >= h {
+                    break;
+                }
+                self.print(x, y, TB_BLUE, TB_DEFAULT, &format!("{}:", label), label_width,
+                           TB_BLUE, TB_DEFAULT, "$");
+                self.print(x + label_width as i32, y, TB_DEFAULT, TB_DEFAULT, value, value_width,
+                           TB_BLUE, TB_DEFAULT, "$");
+            }
+        }
+    }
+
+    /// Draw the lyrics of the song with `media_key` from row `top` on, scrolled to `lyrics_offset`
+    fn draw_lyrics(&self, x: i32, top: i32, media_key: &str) {
+        if self.lyrics_command.is_none() {
+            return;
+        }
+        let (w, h) = self.get_viewport_size();
+        let width = (w - x) as usize;
+        let lines = match self.lyrics {
+            Some((ref key, ref lines)) if key == media_key => lines,
+            _ => {
+                unsafe {
+                    self.print(x, top, TB_BLUE, TB_DEFAULT, "Looking up the lyrics...", width,
+                               TB_BLUE, TB_DEFAULT, "$");
+                }
+                return;
+            },
+        };
+        if lines.is_empty() {
+            unsafe {
+                self.print(x, top, TB_BLUE, TB_DEFAULT, "No lyrics found", width,
+                           TB_BLUE, TB_DEFAULT, "$");
+            }
+            return;
+        }
+        for (y, line) in (top..h).zip(lines.iter().skip(self.lyrics_offset)) {
+            unsafe {
+                self.print(x, y, TB_DEFAULT, TB_DEFAULT, line, width, TB_BLUE, TB_DEFAULT, "$");
+            }
+        }
+    }
+
+    /// The file with the cover that belongs on the screen right now, if there is one
+    fn visible_cover(&self) -> Option<PathBuf> {
+        if !self.in_playing_view() || self.query.starts_with('/') || self.edit_form.is_some() ||
+           self.is_too_small() || self.get_width() < 2 * COVER_COLS {
+            return None;
+        }
+        let playing_key = self.app.client().get_playing().as_ref().map(|x| &x.media.key);
+        match self.cover {
+            Some((ref key, ref path)) if Some(key) == playing_key => path.clone(),
+            _ => None,
+        }
     }
 
-    fn draw_search_results<'a>(&'a mut self) {
-        // TODO Show blue tildes '~' (as in vim) at the end of the range.
+    /// Draw the cover over what termbox drew, if it is not on the screen yet
+    fn draw_cover(&mut self) {
+        let path = match self.visible_cover() {
+            Some(path) => path,
+            None => return,
+        };
+        if self.cover_drawn.is_some() {
+            return;
+        }
+        // keep the cover square (cells are about twice as high as they are wide)
+        let rows = min(COVER_ROWS, self.get_viewport_height());
+        let cols = rows * COVER_COLS / COVER_ROWS;
+        let top = self.header_rows();
+        if let Err(err) = cover::draw(self.cover_graphics, &path, 0, top, cols, rows) {
+            warn!("could not draw the cover art: {}", err);
+        }
+        self.cover_drawn = Some(path);
+    }
+
+    /// Remove the cover from the screen
+    fn erase_cover(&mut self) {
+        self.cover_drawn = None;
+        cover::clear(self.cover_graphics);
+        if self.cover_graphics == Graphics::Sixel {
+            // termbox only draws the cells that changed, and sixels are not cells
+            unsafe {
+                tb_shutdown();
+                tb_init();
+            }
+        }
+    }
+
+    /// Draw a list of songs with the media keys `keys`, scrolled to `selection` and with the
+    /// selected song highlighted if the list is `focused`
+    ///
+    /// If the list is `done`, the rest of the screen is filled with tildes.
+    fn draw_media_list<'a>(&'a self, media: &[&'a Media], selection: &Selection<String>,
+                           keys: &[String], focused: bool, done: bool, marked: &[Media]) {
         let (w, h) = self.get_viewport_size();
+        let offset = selection.offset(keys, h as usize);
         let mut str_table: Vec<Vec<Cow<'a, str>>> = Vec::new();
 
-        let (results, qm_done) = self.client.get_qm_results();
-        for media in results.iter().skip(self.results_offset).take(h as usize) {
-            str_table.push(vec!(Cow::from(media.artist.as_ref()),
-                                Cow::from(media.title.as_ref())));
+        for media in media.iter().skip(offset).take(h as usize) {
+            // without colors, the mark has to show in the text
+            let artist = if marked.iter().any(|x| x.key == media.key) {
+                Cow::from(format!("* {}", media.artist))
+            } else {
+                Cow::from(media.artist.as_ref())
+            };
+            str_table.push(vec!(artist, Cow::from(media.title.as_ref())));
         }
 
         let col_widths = fit_columns(&str_table, &[1f32, 1f32], w as usize);
-        let selected = self.results_focus - self.results_offset;
-        let selection = Some((selected, (TB_BLACK, TB_BLUE, TB_WHITE)));
+        let selected = selection.index(keys) - offset;
+        let selection = if focused {
+            Some((selected, (TB_BLACK, TB_BLUE, TB_WHITE)))
+        } else {
+            None
+        };
         self.draw_table(0, str_table.iter(), &col_widths, (TB_DEFAULT, TB_BLUE, TB_DEFAULT),
                         selection);
 
-                        // (TB_BLACK, TB_BLUE, TB_WHITE)
-                        // (TB_DEFAULT, TB_BLUE, TB_DEFAULT)
-
-
-        if *qm_done {
+        if done {
             // Fill up the rest with blue tildes to indicate end-of-file
             let row = vec!(Cow::from("~"));
-            let from_row = results.iter()
-                              .skip(self.results_offset)
-                              .take(h as usize)
-                              .count();
+            let from_row = str_table.len();
             assert!(from_row <= h as usize);
 
             let str_table = repeat(&row).take(h as usize - from_row);
@@ -709,6 +3665,74 @@ impl TUI {
         }
     }
 
+    /// Draw the `:edit` form, with the cursor in the field that is being edited
+    fn draw_edit_form(&self) {
+        let form = self.edit_form.as_ref().unwrap();
+        let (w, h) = self.get_viewport_size();
+        let label_width = EDIT_FIELDS.iter().map(|x| x.1.len()).max().unwrap_or(0) + 2;
+        let value_width = (w as usize).saturating_sub(label_width);
+        let title = format!("Editing {} - {}", form.media.artist, form.media.title);
+        unsafe {
+            self.print(0, 0, TB_BOLD, TB_DEFAULT, &title, w as usize, TB_BLUE, TB_DEFAULT, "$");
+            for (i, (&(_, label), value)) in EDIT_FIELDS.iter().zip(&form.values).enumerate() {
+                let y = 2 + i as i32;
+                if y >= h {
+                    break;
+                }
+                let (fg, bg) = if i == form.focus {
+                    (TB_BLACK, TB_WHITE)
+                } else {
+                    (TB_DEFAULT, TB_DEFAULT)
+                };
+                self.print(0, y, TB_BOLD, TB_DEFAULT, &format!("{}:", label), label_width,
+                           TB_BLUE, TB_DEFAULT, "$");
+                self.print(label_width as i32, y, fg, bg, value, value_width, TB_BLUE, bg, "$");
+            }
+            let cursor_x = label_width + form.values[form.focus].chars().count();
+            let cursor_y = 2 + form.focus as i32;
+            if cursor_x < w as usize && cursor_y < h {
+                tb_set_cursor(cursor_x as i32, cursor_y + self.header_rows());
+            } else {
+                tb_set_cursor(TB_HIDE_CURSOR, TB_HIDE_CURSOR);
+            }
+        }
+    }
+
+    /// Draw the `:console`: the packets that fit, one per line and the newest at the bottom, and
+    /// the message that is typed below them
+    fn draw_console(&self) {
+        let console = self.console.as_ref().unwrap();
+        let (w, h) = self.get_viewport_size();
+        let rows = (h as usize).saturating_sub(1);
+        let packets = self.app.client().get_packets();
+        let end = packets.len().saturating_sub(console.offset);
+        let start = end.saturating_sub(rows);
+        unsafe {
+            for (y, packet) in packets[start..end].iter().enumerate() {
+                let (arrow, fg) = match packet.direction {
+                    Direction::Sent => ("→", TB_YELLOW),
+                    Direction::Received => ("←", TB_GREEN),
+                };
+                let prefix = format!("{} {} ", self.time_format.time(packet.time), arrow);
+                let prefix_width = prefix.chars().count();
+                self.print(0, y as i32, fg, TB_DEFAULT, &prefix, w as usize, TB_BLUE, TB_DEFAULT,
+                           "$");
+                self.print(prefix_width as i32, y as i32, TB_DEFAULT, TB_DEFAULT,
+                           &packet.json.to_string(), (w as usize).saturating_sub(prefix_width),
+                           TB_BLUE, TB_DEFAULT, "$");
+            }
+            let input = format!("> {}", console.input);
+            self.print(0, rows as i32, TB_BOLD, TB_DEFAULT, &input, w as usize, TB_BLUE,
+                       TB_DEFAULT, "$");
+            let cursor_x = input.chars().count();
+            if cursor_x < w as usize && console.pending.is_none() {
+                tb_set_cursor(cursor_x as i32, rows as i32 + self.header_rows());
+            } else {
+                tb_set_cursor(TB_HIDE_CURSOR, TB_HIDE_CURSOR);
+            }
+        }
+    }
+
     fn draw_table<'a, T>(&self, offset: usize, str_table: T, col_widths: &Vec<usize>,
                          style: (u16, u16, u16),
                          selected: Option<(usize, (u16, u16, u16))>)
@@ -719,10 +3743,11 @@ impl TUI {
             });
             for (j, cell) in row.iter().enumerate() {
                 assert!(j <= col_widths.len());
-                let x = col_widths.iter().take(j).fold(0, |a, b| a + b);
+                let x = col_widths.iter().take(j).fold(0, |a, b| a + b) as i32;
+                let x = x + self.pane.get().map_or(0, |x| x.0);
                 let maxlen = col_widths[j];
                 unsafe {
-                    self.print(x as i32, (y + offset) as i32, fg, bg, cell, maxlen, fg2, bg, "$");
+                    self.print(x, (y + offset) as i32, fg, bg, cell, maxlen, fg2, bg, "$");
                 }
             }
         }
@@ -734,36 +3759,36 @@ impl TUI {
         let maxwidth: usize = if self.status.peek(&()).is_some() {
             (w as usize).saturating_sub(MAX_STATUS_WIDTH)
         } else {
-            w as usize
+            (w as usize).saturating_sub(self.clock_text().chars().count() + 1)
         };
 
         if self.query.starts_with(':') {
-            // hide possible password argument
-            let ref substr = format!(":{} ", CMD_PASSWORD);
-            let ref query = if self.query.starts_with(substr) {
-                let substr_len = substr.len();
-                Cow::Owned(format!(":{} {}", CMD_PASSWORD, self.query
-                    .chars()
-                    .skip(substr_len)
-                    .map(|_| '*')
-                    .collect::<String>()))
-            } else {
-                Cow::Borrowed(&self.query)
+            // hide possible password or passphrase argument, also behind an alias, and already
+            // while the command is shortened to a prefix that is still ambiguous
+            let expanded = self.expand_alias(&self.query);
+            let expanded_cmd = expanded[1..].split(char::is_whitespace).next().unwrap_or("");
+            let secret = self.commands.hides_args(expanded_cmd);
+            let ref query = match self.query.find(' ') {
+                Some(idx) if secret => {
+                    Cow::Owned(format!("{}{}", &self.query[..idx + 1], self.query[idx + 1..]
+                        .chars()
+                        .map(|_| '*')
+                        .collect::<String>()))
+                },
+                _ => Cow::Borrowed(&self.query),
             };
 
             // draw command
-            let commands = COMMANDS;
-            let command: Option<&&str> = commands.iter().filter(|cmd|
-                self.query[1..].starts_with(&cmd[..])).next();
-            if let Some(cmd) = command {
+            let cmd = self.query[1..].split(char::is_whitespace).next().unwrap_or("");
+            if let Resolved::Command(_) = commands::resolve(&self.commands.names(), cmd) {
                 let cmdlen = cmd.len();
                 unsafe {
                     self.print(0, h, TB_DEFAULT, TB_DEFAULT, &query[0..1], maxwidth,
                                TB_DEFAULT, TB_BLUE, "$");
-                    self.print(1, h, TB_BOLD, TB_DEFAULT, &query[1..1+cmdlen], maxwidth - 1,
-                               TB_DEFAULT, TB_BLUE, "$");
+                    self.print(1, h, TB_BOLD, TB_DEFAULT, &query[1..1+cmdlen],
+                               maxwidth.saturating_sub(1), TB_DEFAULT, TB_BLUE, "$");
                     self.print(cmdlen as i32 + 1, h, TB_DEFAULT, TB_DEFAULT, &query[1+cmdlen..],
-                               maxwidth - 1 - cmdlen, TB_DEFAULT, TB_BLUE, "$");
+                               maxwidth.saturating_sub(1 + cmdlen), TB_DEFAULT, TB_BLUE, "$");
                 }
             } else {
                 unsafe {
@@ -781,34 +3806,123 @@ impl TUI {
                 self.print(1, h, TB_BOLD, TB_DEFAULT, &self.query[1..],
                            maxwidth as usize, TB_DEFAULT, TB_DEFAULT, "$");
             }
+            if !self.marked.is_empty() {
+                let count = format!("[{} marked]", self.marked.len());
+                let x = self.query.chars().count() + 1;
+                unsafe {
+                    self.print(x as i32, h, TB_YELLOW | TB_BOLD, TB_DEFAULT, &count,
+                               maxwidth.saturating_sub(x), TB_DEFAULT, TB_DEFAULT, "$");
+                }
+            }
         }
 
         // update cursor
         unsafe {
-            tb_set_cursor(self.query.len() as i32, h);
+            tb_set_cursor(self.query.len() as i32, h + self.header_rows());
         }
     }
 
     fn draw_status(&self) {
+        if let Some(ref msg) = self.crash_message {
+            let (w, h) = self.get_viewport_size();
+            unsafe {
+                self.print(0, h, TB_RED | TB_BOLD, TB_DEFAULT, msg, w as usize,
+                           TB_BLUE, TB_DEFAULT, "$");
+            }
+            return;
+        }
         if let Some(&(ref status, ref ty)) = self.status.peek(&()) {
             let (w, h) = self.get_viewport_size();
             let status_width = min(max(MIN_STATUS_WIDTH, status.len()), MAX_STATUS_WIDTH);
             let offset = (w as usize).saturating_sub(status_width);
             let maxwidth = w as usize - offset;
-            let fg = match *ty {
-                StatusType::Info => TB_BLUE,
-                StatusType::Success => TB_GREEN,
-                StatusType::Warning => TB_YELLOW,
-                StatusType::Error => TB_RED,
-            } | TB_BOLD;
+            let fg = ty.color() | TB_BOLD;
             let bg = TB_DEFAULT;
             unsafe {
                 self.print(offset as i32, h, fg, bg, &status,
                            maxwidth, TB_BLUE, bg, "$");
             }
+        } else if let Some(busy) = self.busy() {
+            let (w, h) = self.get_viewport_size();
+            let text = format!("{} {}", SPINNER[self.spinner_frame], busy);
+            let len = text.chars().count();
+            if len <= w as usize {
+                unsafe {
+                    self.print(w - len as i32, h, TB_BLUE, TB_DEFAULT, &text, len,
+                               TB_BLUE, TB_DEFAULT, "$");
+                }
+            }
+        } else {
+            self.draw_clock();
+        }
+    }
+
+    /// Draw the toasts over the top right of the view, the newest at the top
+    fn draw_toasts(&self) {
+        let (w, h) = self.get_viewport_size();
+        let now = get_time();
+        let toasts = self.toasts.iter().rev().filter(|x| x.until > now);
+        for (y, toast) in toasts.take(h as usize).enumerate() {
+            let width = min(toast.msg.chars().count() + 2, min(MAX_TOAST_WIDTH, w as usize));
+            let x = w - width as i32;
+            unsafe {
+                self.print(x, y as i32, TB_BLACK, toast.ty.color(), &format!(" {}", toast.msg),
+                           width, TB_BLACK, toast.ty.color(), "$ ");
+            }
+        }
+    }
+
+    /// Draw the time and when the queue runs dry where the status goes, if there is no status
+    fn draw_clock(&self) {
+        let (w, h) = self.get_viewport_size();
+        let text = self.clock_text();
+        let len = text.chars().count();
+        if len > w as usize {
+            return;
+        }
+        unsafe {
+            self.print(w - len as i32, h, TB_BLUE, TB_DEFAULT, &text, len, TB_BLUE, TB_DEFAULT, "$");
+        }
+    }
+
+    fn clock_text(&self) -> String {
+        if let Some(ref status) = self.status_template {
+            return self.expand_status(status);
+        }
+        let now = get_time();
+        match *self.app.client().get_playing() {
+            Some(ref playing) => {
+                let requests = self.app.client().get_requests().as_ref()
+                                                .map_or(&[][..], |x| &x[..]);
+                let end = template::queue_end(playing, requests, now);
+                format!("queue ends {}  {}", self.time_format.time(end), self.time_format.time(now))
+            },
+            None => self.time_format.time(now),
+        }
+    }
+
+    /// Draw the `header` template on the line above the viewport, if there is one
+    fn draw_header(&self) {
+        if let Some(ref header) = self.header {
+            let text = self.expand_status(header);
+            unsafe {
+                self.print(0, -1, TB_BOLD, TB_DEFAULT, &text, self.get_width() as usize, TB_BLUE,
+                           TB_DEFAULT, "$");
+            }
         }
     }
 
+    /// Fill in the placeholders of the `header` or `status` template `template`
+    fn expand_status(&self, template: &str) -> String {
+        let fields = StatusFields::new(self.app.client(), self.time_format);
+        template::expand(template, |name| fields.get(name))
+    }
+
+    /// The number of lines above the viewport
+    fn header_rows(&self) -> i32 {
+        if self.header.is_some() { 1 } else { 0 }
+    }
+
     fn get_width(&self) -> i32 {
         unsafe { tb_width() as i32 }
     }
@@ -822,20 +3936,60 @@ impl TUI {
     }
 
     fn get_viewport_width(&self) -> i32 {
-        self.get_width()
+        self.pane.get().map_or(self.get_width(), |x| x.1)
     }
 
     fn get_viewport_height(&self) -> i32 {
-        match self.get_height().checked_sub(1) {
-            Some(h) => h,
-            None => cleanup!(panic!("viewport height is too small")),
-        }
+        // the last line holds the query and the status
+        max(self.get_height() - 1 - self.header_rows(), 0)
     }
 
     fn get_viewport_size(&self) -> (i32, i32) {
         (self.get_viewport_width(), self.get_viewport_height())
     }
 
+    /// Is the request queue on the screen, so that the keys for managing requests apply to it?
+    ///
+    /// In the split layout, they apply to it while it has the focus.
+    fn in_queue_view(&self) -> bool {
+        if !self.views.is_empty() {
+            return false;
+        }
+        self.query.is_empty() ||
+            self.query.starts_with('/') && self.queue_focused && self.is_split()
+    }
+
+    /// Are the queue and the search results shown side by side?
+    ///
+    /// They are not if one of them would be too narrow, or if another view is open.
+    fn is_split(&self) -> bool {
+        let left = self.split_column();
+        let right = self.get_width() - left - 1;
+        self.split_layout && self.views.is_empty() && min(left, right) >= SPLIT_MIN_PANE_WIDTH
+    }
+
+    /// The column of the line between the queue and the search results in the split layout
+    fn split_column(&self) -> i32 {
+        self.get_width() * self.split_percent / 100
+    }
+
+    /// Give the queue `step` percent more of the width in the split layout
+    fn resize_split(&mut self, step: i32) {
+        let percent = self.split_percent + step;
+        self.split_percent = max(SPLIT_MIN_PERCENT, min(percent, SPLIT_MAX_PERCENT));
+        let msg = if self.is_split() {
+            format!("The queue gets {}% of the width", self.split_percent)
+        } else {
+            String::from("The terminal is too narrow to show the queue and the search results")
+        };
+        self.status.insert((), (Cow::from(msg), StatusType::Info));
+    }
+
+    fn is_too_small(&self) -> bool {
+        let (w, h) = self.get_size();
+        w < MIN_WIDTH || h < MIN_HEIGHT + self.header_rows()
+    }
+
 }
 
 impl Drop for TUI {
@@ -851,16 +4005,6 @@ fn unwrap_requested_by<'a>(requested_by: &'a Option<String>) -> &'a str {
     }
 }
 
-fn format_duration(d: Duration) -> String {
-    match () {
-        _ if d.num_days() != 0 => format!("{}d{:02}:{:02}:{:02}",
-            d.num_days(), d.num_hours() % 24, d.num_minutes() % 60, d.num_seconds() % 60),
-        _ if d.num_hours() != 0 => format!("{}:{:02}:{:02}",
-            d.num_hours(), d.num_minutes() % 60, d.num_seconds() % 60),
-        _ =>  format!("{}:{:02}", d.num_minutes(), d.num_seconds() % 60)
-    }
-}
-
 fn fit_columns<'a>(rows: &Vec<Vec<Cow<'a, str>>>, expand_factors: &[f32], fit_width: usize) -> Vec<usize> {
     let col_count = expand_factors.len();
     let mut cols = {
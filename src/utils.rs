@@ -1,6 +1,74 @@
+use std::env;
+use std::error::Error;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+use env_logger;
+use log::{self, Log, LogLevelFilter, LogMetadata, LogRecord};
+
 pub fn show_version_and_exit() -> ! {
     use std::process::exit;
     const VERSION: &'static str = env!("CARGO_PKG_VERSION");
     format!("maruska {}", VERSION);
     exit(0);
 }
+
+/// The name of the user that runs us, to log in as when no username is given
+///
+/// That is `USER` on Unix-like systems and `USERNAME` on Windows.
+#[allow(dead_code)] // the terminal UI asks for a username instead
+pub fn current_user() -> Option<String> {
+    env::var("USER").or_else(|_| env::var("USERNAME")).ok().and_then(|x| {
+        if x.is_empty() { None } else { Some(x) }
+    })
+}
+
+/// Logs to standard error or to a file, filtering like `env_logger`
+struct Logger {
+    filter: env_logger::Logger,
+    target: Mutex<Box<Write + Send>>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &LogMetadata) -> bool {
+        Log::enabled(&self.filter, metadata)
+    }
+
+    fn log(&self, record: &LogRecord) {
+        if Log::enabled(self, record.metadata()) {
+            let mut target = self.target.lock().unwrap();
+            let _ = writeln!(target, "{}:{}: {}", record.level(),
+                             record.location().module_path(), record.args());
+        }
+    }
+}
+
+/// Initialize the logger
+///
+/// `level` (e.g. `"debug"`, or a filter in the format of `RUST_LOG`) overrides the `RUST_LOG`
+/// environment variable. If `log_file` is given, messages are appended to it instead of being
+/// printed. `trace_protocol` logs every packet that is exchanged with the server.
+pub fn init_logger(level: Option<&str>, log_file: Option<&str>, trace_protocol: bool)
+                   -> Result<(), Box<Error>> {
+    let mut builder = env_logger::LogBuilder::new();
+    if let Some(level) = level {
+        builder.parse(level);
+    } else if let Ok(level) = env::var("RUST_LOG") {
+        builder.parse(&level);
+    }
+    if trace_protocol {
+        builder.filter(Some("libclient::comet"), LogLevelFilter::Trace);
+    }
+    let filter = builder.build();
+
+    let target: Box<Write + Send> = match log_file {
+        Some(path) => Box::new(try!(OpenOptions::new().append(true).create(true).open(path))),
+        None => Box::new(io::stderr()),
+    };
+    try!(log::set_logger(|max_level| {
+        max_level.set(filter.filter());
+        Box::new(Logger { filter: filter, target: Mutex::new(target) })
+    }));
+    Ok(())
+}